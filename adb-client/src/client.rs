@@ -1,41 +1,79 @@
-use crate::device::DeviceInfo;
+use crate::device::{DeviceEvent, DeviceInfo};
+use crate::direct::DeviceConnection;
 use crate::error::{AdbError, AdbResult};
+use crate::forward::{ForwardEntry, ForwardSpec};
+use crate::package::{self, InstallOptions, ListPackagesOptions, PackageInfo, PNG_SIGNATURE};
 use crate::protocol::{self, AdbStatus, HostCommand, LocalCommand};
-use crate::sync::{self, DentEntry, StatResponse, SyncHeader, SyncId, SYNC_DATA_MAX};
-use std::path::Path;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tracing::debug;
+use crate::shell::{ShellOutput, ShellSession};
+use crate::stream::AdbStream;
+use crate::sync::{self, DentEntry, DentV2Entry, StatResponse, StatV2Response, SyncId};
+use crate::transfer::{
+    SyncConnection, SyncFeatures, TransferOptions, TransferProgress, TransferStats,
+};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
 
 /// Client for communicating with the ADB server over TCP.
 ///
 /// Each command opens a fresh TCP connection to the ADB server — this matches
 /// the real ADB client behavior where connections are one-shot.
+///
+/// A client can also talk to one device's adbd directly (see `with_device`).
+/// Device services (shell, sync, exec, ...) then work as before, with
+/// `serial` ignored, but host services like `list_devices` or `forward`
+/// return `AdbError::NotSupported`.
+#[derive(Debug, Clone)]
 pub struct AdbClient {
-    host: String,
-    port: u16,
+    transport: Transport,
+}
+
+/// How an `AdbClient` reaches devices.
+#[derive(Debug, Clone)]
+enum Transport {
+    /// Through the ADB server at this address.
+    Server { host: String, port: u16 },
+    /// Straight to the device.
+    Device(DeviceConnection),
 }
 
 impl AdbClient {
     /// Create a client connecting to the default ADB server at `127.0.0.1:5037`.
     pub fn new() -> Self {
-        Self {
-            host: "127.0.0.1".to_string(),
-            port: 5037,
-        }
+        Self::with_address("127.0.0.1", 5037)
     }
 
     /// Create a client connecting to a specific address (useful for testing).
     pub fn with_address(host: &str, port: u16) -> Self {
         Self {
-            host: host.to_string(),
-            port,
+            transport: Transport::Server {
+                host: host.to_string(),
+                port,
+            },
+        }
+    }
+
+    /// Create a client using a direct connection to a device's adbd instead
+    /// of the ADB server.
+    pub fn with_device(device: DeviceConnection) -> Self {
+        Self {
+            transport: Transport::Device(device),
         }
     }
 
     /// Open a new TCP connection to the ADB server.
     async fn connect(&self) -> AdbResult<TcpStream> {
-        let addr = format!("{}:{}", self.host, self.port);
+        let (host, port) = match &self.transport {
+            Transport::Server { host, port } => (host, port),
+            Transport::Device(_) => {
+                return Err(AdbError::NotSupported(
+                    "host services need the ADB server".into(),
+                ))
+            }
+        };
+        let addr = format!("{}:{}", host, port);
         debug!("Connecting to ADB server at {}", addr);
         TcpStream::connect(&addr).await.map_err(|e| {
             if e.kind() == std::io::ErrorKind::ConnectionRefused {
@@ -47,8 +85,16 @@ impl AdbClient {
     }
 
     /// Send a request and read the OKAY/FAIL status response.
-    async fn send_command(stream: &mut TcpStream, command: &[u8]) -> AdbResult<()> {
+    async fn send_command<S>(stream: &mut S, command: &[u8]) -> AdbResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         stream.write_all(command).await?;
+        Self::read_status(stream).await
+    }
+
+    /// Read an OKAY/FAIL status, turning FAIL into an error with its message.
+    async fn read_status<S: AsyncRead + Unpin>(stream: &mut S) -> AdbResult<()> {
         let mut status_buf = [0u8; 4];
         stream.read_exact(&mut status_buf).await?;
         match protocol::parse_status(&status_buf)? {
@@ -63,7 +109,7 @@ impl AdbClient {
     }
 
     /// Read a length-prefixed response body (4-hex-digit length + data).
-    async fn read_length_prefixed(stream: &mut TcpStream) -> AdbResult<Vec<u8>> {
+    async fn read_length_prefixed<S: AsyncRead + Unpin>(stream: &mut S) -> AdbResult<Vec<u8>> {
         let mut len_buf = [0u8; 4];
         stream.read_exact(&mut len_buf).await?;
         let len = protocol::parse_hex_length(&len_buf)?;
@@ -76,19 +122,12 @@ impl AdbClient {
     }
 
     /// Read all remaining data from the stream until EOF.
-    async fn read_to_end(stream: &mut TcpStream) -> AdbResult<Vec<u8>> {
+    async fn read_to_end<S: AsyncRead + Unpin>(stream: &mut S) -> AdbResult<Vec<u8>> {
         let mut data = Vec::new();
         stream.read_to_end(&mut data).await?;
         Ok(data)
     }
 
-    /// Read an 8-byte sync header from the stream.
-    async fn read_sync_header(stream: &mut TcpStream) -> AdbResult<SyncHeader> {
-        let mut buf = [0u8; 8];
-        stream.read_exact(&mut buf).await?;
-        SyncHeader::from_bytes(&buf)
-    }
-
     // --- Transport helpers ---
 
     /// Select a device transport, then execute a local service command.
@@ -97,7 +136,11 @@ impl AdbClient {
         &self,
         serial: Option<&str>,
         command: &LocalCommand,
-    ) -> AdbResult<TcpStream> {
+    ) -> AdbResult<AdbStream> {
+        if let Transport::Device(device) = &self.transport {
+            debug!("Opening service: {:?}", command.to_wire());
+            return Ok(AdbStream::Device(device.open(&command.to_wire()).await?));
+        }
         let mut stream = self.connect().await?;
 
         // Step 1: Select device transport
@@ -112,14 +155,45 @@ impl AdbClient {
         debug!("Sending local command: {:?}", command.to_wire());
         Self::send_command(&mut stream, &command.encode()).await?;
 
-        Ok(stream)
+        Ok(AdbStream::Server(stream))
+    }
+
+    /// Read the second status a forward or reverse request gets once it is
+    /// set up, and the port picked for it if `spec` asked for any (`tcp:0`).
+    async fn read_forward_result<S: AsyncRead + Unpin>(
+        stream: &mut S,
+        spec: &ForwardSpec,
+    ) -> AdbResult<Option<u16>> {
+        Self::read_status(stream).await?;
+        if *spec != ForwardSpec::Tcp(0) {
+            return Ok(None);
+        }
+        let data = Self::read_length_prefixed(stream).await?;
+        let port = String::from_utf8_lossy(&data);
+        port.trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| AdbError::Protocol(format!("Invalid forward port: {:?}", port)))
     }
 
     /// Enter sync mode on a transport, returning the stream ready for sync commands.
-    async fn enter_sync(&self, serial: Option<&str>) -> AdbResult<TcpStream> {
+    async fn enter_sync(&self, serial: Option<&str>) -> AdbResult<AdbStream> {
         self.with_transport(serial, &LocalCommand::Sync).await
     }
 
+    /// Enter sync mode, using whichever v2 sync requests the device's
+    /// feature list says it supports.
+    pub async fn open_sync(&self, serial: Option<&str>) -> AdbResult<SyncConnection<AdbStream>> {
+        // Servers too old to know `features` don't know v2 sync either
+        let features = match self.features(serial).await {
+            Ok(features) => SyncFeatures::from_features(&features),
+            Err(AdbError::ServerFail(_)) => SyncFeatures::default(),
+            Err(e) => return Err(e),
+        };
+        let stream = self.enter_sync(serial).await?;
+        Ok(SyncConnection::new(stream, features))
+    }
+
     // --- Public API ---
 
     /// Get ADB server protocol version.
@@ -133,6 +207,26 @@ impl AdbClient {
             .map_err(|_| AdbError::Protocol(format!("Invalid version hex: {:?}", hex_str)))
     }
 
+    /// Get the features supported by both the server and the device, such
+    /// as `shell_v2` or `stat_v2`.
+    pub async fn features(&self, serial: Option<&str>) -> AdbResult<Vec<String>> {
+        if let Transport::Device(device) = &self.transport {
+            return Ok(device.banner().features.clone());
+        }
+        let mut stream = self.connect().await?;
+        let command = HostCommand::Features {
+            serial: serial.map(str::to_string),
+        };
+        Self::send_command(&mut stream, &command.encode()).await?;
+        let data = Self::read_length_prefixed(&mut stream).await?;
+        Ok(String::from_utf8_lossy(&data)
+            .trim()
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
     /// List connected devices.
     pub async fn list_devices(&self) -> AdbResult<Vec<DeviceInfo>> {
         let mut stream = self.connect().await?;
@@ -151,11 +245,188 @@ impl AdbClient {
         Ok(String::from_utf8_lossy(&data).to_string())
     }
 
-    /// Stream logcat output. Returns the TCP stream for the caller to read from.
-    pub async fn logcat(&self, serial: Option<&str>) -> AdbResult<TcpStream> {
+    /// Start a command on the device over the shell v2 protocol, which keeps
+    /// stdout and stderr apart and reports the exit status. An empty
+    /// `command` starts an interactive shell. With `pty`, the device runs it
+    /// in a terminal of type `term`.
+    pub async fn open_shell(
+        &self,
+        serial: Option<&str>,
+        command: &str,
+        pty: bool,
+        term: Option<&str>,
+    ) -> AdbResult<ShellSession<ReadHalf<AdbStream>, WriteHalf<AdbStream>>> {
+        let command = LocalCommand::ShellV2 {
+            command: command.to_string(),
+            pty,
+            term: term.map(str::to_string),
+        };
+        let stream = self.with_transport(serial, &command).await?;
+        let (reader, writer) = tokio::io::split(stream);
+        Ok(ShellSession::new(reader, writer))
+    }
+
+    /// Execute a shell command on the device over the shell v2 protocol and
+    /// return its stdout, stderr and exit status.
+    pub async fn shell_v2(&self, serial: Option<&str>, command: &str) -> AdbResult<ShellOutput> {
+        let session = self.open_shell(serial, command, false, None).await?;
+        session.wait_with_output().await
+    }
+
+    /// Stream logcat output. Returns the stream for the caller to read from.
+    pub async fn logcat(&self, serial: Option<&str>) -> AdbResult<AdbStream> {
         self.with_transport(serial, &LocalCommand::Logcat).await
     }
 
+    /// Watch devices connect, disconnect and change state.
+    pub async fn track_devices(&self) -> AdbResult<DeviceTracker> {
+        let mut stream = self.connect().await?;
+        Self::send_command(&mut stream, &HostCommand::TrackDevices.encode()).await?;
+        Ok(DeviceTracker {
+            stream,
+            devices: Vec::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Forward connections to `local` on the host to `remote` on the device.
+    /// Returns the port the server picked if `local` is `tcp:0`.
+    pub async fn forward(
+        &self,
+        serial: Option<&str>,
+        local: &ForwardSpec,
+        remote: &ForwardSpec,
+        no_rebind: bool,
+    ) -> AdbResult<Option<u16>> {
+        let mut stream = self.connect().await?;
+        let command = HostCommand::Forward {
+            serial: serial.map(str::to_string),
+            local: local.clone(),
+            remote: remote.clone(),
+            no_rebind,
+        };
+        Self::send_command(&mut stream, &command.encode()).await?;
+        Self::read_forward_result(&mut stream, local).await
+    }
+
+    /// List active forwards, of one device or of all of them.
+    pub async fn list_forward(&self, serial: Option<&str>) -> AdbResult<Vec<ForwardEntry>> {
+        let mut stream = self.connect().await?;
+        Self::send_command(&mut stream, &HostCommand::ListForward.encode()).await?;
+        let data = Self::read_length_prefixed(&mut stream).await?;
+        let mut entries = ForwardEntry::parse_forward_list(&String::from_utf8_lossy(&data));
+        if let Some(serial) = serial {
+            entries.retain(|e| e.serial == serial);
+        }
+        Ok(entries)
+    }
+
+    /// Remove the forward of `local`.
+    pub async fn kill_forward(&self, serial: Option<&str>, local: &ForwardSpec) -> AdbResult<()> {
+        let mut stream = self.connect().await?;
+        let command = HostCommand::KillForward {
+            serial: serial.map(str::to_string),
+            local: local.clone(),
+        };
+        Self::send_command(&mut stream, &command.encode()).await?;
+        Self::read_status(&mut stream).await
+    }
+
+    /// Remove all forwards of a device, or of every device if `serial` is `None`.
+    pub async fn kill_forward_all(&self, serial: Option<&str>) -> AdbResult<()> {
+        let mut stream = self.connect().await?;
+        let command = HostCommand::KillForwardAll {
+            serial: serial.map(str::to_string),
+        };
+        Self::send_command(&mut stream, &command.encode()).await?;
+        Self::read_status(&mut stream).await
+    }
+
+    /// Forward connections to `remote` on the device to `local` on the host.
+    /// Returns the port the device picked if `remote` is `tcp:0`.
+    pub async fn reverse(
+        &self,
+        serial: Option<&str>,
+        remote: &ForwardSpec,
+        local: &ForwardSpec,
+        no_rebind: bool,
+    ) -> AdbResult<Option<u16>> {
+        let command = LocalCommand::ReverseForward {
+            remote: remote.clone(),
+            local: local.clone(),
+            no_rebind,
+        };
+        let mut stream = self.with_transport(serial, &command).await?;
+        Self::read_forward_result(&mut stream, remote).await
+    }
+
+    /// List the reverse forwards of the device.
+    pub async fn list_reverse(&self, serial: Option<&str>) -> AdbResult<Vec<ForwardEntry>> {
+        let mut stream = self
+            .with_transport(serial, &LocalCommand::ReverseList)
+            .await?;
+        Self::read_status(&mut stream).await?;
+        let data = Self::read_length_prefixed(&mut stream).await?;
+        Ok(ForwardEntry::parse_forward_list(&String::from_utf8_lossy(
+            &data,
+        )))
+    }
+
+    /// Remove the reverse forward of `remote`.
+    pub async fn kill_reverse(&self, serial: Option<&str>, remote: &ForwardSpec) -> AdbResult<()> {
+        let command = LocalCommand::ReverseKill(remote.clone());
+        let mut stream = self.with_transport(serial, &command).await?;
+        Self::read_status(&mut stream).await
+    }
+
+    /// Remove all reverse forwards of the device.
+    pub async fn kill_reverse_all(&self, serial: Option<&str>) -> AdbResult<()> {
+        let mut stream = self
+            .with_transport(serial, &LocalCommand::ReverseKillAll)
+            .await?;
+        Self::read_status(&mut stream).await
+    }
+
+    /// Open a connection to `remote` on the device, tunnelled through the
+    /// ADB server.
+    pub async fn open_remote(
+        &self,
+        serial: Option<&str>,
+        remote: &ForwardSpec,
+    ) -> AdbResult<AdbStream> {
+        self.with_transport(serial, &LocalCommand::Connect(remote.clone()))
+            .await
+    }
+
+    /// Relay every connection accepted on `listener` to `remote` on the
+    /// device, each through its own tunnel. This does what `forward` asks the
+    /// server to do, but in this process, and runs until accepting fails.
+    pub async fn run_forward_relay(
+        &self,
+        serial: Option<&str>,
+        listener: TcpListener,
+        remote: &ForwardSpec,
+    ) -> AdbResult<()> {
+        loop {
+            let (mut local, peer) = listener.accept().await?;
+            debug!("Relaying {} to {}", peer, remote);
+            let client = self.clone();
+            let serial = serial.map(str::to_string);
+            let remote = remote.clone();
+            tokio::spawn(async move {
+                let result = async {
+                    let mut tunnel = client.open_remote(serial.as_deref(), &remote).await?;
+                    tokio::io::copy_bidirectional(&mut local, &mut tunnel).await?;
+                    AdbResult::Ok(())
+                }
+                .await;
+                if let Err(e) = result {
+                    warn!("Relay from {} to {} failed: {}", peer, remote, e);
+                }
+            });
+        }
+    }
+
     /// Stat a remote file on the device.
     pub async fn stat(&self, serial: Option<&str>, remote_path: &str) -> AdbResult<StatResponse> {
         let mut stream = self.enter_sync(serial).await?;
@@ -192,6 +463,19 @@ impl AdbClient {
         Ok(stat)
     }
 
+    /// Stat a remote file with 64-bit size and times, using STA2 if the
+    /// device supports it. Symlinks are followed.
+    pub async fn stat_v2(
+        &self,
+        serial: Option<&str>,
+        remote_path: &str,
+    ) -> AdbResult<StatV2Response> {
+        let mut sync = self.open_sync(serial).await?;
+        let stat = sync.stat(remote_path).await?;
+        sync.quit().await?;
+        Ok(stat)
+    }
+
     /// List a remote directory on the device.
     pub async fn list_dir(
         &self,
@@ -207,24 +491,30 @@ impl AdbClient {
         let mut entries = Vec::new();
 
         loop {
-            let header = Self::read_sync_header(&mut stream).await?;
+            let mut id = [0u8; 4];
+            stream.read_exact(&mut id).await?;
 
-            match header.id {
+            match SyncId::from_bytes(&id)? {
                 SyncId::Dent => {
-                    // Read the DENT payload: mode(4) + size(4) + mtime(4) + namelen(4) + name
-                    let mut payload = vec![0u8; header.length as usize];
+                    // mode(4) + size(4) + mtime(4) + namelen(4), then the name
+                    let mut payload = vec![0u8; 16];
                     stream.read_exact(&mut payload).await?;
-                    // The DENT header.length covers: mode + size + mtime + namelen + name
-                    // But the on-wire format has mode/size/mtime/namelen as the first 16 bytes
-                    // of the payload following the DENT id+length header
-                    let entry = DentEntry::from_bytes(&payload)?;
-                    entries.push(entry);
+                    let namelen =
+                        u32::from_le_bytes([payload[12], payload[13], payload[14], payload[15]]);
+                    payload.resize(16 + namelen as usize, 0);
+                    stream.read_exact(&mut payload[16..]).await?;
+                    entries.push(DentEntry::from_bytes(&payload)?);
                 }
                 SyncId::Done => {
+                    // DONE is padded to the size of an empty DENT
+                    let mut rest = [0u8; 16];
+                    stream.read_exact(&mut rest).await?;
                     break;
                 }
                 SyncId::Fail => {
-                    let mut msg = vec![0u8; header.length as usize];
+                    let mut len = [0u8; 4];
+                    stream.read_exact(&mut len).await?;
+                    let mut msg = vec![0u8; u32::from_le_bytes(len) as usize];
                     stream.read_exact(&mut msg).await?;
                     return Err(AdbError::SyncError(
                         String::from_utf8_lossy(&msg).to_string(),
@@ -245,120 +535,319 @@ impl AdbClient {
         Ok(entries)
     }
 
-    /// Push a local file to the device.
+    /// List a remote directory with 64-bit sizes and times, using LIS2 if the
+    /// device supports it.
+    pub async fn list_dir_v2(
+        &self,
+        serial: Option<&str>,
+        remote_path: &str,
+    ) -> AdbResult<Vec<DentV2Entry>> {
+        let mut sync = self.open_sync(serial).await?;
+        let entries = sync.list(remote_path).await?;
+        sync.quit().await?;
+        Ok(entries)
+    }
+
+    /// Push a local file or directory to the device.
     pub async fn push(
         &self,
         serial: Option<&str>,
         local_path: &Path,
         remote_path: &str,
     ) -> AdbResult<()> {
-        let file_data = tokio::fs::read(local_path).await.map_err(|e| {
+        self.push_with(
+            serial,
+            local_path,
+            remote_path,
+            &TransferOptions::default(),
+            &mut |_| {},
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Push a local file or directory tree to the device, reporting progress
+    /// after every chunk. See `SyncConnection::push`.
+    pub async fn push_with(
+        &self,
+        serial: Option<&str>,
+        local_path: &Path,
+        remote_path: &str,
+        options: &TransferOptions,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<TransferStats> {
+        let mut sync = self.open_sync(serial).await?;
+        let stats = sync
+            .push(local_path, remote_path, options, progress)
+            .await?;
+        sync.quit().await?;
+        Ok(stats)
+    }
+
+    /// Pull a remote file or directory from the device to a local path.
+    pub async fn pull(
+        &self,
+        serial: Option<&str>,
+        remote_path: &str,
+        local_path: &Path,
+    ) -> AdbResult<()> {
+        self.pull_with(
+            serial,
+            remote_path,
+            local_path,
+            &TransferOptions::default(),
+            &mut |_| {},
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Pull a remote file or directory tree from the device, reporting
+    /// progress after every chunk. See `SyncConnection::pull`.
+    pub async fn pull_with(
+        &self,
+        serial: Option<&str>,
+        remote_path: &str,
+        local_path: &Path,
+        options: &TransferOptions,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<TransferStats> {
+        let mut sync = self.open_sync(serial).await?;
+        let stats = sync
+            .pull(remote_path, local_path, options, progress)
+            .await?;
+        sync.quit().await?;
+        Ok(stats)
+    }
+
+    // --- Packages and screen capture ---
+
+    /// Run a command on the device with the `exec:` service and return
+    /// everything it writes to stdout, byte for byte.
+    pub async fn exec(&self, serial: Option<&str>, command: &str) -> AdbResult<Vec<u8>> {
+        let mut stream = self
+            .with_transport(serial, &LocalCommand::Exec(command.to_string()))
+            .await?;
+        Self::read_to_end(&mut stream).await
+    }
+
+    /// Run a command with `exec:`, streaming `input` to its stdin, and return
+    /// its output.
+    async fn exec_with_input(
+        &self,
+        serial: Option<&str>,
+        command: &str,
+        input: &mut tokio::fs::File,
+    ) -> AdbResult<String> {
+        let mut stream = self
+            .with_transport(serial, &LocalCommand::Exec(command.to_string()))
+            .await?;
+        tokio::io::copy(input, &mut stream).await?;
+        let data = Self::read_to_end(&mut stream).await?;
+        Ok(String::from_utf8_lossy(&data).to_string())
+    }
+
+    /// Open a local APK, returning it with its size.
+    async fn open_apk(path: &Path) -> AdbResult<(tokio::fs::File, u64)> {
+        let file = tokio::fs::File::open(path).await.map_err(|e| {
             if e.kind() == std::io::ErrorKind::NotFound {
-                AdbError::FileNotFound(local_path.display().to_string())
+                AdbError::FileNotFound(path.display().to_string())
             } else {
                 AdbError::Io(e)
             }
         })?;
+        let size = file.metadata().await?.len();
+        Ok((file, size))
+    }
 
-        let metadata = tokio::fs::metadata(local_path).await?;
-        let mtime = metadata
-            .modified()
-            .ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs() as u32)
-            .unwrap_or(0);
-
-        let mut stream = self.enter_sync(serial).await?;
-
-        // Send SEND request with file mode 0644
-        let req = sync::encode_send_request(remote_path, 0o644);
-        stream.write_all(&req).await?;
+    /// Install an APK, streaming it to the package manager without copying
+    /// it to the device first.
+    pub async fn install(
+        &self,
+        serial: Option<&str>,
+        apk: &Path,
+        options: &InstallOptions,
+    ) -> AdbResult<()> {
+        let (mut file, size) = Self::open_apk(apk).await?;
+        let mut command = format!("cmd package install -S {}", size);
+        for arg in options.to_args() {
+            command.push(' ');
+            command.push_str(arg);
+        }
+        let output = self.exec_with_input(serial, &command, &mut file).await?;
+        package::parse_pm_result(&output)
+    }
 
-        // Send file data in chunks
-        for chunk in file_data.chunks(SYNC_DATA_MAX as usize) {
-            let data_msg = sync::encode_data_chunk(chunk);
-            stream.write_all(&data_msg).await?;
+    /// Install the APKs of one app (a base APK and its splits) together, in
+    /// a single install session. If any of them fails, the session is
+    /// abandoned and nothing is installed.
+    pub async fn install_multiple(
+        &self,
+        serial: Option<&str>,
+        apks: &[PathBuf],
+        options: &InstallOptions,
+    ) -> AdbResult<()> {
+        let mut files = Vec::with_capacity(apks.len());
+        for apk in apks {
+            files.push(Self::open_apk(apk).await?);
         }
+        let total: u64 = files.iter().map(|(_, size)| size).sum();
 
-        // Send DONE with mtime
-        stream.write_all(&sync::encode_done(mtime)).await?;
-
-        // Read response (OKAY or FAIL)
-        let header = Self::read_sync_header(&mut stream).await?;
-        match header.id {
-            SyncId::Okay => {}
-            SyncId::Fail => {
-                let mut msg = vec![0u8; header.length as usize];
-                stream.read_exact(&mut msg).await?;
-                return Err(AdbError::SyncError(
-                    String::from_utf8_lossy(&msg).to_string(),
-                ));
-            }
-            other => {
-                return Err(AdbError::Protocol(format!(
-                    "Expected OKAY after push, got {:?}",
-                    other
-                )));
+        let mut command = format!("cmd package install-create -S {}", total);
+        for arg in options.to_args() {
+            command.push(' ');
+            command.push_str(arg);
+        }
+        let output = self.exec(serial, &command).await?;
+        let session = package::parse_session_id(&String::from_utf8_lossy(&output))?;
+        debug!("Created install session {}", session);
+
+        let result = self.write_session(serial, session, apks, files).await;
+        if let Err(e) = result {
+            let abandon = format!("cmd package install-abandon {}", session);
+            if let Err(abandon_err) = self.exec(serial, &abandon).await {
+                warn!(
+                    "Failed to abandon install session {}: {}",
+                    session, abandon_err
+                );
             }
+            return Err(e);
         }
 
-        // Send QUIT
-        stream.write_all(&sync::encode_quit()).await?;
+        let commit = format!("cmd package install-commit {}", session);
+        let output = self.exec(serial, &commit).await?;
+        package::parse_pm_result(&String::from_utf8_lossy(&output))
+    }
 
+    /// Stream each APK into an install session.
+    async fn write_session(
+        &self,
+        serial: Option<&str>,
+        session: u32,
+        apks: &[PathBuf],
+        files: Vec<(tokio::fs::File, u64)>,
+    ) -> AdbResult<()> {
+        for (index, (apk, (mut file, size))) in apks.iter().zip(files).enumerate() {
+            // The split name only has to be unique in the session, and must
+            // get through the device's shell in one piece
+            let name: String = apk
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let command = format!(
+                "cmd package install-write -S {} {} {}_{} -",
+                size, session, index, name
+            );
+            let output = self.exec_with_input(serial, &command, &mut file).await?;
+            package::parse_pm_result(&output)?;
+        }
         Ok(())
     }
 
-    /// Pull a remote file from the device to a local path.
-    pub async fn pull(
+    /// Uninstall a package. With `keep_data`, its data and cache directories
+    /// are kept.
+    pub async fn uninstall(
         &self,
         serial: Option<&str>,
-        remote_path: &str,
-        local_path: &Path,
+        package: &str,
+        keep_data: bool,
     ) -> AdbResult<()> {
-        let mut stream = self.enter_sync(serial).await?;
-
-        // Send RECV request
-        let req = sync::encode_recv_request(remote_path);
-        stream.write_all(&req).await?;
+        package::check_package_name(package)?;
+        let command = format!(
+            "cmd package uninstall {}{}",
+            if keep_data { "-k " } else { "" },
+            package
+        );
+        let output = self.exec(serial, &command).await?;
+        package::parse_pm_result(&String::from_utf8_lossy(&output))
+    }
 
-        // Read DATA chunks until DONE
-        let mut file_data = Vec::new();
+    /// List installed packages.
+    pub async fn list_packages(
+        &self,
+        serial: Option<&str>,
+        options: &ListPackagesOptions,
+    ) -> AdbResult<Vec<PackageInfo>> {
+        let command = format!("cmd package list packages {}", options.to_args().join(" "));
+        let output = self.exec(serial, &command).await?;
+        Ok(PackageInfo::parse_package_list(&String::from_utf8_lossy(
+            &output,
+        )))
+    }
 
-        loop {
-            let header = Self::read_sync_header(&mut stream).await?;
+    /// Take a screenshot, returned as PNG data.
+    pub async fn screencap(&self, serial: Option<&str>) -> AdbResult<Vec<u8>> {
+        let data = self.exec(serial, "screencap -p").await?;
+        if !data.starts_with(PNG_SIGNATURE) {
+            // Most likely an error message from screencap
+            return Err(AdbError::Protocol(format!(
+                "screencap did not return a PNG: {:?}",
+                String::from_utf8_lossy(&data[..data.len().min(200)]).trim()
+            )));
+        }
+        Ok(data)
+    }
 
-            match header.id {
-                SyncId::Data => {
-                    let mut chunk = vec![0u8; header.length as usize];
-                    stream.read_exact(&mut chunk).await?;
-                    file_data.extend_from_slice(&chunk);
-                }
-                SyncId::Done => {
-                    break;
-                }
-                SyncId::Fail => {
-                    let mut msg = vec![0u8; header.length as usize];
-                    stream.read_exact(&mut msg).await?;
-                    return Err(AdbError::SyncError(
-                        String::from_utf8_lossy(&msg).to_string(),
-                    ));
-                }
-                other => {
-                    return Err(AdbError::Protocol(format!(
-                        "Expected DATA/DONE in pull, got {:?}",
-                        other
-                    )));
-                }
-            }
+    /// Record the screen as a raw H.264 stream, for `time_limit` seconds or
+    /// the device's default of three minutes. The stream ends when the
+    /// recording does.
+    pub async fn screenrecord(
+        &self,
+        serial: Option<&str>,
+        time_limit: Option<u32>,
+    ) -> AdbResult<AdbStream> {
+        let mut command = "screenrecord --output-format=h264".to_string();
+        if let Some(seconds) = time_limit {
+            command.push_str(&format!(" --time-limit {}", seconds));
         }
+        command.push_str(" -");
+        self.with_transport(serial, &LocalCommand::Exec(command))
+            .await
+    }
+}
 
-        // Write to local file
-        tokio::fs::write(local_path, &file_data).await?;
+/// Device connect, disconnect and state change events from
+/// `host:track-devices`.
+///
+/// The server sends the whole device list whenever it changes; the tracker
+/// turns each list into the events that lead to it from the one before.
+pub struct DeviceTracker {
+    stream: TcpStream,
+    devices: Vec<DeviceInfo>,
+    pending: VecDeque<DeviceEvent>,
+}
 
-        // Send QUIT
-        stream.write_all(&sync::encode_quit()).await?;
+impl DeviceTracker {
+    /// The next event, or `None` once the server closes the connection.
+    pub async fn next_event(&mut self) -> AdbResult<Option<DeviceEvent>> {
+        while self.pending.is_empty() {
+            let data = match AdbClient::read_length_prefixed(&mut self.stream).await {
+                Ok(data) => data,
+                Err(AdbError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e),
+            };
+            let devices = DeviceInfo::parse_device_list(&String::from_utf8_lossy(&data));
+            self.pending
+                .extend(DeviceEvent::diff(&self.devices, &devices));
+            self.devices = devices;
+        }
+        Ok(self.pending.pop_front())
+    }
 
-        Ok(())
+    /// The devices as of the last list the server sent.
+    pub fn devices(&self) -> &[DeviceInfo] {
+        &self.devices
     }
 }
 
@@ -371,6 +860,8 @@ impl Default for AdbClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::device::DeviceState;
+    use crate::shell::{self, ShellHeader, ShellId};
     use tokio::io::AsyncWriteExt;
     use tokio::net::TcpListener;
 
@@ -506,6 +997,343 @@ mod tests {
         assert_eq!(output, "hello world\n");
     }
 
+    /// Accept the transport and the shell v2 command, checking the latter.
+    async fn accept_shell_v2(socket: &mut TcpStream, expected: &[u8]) {
+        let mut buf = [0u8; 256];
+        let _ = socket.read(&mut buf).await;
+        socket.write_all(b"OKAY").await.unwrap();
+        let n = socket.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[4..n], expected);
+        socket.write_all(b"OKAY").await.unwrap();
+    }
+
+    /// Read one shell v2 packet from the client.
+    async fn read_shell_packet(socket: &mut TcpStream) -> (ShellId, Vec<u8>) {
+        let mut header = [0u8; 5];
+        socket.read_exact(&mut header).await.unwrap();
+        let header = ShellHeader::from_bytes(&header).unwrap();
+        let mut data = vec![0u8; header.length as usize];
+        socket.read_exact(&mut data).await.unwrap();
+        (header.id, data)
+    }
+
+    #[tokio::test]
+    async fn test_shell_v2_command() {
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                accept_shell_v2(&mut socket, b"shell,v2,raw:ls /missing").await;
+                assert_eq!(
+                    read_shell_packet(&mut socket).await,
+                    (ShellId::CloseStdin, Vec::new())
+                );
+                let mut resp = Vec::new();
+                resp.extend(shell::encode_packet(ShellId::Stdout, b"/sdcard\n"));
+                resp.extend(shell::encode_packet(
+                    ShellId::Stderr,
+                    b"ls: /missing: No such file or directory\n",
+                ));
+                resp.extend(shell::encode_packet(ShellId::Exit, &[1]));
+                socket.write_all(&resp).await.unwrap();
+            });
+        })
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let output = client.shell_v2(None, "ls /missing").await.unwrap();
+        assert_eq!(output.stdout, b"/sdcard\n");
+        assert_eq!(output.stderr, b"ls: /missing: No such file or directory\n");
+        assert_eq!(output.exit_code, 1);
+    }
+
+    #[tokio::test]
+    async fn test_shell_v2_interactive() {
+        // The mock echoes stdin back like a PTY would, and exits once stdin
+        // is closed.
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                accept_shell_v2(&mut socket, b"shell,v2,TERM=xterm,pty:").await;
+                loop {
+                    match read_shell_packet(&mut socket).await {
+                        (ShellId::Stdin, data) => {
+                            let packet = shell::encode_packet(ShellId::Stdout, &data);
+                            socket.write_all(&packet).await.unwrap();
+                        }
+                        (ShellId::WindowSizeChange, data) => {
+                            assert_eq!(data, b"50x132,0x0\0");
+                        }
+                        (ShellId::CloseStdin, _) => break,
+                        (other, _) => panic!("Unexpected packet {:?}", other),
+                    }
+                }
+                let packet = shell::encode_packet(ShellId::Exit, &[0]);
+                socket.write_all(&packet).await.unwrap();
+            });
+        })
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let session = client
+            .open_shell(None, "", true, Some("xterm"))
+            .await
+            .unwrap();
+        let (mut reader, mut writer) = session.split();
+        writer.resize(50, 132).await.unwrap();
+        writer.write_stdin(b"echo hi\r").await.unwrap();
+        assert_eq!(
+            reader.read_packet().await.unwrap(),
+            Some((ShellId::Stdout, b"echo hi\r".to_vec()))
+        );
+        writer.close_stdin().await.unwrap();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let code = reader.copy_output(&mut stdout, &mut stderr).await.unwrap();
+        assert_eq!(code, 0);
+        assert!(stdout.is_empty() && stderr.is_empty());
+    }
+
+    /// Spawn a mock that checks the request it gets, then sends a byte sequence.
+    async fn mock_expect_request(expected: &'static [u8], response: Vec<u8>) -> u16 {
+        mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 256];
+                let n = socket.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], expected);
+                socket.write_all(&response).await.unwrap();
+            });
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_forward() {
+        let port = mock_expect_request(
+            b"0033host-serial:emulator-5554:forward:tcp:6100;tcp:7100",
+            b"OKAYOKAY".to_vec(),
+        )
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let resolved = client
+            .forward(
+                Some("emulator-5554"),
+                &ForwardSpec::Tcp(6100),
+                &ForwardSpec::Tcp(7100),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resolved, None);
+    }
+
+    #[tokio::test]
+    async fn test_forward_any_port() {
+        let port = mock_expect_request(
+            b"0025host:forward:norebind:tcp:0;jdwp:4321",
+            b"OKAYOKAY000541234".to_vec(),
+        )
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let resolved = client
+            .forward(None, &ForwardSpec::Tcp(0), &ForwardSpec::Jdwp(4321), true)
+            .await
+            .unwrap();
+        assert_eq!(resolved, Some(41234));
+    }
+
+    #[tokio::test]
+    async fn test_forward_rebind_fail() {
+        // The server accepts the request, then refuses to set it up
+        let port =
+            mock_simple_response(b"OKAYFAIL001Dcannot rebind existing socket".to_vec()).await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let result = client
+            .forward(None, &ForwardSpec::Tcp(6100), &ForwardSpec::Tcp(7100), true)
+            .await;
+        match result {
+            Err(AdbError::ServerFail(msg)) => assert_eq!(msg, "cannot rebind existing socket"),
+            other => panic!("Expected ServerFail, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_forward() {
+        let list = b"emulator-5554 tcp:6100 tcp:7100\nR5CT200XXXX tcp:9222 localabstract:chrome_devtools_remote\n";
+        let mut response = Vec::new();
+        response.extend_from_slice(b"OKAY");
+        response.extend_from_slice(format!("{:04X}", list.len()).as_bytes());
+        response.extend_from_slice(list);
+        let port = mock_expect_request(b"0011host:list-forward", response).await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let entries = client.list_forward(Some("R5CT200XXXX")).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].local, "tcp:9222");
+        assert_eq!(entries[0].remote, "localabstract:chrome_devtools_remote");
+    }
+
+    #[tokio::test]
+    async fn test_kill_forward() {
+        let port =
+            mock_expect_request(b"0019host:killforward:tcp:6100", b"OKAYOKAY".to_vec()).await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        client
+            .kill_forward(None, &ForwardSpec::Tcp(6100))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reverse() {
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 256];
+                let _ = socket.read(&mut buf).await;
+                socket.write_all(b"OKAY").await.unwrap();
+                let n = socket.read(&mut buf).await.unwrap();
+                assert_eq!(
+                    &buf[4..n],
+                    b"reverse:forward:localabstract:devtools;tcp:9222"
+                );
+                socket.write_all(b"OKAYOKAY").await.unwrap();
+            });
+        })
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let resolved = client
+            .reverse(
+                None,
+                &ForwardSpec::LocalAbstract("devtools".into()),
+                &ForwardSpec::Tcp(9222),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(resolved, None);
+    }
+
+    #[tokio::test]
+    async fn test_list_reverse() {
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 256];
+                let _ = socket.read(&mut buf).await;
+                socket.write_all(b"OKAY").await.unwrap();
+                let n = socket.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[4..n], b"reverse:list-forward");
+                let list = b"host-19 tcp:8081 tcp:8081\n";
+                let mut resp = b"OKAYOKAY".to_vec();
+                resp.extend_from_slice(format!("{:04X}", list.len()).as_bytes());
+                resp.extend_from_slice(list);
+                socket.write_all(&resp).await.unwrap();
+            });
+        })
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let entries = client.list_reverse(None).await.unwrap();
+        assert_eq!(
+            entries,
+            vec![ForwardEntry {
+                serial: "host-19".into(),
+                local: "tcp:8081".into(),
+                remote: "tcp:8081".into(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_track_devices() {
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 256];
+                let n = socket.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"0012host:track-devices");
+                socket.write_all(b"OKAY").await.unwrap();
+                for list in [
+                    "emulator-5554\toffline\n",
+                    "emulator-5554\tdevice\n",
+                    "emulator-5554\tdevice\nR5CT200XXXX\tunauthorized\n",
+                    "",
+                ] {
+                    let msg = format!("{:04X}{}", list.len(), list);
+                    socket.write_all(msg.as_bytes()).await.unwrap();
+                }
+            });
+        })
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let mut tracker = client.track_devices().await.unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = tracker.next_event().await.unwrap() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::Connected(DeviceInfo {
+                    serial: "emulator-5554".into(),
+                    state: DeviceState::Offline,
+                }),
+                DeviceEvent::StateChanged {
+                    serial: "emulator-5554".into(),
+                    old: DeviceState::Offline,
+                    new: DeviceState::Device,
+                },
+                DeviceEvent::Connected(DeviceInfo {
+                    serial: "R5CT200XXXX".into(),
+                    state: DeviceState::Unauthorized,
+                }),
+                DeviceEvent::Disconnected("emulator-5554".into()),
+                DeviceEvent::Disconnected("R5CT200XXXX".into()),
+            ]
+        );
+        assert!(tracker.devices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forward_relay() {
+        // The mock plays the device side of the tunnel: an upper-casing echo
+        // server behind `tcp:7100`.
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 256];
+                let _ = socket.read(&mut buf).await;
+                socket.write_all(b"OKAY").await.unwrap();
+                let n = socket.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"0008tcp:7100");
+                socket.write_all(b"OKAY").await.unwrap();
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    let upper = buf[..n].to_ascii_uppercase();
+                    socket.write_all(&upper).await.unwrap();
+                }
+            });
+        })
+        .await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_port = listener.local_addr().unwrap().port();
+        let client = AdbClient::with_address("127.0.0.1", port);
+        tokio::spawn(async move {
+            let _ = client
+                .run_forward_relay(None, listener, &ForwardSpec::Tcp(7100))
+                .await;
+        });
+
+        let mut local = TcpStream::connect(("127.0.0.1", local_port)).await.unwrap();
+        local.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        local.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"PING");
+    }
+
     #[tokio::test]
     async fn test_stat_file() {
         // Mock: transport OKAY, sync OKAY, then STAT response
@@ -544,4 +1372,268 @@ mod tests {
         assert!(stat.is_file());
         assert_eq!(stat.permissions(), 0o644);
     }
+
+    #[tokio::test]
+    async fn test_features() {
+        let port = mock_expect_request(
+            b"0022host-serial:emulator-5554:features",
+            b"OKAY001Ashell_v2,cmd,stat_v2,ls_v2".to_vec(),
+        )
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let features = client.features(Some("emulator-5554")).await.unwrap();
+        assert_eq!(features, ["shell_v2", "cmd", "stat_v2", "ls_v2"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_dir() {
+        let port = mock_adb_server(move |mut socket| {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 256];
+
+                // transport, sync, then the LIST request
+                for _ in 0..2 {
+                    let _ = socket.read(&mut buf).await;
+                    socket.write_all(b"OKAY").await.unwrap();
+                }
+                let _ = socket.read(&mut buf).await;
+
+                let mut resp = Vec::new();
+                for (mode, size, name) in [(0o040755u32, 4096u32, "DCIM"), (0o100644, 12, "a.txt")]
+                {
+                    resp.extend_from_slice(b"DENT");
+                    resp.extend_from_slice(&mode.to_le_bytes());
+                    resp.extend_from_slice(&size.to_le_bytes());
+                    resp.extend_from_slice(&1_700_000_000u32.to_le_bytes());
+                    resp.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                    resp.extend_from_slice(name.as_bytes());
+                }
+                resp.extend_from_slice(b"DONE");
+                resp.extend_from_slice(&[0u8; 16]);
+                socket.write_all(&resp).await.unwrap();
+
+                // Read QUIT
+                let _ = socket.read(&mut buf).await;
+            });
+        })
+        .await;
+
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let entries = client.list_dir(None, "/sdcard").await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "DCIM");
+        assert_eq!(entries[0].mode, 0o040755);
+        assert_eq!(entries[1].name, "a.txt");
+        assert_eq!(entries[1].size, 12);
+    }
+
+    /// Read one length-prefixed request.
+    async fn read_request(socket: &mut TcpStream) -> String {
+        let mut len = [0u8; 4];
+        socket.read_exact(&mut len).await.unwrap();
+        let mut payload = vec![0u8; protocol::parse_hex_length(&len).unwrap()];
+        socket.read_exact(&mut payload).await.unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    /// Spawn a mock that serves one `exec:` command per connection: it
+    /// checks the command, reads `input_len` bytes of input and replies with
+    /// `output`. Returns the port and the input each command got.
+    async fn mock_exec_server(
+        steps: Vec<(&'static str, usize, &'static [u8])>,
+    ) -> (u16, tokio::task::JoinHandle<Vec<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let mut inputs = Vec::new();
+            for (command, input_len, output) in steps {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                assert_eq!(read_request(&mut socket).await, "host:transport-any");
+                socket.write_all(b"OKAY").await.unwrap();
+                assert_eq!(read_request(&mut socket).await, format!("exec:{}", command));
+                socket.write_all(b"OKAY").await.unwrap();
+                let mut input = vec![0u8; input_len];
+                socket.read_exact(&mut input).await.unwrap();
+                inputs.push(input);
+                socket.write_all(output).await.unwrap();
+            }
+            inputs
+        });
+        (port, handle)
+    }
+
+    #[tokio::test]
+    async fn test_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let apk = dir.path().join("app.apk");
+        std::fs::write(&apk, b"PK\x03\x04 fake apk").unwrap();
+
+        let (port, inputs) =
+            mock_exec_server(vec![("cmd package install -S 13 -r -g", 13, b"Success\n")]).await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let options = InstallOptions {
+            reinstall: true,
+            grant_permissions: true,
+            ..Default::default()
+        };
+        client.install(None, &apk, &options).await.unwrap();
+        assert_eq!(inputs.await.unwrap(), [b"PK\x03\x04 fake apk".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_install_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let apk = dir.path().join("app.apk");
+        std::fs::write(&apk, b"old").unwrap();
+
+        let (port, _) = mock_exec_server(vec![(
+            "cmd package install -S 3",
+            3,
+            b"Failure [INSTALL_FAILED_VERSION_DOWNGRADE]\n",
+        )])
+        .await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let result = client.install(None, &apk, &InstallOptions::default()).await;
+        match result {
+            Err(AdbError::PackageError(msg)) => {
+                assert!(msg.contains("INSTALL_FAILED_VERSION_DOWNGRADE"))
+            }
+            other => panic!("Expected PackageError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_install_multiple() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.apk");
+        let split = dir.path().join("split config.apk");
+        std::fs::write(&base, b"base").unwrap();
+        std::fs::write(&split, b"split!").unwrap();
+
+        let (port, inputs) = mock_exec_server(vec![
+            (
+                "cmd package install-create -S 10",
+                0,
+                b"Success: created install session [77]\n",
+            ),
+            (
+                "cmd package install-write -S 4 77 0_base.apk -",
+                4,
+                b"Success: streamed 4 bytes\n",
+            ),
+            (
+                "cmd package install-write -S 6 77 1_split_config.apk -",
+                6,
+                b"Success: streamed 6 bytes\n",
+            ),
+            ("cmd package install-commit 77", 0, b"Success\n"),
+        ])
+        .await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        client
+            .install_multiple(None, &[base, split], &InstallOptions::default())
+            .await
+            .unwrap();
+        let inputs = inputs.await.unwrap();
+        assert_eq!(inputs[1], b"base");
+        assert_eq!(inputs[2], b"split!");
+    }
+
+    #[tokio::test]
+    async fn test_install_multiple_abandons_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.apk");
+        std::fs::write(&base, b"base").unwrap();
+
+        let (port, steps) = mock_exec_server(vec![
+            (
+                "cmd package install-create -S 4",
+                0,
+                b"Success: created install session [5]\n",
+            ),
+            (
+                "cmd package install-write -S 4 5 0_base.apk -",
+                4,
+                b"Failure [INSTALL_FAILED_INVALID_APK]\n",
+            ),
+            ("cmd package install-abandon 5", 0, b"Success\n"),
+        ])
+        .await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let result = client
+            .install_multiple(None, &[base], &InstallOptions::default())
+            .await;
+        assert!(matches!(result, Err(AdbError::PackageError(_))));
+        // every step, the abandon included, was served
+        assert_eq!(steps.await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_uninstall() {
+        let (port, _) = mock_exec_server(vec![(
+            "cmd package uninstall -k com.example",
+            0,
+            b"Success\n",
+        )])
+        .await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        client.uninstall(None, "com.example", true).await.unwrap();
+
+        // never sent to the device
+        for package in ["com.example app", "com.example;reboot"] {
+            let result = client.uninstall(None, package, false).await;
+            assert!(matches!(result, Err(AdbError::InvalidArgument(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_packages() {
+        let (port, _) = mock_exec_server(vec![(
+            "cmd package list packages -f -i -U --show-versioncode -3",
+            0,
+            b"package:/data/app/~~x==/com.example-y==/base.apk=com.example versionCode:3  installer=null uid:10200\n\
+              package:/data/app/com.other-1/base.apk=com.other versionCode:12  installer=com.android.vending uid:10201\n",
+        )])
+        .await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let options = ListPackagesOptions {
+            third_party: true,
+            ..Default::default()
+        };
+        let packages = client.list_packages(None, &options).await.unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "com.example");
+        assert_eq!(packages[0].version_code, Some(3));
+        assert_eq!(
+            packages[1].installer.as_deref(),
+            Some("com.android.vending")
+        );
+        assert_eq!(packages[1].uid, Some(10201));
+    }
+
+    #[tokio::test]
+    async fn test_screencap() {
+        let (port, _) = mock_exec_server(vec![(
+            "screencap -p",
+            0,
+            b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR",
+        )])
+        .await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        let png = client.screencap(None).await.unwrap();
+        assert!(png.starts_with(PNG_SIGNATURE));
+        assert_eq!(png.len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_screencap_error_output() {
+        let (port, _) =
+            mock_exec_server(vec![("screencap -p", 0, b"Error: capture failed\n")]).await;
+        let client = AdbClient::with_address("127.0.0.1", port);
+        match client.screencap(None).await {
+            Err(AdbError::Protocol(msg)) => assert!(msg.contains("capture failed")),
+            other => panic!("Expected Protocol error, got {:?}", other),
+        }
+    }
 }
@@ -78,6 +78,63 @@ impl fmt::Display for DeviceInfo {
     }
 }
 
+/// A change between two device lists, as reported by `host:track-devices`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// A device appeared.
+    Connected(DeviceInfo),
+    /// A known device moved to another state (e.g. authorized).
+    StateChanged {
+        serial: String,
+        old: DeviceState,
+        new: DeviceState,
+    },
+    /// A device went away; holds its serial.
+    Disconnected(String),
+}
+
+impl DeviceEvent {
+    /// The events that turn the device list `old` into `new`: state changes
+    /// and connections in the order of `new`, then disconnections in the
+    /// order of `old`.
+    pub fn diff(old: &[DeviceInfo], new: &[DeviceInfo]) -> Vec<DeviceEvent> {
+        let mut events = Vec::new();
+        for device in new {
+            match old.iter().find(|d| d.serial == device.serial) {
+                None => events.push(DeviceEvent::Connected(device.clone())),
+                Some(previous) if previous.state != device.state => {
+                    events.push(DeviceEvent::StateChanged {
+                        serial: device.serial.clone(),
+                        old: previous.state.clone(),
+                        new: device.state.clone(),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        for device in old {
+            if !new.iter().any(|d| d.serial == device.serial) {
+                events.push(DeviceEvent::Disconnected(device.serial.clone()));
+            }
+        }
+        events
+    }
+}
+
+impl fmt::Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceEvent::Connected(device) => {
+                write!(f, "{} connected ({})", device.serial, device.state)
+            }
+            DeviceEvent::StateChanged { serial, old, new } => {
+                write!(f, "{} {} -> {}", serial, old, new)
+            }
+            DeviceEvent::Disconnected(serial) => write!(f, "{} disconnected", serial),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -139,4 +196,29 @@ mod tests {
         };
         assert_eq!(info.to_string(), "emulator-5554\tdevice");
     }
+
+    #[test]
+    fn test_device_event_diff() {
+        let old =
+            DeviceInfo::parse_device_list("emulator-5554\tdevice\nR5CT200XXXX\tunauthorized\n");
+        let new = DeviceInfo::parse_device_list("R5CT200XXXX\tdevice\n192.168.1.5:5555\toffline\n");
+        let events = DeviceEvent::diff(&old, &new);
+        assert_eq!(
+            events,
+            vec![
+                DeviceEvent::StateChanged {
+                    serial: "R5CT200XXXX".into(),
+                    old: DeviceState::Unauthorized,
+                    new: DeviceState::Device,
+                },
+                DeviceEvent::Connected(DeviceInfo {
+                    serial: "192.168.1.5:5555".into(),
+                    state: DeviceState::Offline,
+                }),
+                DeviceEvent::Disconnected("emulator-5554".into()),
+            ]
+        );
+        assert_eq!(events[0].to_string(), "R5CT200XXXX unauthorized -> device");
+        assert!(DeviceEvent::diff(&new, &new).is_empty());
+    }
 }
@@ -0,0 +1,747 @@
+use crate::auth::AdbKey;
+use crate::error::{AdbError, AdbResult};
+use crate::message::{
+    self, Command, DeviceBanner, Message, AUTH_RSAPUBLICKEY, AUTH_SIGNATURE, AUTH_TOKEN, A_VERSION,
+    MAX_PAYLOAD,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender, WeakUnboundedSender};
+use tracing::{debug, warn};
+
+// A direct connection multiplexes streams over one socket to adbd. After
+// the CNXN/AUTH handshake, three kinds of tasks share it:
+//
+// - one writes every outgoing message to the socket,
+// - one reads incoming messages and routes them by stream ID (arg1),
+// - one per open stream moves data between a DuplexStream, whose other end
+//   the caller has, and WRTE messages. Each side may only have one WRTE in
+//   flight per stream and must wait for the other's OKAY before the next.
+
+/// Where incoming messages for each open stream go, by our ID for it.
+/// `None` once the connection is gone.
+type Routes = Arc<Mutex<Option<HashMap<u32, UnboundedSender<Message>>>>>;
+
+fn connection_closed() -> AdbError {
+    AdbError::Io(std::io::Error::new(
+        std::io::ErrorKind::ConnectionAborted,
+        "Device connection closed",
+    ))
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Message) -> AdbResult<()> {
+    writer.write_all(&message.encode()).await?;
+    Ok(())
+}
+
+/// A connection straight to a device's adbd, without the ADB server, over
+/// which any number of streams to services can be open at once.
+#[derive(Debug, Clone)]
+pub struct DeviceConnection {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    banner: DeviceBanner,
+    version: u32,
+    max_payload: u32,
+    outgoing: UnboundedSender<Message>,
+    routes: Routes,
+    next_id: AtomicU32,
+}
+
+impl DeviceConnection {
+    /// Connect to adbd at `addr` (usually port 5555), authenticating with `key`.
+    ///
+    /// If the device doesn't know the key yet, it is sent over, and this
+    /// waits until someone accepts it on the device's screen.
+    pub async fn connect<A: ToSocketAddrs>(addr: A, key: &AdbKey) -> AdbResult<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Self::handshake(stream, key).await
+    }
+
+    /// Run the CNXN/AUTH handshake on a stream to adbd, then start serving
+    /// streams over it.
+    pub async fn handshake<S>(mut stream: S, key: &AdbKey) -> AdbResult<Self>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let hello = Message::new(Command::Cnxn, A_VERSION, MAX_PAYLOAD, b"host::\0".to_vec());
+        write_message(&mut stream, &hello).await?;
+
+        let mut signed = false;
+        let mut sent_public_key = false;
+        let connected = loop {
+            let msg = message::read_message(&mut stream, MAX_PAYLOAD).await?;
+            match (msg.command, msg.arg0) {
+                (Command::Cnxn, _) => break msg,
+                // First try a signature; if the device doesn't know the key,
+                // it sends a new token and we offer the public key instead
+                (Command::Auth, AUTH_TOKEN) if !signed => {
+                    debug!("Signing AUTH token");
+                    let signature = key.sign_token(&msg.data)?;
+                    let reply = Message::new(Command::Auth, AUTH_SIGNATURE, 0, signature);
+                    write_message(&mut stream, &reply).await?;
+                    signed = true;
+                }
+                (Command::Auth, AUTH_TOKEN) if !sent_public_key => {
+                    warn!("Device does not know our key; confirm the prompt on the device");
+                    let mut public_key = key.public_key_string()?.into_bytes();
+                    public_key.push(0);
+                    let reply = Message::new(Command::Auth, AUTH_RSAPUBLICKEY, 0, public_key);
+                    write_message(&mut stream, &reply).await?;
+                    sent_public_key = true;
+                }
+                (Command::Auth, AUTH_TOKEN) => {
+                    return Err(AdbError::AuthFailed(
+                        "The device rejected our public key".into(),
+                    ));
+                }
+                (Command::Stls, _) => {
+                    return Err(AdbError::NotSupported(
+                        "TLS connections (wireless debugging)".into(),
+                    ));
+                }
+                (command, arg0) => {
+                    return Err(AdbError::Protocol(format!(
+                        "Unexpected {:?} message ({}) while connecting",
+                        command, arg0
+                    )));
+                }
+            }
+        };
+
+        let banner = DeviceBanner::parse(&connected.data);
+        debug!(
+            "Connected to {} device, version {:#x}, max payload {}",
+            banner.state, connected.arg0, connected.arg1
+        );
+        let (reader, writer) = tokio::io::split(stream);
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let routes: Routes = Arc::new(Mutex::new(Some(HashMap::new())));
+        tokio::spawn(write_messages(writer, outgoing_rx));
+        tokio::spawn(route_messages(reader, routes.clone(), outgoing.downgrade()));
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                banner,
+                version: connected.arg0.min(A_VERSION),
+                max_payload: connected.arg1.min(MAX_PAYLOAD),
+                outgoing,
+                routes,
+                next_id: AtomicU32::new(1),
+            }),
+        })
+    }
+
+    /// What the device said about itself when it accepted the connection.
+    pub fn banner(&self) -> &DeviceBanner {
+        &self.inner.banner
+    }
+
+    /// The protocol version both sides speak.
+    pub fn version(&self) -> u32 {
+        self.inner.version
+    }
+
+    /// The largest WRTE payload both sides accept.
+    pub fn max_payload(&self) -> u32 {
+        self.inner.max_payload
+    }
+
+    /// Open a stream to a service on the device, like `shell:ls` or `sync:`.
+    pub async fn open(&self, service: &str) -> AdbResult<DuplexStream> {
+        let local_id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.inner
+            .routes
+            .lock()
+            .unwrap()
+            .as_mut()
+            .ok_or_else(connection_closed)?
+            .insert(local_id, tx);
+
+        let mut data = service.as_bytes().to_vec();
+        data.push(0);
+        self.inner
+            .outgoing
+            .send(Message::new(Command::Open, local_id, 0, data))
+            .map_err(|_| connection_closed())?;
+
+        let remote_id = match rx.recv().await {
+            Some(msg) if msg.command == Command::Okay => msg.arg0,
+            other => {
+                remove_route(&self.inner.routes, local_id);
+                return Err(match other {
+                    Some(_) => AdbError::ServiceRefused(service.to_string()),
+                    None => connection_closed(),
+                });
+            }
+        };
+        debug!("Opened {:?} as {} -> {}", service, local_id, remote_id);
+
+        let max_payload = self.inner.max_payload as usize;
+        let (stream, ours) = tokio::io::duplex(max_payload);
+        tokio::spawn(run_stream(
+            StreamIds {
+                local: local_id,
+                remote: remote_id,
+            },
+            max_payload,
+            rx,
+            self.inner.outgoing.clone(),
+            self.inner.routes.clone(),
+            ours,
+        ));
+        Ok(stream)
+    }
+}
+
+fn remove_route(routes: &Routes, local_id: u32) {
+    if let Some(routes) = routes.lock().unwrap().as_mut() {
+        routes.remove(&local_id);
+    }
+}
+
+/// Write messages to the device until every sender is gone, then close our
+/// side of the connection.
+async fn write_messages<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut messages: UnboundedReceiver<Message>,
+) {
+    while let Some(msg) = messages.recv().await {
+        if let Err(e) = write_message(&mut writer, &msg).await {
+            debug!("Writing to device failed: {}", e);
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Read messages from the device and hand each to the stream it is for.
+async fn route_messages<R: AsyncRead + Unpin>(
+    mut reader: R,
+    routes: Routes,
+    outgoing: WeakUnboundedSender<Message>,
+) {
+    let reply = |msg: Message| {
+        if let Some(outgoing) = outgoing.upgrade() {
+            let _ = outgoing.send(msg);
+        }
+    };
+    loop {
+        let msg = match message::read_message(&mut reader, MAX_PAYLOAD).await {
+            Ok(msg) => msg,
+            Err(e) => {
+                debug!("Device connection closed: {}", e);
+                break;
+            }
+        };
+        match msg.command {
+            Command::Okay | Command::Wrte | Command::Clse => {
+                let route = routes
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .and_then(|routes| routes.get(&msg.arg1).cloned());
+                match route {
+                    Some(route) => {
+                        let _ = route.send(msg);
+                    }
+                    // Data for a stream we already closed
+                    None if msg.command == Command::Wrte => {
+                        reply(Message::new(Command::Clse, 0, msg.arg0, Vec::new()))
+                    }
+                    None => {}
+                }
+            }
+            // Streams the device opens to us, as for reverse forwards,
+            // aren't supported
+            Command::Open => reply(Message::new(Command::Clse, 0, msg.arg0, Vec::new())),
+            other => debug!("Ignoring {:?} message from device", other),
+        }
+    }
+    // Dropping the routes ends every stream
+    routes.lock().unwrap().take();
+}
+
+/// Our ID and the device's ID for a stream.
+#[derive(Debug, Clone, Copy)]
+struct StreamIds {
+    local: u32,
+    remote: u32,
+}
+
+/// Move data between the caller's end of a stream and the device, one WRTE
+/// at a time in each direction, until either side closes it.
+async fn run_stream(
+    ids: StreamIds,
+    max_payload: usize,
+    mut messages: UnboundedReceiver<Message>,
+    outgoing: UnboundedSender<Message>,
+    routes: Routes,
+    stream: DuplexStream,
+) {
+    let send = |command: Command, data: Vec<u8>| {
+        let _ = outgoing.send(Message::new(command, ids.local, ids.remote, data));
+    };
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let mut buf = vec![0u8; max_payload];
+    // Data from the device the caller hasn't taken yet; it is acknowledged
+    // once it has been
+    let mut received = Vec::new();
+    // Whether the device acknowledged our last WRTE
+    let mut ready = true;
+
+    loop {
+        tokio::select! {
+            msg = messages.recv() => match msg {
+                Some(msg) if msg.command == Command::Wrte => received.extend(msg.data),
+                Some(msg) if msg.command == Command::Okay => ready = true,
+                _ => {
+                    // Closed by the device, or the connection is gone; what
+                    // it sent before still goes to the caller
+                    let _ = writer.write_all(&received).await;
+                    break;
+                }
+            },
+            result = reader.read(&mut buf), if ready => match result {
+                Ok(n) if n > 0 => {
+                    send(Command::Wrte, buf[..n].to_vec());
+                    ready = false;
+                }
+                _ => {
+                    send(Command::Clse, Vec::new());
+                    break;
+                }
+            },
+            result = writer.write(&received), if !received.is_empty() => match result {
+                Ok(n) => {
+                    received.drain(..n);
+                    if received.is_empty() {
+                        send(Command::Okay, Vec::new());
+                    }
+                }
+                Err(_) => {
+                    send(Command::Clse, Vec::new());
+                    break;
+                }
+            },
+        }
+    }
+    debug!("Closed stream {} -> {}", ids.local, ids.remote);
+    remove_route(&routes, ids.local);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{decode_public_key, verify_token};
+    use crate::client::AdbClient;
+    use crate::sync::SyncId;
+    use crate::transfer::tests::{serve, MockDevice};
+    use rsa::rand_core::{OsRng, RngCore};
+    use rsa::RsaPublicKey;
+    use std::collections::VecDeque;
+    use tokio::io::WriteHalf;
+    use tokio::net::tcp::OwnedWriteHalf;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    const FAKE_MAX_PAYLOAD: u32 = 4096;
+    const FAKE_BANNER: &[u8] =
+        b"device::ro.product.model=Fake;features=shell_v2,cmd,stat_v2,ls_v2,sendrecv_v2\0";
+
+    /// How the fake adbd treats the host.
+    struct FakeAdbd {
+        /// Keys the device already trusts.
+        trusted: Vec<RsaPublicKey>,
+        /// Whether someone taps "Allow" when the host offers a new key.
+        allow_new_keys: bool,
+        device: Arc<Mutex<MockDevice>>,
+    }
+
+    /// What the fake adbd saw.
+    #[derive(Debug, Default)]
+    struct FakeLog {
+        offered_keys: Vec<RsaPublicKey>,
+        services: Vec<String>,
+    }
+
+    /// A stream, as the fake adbd sees it.
+    struct FakeStream {
+        host_id: u32,
+        input: WriteHalf<DuplexStream>,
+        /// Service output not sent yet.
+        output: VecDeque<Vec<u8>>,
+        /// Whether our last WRTE waits for the host's OKAY.
+        awaiting_okay: bool,
+        /// Whether the host's last WRTE waits for our OKAY.
+        host_unacked: bool,
+        /// Whether the service is done writing.
+        finished: bool,
+    }
+
+    async fn send(writer: &mut OwnedWriteHalf, msg: Message) {
+        writer.write_all(&msg.encode()).await.unwrap();
+    }
+
+    /// Start a service, returning our end of its stream, or `None` if there
+    /// is no such service.
+    fn start_service(service: &str, device: &Arc<Mutex<MockDevice>>) -> Option<DuplexStream> {
+        let (ours, mut theirs) = tokio::io::duplex(64 * 1024);
+        if service == "sync:" {
+            tokio::spawn(serve(theirs, device.clone()));
+        } else if let Some(text) = service.strip_prefix("shell:echo ") {
+            let text = format!("{}\n", text);
+            tokio::spawn(async move {
+                let _ = theirs.write_all(text.as_bytes()).await;
+            });
+        } else if service == "exec:cat" {
+            tokio::spawn(async move {
+                let (mut input, mut output) = tokio::io::split(theirs);
+                let _ = tokio::io::copy(&mut input, &mut output).await;
+            });
+        } else {
+            return None;
+        }
+        Some(ours)
+    }
+
+    /// The next message a stream has for the host, if it may send one:
+    /// a WRTE of queued output, or CLSE once the service is done.
+    fn next_message(id: u32, stream: &mut FakeStream) -> Option<Message> {
+        if stream.awaiting_okay {
+            return None;
+        }
+        if let Some(chunk) = stream.output.pop_front() {
+            stream.awaiting_okay = true;
+            Some(Message::new(Command::Wrte, id, stream.host_id, chunk))
+        } else if stream.finished {
+            Some(Message::new(Command::Clse, id, stream.host_id, Vec::new()))
+        } else {
+            None
+        }
+    }
+
+    /// Accept one host connection and play adbd on it, checking that the
+    /// host keeps to the protocol: CNXN first, AUTH answers of the right
+    /// kind, well-formed OPENs, and on every stream no WRTE larger than the
+    /// negotiated payload or sent before the last one was acknowledged.
+    /// Runs until the host hangs up.
+    async fn run_fake_adbd(listener: TcpListener, adbd: FakeAdbd) -> FakeLog {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (mut reader, mut writer) = socket.into_split();
+        let mut log = FakeLog::default();
+
+        let hello = message::read_message(&mut reader, MAX_PAYLOAD)
+            .await
+            .unwrap();
+        assert_eq!(hello.command, Command::Cnxn);
+        assert_eq!((hello.arg0, hello.arg1), (A_VERSION, MAX_PAYLOAD));
+        assert_eq!(hello.data, b"host::\0");
+
+        let mut trusted = adbd.trusted;
+        loop {
+            let mut token = [0u8; 20];
+            OsRng.fill_bytes(&mut token);
+            let auth = Message::new(Command::Auth, AUTH_TOKEN, 0, token.to_vec());
+            send(&mut writer, auth).await;
+            let Ok(reply) = message::read_message(&mut reader, MAX_PAYLOAD).await else {
+                return log;
+            };
+            assert_eq!(reply.command, Command::Auth);
+            match reply.arg0 {
+                AUTH_SIGNATURE => {
+                    if trusted.iter().any(|k| verify_token(k, &token, &reply.data)) {
+                        break;
+                    }
+                }
+                AUTH_RSAPUBLICKEY => {
+                    assert_eq!(reply.data.last(), Some(&0));
+                    let text = std::str::from_utf8(&reply.data).unwrap();
+                    let key = decode_public_key(text).unwrap();
+                    log.offered_keys.push(key.clone());
+                    if !adbd.allow_new_keys {
+                        // Nobody said yes; hang up
+                        return log;
+                    }
+                    trusted.push(key);
+                    break;
+                }
+                other => panic!("Unexpected AUTH type {}", other),
+            }
+        }
+        let connected = Message::new(
+            Command::Cnxn,
+            A_VERSION,
+            FAKE_MAX_PAYLOAD,
+            FAKE_BANNER.to_vec(),
+        );
+        send(&mut writer, connected).await;
+
+        let (host_tx, mut host_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok(msg) = message::read_message(&mut reader, MAX_PAYLOAD).await {
+                if host_tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        let (output_tx, mut output_rx) = mpsc::unbounded_channel::<(u32, Option<Vec<u8>>)>();
+        let mut streams: HashMap<u32, FakeStream> = HashMap::new();
+        let mut next_id = 100;
+
+        loop {
+            // Host messages first, so one that shouldn't have been sent yet
+            // is seen before we acknowledge anything
+            let id = tokio::select! {
+                biased;
+                msg = host_rx.recv() => {
+                    let Some(msg) = msg else { break };
+                    match msg.command {
+                        Command::Open => {
+                            assert_ne!(msg.arg0, 0, "OPEN without a stream ID");
+                            assert_eq!(msg.arg1, 0);
+                            assert!(
+                                streams.values().all(|s| s.host_id != msg.arg0),
+                                "Stream ID {} reused",
+                                msg.arg0
+                            );
+                            assert_eq!(msg.data.last(), Some(&0), "Service name without NUL");
+                            let service =
+                                String::from_utf8(msg.data[..msg.data.len() - 1].to_vec()).unwrap();
+                            log.services.push(service.clone());
+                            let Some(ours) = start_service(&service, &adbd.device) else {
+                                let refuse = Message::new(Command::Clse, 0, msg.arg0, Vec::new());
+                                send(&mut writer, refuse).await;
+                                continue;
+                            };
+
+                            let id = next_id;
+                            next_id += 1;
+                            let (mut output, input) = tokio::io::split(ours);
+                            let output_tx = output_tx.clone();
+                            tokio::spawn(async move {
+                                let mut buf = vec![0u8; FAKE_MAX_PAYLOAD as usize];
+                                while let Ok(n @ 1..) = output.read(&mut buf).await {
+                                    let _ = output_tx.send((id, Some(buf[..n].to_vec())));
+                                }
+                                let _ = output_tx.send((id, None));
+                            });
+                            streams.insert(
+                                id,
+                                FakeStream {
+                                    host_id: msg.arg0,
+                                    input,
+                                    output: VecDeque::new(),
+                                    awaiting_okay: false,
+                                    host_unacked: false,
+                                    finished: false,
+                                },
+                            );
+                            send(&mut writer, Message::new(Command::Okay, id, msg.arg0, Vec::new()))
+                                .await;
+                            continue;
+                        }
+                        Command::Wrte => {
+                            let stream = streams
+                                .get_mut(&msg.arg1)
+                                .unwrap_or_else(|| panic!("WRTE to unknown stream {}", msg.arg1));
+                            assert_eq!(msg.arg0, stream.host_id);
+                            assert!(
+                                msg.data.len() <= FAKE_MAX_PAYLOAD as usize,
+                                "WRTE of {} bytes",
+                                msg.data.len()
+                            );
+                            assert!(!stream.host_unacked, "WRTE before the last was acknowledged");
+                            stream.input.write_all(&msg.data).await.unwrap();
+                            stream.host_unacked = true;
+                            continue;
+                        }
+                        Command::Okay => {
+                            // The host may acknowledge a WRTE it got after
+                            // it closed the stream
+                            let Some(stream) = streams.get_mut(&msg.arg1) else { continue };
+                            assert_eq!(msg.arg0, stream.host_id);
+                            assert!(stream.awaiting_okay, "OKAY without a WRTE");
+                            stream.awaiting_okay = false;
+                            msg.arg1
+                        }
+                        Command::Clse => {
+                            if let Some(stream) = streams.remove(&msg.arg1) {
+                                assert_eq!(msg.arg0, stream.host_id);
+                            }
+                            continue;
+                        }
+                        other => panic!("Unexpected {:?} after connecting", other),
+                    }
+                }
+                output = output_rx.recv() => {
+                    let (id, chunk) = output.unwrap();
+                    let Some(stream) = streams.get_mut(&id) else { continue };
+                    match chunk {
+                        Some(chunk) => stream.output.push_back(chunk),
+                        None => stream.finished = true,
+                    }
+                    id
+                }
+                // Nothing else to do: acknowledge what the host sent
+                _ = std::future::ready(()), if streams.values().any(|s| s.host_unacked) => {
+                    for (id, stream) in streams.iter_mut().filter(|(_, s)| s.host_unacked) {
+                        stream.host_unacked = false;
+                        let okay = Message::new(Command::Okay, *id, stream.host_id, Vec::new());
+                        send(&mut writer, okay).await;
+                    }
+                    continue;
+                }
+            };
+
+            let Some(stream) = streams.get_mut(&id) else {
+                continue;
+            };
+            if let Some(msg) = next_message(id, stream) {
+                if msg.command == Command::Clse {
+                    streams.remove(&id);
+                }
+                send(&mut writer, msg).await;
+            }
+        }
+        log
+    }
+
+    async fn start_fake_adbd(adbd: FakeAdbd) -> (String, JoinHandle<FakeLog>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        (addr, tokio::spawn(run_fake_adbd(listener, adbd)))
+    }
+
+    fn fake_adbd(trusted: Vec<RsaPublicKey>, allow_new_keys: bool) -> FakeAdbd {
+        FakeAdbd {
+            trusted,
+            allow_new_keys,
+            device: Arc::default(),
+        }
+    }
+
+    /// A key the fake adbd trusts, and a connection made with it.
+    async fn connect_trusted(
+        device: Arc<Mutex<MockDevice>>,
+    ) -> (DeviceConnection, JoinHandle<FakeLog>) {
+        let key = AdbKey::generate().unwrap();
+        let (addr, adbd) = start_fake_adbd(FakeAdbd {
+            device,
+            ..fake_adbd(vec![key.public_key()], false)
+        })
+        .await;
+        (DeviceConnection::connect(addr, &key).await.unwrap(), adbd)
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_trusted_key() {
+        let (device, adbd) = connect_trusted(Arc::default()).await;
+        assert_eq!(device.version(), A_VERSION);
+        assert_eq!(device.max_payload(), FAKE_MAX_PAYLOAD);
+        assert_eq!(device.banner().state, "device");
+        assert_eq!(device.banner().property("ro.product.model"), Some("Fake"));
+        drop(device);
+        assert!(adbd.await.unwrap().offered_keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_offers_public_key() {
+        let key = AdbKey::generate().unwrap();
+        let other = AdbKey::generate().unwrap();
+        let (addr, adbd) = start_fake_adbd(fake_adbd(vec![other.public_key()], true)).await;
+        let device = DeviceConnection::connect(addr, &key).await.unwrap();
+        drop(device);
+        assert_eq!(adbd.await.unwrap().offered_keys, [key.public_key()]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_rejected() {
+        let key = AdbKey::generate().unwrap();
+        let (addr, adbd) = start_fake_adbd(fake_adbd(Vec::new(), false)).await;
+        assert!(DeviceConnection::connect(addr, &key).await.is_err());
+        assert_eq!(adbd.await.unwrap().offered_keys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shell_over_direct_connection() {
+        let (device, adbd) = connect_trusted(Arc::default()).await;
+        let client = AdbClient::with_device(device.clone());
+        assert_eq!(client.shell(None, "echo hello").await.unwrap(), "hello\n");
+        match device.open("bogus:").await {
+            Err(AdbError::ServiceRefused(service)) => assert_eq!(service, "bogus:"),
+            other => panic!("Expected ServiceRefused, got {:?}", other),
+        }
+        assert!(client
+            .features(None)
+            .await
+            .unwrap()
+            .contains(&"stat_v2".to_string()));
+        assert!(matches!(
+            client.list_devices().await,
+            Err(AdbError::NotSupported(_))
+        ));
+
+        drop((client, device));
+        assert_eq!(adbd.await.unwrap().services, ["shell:echo hello", "bogus:"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_flow_control() {
+        // 100 KB each way through a 4 KB payload limit: many WRTEs, each
+        // waiting for its OKAY
+        let (device, adbd) = connect_trusted(Arc::default()).await;
+        let stream = device.open("exec:cat").await.unwrap();
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+        let sent = data.clone();
+        let sender = tokio::spawn(async move {
+            writer.write_all(&sent).await.unwrap();
+            writer
+        });
+        let mut echoed = vec![0u8; data.len()];
+        reader.read_exact(&mut echoed).await.unwrap();
+        assert!(echoed == data);
+
+        drop((sender.await.unwrap(), reader, device));
+        adbd.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_over_direct_connection() {
+        let mock = Arc::new(Mutex::new(MockDevice::default()));
+        mock.lock().unwrap().dirs.push("/sdcard".into());
+        let (device, adbd) = connect_trusted(mock.clone()).await;
+        let client = AdbClient::with_device(device);
+
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join("big.bin");
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7) as u8).collect();
+        std::fs::write(&local, &data).unwrap();
+
+        client.push(None, &local, "/sdcard").await.unwrap();
+        {
+            let mock = mock.lock().unwrap();
+            assert!(mock.files["/sdcard/big.bin"].data == data);
+            // The banner's features turn on v2 sync
+            assert!(mock.requests.contains(&SyncId::Snd2));
+        }
+
+        let pulled = dir.path().join("pulled.bin");
+        client.pull(None, "/sdcard/big.bin", &pulled).await.unwrap();
+        assert!(std::fs::read(&pulled).unwrap() == data);
+
+        drop(client);
+        let log = adbd.await.unwrap();
+        assert!(log.services.iter().all(|s| s == "sync:"));
+    }
+}
@@ -31,6 +31,21 @@ pub enum AdbError {
 
     #[error("File not found: {0}")]
     FileNotFound(String),
+
+    #[error("Package manager error: {0}")]
+    PackageError(String),
+
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+
+    #[error("Device refused to open service {0:?}")]
+    ServiceRefused(String),
+
+    #[error("Not supported over a direct device connection: {0}")]
+    NotSupported(String),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
 }
 
 pub type AdbResult<T> = Result<T, AdbError>;
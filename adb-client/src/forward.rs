@@ -0,0 +1,138 @@
+use crate::error::{AdbError, AdbResult};
+use std::fmt;
+use std::str::FromStr;
+
+/// One end of a forward or reverse forward, in ADB's `type:address` syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardSpec {
+    /// A TCP port (`tcp:8080`). Port 0 asks the server to pick one when
+    /// used as the local end of a forward.
+    Tcp(u16),
+    /// A Unix domain socket in the abstract namespace (`localabstract:name`).
+    LocalAbstract(String),
+    /// The JDWP connection of a process on the device (`jdwp:pid`).
+    Jdwp(u32),
+}
+
+impl fmt::Display for ForwardSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardSpec::Tcp(port) => write!(f, "tcp:{}", port),
+            ForwardSpec::LocalAbstract(name) => write!(f, "localabstract:{}", name),
+            ForwardSpec::Jdwp(pid) => write!(f, "jdwp:{}", pid),
+        }
+    }
+}
+
+impl FromStr for ForwardSpec {
+    type Err = AdbError;
+
+    fn from_str(s: &str) -> AdbResult<Self> {
+        let invalid = || AdbError::Protocol(format!("Invalid forward spec: {:?}", s));
+        let (kind, address) = s.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "tcp" => address.parse().map(ForwardSpec::Tcp).map_err(|_| invalid()),
+            "localabstract" if !address.is_empty() => {
+                Ok(ForwardSpec::LocalAbstract(address.to_string()))
+            }
+            "jdwp" => address
+                .parse()
+                .map(ForwardSpec::Jdwp)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// An active forward, as listed by `host:list-forward` or
+/// `reverse:list-forward`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardEntry {
+    /// Device the forward belongs to. For reverse forwards this is the
+    /// transport name the device knows the host by (e.g. "host-19").
+    pub serial: String,
+    pub local: String,
+    pub remote: String,
+}
+
+impl ForwardEntry {
+    /// Parse the `serial local remote\n` format of a forward list.
+    ///
+    /// Example input: `"emulator-5554 tcp:6100 tcp:7100\n"`
+    pub fn parse_forward_list(data: &str) -> Vec<ForwardEntry> {
+        data.lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                Some(ForwardEntry {
+                    serial: parts.next()?.to_string(),
+                    local: parts.next()?.to_string(),
+                    remote: parts.next()?.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ForwardEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.serial, self.local, self.remote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forward_spec_round_trip() {
+        for s in [
+            "tcp:8080",
+            "tcp:0",
+            "localabstract:chrome_devtools",
+            "jdwp:1234",
+        ] {
+            let spec: ForwardSpec = s.parse().unwrap();
+            assert_eq!(spec.to_string(), s);
+        }
+        assert_eq!(
+            "localabstract:foo".parse::<ForwardSpec>().unwrap(),
+            ForwardSpec::LocalAbstract("foo".into())
+        );
+    }
+
+    #[test]
+    fn test_forward_spec_invalid() {
+        for s in [
+            "tcp",
+            "tcp:http",
+            "tcp:70000",
+            "localabstract:",
+            "jdwp:-1",
+            "udp:53",
+        ] {
+            assert!(
+                s.parse::<ForwardSpec>().is_err(),
+                "{:?} should be invalid",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_forward_list() {
+        let data = "emulator-5554 tcp:6100 tcp:7100\nR5CT200XXXX tcp:9222 localabstract:chrome_devtools_remote\n";
+        let entries = ForwardEntry::parse_forward_list(data);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].serial, "emulator-5554");
+        assert_eq!(entries[0].local, "tcp:6100");
+        assert_eq!(entries[0].remote, "tcp:7100");
+        assert_eq!(entries[1].remote, "localabstract:chrome_devtools_remote");
+        assert_eq!(entries[0].to_string(), "emulator-5554 tcp:6100 tcp:7100");
+    }
+
+    #[test]
+    fn test_parse_forward_list_empty_and_malformed() {
+        assert!(ForwardEntry::parse_forward_list("").is_empty());
+        assert!(ForwardEntry::parse_forward_list("\nonly-two tcp:1\n").is_empty());
+    }
+}
@@ -1,11 +1,31 @@
+mod auth;
 mod client;
 mod device;
+mod direct;
 mod error;
+mod forward;
+mod message;
+mod package;
 mod protocol;
+mod shell;
+mod stream;
 mod sync;
+mod transfer;
 
-pub use client::AdbClient;
-pub use device::{DeviceInfo, DeviceState};
+pub use auth::{decode_public_key, encode_public_key, verify_token, AdbKey};
+pub use client::{AdbClient, DeviceTracker};
+pub use device::{DeviceEvent, DeviceInfo, DeviceState};
+pub use direct::DeviceConnection;
 pub use error::{AdbError, AdbResult};
+pub use forward::{ForwardEntry, ForwardSpec};
+pub use message::DeviceBanner;
+pub use package::{InstallOptions, ListPackagesOptions, PackageInfo};
 pub use protocol::{HostCommand, LocalCommand};
-pub use sync::{DentEntry, StatResponse, SyncHeader, SyncId, SYNC_DATA_MAX};
+pub use shell::{ShellHeader, ShellId, ShellOutput, ShellReader, ShellSession, ShellWriter};
+pub use stream::AdbStream;
+pub use sync::{
+    DentEntry, DentV2Entry, StatResponse, StatV2Response, SyncHeader, SyncId, SYNC_DATA_MAX,
+};
+pub use transfer::{
+    SyncConnection, SyncFeatures, TransferOptions, TransferProgress, TransferStats,
+};
@@ -1,6 +1,12 @@
-use adb_client::AdbClient;
+mod terminal;
+
+use adb_client::{
+    AdbClient, AdbKey, AdbResult, DeviceConnection, ForwardEntry, ForwardSpec, InstallOptions,
+    ListPackagesOptions, ShellWriter, TransferOptions, TransferProgress, TransferStats,
+};
 use clap::{CommandFactory, Parser, Subcommand};
 use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[derive(Parser)]
 #[command(name = "adb-client")]
@@ -18,6 +24,15 @@ struct Cli {
     #[arg(short, long)]
     serial: Option<String>,
 
+    /// Talk to adbd at this address (e.g. 192.168.1.20:5555) directly,
+    /// without the ADB server.
+    #[arg(long, value_name = "ADDR")]
+    direct: Option<String>,
+
+    /// Private key for authenticating with --direct [default: ~/.android/adbkey].
+    #[arg(long, value_name = "PATH")]
+    key: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -30,32 +45,177 @@ enum Commands {
     /// List connected devices.
     Devices,
 
-    /// Run a shell command on the device.
+    /// Run a shell command on the device, or an interactive shell without one.
     Shell {
+        /// Allocate a PTY even when running a command.
+        #[arg(short = 't', conflicts_with = "no_pty")]
+        pty: bool,
+
+        /// Never allocate a PTY.
+        #[arg(short = 'T')]
+        no_pty: bool,
+
+        /// Use the legacy shell protocol, which merges stderr into stdout
+        /// and loses the exit status.
+        #[arg(long)]
+        legacy: bool,
+
         /// Shell command to execute.
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
     },
 
-    /// Push a local file to the device.
+    /// Push a local file or directory to the device.
     Push {
-        /// Local file path.
+        /// Only push files that are newer or a different size on the host.
+        #[arg(long)]
+        sync: bool,
+        /// Local file or directory path.
         local: PathBuf,
-        /// Remote file path on device.
+        /// Remote path on device.
         remote: String,
     },
 
-    /// Pull a file from the device.
+    /// Pull a file or directory from the device.
     Pull {
-        /// Remote file path on device.
+        /// Only pull files that are newer or a different size on the device.
+        #[arg(long)]
+        sync: bool,
+        /// Remote file or directory path on device.
         remote: String,
-        /// Local file path.
+        /// Local path.
         local: PathBuf,
     },
 
+    /// Forward a host socket to a device socket (tcp:PORT, localabstract:NAME, jdwp:PID).
+    Forward {
+        /// List all forwards.
+        #[arg(long, conflicts_with_all = ["remove", "remove_all"])]
+        list: bool,
+
+        /// Remove the forward of a local socket.
+        #[arg(long, value_name = "LOCAL", conflicts_with = "remove_all")]
+        remove: Option<ForwardSpec>,
+
+        /// Remove all forwards.
+        #[arg(long)]
+        remove_all: bool,
+
+        /// Fail if the local socket is already forwarded.
+        #[arg(long)]
+        no_rebind: bool,
+
+        /// Relay connections in this process instead of asking the server
+        /// (LOCAL must be tcp:PORT).
+        #[arg(long, conflicts_with = "no_rebind")]
+        relay: bool,
+
+        /// Host socket, e.g. tcp:6100 (tcp:0 picks a free port).
+        #[arg(required_unless_present_any = ["list", "remove", "remove_all"])]
+        local: Option<ForwardSpec>,
+
+        /// Device socket, e.g. tcp:7100.
+        #[arg(required_unless_present_any = ["list", "remove", "remove_all"])]
+        remote: Option<ForwardSpec>,
+    },
+
+    /// Forward a device socket to a host socket.
+    Reverse {
+        /// List all reverse forwards of the device.
+        #[arg(long, conflicts_with_all = ["remove", "remove_all"])]
+        list: bool,
+
+        /// Remove the reverse forward of a device socket.
+        #[arg(long, value_name = "REMOTE", conflicts_with = "remove_all")]
+        remove: Option<ForwardSpec>,
+
+        /// Remove all reverse forwards of the device.
+        #[arg(long)]
+        remove_all: bool,
+
+        /// Fail if the device socket is already forwarded.
+        #[arg(long)]
+        no_rebind: bool,
+
+        /// Device socket, e.g. tcp:8081 (tcp:0 picks a free port).
+        #[arg(required_unless_present_any = ["list", "remove", "remove_all"])]
+        remote: Option<ForwardSpec>,
+
+        /// Host socket, e.g. tcp:8081.
+        #[arg(required_unless_present_any = ["list", "remove", "remove_all"])]
+        local: Option<ForwardSpec>,
+    },
+
+    /// Print device connect, disconnect and state change events as they happen.
+    TrackDevices,
+
     /// Stream device logs (logcat).
     Logcat,
 
+    /// Install an app from one APK, or from a base APK and its splits.
+    Install {
+        /// Replace an existing installation, keeping its data.
+        #[arg(short = 'r')]
+        reinstall: bool,
+        /// Allow a lower version code than the installed one.
+        #[arg(short = 'd')]
+        allow_downgrade: bool,
+        /// Grant all runtime permissions.
+        #[arg(short = 'g')]
+        grant_permissions: bool,
+        /// Allow test-only APKs.
+        #[arg(short = 't')]
+        allow_test: bool,
+        /// APK files; more than one are installed together in one session.
+        #[arg(required = true)]
+        apks: Vec<PathBuf>,
+    },
+
+    /// Uninstall a package.
+    Uninstall {
+        /// Keep the data and cache directories.
+        #[arg(short = 'k')]
+        keep_data: bool,
+        /// Package name.
+        package: String,
+    },
+
+    /// List installed packages.
+    Packages {
+        /// Only third-party packages.
+        #[arg(short = '3')]
+        third_party: bool,
+        /// Only system packages.
+        #[arg(short = 's')]
+        system: bool,
+        /// Only disabled packages.
+        #[arg(short = 'd')]
+        disabled: bool,
+        /// Only enabled packages.
+        #[arg(short = 'e')]
+        enabled: bool,
+        /// Also show APK path, version code, installer and UID.
+        #[arg(short = 'l', long)]
+        long: bool,
+        /// Only packages whose name contains this text.
+        filter: Option<String>,
+    },
+
+    /// Save a screenshot as PNG.
+    Screencap {
+        /// Output file.
+        output: PathBuf,
+    },
+
+    /// Record the screen to a raw H.264 file.
+    Screenrecord {
+        /// Stop after this many seconds (the device's limit is 180).
+        #[arg(long)]
+        time_limit: Option<u32>,
+        /// Output file.
+        output: PathBuf,
+    },
+
     /// Stat a remote file on the device.
     Stat {
         /// Remote path on device.
@@ -67,6 +227,97 @@ enum Commands {
         /// Remote directory path on device.
         path: String,
     },
+
+    /// Generate a key for --direct, and its .pub file.
+    Keygen {
+        /// Where to save the private key [default: ~/.android/adbkey].
+        path: Option<PathBuf>,
+    },
+}
+
+/// Show how far the current file has got, on one line of stderr.
+fn print_progress(progress: &TransferProgress) {
+    let percent = match progress.size {
+        0 => 100,
+        size => progress.transferred * 100 / size,
+    };
+    eprint!("\r[{:>3}%] {}", percent, progress.path);
+    if progress.transferred >= progress.size {
+        eprintln!();
+    }
+}
+
+fn print_transfer_stats(stats: &TransferStats) {
+    print!("{} file(s) transferred, {} bytes", stats.files, stats.bytes);
+    if stats.skipped > 0 {
+        print!(", {} skipped", stats.skipped);
+    }
+    println!();
+}
+
+fn print_forwards(entries: &[ForwardEntry]) {
+    for entry in entries {
+        println!("{}", entry);
+    }
+}
+
+/// Run `command` over shell v2, forwarding stdin and terminal resizes to it
+/// and its output to stdout and stderr, and return its exit status.
+async fn run_shell(
+    client: &AdbClient,
+    serial: Option<&str>,
+    command: &str,
+    pty: bool,
+) -> AdbResult<u8> {
+    let term = std::env::var("TERM").ok().filter(|_| pty);
+    let session = client
+        .open_shell(serial, command, pty, term.as_deref())
+        .await?;
+    let (mut reader, mut writer) = session.split();
+
+    let raw_mode = if pty {
+        if let Some((rows, cols)) = terminal::window_size() {
+            writer.resize(rows, cols).await?;
+        }
+        terminal::RawMode::enable()?
+    } else {
+        None
+    };
+    let input = tokio::spawn(forward_input(tokio::io::stdin(), writer, pty));
+
+    let result = reader
+        .copy_output(&mut tokio::io::stdout(), &mut tokio::io::stderr())
+        .await;
+    input.abort();
+    drop(raw_mode);
+    result
+}
+
+/// Send everything read from `input` to the shell, then close its stdin. With
+/// a PTY, also keep it told of the terminal size.
+async fn forward_input<R, W>(mut input: R, mut writer: ShellWriter<W>, pty: bool) -> AdbResult<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut resizes = terminal::Resizes::new()?;
+    let mut buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            n = input.read(&mut buf) => {
+                let n = n?;
+                if n == 0 {
+                    return writer.close_stdin().await;
+                }
+                writer.write_stdin(&buf[..n]).await?;
+            }
+            _ = resizes.recv(), if pty => {
+                if let Some((rows, cols)) = terminal::window_size() {
+                    writer.resize(rows, cols).await?;
+                }
+            }
+        }
+    }
 }
 
 #[tokio::main]
@@ -84,7 +335,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let client = AdbClient::with_address(&cli.host, cli.port);
+    if let Commands::Keygen { path } = &command {
+        let path = path
+            .clone()
+            .or(cli.key)
+            .or_else(AdbKey::default_path)
+            .ok_or("No home directory to put the key in; give a path")?;
+        let key = AdbKey::generate()?;
+        key.save(&path)?;
+        println!("Saved key to {}", path.display());
+        println!("{}", key.public_key_string()?);
+        return Ok(());
+    }
+
+    let client = match &cli.direct {
+        Some(addr) => {
+            let key_path = cli
+                .key
+                .or_else(AdbKey::default_path)
+                .ok_or("No home directory to find the key in; use --key")?;
+            let key = AdbKey::load_or_generate(&key_path)?;
+            AdbClient::with_device(DeviceConnection::connect(addr.as_str(), &key).await?)
+        }
+        None => AdbClient::with_address(&cli.host, cli.port),
+    };
 
     match command {
         Commands::Version => {
@@ -102,30 +376,218 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
-        Commands::Shell { command } => {
-            if command.is_empty() {
-                eprintln!("Error: no shell command specified");
-                std::process::exit(1);
-            }
+        Commands::Shell {
+            pty,
+            no_pty,
+            legacy,
+            command,
+        } => {
             let cmd = command.join(" ");
-            let output = client.shell(cli.serial.as_deref(), &cmd).await?;
-            print!("{}", output);
+            if legacy {
+                if cmd.is_empty() {
+                    eprintln!("Error: no shell command specified");
+                    std::process::exit(1);
+                }
+                let output = client.shell(cli.serial.as_deref(), &cmd).await?;
+                print!("{}", output);
+            } else {
+                let pty = !no_pty && (pty || (cmd.is_empty() && terminal::stdin_is_tty()));
+                let code = run_shell(&client, cli.serial.as_deref(), &cmd, pty).await?;
+                // stdin may still be blocked in a read that would keep the
+                // runtime from shutting down
+                std::process::exit(code as i32);
+            }
         }
-        Commands::Push { local, remote } => {
-            client.push(cli.serial.as_deref(), &local, &remote).await?;
+        Commands::Push {
+            sync,
+            local,
+            remote,
+        } => {
+            let options = TransferOptions { sync };
+            let stats = client
+                .push_with(
+                    cli.serial.as_deref(),
+                    &local,
+                    &remote,
+                    &options,
+                    &mut print_progress,
+                )
+                .await?;
             println!("Pushed {} -> {}", local.display(), remote);
+            print_transfer_stats(&stats);
         }
-        Commands::Pull { remote, local } => {
-            client.pull(cli.serial.as_deref(), &remote, &local).await?;
+        Commands::Pull {
+            sync,
+            remote,
+            local,
+        } => {
+            let options = TransferOptions { sync };
+            let stats = client
+                .pull_with(
+                    cli.serial.as_deref(),
+                    &remote,
+                    &local,
+                    &options,
+                    &mut print_progress,
+                )
+                .await?;
             println!("Pulled {} -> {}", remote, local.display());
+            print_transfer_stats(&stats);
+        }
+        Commands::Forward {
+            list,
+            remove,
+            remove_all,
+            no_rebind,
+            relay,
+            local,
+            remote,
+        } => {
+            let serial = cli.serial.as_deref();
+            if list {
+                print_forwards(&client.list_forward(serial).await?);
+            } else if let Some(local) = remove {
+                client.kill_forward(serial, &local).await?;
+            } else if remove_all {
+                client.kill_forward_all(serial).await?;
+            } else if let (Some(local), Some(remote)) = (local, remote) {
+                if relay {
+                    let port = match local {
+                        ForwardSpec::Tcp(port) => port,
+                        other => {
+                            eprintln!("Error: --relay needs a tcp: local socket, got {}", other);
+                            std::process::exit(1);
+                        }
+                    };
+                    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+                    println!("Relaying {} -> {}", listener.local_addr()?, remote);
+                    client.run_forward_relay(serial, listener, &remote).await?;
+                } else if let Some(port) =
+                    client.forward(serial, &local, &remote, no_rebind).await?
+                {
+                    println!("{}", port);
+                }
+            }
+        }
+        Commands::Reverse {
+            list,
+            remove,
+            remove_all,
+            no_rebind,
+            remote,
+            local,
+        } => {
+            let serial = cli.serial.as_deref();
+            if list {
+                print_forwards(&client.list_reverse(serial).await?);
+            } else if let Some(remote) = remove {
+                client.kill_reverse(serial, &remote).await?;
+            } else if remove_all {
+                client.kill_reverse_all(serial).await?;
+            } else if let (Some(remote), Some(local)) = (remote, local) {
+                if let Some(port) = client.reverse(serial, &remote, &local, no_rebind).await? {
+                    println!("{}", port);
+                }
+            }
+        }
+        Commands::TrackDevices => {
+            let mut tracker = client.track_devices().await?;
+            while let Some(event) = tracker.next_event().await? {
+                println!("{}", event);
+            }
         }
         Commands::Logcat => {
             let mut stream = client.logcat(cli.serial.as_deref()).await?;
             let mut stdout = tokio::io::stdout();
             tokio::io::copy(&mut stream, &mut stdout).await?;
         }
+        Commands::Install {
+            reinstall,
+            allow_downgrade,
+            grant_permissions,
+            allow_test,
+            apks,
+        } => {
+            let options = InstallOptions {
+                reinstall,
+                allow_downgrade,
+                grant_permissions,
+                allow_test,
+            };
+            if let [apk] = apks.as_slice() {
+                client.install(cli.serial.as_deref(), apk, &options).await?;
+            } else {
+                client
+                    .install_multiple(cli.serial.as_deref(), &apks, &options)
+                    .await?;
+            }
+            println!("Success");
+        }
+        Commands::Uninstall { keep_data, package } => {
+            client
+                .uninstall(cli.serial.as_deref(), &package, keep_data)
+                .await?;
+            println!("Success");
+        }
+        Commands::Packages {
+            third_party,
+            system,
+            disabled,
+            enabled,
+            long,
+            filter,
+        } => {
+            let options = ListPackagesOptions {
+                third_party,
+                system,
+                disabled,
+                enabled,
+                filter,
+            };
+            for package in client
+                .list_packages(cli.serial.as_deref(), &options)
+                .await?
+            {
+                if !long {
+                    println!("{}", package.name);
+                    continue;
+                }
+                println!(
+                    "{:<48} {:>10} {:>6}  {:<24} {}",
+                    package.name,
+                    package.version_code.map_or("-".into(), |v| v.to_string()),
+                    package.uid.map_or("-".into(), |u| u.to_string()),
+                    package.installer.as_deref().unwrap_or("-"),
+                    package.path.as_deref().unwrap_or("-"),
+                );
+            }
+        }
+        Commands::Screencap { output } => {
+            let png = client.screencap(cli.serial.as_deref()).await?;
+            tokio::fs::write(&output, &png).await?;
+            println!("Saved {} ({} bytes)", output.display(), png.len());
+        }
+        Commands::Screenrecord { time_limit, output } => {
+            let mut stream = client
+                .screenrecord(cli.serial.as_deref(), time_limit)
+                .await?;
+            let mut file = tokio::fs::File::create(&output).await?;
+            eprintln!("Recording to {}, press Ctrl-C to stop", output.display());
+            tokio::select! {
+                result = tokio::io::copy(&mut stream, &mut file) => {
+                    result?;
+                }
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            file.flush().await?;
+            println!("Saved {}", output.display());
+        }
         Commands::Stat { path } => {
-            let stat = client.stat(cli.serial.as_deref(), &path).await?;
+            let stat = client.stat_v2(cli.serial.as_deref(), &path).await?;
+            if !stat.exists() {
+                eprintln!("Error: {}: No such file or directory", path);
+                std::process::exit(1);
+            }
             println!("Mode:     {:o}", stat.mode);
             println!("Size:     {} bytes", stat.size);
             println!("Modified: {} (unix timestamp)", stat.mtime);
@@ -136,26 +598,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         Commands::Ls { path } => {
-            let entries = client.list_dir(cli.serial.as_deref(), &path).await?;
+            let entries = client.list_dir_v2(cli.serial.as_deref(), &path).await?;
             if entries.is_empty() {
                 println!("(empty)");
             } else {
                 for entry in &entries {
-                    let type_char = if (entry.mode & 0o170000) == 0o040000 {
+                    let type_char = if entry.stat.is_directory() {
                         'd'
+                    } else if entry.stat.is_symlink() {
+                        'l'
                     } else {
                         '-'
                     };
                     println!(
                         "{}{:o}  {:>8}  {}",
                         type_char,
-                        entry.mode & 0o7777,
-                        entry.size,
+                        entry.stat.mode & 0o7777,
+                        entry.stat.size,
                         entry.name
                     );
                 }
             }
         }
+        Commands::Keygen { .. } => unreachable!("handled before connecting"),
     }
 
     Ok(())
@@ -0,0 +1,347 @@
+use crate::error::{AdbError, AdbResult};
+use std::collections::BTreeMap;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// The device-side protocol, spoken between the adb server and adbd (here,
+// between us and adbd directly). Every message is a 24-byte header of six
+// little-endian u32s, followed by `data_length` bytes of payload:
+//
+// {command}{arg0}{arg1}{data_length}{data_check}{magic}{data}
+//
+// `magic` is `command ^ 0xFFFFFFFF`, and `data_check` is the sum of the
+// payload bytes (devices from protocol version 0x01000001 send 0 instead).
+
+/// Protocol version we speak: the one that made the checksum optional.
+pub const A_VERSION: u32 = 0x0100_0001;
+/// Largest payload we accept, as the adb server advertises it.
+pub const MAX_PAYLOAD: u32 = 1024 * 1024;
+/// Size of a message header.
+pub const HEADER_SIZE: usize = 24;
+
+/// AUTH message types (arg0).
+pub const AUTH_TOKEN: u32 = 1;
+pub const AUTH_SIGNATURE: u32 = 2;
+pub const AUTH_RSAPUBLICKEY: u32 = 3;
+
+/// Message commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Connect: version, max payload and the system identity banner.
+    Cnxn,
+    /// Authentication: a token, a signature of it, or a public key.
+    Auth,
+    /// Open a stream to a service.
+    Open,
+    /// A stream was opened, or the last WRTE on it was consumed.
+    Okay,
+    /// Close a stream.
+    Clse,
+    /// Data on a stream.
+    Wrte,
+    /// Internal to the adb server, never sent to a device.
+    Sync,
+    /// Switch the connection to TLS.
+    Stls,
+}
+
+impl Command {
+    /// The on-wire value: the four ASCII characters read as a little-endian u32.
+    pub fn as_u32(&self) -> u32 {
+        u32::from_le_bytes(*self.as_bytes())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 4] {
+        match self {
+            Command::Cnxn => b"CNXN",
+            Command::Auth => b"AUTH",
+            Command::Open => b"OPEN",
+            Command::Okay => b"OKAY",
+            Command::Clse => b"CLSE",
+            Command::Wrte => b"WRTE",
+            Command::Sync => b"SYNC",
+            Command::Stls => b"STLS",
+        }
+    }
+
+    /// Parse an on-wire value into a `Command`.
+    pub fn from_u32(value: u32) -> AdbResult<Command> {
+        match &value.to_le_bytes() {
+            b"CNXN" => Ok(Command::Cnxn),
+            b"AUTH" => Ok(Command::Auth),
+            b"OPEN" => Ok(Command::Open),
+            b"OKAY" => Ok(Command::Okay),
+            b"CLSE" => Ok(Command::Clse),
+            b"WRTE" => Ok(Command::Wrte),
+            b"SYNC" => Ok(Command::Sync),
+            b"STLS" => Ok(Command::Stls),
+            other => Err(AdbError::Protocol(format!(
+                "Unknown message command: {:?}",
+                String::from_utf8_lossy(other)
+            ))),
+        }
+    }
+}
+
+/// The sum of the payload bytes, as `data_check` carries it.
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().map(|&b| b as u32).fold(0, u32::wrapping_add)
+}
+
+/// The 24-byte header of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub command: Command,
+    pub arg0: u32,
+    pub arg1: u32,
+    pub data_length: u32,
+    pub data_check: u32,
+    pub magic: u32,
+}
+
+impl MessageHeader {
+    /// Serialize to exactly 24 bytes.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        let fields = [
+            self.command.as_u32(),
+            self.arg0,
+            self.arg1,
+            self.data_length,
+            self.data_check,
+            self.magic,
+        ];
+        for (chunk, field) in buf.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Parse from a byte slice (must be at least 24 bytes), checking the magic.
+    pub fn from_bytes(buf: &[u8]) -> AdbResult<Self> {
+        if buf.len() < HEADER_SIZE {
+            return Err(AdbError::Protocol(format!(
+                "Message header too short: {} bytes, need {}",
+                buf.len(),
+                HEADER_SIZE
+            )));
+        }
+        let field = |i: usize| u32::from_le_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        let command = Command::from_u32(field(0))?;
+        let header = Self {
+            command,
+            arg0: field(1),
+            arg1: field(2),
+            data_length: field(3),
+            data_check: field(4),
+            magic: field(5),
+        };
+        if header.magic != header.command.as_u32() ^ 0xFFFF_FFFF {
+            return Err(AdbError::Protocol(format!(
+                "Bad magic {:#010x} for {:?} message",
+                header.magic, command
+            )));
+        }
+        Ok(header)
+    }
+}
+
+/// A message with its payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub command: Command,
+    pub arg0: u32,
+    pub arg1: u32,
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(command: Command, arg0: u32, arg1: u32, data: Vec<u8>) -> Self {
+        Self {
+            command,
+            arg0,
+            arg1,
+            data,
+        }
+    }
+
+    pub fn header(&self) -> MessageHeader {
+        MessageHeader {
+            command: self.command,
+            arg0: self.arg0,
+            arg1: self.arg1,
+            data_length: self.data.len() as u32,
+            data_check: checksum(&self.data),
+            magic: self.command.as_u32() ^ 0xFFFF_FFFF,
+        }
+    }
+
+    /// Encode header and payload. The checksum is always filled in, so
+    /// devices that still check it are happy too.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        buf.extend_from_slice(&self.header().to_bytes());
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+}
+
+/// Read one message, checking its size against `max_payload` and its
+/// checksum unless the sender left it out.
+pub async fn read_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_payload: u32,
+) -> AdbResult<Message> {
+    let mut buf = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut buf).await?;
+    let header = MessageHeader::from_bytes(&buf)?;
+    if header.data_length > max_payload {
+        return Err(AdbError::Protocol(format!(
+            "{:?} payload of {} bytes is over the maximum of {}",
+            header.command, header.data_length, max_payload
+        )));
+    }
+    let mut data = vec![0u8; header.data_length as usize];
+    reader.read_exact(&mut data).await?;
+    if header.data_check != 0 && header.data_check != checksum(&data) {
+        return Err(AdbError::Protocol(format!(
+            "Bad checksum for {:?} message: {:#x}, expected {:#x}",
+            header.command,
+            header.data_check,
+            checksum(&data)
+        )));
+    }
+    Ok(Message::new(header.command, header.arg0, header.arg1, data))
+}
+
+/// The system identity a device sends in its CNXN message.
+///
+/// Example: `"device::ro.product.name=sdk;ro.product.model=Pixel;features=shell_v2,cmd"`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceBanner {
+    /// Connection state: "device", "recovery", "sideload", ...
+    pub state: String,
+    /// Device properties, like `ro.product.model`.
+    pub properties: BTreeMap<String, String>,
+    /// Features the device supports, like `shell_v2` and `stat_v2`.
+    pub features: Vec<String>,
+}
+
+impl DeviceBanner {
+    /// Parse a banner, with or without its trailing NUL.
+    pub fn parse(data: &[u8]) -> DeviceBanner {
+        let text = String::from_utf8_lossy(data);
+        let text = text.trim_end_matches('\0');
+        let (state, rest) = text.split_once("::").unwrap_or((text, ""));
+        let mut banner = DeviceBanner {
+            state: state.to_string(),
+            ..DeviceBanner::default()
+        };
+        for item in rest.split(';') {
+            let Some((key, value)) = item.split_once('=') else {
+                continue;
+            };
+            if key == "features" {
+                banner.features = value
+                    .split(',')
+                    .filter(|f| !f.is_empty())
+                    .map(str::to_string)
+                    .collect();
+            } else {
+                banner.properties.insert(key.to_string(), value.to_string());
+            }
+        }
+        banner
+    }
+
+    pub fn property(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_values() {
+        // The values adb's own sources define
+        assert_eq!(Command::Cnxn.as_u32(), 0x4e58_4e43);
+        assert_eq!(Command::Wrte.as_u32(), 0x4554_5257);
+        for command in [
+            Command::Cnxn,
+            Command::Auth,
+            Command::Open,
+            Command::Okay,
+            Command::Clse,
+            Command::Wrte,
+            Command::Sync,
+            Command::Stls,
+        ] {
+            assert_eq!(Command::from_u32(command.as_u32()).unwrap(), command);
+        }
+        assert!(Command::from_u32(0).is_err());
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"host::\0"), 0x232);
+        assert_eq!(checksum(&[0xff; 4]), 0x3fc);
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let message = Message::new(Command::Open, 7, 0, b"shell:ls\0".to_vec());
+        let encoded = message.encode();
+        assert_eq!(encoded.len(), HEADER_SIZE + 9);
+        assert_eq!(&encoded[..4], b"OPEN");
+        let header = MessageHeader::from_bytes(&encoded).unwrap();
+        assert_eq!(header, message.header());
+        assert_eq!(header.magic, !Command::Open.as_u32());
+    }
+
+    #[test]
+    fn test_header_bad_magic() {
+        let mut encoded = Message::new(Command::Okay, 1, 2, Vec::new()).encode();
+        encoded[20] ^= 1;
+        assert!(MessageHeader::from_bytes(&encoded).is_err());
+        assert!(MessageHeader::from_bytes(&encoded[..23]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_message() {
+        let message = Message::new(Command::Wrte, 3, 1, b"hello".to_vec());
+        let encoded = message.encode();
+        let read = read_message(&mut &encoded[..], MAX_PAYLOAD).await.unwrap();
+        assert_eq!(read, message);
+
+        // A zero checksum means "not checked"
+        let mut unchecked = encoded.clone();
+        unchecked[16..20].copy_from_slice(&[0; 4]);
+        assert_eq!(
+            read_message(&mut &unchecked[..], MAX_PAYLOAD)
+                .await
+                .unwrap(),
+            message
+        );
+
+        let mut corrupt = encoded.clone();
+        corrupt[HEADER_SIZE] = b'j';
+        assert!(read_message(&mut &corrupt[..], MAX_PAYLOAD).await.is_err());
+        assert!(read_message(&mut &encoded[..], 4).await.is_err());
+    }
+
+    #[test]
+    fn test_parse_banner() {
+        let banner = DeviceBanner::parse(
+            b"device::ro.product.name=sdk_gphone;ro.product.model=Pixel 7;ro.product.device=panther;features=shell_v2,cmd,stat_v2\0",
+        );
+        assert_eq!(banner.state, "device");
+        assert_eq!(banner.property("ro.product.model"), Some("Pixel 7"));
+        assert_eq!(banner.features, ["shell_v2", "cmd", "stat_v2"]);
+
+        let banner = DeviceBanner::parse(b"recovery::");
+        assert_eq!(banner.state, "recovery");
+        assert!(banner.features.is_empty());
+    }
+}
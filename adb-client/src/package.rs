@@ -0,0 +1,274 @@
+use crate::error::{AdbError, AdbResult};
+
+/// An installed package, as listed by
+/// `pm list packages -f -i -U --show-versioncode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageInfo {
+    /// Package name (e.g. "com.android.chrome").
+    pub name: String,
+    /// Path of the base APK.
+    pub path: Option<String>,
+    pub version_code: Option<u64>,
+    /// Package that installed it (e.g. "com.android.vending"), if any.
+    pub installer: Option<String>,
+    pub uid: Option<u32>,
+}
+
+impl PackageInfo {
+    /// Parse one line of `pm list packages` output.
+    ///
+    /// Example input:
+    /// `"package:/data/app/~~a1==/com.example-b2==/base.apk=com.example versionCode:42 installer=com.android.vending uid:10123"`
+    pub fn parse_line(line: &str) -> Option<PackageInfo> {
+        let mut parts = line.split_whitespace();
+        let first = parts.next()?.strip_prefix("package:")?;
+        // APK paths can contain '=' themselves, the package name can't
+        let (path, name) = match first.rsplit_once('=') {
+            Some((path, name)) => (Some(path.to_string()), name),
+            None => (None, first),
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut info = PackageInfo {
+            name: name.to_string(),
+            path,
+            version_code: None,
+            installer: None,
+            uid: None,
+        };
+        for part in parts {
+            if let Some(code) = part.strip_prefix("versionCode:") {
+                info.version_code = code.parse().ok();
+            } else if let Some(installer) = part.strip_prefix("installer=") {
+                if installer != "null" {
+                    info.installer = Some(installer.to_string());
+                }
+            } else if let Some(uid) = part.strip_prefix("uid:") {
+                info.uid = uid.parse().ok();
+            }
+        }
+        Some(info)
+    }
+
+    /// Parse the full output of `pm list packages`, skipping lines that
+    /// aren't packages.
+    pub fn parse_package_list(data: &str) -> Vec<PackageInfo> {
+        data.lines().filter_map(PackageInfo::parse_line).collect()
+    }
+}
+
+/// Which packages `list_packages` returns.
+#[derive(Debug, Clone, Default)]
+pub struct ListPackagesOptions {
+    /// Only third-party packages (`-3`).
+    pub third_party: bool,
+    /// Only system packages (`-s`).
+    pub system: bool,
+    /// Only disabled packages (`-d`).
+    pub disabled: bool,
+    /// Only enabled packages (`-e`).
+    pub enabled: bool,
+    /// Only packages whose name contains this text.
+    pub filter: Option<String>,
+}
+
+impl ListPackagesOptions {
+    /// The `pm list packages` arguments, asking for every field of
+    /// `PackageInfo`.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = ["-f", "-i", "-U", "--show-versioncode"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        for (set, flag) in [
+            (self.third_party, "-3"),
+            (self.system, "-s"),
+            (self.disabled, "-d"),
+            (self.enabled, "-e"),
+        ] {
+            if set {
+                args.push(flag.to_string());
+            }
+        }
+        if let Some(filter) = &self.filter {
+            args.push(shell_quote(filter));
+        }
+        args
+    }
+}
+
+/// Check that `name` is a package name, made of letters, digits, `_` and
+/// `.`, before it goes into a command line the device's shell runs.
+pub fn check_package_name(name: &str) -> AdbResult<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        return Err(AdbError::InvalidArgument(format!(
+            "not a package name: {:?}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Quote `arg` so that the device's shell takes it as a single word.
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Flags for `pm install`.
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// Replace an existing installation, keeping its data (`-r`).
+    pub reinstall: bool,
+    /// Allow installing a lower version code (`-d`).
+    pub allow_downgrade: bool,
+    /// Grant all runtime permissions (`-g`).
+    pub grant_permissions: bool,
+    /// Allow test-only APKs (`-t`).
+    pub allow_test: bool,
+}
+
+impl InstallOptions {
+    pub fn to_args(&self) -> Vec<&'static str> {
+        [
+            (self.reinstall, "-r"),
+            (self.allow_downgrade, "-d"),
+            (self.grant_permissions, "-g"),
+            (self.allow_test, "-t"),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect()
+    }
+}
+
+/// Check the output of a package manager command: `Success` (maybe with
+/// details after it) or `Failure [REASON]`.
+pub fn parse_pm_result(output: &str) -> AdbResult<()> {
+    let output = output.trim();
+    if output.starts_with("Success") {
+        Ok(())
+    } else if output.is_empty() {
+        Err(AdbError::PackageError(
+            "No response from package manager".into(),
+        ))
+    } else {
+        Err(AdbError::PackageError(output.to_string()))
+    }
+}
+
+/// Get the session ID out of `install-create` output:
+/// `Success: created install session [1234]`.
+pub fn parse_session_id(output: &str) -> AdbResult<u32> {
+    parse_pm_result(output)?;
+    output
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .and_then(|(id, _)| id.parse().ok())
+        .ok_or_else(|| AdbError::Protocol(format!("No install session ID in {:?}", output.trim())))
+}
+
+/// The PNG file signature, which `screencap -p` output starts with.
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_package_line_full() {
+        let line = "package:/data/app/~~a1==/com.example-b2==/base.apk=com.example versionCode:42  installer=com.android.vending uid:10123";
+        let info = PackageInfo::parse_line(line).unwrap();
+        assert_eq!(info.name, "com.example");
+        assert_eq!(
+            info.path.as_deref(),
+            Some("/data/app/~~a1==/com.example-b2==/base.apk")
+        );
+        assert_eq!(info.version_code, Some(42));
+        assert_eq!(info.installer.as_deref(), Some("com.android.vending"));
+        assert_eq!(info.uid, Some(10123));
+    }
+
+    #[test]
+    fn test_parse_package_list() {
+        let data = "package:com.android.shell\npackage:/system/app/Foo/Foo.apk=com.foo versionCode:1 installer=null uid:1000\r\n\nWARNING: something\n";
+        let packages = PackageInfo::parse_package_list(data);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "com.android.shell");
+        assert_eq!(packages[0].path, None);
+        assert_eq!(packages[1].installer, None);
+        assert_eq!(packages[1].uid, Some(1000));
+    }
+
+    #[test]
+    fn test_list_packages_args() {
+        let options = ListPackagesOptions {
+            third_party: true,
+            filter: Some("google".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.to_args().join(" "),
+            "-f -i -U --show-versioncode -3 'google'"
+        );
+
+        let options = ListPackagesOptions {
+            filter: Some("a b;reboot $(x) `y` 'z'".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.to_args().last().unwrap(),
+            r"'a b;reboot $(x) `y` '\''z'\'''"
+        );
+    }
+
+    #[test]
+    fn test_check_package_name() {
+        assert!(check_package_name("com.example.app_2").is_ok());
+        for name in ["", "com.example app", "com.example;reboot", "$(reboot)"] {
+            assert!(matches!(
+                check_package_name(name),
+                Err(AdbError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_install_args() {
+        assert!(InstallOptions::default().to_args().is_empty());
+        let options = InstallOptions {
+            reinstall: true,
+            grant_permissions: true,
+            ..Default::default()
+        };
+        assert_eq!(options.to_args(), ["-r", "-g"]);
+    }
+
+    #[test]
+    fn test_parse_pm_result() {
+        assert!(parse_pm_result("Success\n").is_ok());
+        assert!(parse_pm_result("Success: streamed 10 bytes\n").is_ok());
+        match parse_pm_result("Failure [INSTALL_FAILED_ALREADY_EXISTS]\n") {
+            Err(AdbError::PackageError(msg)) => {
+                assert_eq!(msg, "Failure [INSTALL_FAILED_ALREADY_EXISTS]")
+            }
+            other => panic!("Expected PackageError, got {:?}", other),
+        }
+        assert!(parse_pm_result("").is_err());
+    }
+
+    #[test]
+    fn test_parse_session_id() {
+        assert_eq!(
+            parse_session_id("Success: created install session [1234]\n").unwrap(),
+            1234
+        );
+        assert!(parse_session_id("Success\n").is_err());
+        assert!(parse_session_id("Failure [no]").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::error::{AdbError, AdbResult};
+use crate::forward::ForwardSpec;
 
 // ADB server protocol uses a simple length-prefixed format:
 //
@@ -69,6 +70,34 @@ pub enum HostCommand {
     TransportAny,
     /// Kill the ADB server.
     Kill,
+    /// Forward connections to `local` on the host to `remote` on a device.
+    Forward {
+        serial: Option<String>,
+        local: ForwardSpec,
+        remote: ForwardSpec,
+        /// Fail instead of replacing an existing forward of `local`.
+        no_rebind: bool,
+    },
+    /// List the forwards of all devices.
+    ListForward,
+    /// Remove the forward of `local`.
+    KillForward {
+        serial: Option<String>,
+        local: ForwardSpec,
+    },
+    /// Remove all forwards of a device, or of every device.
+    KillForwardAll { serial: Option<String> },
+    /// List the features a device supports (e.g. `shell_v2`, `stat_v2`).
+    Features { serial: Option<String> },
+}
+
+/// The prefix of host commands about one device: `host-serial:SERIAL` for a
+/// specific device, or `host` for the only one.
+fn host_prefix(serial: &Option<String>) -> String {
+    match serial {
+        Some(serial) => format!("host-serial:{}", serial),
+        None => "host".to_string(),
+    }
 }
 
 impl HostCommand {
@@ -82,6 +111,26 @@ impl HostCommand {
             HostCommand::Transport(serial) => format!("host:transport:{}", serial),
             HostCommand::TransportAny => "host:transport-any".to_string(),
             HostCommand::Kill => "host:kill".to_string(),
+            HostCommand::Forward {
+                serial,
+                local,
+                remote,
+                no_rebind,
+            } => format!(
+                "{}:forward:{}{};{}",
+                host_prefix(serial),
+                if *no_rebind { "norebind:" } else { "" },
+                local,
+                remote
+            ),
+            HostCommand::ListForward => "host:list-forward".to_string(),
+            HostCommand::KillForward { serial, local } => {
+                format!("{}:killforward:{}", host_prefix(serial), local)
+            }
+            HostCommand::KillForwardAll { serial } => {
+                format!("{}:killforward-all", host_prefix(serial))
+            }
+            HostCommand::Features { serial } => format!("{}:features", host_prefix(serial)),
         }
     }
 
@@ -98,10 +147,36 @@ pub enum LocalCommand {
     Shell(String),
     /// Open an interactive shell session.
     ShellInteractive,
+    /// Run a command, or an interactive shell if it is empty, over the shell
+    /// v2 protocol. With `pty`, the device allocates a terminal of type
+    /// `term` for it.
+    ShellV2 {
+        command: String,
+        pty: bool,
+        term: Option<String>,
+    },
     /// Stream logcat output.
     Logcat,
     /// Enter file sync mode.
     Sync,
+    /// Forward connections to `remote` on the device to `local` on the host.
+    ReverseForward {
+        remote: ForwardSpec,
+        local: ForwardSpec,
+        /// Fail instead of replacing an existing reverse forward of `remote`.
+        no_rebind: bool,
+    },
+    /// List the reverse forwards of the device.
+    ReverseList,
+    /// Remove the reverse forward of `remote`.
+    ReverseKill(ForwardSpec),
+    /// Remove all reverse forwards of the device.
+    ReverseKillAll,
+    /// Open a connection to a socket on the device.
+    Connect(ForwardSpec),
+    /// Run a command without a shell's line ending translation, so binary
+    /// input and output (APKs, PNGs) pass through unchanged.
+    Exec(String),
 }
 
 impl LocalCommand {
@@ -110,8 +185,31 @@ impl LocalCommand {
         match self {
             LocalCommand::Shell(cmd) => format!("shell:{}", cmd),
             LocalCommand::ShellInteractive => "shell:".to_string(),
+            LocalCommand::ShellV2 { command, pty, term } => {
+                let mut args = vec!["v2".to_string()];
+                if let Some(term) = term {
+                    args.push(format!("TERM={}", term));
+                }
+                args.push(if *pty { "pty" } else { "raw" }.to_string());
+                format!("shell,{}:{}", args.join(","), command)
+            }
             LocalCommand::Logcat => "shell:logcat".to_string(),
             LocalCommand::Sync => "sync:".to_string(),
+            LocalCommand::ReverseForward {
+                remote,
+                local,
+                no_rebind,
+            } => format!(
+                "reverse:forward:{}{};{}",
+                if *no_rebind { "norebind:" } else { "" },
+                remote,
+                local
+            ),
+            LocalCommand::ReverseList => "reverse:list-forward".to_string(),
+            LocalCommand::ReverseKill(remote) => format!("reverse:killforward:{}", remote),
+            LocalCommand::ReverseKillAll => "reverse:killforward-all".to_string(),
+            LocalCommand::Connect(spec) => spec.to_string(),
+            LocalCommand::Exec(cmd) => format!("exec:{}", cmd),
         }
     }
 
@@ -188,6 +286,83 @@ mod tests {
         );
         assert_eq!(HostCommand::TransportAny.to_wire(), "host:transport-any");
         assert_eq!(HostCommand::Kill.to_wire(), "host:kill");
+        assert_eq!(
+            HostCommand::Features { serial: None }.to_wire(),
+            "host:features"
+        );
+        assert_eq!(
+            HostCommand::Features {
+                serial: Some("emulator-5554".into())
+            }
+            .to_wire(),
+            "host-serial:emulator-5554:features"
+        );
+    }
+
+    #[test]
+    fn test_forward_command_wire_format() {
+        assert_eq!(
+            HostCommand::Forward {
+                serial: Some("emulator-5554".into()),
+                local: ForwardSpec::Tcp(6100),
+                remote: ForwardSpec::Tcp(7100),
+                no_rebind: false,
+            }
+            .to_wire(),
+            "host-serial:emulator-5554:forward:tcp:6100;tcp:7100"
+        );
+        assert_eq!(
+            HostCommand::Forward {
+                serial: None,
+                local: ForwardSpec::Tcp(0),
+                remote: ForwardSpec::Jdwp(1234),
+                no_rebind: true,
+            }
+            .to_wire(),
+            "host:forward:norebind:tcp:0;jdwp:1234"
+        );
+        assert_eq!(HostCommand::ListForward.to_wire(), "host:list-forward");
+        assert_eq!(
+            HostCommand::KillForward {
+                serial: None,
+                local: ForwardSpec::Tcp(6100),
+            }
+            .to_wire(),
+            "host:killforward:tcp:6100"
+        );
+        assert_eq!(
+            HostCommand::KillForwardAll {
+                serial: Some("emulator-5554".into()),
+            }
+            .to_wire(),
+            "host-serial:emulator-5554:killforward-all"
+        );
+    }
+
+    #[test]
+    fn test_reverse_command_wire_format() {
+        assert_eq!(
+            LocalCommand::ReverseForward {
+                remote: ForwardSpec::LocalAbstract("devtools".into()),
+                local: ForwardSpec::Tcp(9222),
+                no_rebind: false,
+            }
+            .to_wire(),
+            "reverse:forward:localabstract:devtools;tcp:9222"
+        );
+        assert_eq!(LocalCommand::ReverseList.to_wire(), "reverse:list-forward");
+        assert_eq!(
+            LocalCommand::ReverseKill(ForwardSpec::Tcp(8081)).to_wire(),
+            "reverse:killforward:tcp:8081"
+        );
+        assert_eq!(
+            LocalCommand::ReverseKillAll.to_wire(),
+            "reverse:killforward-all"
+        );
+        assert_eq!(
+            LocalCommand::Connect(ForwardSpec::Tcp(7100)).to_wire(),
+            "tcp:7100"
+        );
     }
 
     #[test]
@@ -209,7 +384,29 @@ mod tests {
         );
         assert_eq!(LocalCommand::ShellInteractive.to_wire(), "shell:");
         assert_eq!(LocalCommand::Logcat.to_wire(), "shell:logcat");
+        assert_eq!(
+            LocalCommand::ShellV2 {
+                command: "ls".into(),
+                pty: false,
+                term: None,
+            }
+            .to_wire(),
+            "shell,v2,raw:ls"
+        );
+        assert_eq!(
+            LocalCommand::ShellV2 {
+                command: String::new(),
+                pty: true,
+                term: Some("xterm-256color".into()),
+            }
+            .to_wire(),
+            "shell,v2,TERM=xterm-256color,pty:"
+        );
         assert_eq!(LocalCommand::Sync.to_wire(), "sync:");
+        assert_eq!(
+            LocalCommand::Exec("screencap -p".into()).to_wire(),
+            "exec:screencap -p"
+        );
     }
 
     #[test]
@@ -0,0 +1,330 @@
+use crate::error::{AdbError, AdbResult};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Shell v2 protocol (`shell,v2,...:command`) frames everything in both
+// directions as packets with a 5-byte header:
+//
+// {1-byte packet id}{4-byte little-endian payload length}{payload}
+//
+// Unlike the legacy `shell:` service, stdout and stderr arrive as separate
+// packets, and the command's exit status is sent in an EXIT packet before
+// the device closes the connection.
+
+/// Shell v2 packet IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellId {
+    /// Input for the command (client to device).
+    Stdin,
+    /// Standard output of the command (device to client).
+    Stdout,
+    /// Standard error of the command (device to client).
+    Stderr,
+    /// Exit status of the command, as a single byte (device to client).
+    Exit,
+    /// No more input for the command (client to device).
+    CloseStdin,
+    /// The terminal was resized (client to device).
+    WindowSizeChange,
+}
+
+impl ShellId {
+    /// The on-wire byte for this packet ID.
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            ShellId::Stdin => 0,
+            ShellId::Stdout => 1,
+            ShellId::Stderr => 2,
+            ShellId::Exit => 3,
+            ShellId::CloseStdin => 4,
+            ShellId::WindowSizeChange => 5,
+        }
+    }
+
+    /// Parse an on-wire byte into a `ShellId`.
+    pub fn from_byte(byte: u8) -> AdbResult<ShellId> {
+        match byte {
+            0 => Ok(ShellId::Stdin),
+            1 => Ok(ShellId::Stdout),
+            2 => Ok(ShellId::Stderr),
+            3 => Ok(ShellId::Exit),
+            4 => Ok(ShellId::CloseStdin),
+            5 => Ok(ShellId::WindowSizeChange),
+            other => Err(AdbError::Protocol(format!(
+                "Unknown shell packet ID: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// The 5-byte shell v2 header: 1-byte packet ID + 4-byte little-endian length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellHeader {
+    pub id: ShellId,
+    pub length: u32,
+}
+
+impl ShellHeader {
+    pub fn new(id: ShellId, length: u32) -> Self {
+        Self { id, length }
+    }
+
+    /// Serialize to exactly 5 bytes.
+    pub fn to_bytes(&self) -> [u8; 5] {
+        let mut buf = [0u8; 5];
+        buf[0] = self.id.as_byte();
+        buf[1..5].copy_from_slice(&self.length.to_le_bytes());
+        buf
+    }
+
+    /// Parse from a byte slice (must be at least 5 bytes).
+    pub fn from_bytes(buf: &[u8]) -> AdbResult<Self> {
+        if buf.len() < 5 {
+            return Err(AdbError::Protocol(format!(
+                "Shell header too short: {} bytes, need 5",
+                buf.len()
+            )));
+        }
+        let id = ShellId::from_byte(buf[0])?;
+        let length = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]);
+        Ok(Self { id, length })
+    }
+}
+
+/// Encode a shell v2 packet: header + payload.
+pub fn encode_packet(id: ShellId, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5 + data.len());
+    buf.extend_from_slice(&ShellHeader::new(id, data.len() as u32).to_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Encode a WINDOW_SIZE_CHANGE packet. The payload is the text
+/// `"{rows}x{cols},{x_pixels}x{y_pixels}"`, NUL-terminated.
+pub fn encode_window_size(rows: u16, cols: u16, x_pixels: u16, y_pixels: u16) -> Vec<u8> {
+    let size = format!("{}x{},{}x{}\0", rows, cols, x_pixels, y_pixels);
+    encode_packet(ShellId::WindowSizeChange, size.as_bytes())
+}
+
+/// Everything a command run over shell v2 wrote, with its exit status.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShellOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: u8,
+}
+
+/// The receiving half of a shell v2 session.
+pub struct ShellReader<R> {
+    inner: R,
+}
+
+impl<R: AsyncRead + Unpin> ShellReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// Read the next packet, or `None` if the device closed the connection.
+    pub async fn read_packet(&mut self) -> AdbResult<Option<(ShellId, Vec<u8>)>> {
+        let mut buf = [0u8; 5];
+        match self.inner.read_exact(&mut buf).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(AdbError::Io(e)),
+        }
+        let header = ShellHeader::from_bytes(&buf)?;
+        let mut data = vec![0u8; header.length as usize];
+        self.inner.read_exact(&mut data).await?;
+        Ok(Some((header.id, data)))
+    }
+
+    /// Copy stdout and stderr packets to `stdout` and `stderr` as they
+    /// arrive, until the EXIT packet, and return the exit status.
+    pub async fn copy_output<O, E>(&mut self, stdout: &mut O, stderr: &mut E) -> AdbResult<u8>
+    where
+        O: AsyncWrite + Unpin,
+        E: AsyncWrite + Unpin,
+    {
+        loop {
+            match self.read_packet().await? {
+                Some((ShellId::Stdout, data)) => {
+                    stdout.write_all(&data).await?;
+                    stdout.flush().await?;
+                }
+                Some((ShellId::Stderr, data)) => {
+                    stderr.write_all(&data).await?;
+                    stderr.flush().await?;
+                }
+                Some((ShellId::Exit, data)) => {
+                    return data
+                        .first()
+                        .copied()
+                        .ok_or_else(|| AdbError::Protocol("Empty shell EXIT packet".into()));
+                }
+                Some((other, _)) => {
+                    return Err(AdbError::Protocol(format!(
+                        "Unexpected shell packet from device: {:?}",
+                        other
+                    )));
+                }
+                None => {
+                    return Err(AdbError::Protocol(
+                        "Shell closed without an exit status".into(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// The sending half of a shell v2 session.
+pub struct ShellWriter<W> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin> ShellWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Send input to the command.
+    pub async fn write_stdin(&mut self, data: &[u8]) -> AdbResult<()> {
+        self.inner
+            .write_all(&encode_packet(ShellId::Stdin, data))
+            .await?;
+        Ok(())
+    }
+
+    /// Tell the command there is no more input.
+    pub async fn close_stdin(&mut self) -> AdbResult<()> {
+        self.inner
+            .write_all(&encode_packet(ShellId::CloseStdin, &[]))
+            .await?;
+        Ok(())
+    }
+
+    /// Tell the device's PTY the terminal size changed.
+    pub async fn resize(&mut self, rows: u16, cols: u16) -> AdbResult<()> {
+        self.inner
+            .write_all(&encode_window_size(rows, cols, 0, 0))
+            .await?;
+        Ok(())
+    }
+}
+
+/// A shell v2 session: a command running on the device, with its input,
+/// output and exit status.
+///
+/// Use the halves directly, or `split` them to read output while another
+/// task forwards input.
+pub struct ShellSession<R, W> {
+    pub reader: ShellReader<R>,
+    pub writer: ShellWriter<W>,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> ShellSession<R, W> {
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: ShellReader::new(reader),
+            writer: ShellWriter::new(writer),
+        }
+    }
+
+    pub fn split(self) -> (ShellReader<R>, ShellWriter<W>) {
+        (self.reader, self.writer)
+    }
+
+    /// Close stdin, then collect all output until the command exits.
+    pub async fn wait_with_output(mut self) -> AdbResult<ShellOutput> {
+        self.writer.close_stdin().await?;
+        let mut output = ShellOutput::default();
+        output.exit_code = self
+            .reader
+            .copy_output(&mut output.stdout, &mut output.stderr)
+            .await?;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_id_all_variants_round_trip() {
+        let variants = [
+            (ShellId::Stdin, 0),
+            (ShellId::Stdout, 1),
+            (ShellId::Stderr, 2),
+            (ShellId::Exit, 3),
+            (ShellId::CloseStdin, 4),
+            (ShellId::WindowSizeChange, 5),
+        ];
+        for (id, byte) in variants {
+            assert_eq!(id.as_byte(), byte);
+            assert_eq!(ShellId::from_byte(byte).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn test_shell_id_unknown() {
+        assert!(ShellId::from_byte(6).is_err());
+    }
+
+    #[test]
+    fn test_shell_header_round_trip() {
+        let header = ShellHeader::new(ShellId::Stderr, 0x01020304);
+        let bytes = header.to_bytes();
+        assert_eq!(bytes, [2, 0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(ShellHeader::from_bytes(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn test_shell_header_too_short() {
+        assert!(ShellHeader::from_bytes(&[1, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_encode_packet() {
+        assert_eq!(
+            encode_packet(ShellId::Stdin, b"ls\n"),
+            [0, 3, 0, 0, 0, b'l', b's', b'\n']
+        );
+        assert_eq!(encode_packet(ShellId::CloseStdin, &[]), [4, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_window_size() {
+        let packet = encode_window_size(24, 80, 0, 0);
+        let header = ShellHeader::from_bytes(&packet).unwrap();
+        assert_eq!(header.id, ShellId::WindowSizeChange);
+        assert_eq!(&packet[5..], b"24x80,0x0\0");
+        assert_eq!(header.length as usize, packet.len() - 5);
+    }
+
+    #[tokio::test]
+    async fn test_copy_output_separates_streams() {
+        let mut wire = Vec::new();
+        wire.extend(encode_packet(ShellId::Stdout, b"out "));
+        wire.extend(encode_packet(ShellId::Stderr, b"err"));
+        wire.extend(encode_packet(ShellId::Stdout, b"more"));
+        wire.extend(encode_packet(ShellId::Exit, &[42]));
+
+        let mut reader = ShellReader::new(&wire[..]);
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let code = reader.copy_output(&mut stdout, &mut stderr).await.unwrap();
+        assert_eq!(code, 42);
+        assert_eq!(stdout, b"out more");
+        assert_eq!(stderr, b"err");
+    }
+
+    #[tokio::test]
+    async fn test_copy_output_without_exit() {
+        let wire = encode_packet(ShellId::Stdout, b"partial");
+        let mut reader = ShellReader::new(&wire[..]);
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let result = reader.copy_output(&mut stdout, &mut stderr).await;
+        assert!(matches!(result, Err(AdbError::Protocol(_))));
+        assert_eq!(stdout, b"partial");
+    }
+}
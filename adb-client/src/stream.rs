@@ -0,0 +1,55 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::net::TcpStream;
+
+/// A stream to a service on a device, through whichever transport the
+/// client uses.
+#[derive(Debug)]
+pub enum AdbStream {
+    /// A connection to the ADB server, switched over to the device.
+    Server(TcpStream),
+    /// A stream multiplexed over a direct connection to adbd.
+    Device(DuplexStream),
+}
+
+impl AsyncRead for AdbStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AdbStream::Server(stream) => Pin::new(stream).poll_read(cx, buf),
+            AdbStream::Device(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AdbStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            AdbStream::Server(stream) => Pin::new(stream).poll_write(cx, buf),
+            AdbStream::Device(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AdbStream::Server(stream) => Pin::new(stream).poll_flush(cx),
+            AdbStream::Device(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AdbStream::Server(stream) => Pin::new(stream).poll_shutdown(cx),
+            AdbStream::Device(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -29,6 +29,18 @@ pub enum SyncId {
     Dent,
     /// Quit sync mode.
     Quit,
+    /// Query file metadata with 64-bit sizes, following symlinks (v2).
+    Sta2,
+    /// Query file metadata with 64-bit sizes, not following symlinks (v2).
+    Lst2,
+    /// List directory contents with 64-bit sizes (v2).
+    Lis2,
+    /// Directory entry (response to LIS2).
+    Dnt2,
+    /// Send a file, with mode and flags in a separate message (v2).
+    Snd2,
+    /// Receive a file, with flags in a separate message (v2).
+    Rcv2,
 }
 
 impl SyncId {
@@ -45,6 +57,12 @@ impl SyncId {
             SyncId::Fail => b"FAIL",
             SyncId::Dent => b"DENT",
             SyncId::Quit => b"QUIT",
+            SyncId::Sta2 => b"STA2",
+            SyncId::Lst2 => b"LST2",
+            SyncId::Lis2 => b"LIS2",
+            SyncId::Dnt2 => b"DNT2",
+            SyncId::Snd2 => b"SND2",
+            SyncId::Rcv2 => b"RCV2",
         }
     }
 
@@ -67,6 +85,12 @@ impl SyncId {
             b"FAIL" => Ok(SyncId::Fail),
             b"DENT" => Ok(SyncId::Dent),
             b"QUIT" => Ok(SyncId::Quit),
+            b"STA2" => Ok(SyncId::Sta2),
+            b"LST2" => Ok(SyncId::Lst2),
+            b"LIS2" => Ok(SyncId::Lis2),
+            b"DNT2" => Ok(SyncId::Dnt2),
+            b"SND2" => Ok(SyncId::Snd2),
+            b"RCV2" => Ok(SyncId::Rcv2),
             other => Err(AdbError::Protocol(format!(
                 "Unknown sync ID: {:?}",
                 String::from_utf8_lossy(other)
@@ -206,34 +230,208 @@ impl DentEntry {
     }
 }
 
-/// Encode a STAT request: `STAT` + LE path length + path bytes.
-pub fn encode_stat_request(remote_path: &str) -> Vec<u8> {
+/// STA2/LST2 response: file metadata with 64-bit sizes and times.
+///
+/// The on-wire format is 72 bytes total: `STA2` (or `LST2`) (4) + error (4) +
+/// dev (8) + ino (8) + mode (4) + nlink (4) + uid (4) + gid (4) + size (8) +
+/// atime (8) + mtime (8) + ctime (8). This struct holds the 68 bytes after the id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatV2Response {
+    /// `errno` of the failed stat, or 0.
+    pub error: u32,
+    pub dev: u64,
+    pub ino: u64,
+    /// Unix file mode (type + permissions).
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// File size in bytes.
+    pub size: u64,
+    pub atime: i64,
+    /// Last modification time (Unix timestamp).
+    pub mtime: i64,
+    pub ctime: i64,
+}
+
+/// Size of a STA2/LST2 response after the id.
+pub const STAT_V2_SIZE: usize = 68;
+
+impl StatV2Response {
+    /// Parse from the 68 bytes following the STA2/LST2 id.
+    pub fn from_bytes(buf: &[u8]) -> AdbResult<Self> {
+        if buf.len() < STAT_V2_SIZE {
+            return Err(AdbError::Protocol(format!(
+                "STA2 response too short: {} bytes, need {}",
+                buf.len(),
+                STAT_V2_SIZE
+            )));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        Ok(Self {
+            error: u32_at(0),
+            dev: u64_at(4),
+            ino: u64_at(12),
+            mode: u32_at(20),
+            nlink: u32_at(24),
+            uid: u32_at(28),
+            gid: u32_at(32),
+            size: u64_at(36),
+            atime: u64_at(44) as i64,
+            mtime: u64_at(52) as i64,
+            ctime: u64_at(60) as i64,
+        })
+    }
+
+    /// Serialize to the 68 bytes following the id.
+    pub fn to_bytes(&self) -> [u8; STAT_V2_SIZE] {
+        let mut buf = [0u8; STAT_V2_SIZE];
+        buf[0..4].copy_from_slice(&self.error.to_le_bytes());
+        buf[4..12].copy_from_slice(&self.dev.to_le_bytes());
+        buf[12..20].copy_from_slice(&self.ino.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.mode.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.nlink.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.uid.to_le_bytes());
+        buf[32..36].copy_from_slice(&self.gid.to_le_bytes());
+        buf[36..44].copy_from_slice(&self.size.to_le_bytes());
+        buf[44..52].copy_from_slice(&self.atime.to_le_bytes());
+        buf[52..60].copy_from_slice(&self.mtime.to_le_bytes());
+        buf[60..68].copy_from_slice(&self.ctime.to_le_bytes());
+        buf
+    }
+
+    /// Whether the stat succeeded. Legacy STAT reports a missing file as
+    /// all zeros instead.
+    pub fn exists(&self) -> bool {
+        self.error == 0 && self.mode != 0
+    }
+
+    /// Whether this is a regular file (S_IFREG = 0o100000).
+    pub fn is_file(&self) -> bool {
+        (self.mode & 0o170000) == 0o100000
+    }
+
+    /// Whether this is a directory (S_IFDIR = 0o040000).
+    pub fn is_directory(&self) -> bool {
+        (self.mode & 0o170000) == 0o040000
+    }
+
+    /// Whether this is a symbolic link (S_IFLNK = 0o120000).
+    pub fn is_symlink(&self) -> bool {
+        (self.mode & 0o170000) == 0o120000
+    }
+
+    /// Extract the permission bits (lower 12 bits).
+    pub fn permissions(&self) -> u32 {
+        self.mode & 0o7777
+    }
+}
+
+impl From<&StatResponse> for StatV2Response {
+    fn from(stat: &StatResponse) -> Self {
+        Self {
+            mode: stat.mode,
+            size: stat.size as u64,
+            mtime: stat.mtime as i64,
+            ..Self::default()
+        }
+    }
+}
+
+/// Directory entry from LIS2 command response (DNT2).
+///
+/// On-wire format: `DNT2` (4) + the 68 bytes of a STA2 response + namelen (4) + name.
+/// This struct holds everything after the `DNT2` id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DentV2Entry {
+    pub stat: StatV2Response,
+    /// File/directory name.
+    pub name: String,
+}
+
+impl DentV2Entry {
+    /// Parse from raw bytes: stat (68) + namelen (4) + name.
+    pub fn from_bytes(buf: &[u8]) -> AdbResult<Self> {
+        if buf.len() < STAT_V2_SIZE + 4 {
+            return Err(AdbError::Protocol(format!(
+                "DNT2 entry too short: {} bytes, need at least {}",
+                buf.len(),
+                STAT_V2_SIZE + 4
+            )));
+        }
+        let stat = StatV2Response::from_bytes(&buf[..STAT_V2_SIZE])?;
+        let namelen =
+            u32::from_le_bytes(buf[STAT_V2_SIZE..STAT_V2_SIZE + 4].try_into().unwrap()) as usize;
+        let name_start = STAT_V2_SIZE + 4;
+        if buf.len() < name_start + namelen {
+            return Err(AdbError::Protocol(format!(
+                "DNT2 entry name truncated: have {} bytes, need {}",
+                buf.len() - name_start,
+                namelen
+            )));
+        }
+        let name = String::from_utf8_lossy(&buf[name_start..name_start + namelen]).to_string();
+        Ok(Self { stat, name })
+    }
+}
+
+impl From<&DentEntry> for DentV2Entry {
+    fn from(dent: &DentEntry) -> Self {
+        Self {
+            stat: StatV2Response {
+                mode: dent.mode,
+                size: dent.size as u64,
+                mtime: dent.mtime as i64,
+                ..StatV2Response::default()
+            },
+            name: dent.name.clone(),
+        }
+    }
+}
+
+/// Encode a request that is just an id and a path: `{id}` + LE path length + path bytes.
+pub fn encode_path_request(id: SyncId, remote_path: &str) -> Vec<u8> {
     let path_bytes = remote_path.as_bytes();
     let mut buf = Vec::with_capacity(8 + path_bytes.len());
-    buf.extend_from_slice(b"STAT");
+    buf.extend_from_slice(id.as_bytes());
     buf.extend_from_slice(&(path_bytes.len() as u32).to_le_bytes());
     buf.extend_from_slice(path_bytes);
     buf
 }
 
+/// Encode a SND2 request: `SND2` + LE path length + path, then
+/// `SND2` + LE mode + LE flags.
+pub fn encode_send_v2_request(remote_path: &str, mode: u32, flags: u32) -> Vec<u8> {
+    let mut buf = encode_path_request(SyncId::Snd2, remote_path);
+    buf.extend_from_slice(b"SND2");
+    buf.extend_from_slice(&mode.to_le_bytes());
+    buf.extend_from_slice(&flags.to_le_bytes());
+    buf
+}
+
+/// Encode a RCV2 request: `RCV2` + LE path length + path, then
+/// `RCV2` + LE flags.
+pub fn encode_recv_v2_request(remote_path: &str, flags: u32) -> Vec<u8> {
+    let mut buf = encode_path_request(SyncId::Rcv2, remote_path);
+    buf.extend_from_slice(b"RCV2");
+    buf.extend_from_slice(&flags.to_le_bytes());
+    buf
+}
+
+/// Encode a STAT request: `STAT` + LE path length + path bytes.
+pub fn encode_stat_request(remote_path: &str) -> Vec<u8> {
+    encode_path_request(SyncId::Stat, remote_path)
+}
+
 /// Encode a LIST request: `LIST` + LE path length + path bytes.
 pub fn encode_list_request(remote_path: &str) -> Vec<u8> {
-    let path_bytes = remote_path.as_bytes();
-    let mut buf = Vec::with_capacity(8 + path_bytes.len());
-    buf.extend_from_slice(b"LIST");
-    buf.extend_from_slice(&(path_bytes.len() as u32).to_le_bytes());
-    buf.extend_from_slice(path_bytes);
-    buf
+    encode_path_request(SyncId::List, remote_path)
 }
 
 /// Encode a RECV request: `RECV` + LE path length + path bytes.
 pub fn encode_recv_request(remote_path: &str) -> Vec<u8> {
-    let path_bytes = remote_path.as_bytes();
-    let mut buf = Vec::with_capacity(8 + path_bytes.len());
-    buf.extend_from_slice(b"RECV");
-    buf.extend_from_slice(&(path_bytes.len() as u32).to_le_bytes());
-    buf.extend_from_slice(path_bytes);
-    buf
+    encode_path_request(SyncId::Recv, remote_path)
 }
 
 /// Encode a SEND request: `SEND` + LE length + `{remote_path},{mode}`.
@@ -517,6 +715,107 @@ mod tests {
         assert_eq!(val, 0);
     }
 
+    // --- Sync v2 tests ---
+
+    #[test]
+    fn test_sync_id_v2_variants_round_trip() {
+        for (id, bytes) in [
+            (SyncId::Sta2, b"STA2"),
+            (SyncId::Lst2, b"LST2"),
+            (SyncId::Lis2, b"LIS2"),
+            (SyncId::Dnt2, b"DNT2"),
+            (SyncId::Snd2, b"SND2"),
+            (SyncId::Rcv2, b"RCV2"),
+        ] {
+            assert_eq!(id.as_bytes(), bytes);
+            assert_eq!(SyncId::from_bytes(bytes).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn test_stat_v2_round_trip_large_file() {
+        let stat = StatV2Response {
+            mode: 0o100644,
+            nlink: 1,
+            uid: 1000,
+            gid: 1000,
+            size: 5 * 1024 * 1024 * 1024, // 5 GB, more than STAT can say
+            mtime: 1_700_000_000,
+            ..StatV2Response::default()
+        };
+        let bytes = stat.to_bytes();
+        // size sits after error, dev, ino, mode, nlink, uid and gid
+        assert_eq!(&bytes[36..44], &(5u64 << 30).to_le_bytes());
+        let parsed = StatV2Response::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, stat);
+        assert!(parsed.exists() && parsed.is_file());
+        assert_eq!(parsed.permissions(), 0o644);
+    }
+
+    #[test]
+    fn test_stat_v2_error_and_too_short() {
+        let stat = StatV2Response {
+            error: 2, // ENOENT
+            ..StatV2Response::default()
+        };
+        assert!(!StatV2Response::from_bytes(&stat.to_bytes())
+            .unwrap()
+            .exists());
+        assert!(StatV2Response::from_bytes(&[0u8; 67]).is_err());
+    }
+
+    #[test]
+    fn test_stat_v2_from_v1() {
+        let v1 = StatResponse {
+            mode: 0o040755,
+            size: 4096,
+            mtime: 1_700_000_000,
+        };
+        let v2 = StatV2Response::from(&v1);
+        assert!(v2.is_directory());
+        assert_eq!(v2.size, 4096);
+        assert_eq!(v2.mtime, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_dent_v2_entry_parse() {
+        let stat = StatV2Response {
+            mode: 0o120777,
+            size: 12,
+            ..StatV2Response::default()
+        };
+        let mut buf = stat.to_bytes().to_vec();
+        buf.extend_from_slice(&6u32.to_le_bytes());
+        buf.extend_from_slice(b"sdcard");
+        let dent = DentV2Entry::from_bytes(&buf).unwrap();
+        assert_eq!(dent.name, "sdcard");
+        assert!(dent.stat.is_symlink());
+
+        buf.truncate(buf.len() - 1);
+        assert!(DentV2Entry::from_bytes(&buf).is_err());
+    }
+
+    #[test]
+    fn test_encode_send_v2_request() {
+        let encoded = encode_send_v2_request("/sdcard/big.bin", 0o100644, 0);
+        let header = SyncHeader::from_bytes(&encoded[0..8]).unwrap();
+        assert_eq!(header.id, SyncId::Snd2);
+        assert_eq!(header.length, 15);
+        assert_eq!(&encoded[8..23], b"/sdcard/big.bin");
+        let setup = SyncHeader::from_bytes(&encoded[23..31]).unwrap();
+        assert_eq!(setup.id, SyncId::Snd2);
+        assert_eq!(setup.length, 0o100644); // the mode, in the length slot
+        assert_eq!(&encoded[31..], &0u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_encode_recv_v2_request() {
+        let encoded = encode_recv_v2_request("/sdcard/big.bin", 0);
+        assert_eq!(&encoded[0..4], b"RCV2");
+        assert_eq!(&encoded[8..23], b"/sdcard/big.bin");
+        assert_eq!(&encoded[23..], b"RCV2\0\0\0\0");
+    }
+
     // --- Round-trip tests ---
 
     #[test]
@@ -538,6 +837,16 @@ mod tests {
             ("recv", encode_recv_request("/test"), SyncId::Recv),
             ("send", encode_send_request("/test", 0o644), SyncId::Send),
             ("data", encode_data_chunk(b"payload"), SyncId::Data),
+            (
+                "sta2",
+                encode_path_request(SyncId::Sta2, "/test"),
+                SyncId::Sta2,
+            ),
+            (
+                "lis2",
+                encode_path_request(SyncId::Lis2, "/test"),
+                SyncId::Lis2,
+            ),
         ];
 
         for (name, encoded, expected_id) in test_cases {
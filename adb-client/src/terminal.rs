@@ -0,0 +1,99 @@
+//! Terminal handling for interactive shells: raw mode, so keystrokes go to
+//! the device's PTY as they are typed, and the window size, which the PTY
+//! is told about whenever it changes.
+
+#[cfg(unix)]
+mod imp {
+    use std::io;
+    use tokio::signal::unix::{signal, Signal, SignalKind};
+
+    /// Whether stdin is a terminal.
+    pub fn stdin_is_tty() -> bool {
+        unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+    }
+
+    /// The size of the terminal on stdout as (rows, columns), if it is one.
+    pub fn window_size() -> Option<(u16, u16)> {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        (ret == 0 && size.ws_row > 0).then_some((size.ws_row, size.ws_col))
+    }
+
+    /// Puts the terminal on stdin in raw mode until dropped.
+    pub struct RawMode {
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        /// Returns `None` if stdin is not a terminal.
+        pub fn enable() -> io::Result<Option<RawMode>> {
+            if !stdin_is_tty() {
+                return Ok(None);
+            }
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+            unsafe { libc::cfmakeraw(&mut termios) };
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Some(RawMode { original }))
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original) };
+        }
+    }
+
+    /// Notifications of the terminal being resized (SIGWINCH).
+    pub struct Resizes(Signal);
+
+    impl Resizes {
+        pub fn new() -> io::Result<Resizes> {
+            signal(SignalKind::window_change()).map(Resizes)
+        }
+
+        pub async fn recv(&mut self) {
+            self.0.recv().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::io;
+
+    pub fn stdin_is_tty() -> bool {
+        false
+    }
+
+    pub fn window_size() -> Option<(u16, u16)> {
+        None
+    }
+
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> io::Result<Option<RawMode>> {
+            Ok(None)
+        }
+    }
+
+    pub struct Resizes;
+
+    impl Resizes {
+        pub fn new() -> io::Result<Resizes> {
+            Ok(Resizes)
+        }
+
+        pub async fn recv(&mut self) {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+pub use imp::*;
@@ -0,0 +1,1046 @@
+use crate::error::{AdbError, AdbResult};
+use crate::sync::{
+    self, DentEntry, DentV2Entry, StatResponse, StatV2Response, SyncHeader, SyncId, STAT_V2_SIZE,
+    SYNC_DATA_MAX,
+};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Which v2 sync requests a device supports, from its feature list.
+///
+/// Without them, sizes and times are 32-bit and files over 4 GB can't be
+/// told apart from smaller ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncFeatures {
+    /// STA2 and LST2.
+    pub stat_v2: bool,
+    /// LIS2.
+    pub ls_v2: bool,
+    /// SND2 and RCV2.
+    pub sendrecv_v2: bool,
+}
+
+impl SyncFeatures {
+    /// Pick out the sync features from a `host:features` list.
+    pub fn from_features<S: AsRef<str>>(features: &[S]) -> Self {
+        let has = |name: &str| features.iter().any(|f| f.as_ref() == name);
+        Self {
+            stat_v2: has("stat_v2"),
+            ls_v2: has("ls_v2"),
+            sendrecv_v2: has("sendrecv_v2"),
+        }
+    }
+}
+
+/// Options for recursive push and pull.
+#[derive(Debug, Clone, Default)]
+pub struct TransferOptions {
+    /// Skip files whose size and modification time already match at the
+    /// destination, like `adb push --sync`.
+    pub sync: bool,
+}
+
+/// Progress of one file in a transfer, reported after every chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferProgress<'a> {
+    /// Remote path of the file.
+    pub path: &'a str,
+    /// Bytes transferred so far.
+    pub transferred: u64,
+    /// Size of the file.
+    pub size: u64,
+}
+
+/// What a push or pull did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    /// Files transferred.
+    pub files: usize,
+    /// Files skipped because they were unchanged.
+    pub skipped: usize,
+    /// Bytes transferred.
+    pub bytes: u64,
+}
+
+/// Join a name onto a remote directory path.
+fn join_remote(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// The last component of a remote path.
+fn remote_basename(path: &str) -> &str {
+    path.trim_end_matches('/').rsplit('/').next().unwrap_or("")
+}
+
+/// Seconds since the Unix epoch of a local file's modification time.
+fn local_mtime(metadata: &std::fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// The mode a pushed file gets: a regular file with the local permissions.
+fn local_mode(metadata: &std::fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        0o100000 | (metadata.permissions().mode() & 0o777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0o100644
+    }
+}
+
+/// A connection in sync mode, using the v2 requests the device supports and
+/// the legacy ones otherwise.
+pub struct SyncConnection<S> {
+    stream: S,
+    features: SyncFeatures,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SyncConnection<S> {
+    /// Wrap a stream that has already entered sync mode (`sync:`).
+    pub fn new(stream: S, features: SyncFeatures) -> Self {
+        Self { stream, features }
+    }
+
+    pub fn features(&self) -> SyncFeatures {
+        self.features
+    }
+
+    async fn read_header(&mut self) -> AdbResult<SyncHeader> {
+        let mut buf = [0u8; 8];
+        self.stream.read_exact(&mut buf).await?;
+        SyncHeader::from_bytes(&buf)
+    }
+
+    /// Read the message of a FAIL whose header said it is `length` bytes.
+    async fn read_fail(&mut self, length: u32) -> AdbResult<AdbError> {
+        let mut msg = vec![0u8; length as usize];
+        self.stream.read_exact(&mut msg).await?;
+        Ok(AdbError::SyncError(
+            String::from_utf8_lossy(&msg).to_string(),
+        ))
+    }
+
+    /// Read an OKAY, or the error of a FAIL.
+    async fn read_okay(&mut self, after: &str) -> AdbResult<()> {
+        let header = self.read_header().await?;
+        match header.id {
+            SyncId::Okay => Ok(()),
+            SyncId::Fail => Err(self.read_fail(header.length).await?),
+            other => Err(AdbError::Protocol(format!(
+                "Expected OKAY after {}, got {:?}",
+                after, other
+            ))),
+        }
+    }
+
+    /// Stat a remote path, following symlinks. A missing file is not an
+    /// error; check `exists()`.
+    pub async fn stat(&mut self, remote_path: &str) -> AdbResult<StatV2Response> {
+        self.stat_with(SyncId::Sta2, remote_path).await
+    }
+
+    /// Stat a remote path without following symlinks.
+    pub async fn lstat(&mut self, remote_path: &str) -> AdbResult<StatV2Response> {
+        self.stat_with(SyncId::Lst2, remote_path).await
+    }
+
+    async fn stat_with(&mut self, id: SyncId, remote_path: &str) -> AdbResult<StatV2Response> {
+        if !self.features.stat_v2 {
+            // Legacy STAT does an lstat, with 32-bit size and mtime
+            let req = sync::encode_stat_request(remote_path);
+            self.stream.write_all(&req).await?;
+            let header = self.read_header().await?;
+            if header.id == SyncId::Fail {
+                return Err(self.read_fail(header.length).await?);
+            }
+            if header.id != SyncId::Stat {
+                return Err(AdbError::Protocol(format!(
+                    "Expected STAT response, got {:?}",
+                    header.id
+                )));
+            }
+            let mut buf = [0u8; 12];
+            buf[0..4].copy_from_slice(&header.length.to_le_bytes());
+            self.stream.read_exact(&mut buf[4..]).await?;
+            return Ok(StatV2Response::from(&StatResponse::from_bytes(&buf)?));
+        }
+
+        let req = sync::encode_path_request(id, remote_path);
+        self.stream.write_all(&req).await?;
+        let mut buf = [0u8; 4 + STAT_V2_SIZE];
+        self.stream.read_exact(&mut buf[..4]).await?;
+        match SyncId::from_bytes(&buf[..4])? {
+            response if response == id => {}
+            SyncId::Fail => {
+                let mut len = [0u8; 4];
+                self.stream.read_exact(&mut len).await?;
+                return Err(self.read_fail(u32::from_le_bytes(len)).await?);
+            }
+            other => {
+                return Err(AdbError::Protocol(format!(
+                    "Expected {:?} response, got {:?}",
+                    id, other
+                )))
+            }
+        }
+        self.stream.read_exact(&mut buf[4..]).await?;
+        StatV2Response::from_bytes(&buf[4..])
+    }
+
+    /// List a remote directory, including `.` and `..`.
+    pub async fn list(&mut self, remote_path: &str) -> AdbResult<Vec<DentV2Entry>> {
+        let (request, entry_id, fixed_size) = if self.features.ls_v2 {
+            (SyncId::Lis2, SyncId::Dnt2, STAT_V2_SIZE + 4)
+        } else {
+            (SyncId::List, SyncId::Dent, 16)
+        };
+        let req = sync::encode_path_request(request, remote_path);
+        self.stream.write_all(&req).await?;
+
+        let mut entries = Vec::new();
+        loop {
+            let mut id = [0u8; 4];
+            self.stream.read_exact(&mut id).await?;
+            let id = SyncId::from_bytes(&id)?;
+            if id == SyncId::Fail {
+                let mut len = [0u8; 4];
+                self.stream.read_exact(&mut len).await?;
+                return Err(self.read_fail(u32::from_le_bytes(len)).await?);
+            }
+            if id != entry_id && id != SyncId::Done {
+                return Err(AdbError::Protocol(format!(
+                    "Unexpected sync ID in {:?} response: {:?}",
+                    request, id
+                )));
+            }
+
+            // DONE is the size of an entry with an empty name
+            let mut buf = vec![0u8; fixed_size];
+            self.stream.read_exact(&mut buf).await?;
+            if id == SyncId::Done {
+                break;
+            }
+            let namelen = u32::from_le_bytes(buf[fixed_size - 4..].try_into().unwrap()) as usize;
+            buf.resize(fixed_size + namelen, 0);
+            self.stream.read_exact(&mut buf[fixed_size..]).await?;
+            entries.push(if self.features.ls_v2 {
+                DentV2Entry::from_bytes(&buf)?
+            } else {
+                DentV2Entry::from(&DentEntry::from_bytes(&buf)?)
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Send one local file, streaming it in chunks.
+    pub async fn send_file(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        mode: u32,
+        mtime: i64,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<u64> {
+        let mut file = fs::File::open(local_path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                AdbError::FileNotFound(local_path.display().to_string())
+            } else {
+                AdbError::Io(e)
+            }
+        })?;
+        let size = file.metadata().await?.len();
+
+        let req = if self.features.sendrecv_v2 {
+            sync::encode_send_v2_request(remote_path, mode, 0)
+        } else {
+            sync::encode_send_request(remote_path, mode)
+        };
+        self.stream.write_all(&req).await?;
+
+        let mut buf = vec![0u8; SYNC_DATA_MAX as usize];
+        let mut transferred = 0u64;
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            self.stream
+                .write_all(&sync::encode_data_chunk(&buf[..n]))
+                .await?;
+            transferred += n as u64;
+            progress(&TransferProgress {
+                path: remote_path,
+                transferred,
+                size,
+            });
+        }
+
+        self.stream
+            .write_all(&sync::encode_done(mtime as u32))
+            .await?;
+        self.read_okay("push").await?;
+        Ok(transferred)
+    }
+
+    /// Receive one remote file of `size` bytes into a local file.
+    pub async fn recv_file(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        size: u64,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<u64> {
+        let req = if self.features.sendrecv_v2 {
+            sync::encode_recv_v2_request(remote_path, 0)
+        } else {
+            sync::encode_recv_request(remote_path)
+        };
+        self.stream.write_all(&req).await?;
+
+        let mut file = fs::File::create(local_path).await?;
+        let mut transferred = 0u64;
+        loop {
+            let header = self.read_header().await?;
+            match header.id {
+                SyncId::Data => {
+                    let mut chunk = vec![0u8; header.length as usize];
+                    self.stream.read_exact(&mut chunk).await?;
+                    file.write_all(&chunk).await?;
+                    transferred += chunk.len() as u64;
+                    progress(&TransferProgress {
+                        path: remote_path,
+                        transferred,
+                        size,
+                    });
+                }
+                SyncId::Done => break,
+                SyncId::Fail => {
+                    let err = self.read_fail(header.length).await?;
+                    drop(file);
+                    let _ = fs::remove_file(local_path).await;
+                    return Err(err);
+                }
+                other => {
+                    return Err(AdbError::Protocol(format!(
+                        "Expected DATA/DONE in pull, got {:?}",
+                        other
+                    )));
+                }
+            }
+        }
+        file.flush().await?;
+        Ok(transferred)
+    }
+
+    /// Leave sync mode.
+    pub async fn quit(mut self) -> AdbResult<()> {
+        self.stream.write_all(&sync::encode_quit()).await?;
+        Ok(())
+    }
+
+    /// Push a local file or directory tree. Into an existing remote
+    /// directory, it lands under its own name; otherwise it takes the name
+    /// of `remote_path`. Modes and modification times are kept. Empty
+    /// directories are not created, as the device only makes the parents
+    /// of files it receives.
+    pub async fn push(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        options: &TransferOptions,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<TransferStats> {
+        let metadata = fs::metadata(local_path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                AdbError::FileNotFound(local_path.display().to_string())
+            } else {
+                AdbError::Io(e)
+            }
+        })?;
+        let remote_stat = self.stat(remote_path).await?;
+        let target = match local_path.file_name() {
+            Some(name) if remote_stat.exists() && remote_stat.is_directory() => {
+                join_remote(remote_path, &name.to_string_lossy())
+            }
+            _ => remote_path.to_string(),
+        };
+
+        let mut stats = TransferStats::default();
+        if !metadata.is_dir() {
+            self.push_file(
+                local_path, &target, &metadata, options, &mut stats, progress,
+            )
+            .await?;
+            return Ok(stats);
+        }
+
+        let mut pending = vec![(local_path.to_path_buf(), target)];
+        while let Some((dir, remote_dir)) = pending.pop() {
+            let mut children = Vec::new();
+            let mut read_dir = fs::read_dir(&dir).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                children.push(entry);
+            }
+            children.sort_by_key(|e| e.file_name());
+
+            for entry in children {
+                let path = entry.path();
+                let remote = join_remote(&remote_dir, &entry.file_name().to_string_lossy());
+                // follow symlinks, like adb push does
+                let metadata = fs::metadata(&path).await?;
+                if metadata.is_dir() {
+                    pending.push((path, remote));
+                } else if metadata.is_file() {
+                    self.push_file(&path, &remote, &metadata, options, &mut stats, progress)
+                        .await?;
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn push_file(
+        &mut self,
+        local_path: &Path,
+        remote_path: &str,
+        metadata: &std::fs::Metadata,
+        options: &TransferOptions,
+        stats: &mut TransferStats,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<()> {
+        let mtime = local_mtime(metadata);
+        if options.sync {
+            let remote = self.stat(remote_path).await?;
+            if remote.exists() && remote.size == metadata.len() && remote.mtime == mtime {
+                stats.skipped += 1;
+                return Ok(());
+            }
+        }
+        let mode = local_mode(metadata);
+        stats.bytes += self
+            .send_file(local_path, remote_path, mode, mtime, progress)
+            .await?;
+        stats.files += 1;
+        Ok(())
+    }
+
+    /// Pull a remote file or directory tree. Into an existing local
+    /// directory, it lands under its own name; otherwise it takes the name
+    /// of `local_path`. Modes and modification times are kept. Symlinks to
+    /// files are pulled as files; symlinks to directories are skipped.
+    pub async fn pull(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        options: &TransferOptions,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<TransferStats> {
+        let stat = self.stat(remote_path).await?;
+        if !stat.exists() {
+            return Err(AdbError::FileNotFound(remote_path.to_string()));
+        }
+        let target = if local_path.is_dir() {
+            local_path.join(remote_basename(remote_path))
+        } else {
+            local_path.to_path_buf()
+        };
+
+        let mut stats = TransferStats::default();
+        if !stat.is_directory() {
+            self.pull_file(remote_path, &target, &stat, options, &mut stats, progress)
+                .await?;
+            return Ok(stats);
+        }
+
+        // Directory modes wait until the whole tree is in, since a read-only
+        // directory would refuse the entries still to come.
+        let mut dirs = Vec::new();
+        let mut pending = vec![(remote_path.to_string(), target, stat)];
+        while let Some((remote_dir, dir, dir_stat)) = pending.pop() {
+            fs::create_dir_all(&dir).await?;
+            make_writable(&dir).await?;
+            let mut entries = self.list(&remote_dir).await?;
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+            for entry in entries {
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+                let remote = join_remote(&remote_dir, &entry.name);
+                let path = dir.join(&entry.name);
+                if entry.stat.is_directory() {
+                    pending.push((remote, path, entry.stat));
+                    continue;
+                }
+                let stat = if entry.stat.is_symlink() {
+                    self.stat(&remote).await?
+                } else {
+                    entry.stat
+                };
+                if stat.is_file() {
+                    self.pull_file(&remote, &path, &stat, options, &mut stats, progress)
+                        .await?;
+                }
+            }
+            dirs.push((dir, dir_stat));
+        }
+        // every directory was visited before its subdirectories
+        for (dir, dir_stat) in dirs.iter().rev() {
+            set_mode(dir, dir_stat).await?;
+        }
+        Ok(stats)
+    }
+
+    async fn pull_file(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        stat: &StatV2Response,
+        options: &TransferOptions,
+        stats: &mut TransferStats,
+        progress: &mut dyn FnMut(&TransferProgress),
+    ) -> AdbResult<()> {
+        if options.sync {
+            if let Ok(local) = fs::metadata(local_path).await {
+                if local.is_file() && local.len() == stat.size && local_mtime(&local) == stat.mtime
+                {
+                    stats.skipped += 1;
+                    return Ok(());
+                }
+            }
+        }
+        make_writable(local_path).await?;
+        stats.bytes += self
+            .recv_file(remote_path, local_path, stat.size, progress)
+            .await?;
+        stats.files += 1;
+
+        let file = std::fs::File::options().write(true).open(local_path)?;
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(stat.mtime.max(0) as u64))?;
+        set_mode(local_path, stat).await
+    }
+}
+
+/// Give a pulled file or directory the permissions it had on the device.
+async fn set_mode(path: &Path, stat: &StatV2Response) -> AdbResult<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if stat.permissions() != 0 {
+            let permissions = std::fs::Permissions::from_mode(stat.permissions() & 0o777);
+            fs::set_permissions(path, permissions).await?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, stat);
+    Ok(())
+}
+
+/// Let the owner write to `path` again if an earlier pull left it read-only.
+/// A missing `path` is fine.
+async fn make_writable(path: &Path) -> AdbResult<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(path).await {
+            let mode = metadata.permissions().mode();
+            if mode & 0o200 == 0 {
+                let permissions = std::fs::Permissions::from_mode(mode | 0o200);
+                fs::set_permissions(path, permissions).await?;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::DuplexStream;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) struct MockFile {
+        pub(crate) mode: u32,
+        pub(crate) mtime: i64,
+        pub(crate) data: Vec<u8>,
+    }
+
+    /// Remote files by path. Directories are implied by the files in them,
+    /// plus any listed in `dirs`.
+    #[derive(Default)]
+    pub(crate) struct MockDevice {
+        pub(crate) files: BTreeMap<String, MockFile>,
+        pub(crate) dirs: Vec<String>,
+        /// Modes of directories that are not 0o755.
+        pub(crate) dir_modes: BTreeMap<String, u32>,
+        /// Requests received, by ID.
+        pub(crate) requests: Vec<SyncId>,
+    }
+
+    impl MockDevice {
+        fn stat(&self, path: &str) -> StatV2Response {
+            let path = path.trim_end_matches('/');
+            if let Some(file) = self.files.get(path) {
+                return StatV2Response {
+                    mode: file.mode,
+                    size: file.data.len() as u64,
+                    mtime: file.mtime,
+                    ..StatV2Response::default()
+                };
+            }
+            let prefix = format!("{}/", path);
+            let is_dir = path.is_empty()
+                || self.dirs.iter().any(|d| d == path)
+                || self.files.keys().any(|f| f.starts_with(&prefix));
+            if is_dir {
+                StatV2Response {
+                    mode: 0o040000 | self.dir_modes.get(path).copied().unwrap_or(0o755),
+                    size: 4096,
+                    ..StatV2Response::default()
+                }
+            } else {
+                StatV2Response {
+                    error: 2,
+                    ..StatV2Response::default()
+                }
+            }
+        }
+
+        fn list(&self, path: &str) -> Vec<(String, StatV2Response)> {
+            let prefix = format!("{}/", path.trim_end_matches('/'));
+            let mut names: Vec<String> = self
+                .files
+                .keys()
+                .chain(self.dirs.iter())
+                .filter_map(|p| p.strip_prefix(&prefix))
+                .map(|rest| rest.split('/').next().unwrap().to_string())
+                .collect();
+            names.dedup();
+            let mut entries = vec![(".".to_string(), self.stat(path))];
+            for name in names {
+                let stat = self.stat(&format!("{}{}", prefix, name));
+                entries.push((name, stat));
+            }
+            entries
+        }
+    }
+
+    async fn read_path(stream: &mut DuplexStream, len: u32) -> String {
+        let mut path = vec![0u8; len as usize];
+        stream.read_exact(&mut path).await.unwrap();
+        String::from_utf8(path).unwrap()
+    }
+
+    async fn read_header(stream: &mut DuplexStream) -> Option<SyncHeader> {
+        let mut buf = [0u8; 8];
+        stream.read_exact(&mut buf).await.ok()?;
+        Some(SyncHeader::from_bytes(&buf).unwrap())
+    }
+
+    /// Receive DATA chunks until DONE, returning the data and the mtime.
+    async fn read_file(stream: &mut DuplexStream) -> (Vec<u8>, i64) {
+        let mut data = Vec::new();
+        loop {
+            let header = read_header(stream).await.unwrap();
+            match header.id {
+                SyncId::Data => {
+                    let mut chunk = vec![0u8; header.length as usize];
+                    stream.read_exact(&mut chunk).await.unwrap();
+                    data.extend(chunk);
+                }
+                SyncId::Done => return (data, header.length as i64),
+                other => panic!("Unexpected {:?} in send", other),
+            }
+        }
+    }
+
+    /// Serve sync requests against `device` until QUIT.
+    pub(crate) async fn serve(mut stream: DuplexStream, device: Arc<Mutex<MockDevice>>) {
+        while let Some(header) = read_header(&mut stream).await {
+            device.lock().unwrap().requests.push(header.id);
+            let mut resp = Vec::new();
+            match header.id {
+                SyncId::Stat => {
+                    let path = read_path(&mut stream, header.length).await;
+                    let stat = device.lock().unwrap().stat(&path);
+                    resp.extend_from_slice(b"STAT");
+                    let stat = if stat.error == 0 {
+                        stat
+                    } else {
+                        StatV2Response::default()
+                    };
+                    resp.extend_from_slice(&stat.mode.to_le_bytes());
+                    resp.extend_from_slice(&(stat.size as u32).to_le_bytes());
+                    resp.extend_from_slice(&(stat.mtime as u32).to_le_bytes());
+                }
+                SyncId::Sta2 | SyncId::Lst2 => {
+                    let path = read_path(&mut stream, header.length).await;
+                    resp.extend_from_slice(header.id.as_bytes());
+                    resp.extend_from_slice(&device.lock().unwrap().stat(&path).to_bytes());
+                }
+                SyncId::List | SyncId::Lis2 => {
+                    let path = read_path(&mut stream, header.length).await;
+                    let v2 = header.id == SyncId::Lis2;
+                    for (name, stat) in device.lock().unwrap().list(&path) {
+                        if v2 {
+                            resp.extend_from_slice(b"DNT2");
+                            resp.extend_from_slice(&stat.to_bytes());
+                        } else {
+                            resp.extend_from_slice(b"DENT");
+                            resp.extend_from_slice(&stat.mode.to_le_bytes());
+                            resp.extend_from_slice(&(stat.size as u32).to_le_bytes());
+                            resp.extend_from_slice(&(stat.mtime as u32).to_le_bytes());
+                        }
+                        resp.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                        resp.extend_from_slice(name.as_bytes());
+                    }
+                    resp.extend_from_slice(b"DONE");
+                    resp.extend(vec![0u8; if v2 { STAT_V2_SIZE + 4 } else { 16 }]);
+                }
+                SyncId::Send | SyncId::Snd2 => {
+                    let payload = read_path(&mut stream, header.length).await;
+                    let (path, mode) = if header.id == SyncId::Snd2 {
+                        let setup = read_header(&mut stream).await.unwrap();
+                        assert_eq!(setup.id, SyncId::Snd2);
+                        let mut flags = [0u8; 4];
+                        stream.read_exact(&mut flags).await.unwrap();
+                        (payload, setup.length)
+                    } else {
+                        let (path, mode) = payload.rsplit_once(',').unwrap();
+                        (path.to_string(), mode.parse().unwrap())
+                    };
+                    let (data, mtime) = read_file(&mut stream).await;
+                    device
+                        .lock()
+                        .unwrap()
+                        .files
+                        .insert(path, MockFile { mode, mtime, data });
+                    resp.extend_from_slice(b"OKAY\0\0\0\0");
+                }
+                SyncId::Recv | SyncId::Rcv2 => {
+                    let path = read_path(&mut stream, header.length).await;
+                    if header.id == SyncId::Rcv2 {
+                        let mut setup = [0u8; 8];
+                        stream.read_exact(&mut setup).await.unwrap();
+                        assert_eq!(&setup[..4], b"RCV2");
+                    }
+                    match device.lock().unwrap().files.get(&path) {
+                        Some(file) => {
+                            // small chunks, to see progress move
+                            for chunk in file.data.chunks(4) {
+                                resp.extend(sync::encode_data_chunk(chunk));
+                            }
+                            resp.extend_from_slice(b"DONE\0\0\0\0");
+                        }
+                        None => {
+                            let msg = b"No such file or directory";
+                            resp.extend_from_slice(b"FAIL");
+                            resp.extend_from_slice(&(msg.len() as u32).to_le_bytes());
+                            resp.extend_from_slice(msg);
+                        }
+                    }
+                }
+                SyncId::Quit => return,
+                other => panic!("Unexpected request {:?}", other),
+            }
+            stream.write_all(&resp).await.unwrap();
+        }
+    }
+
+    fn connect(
+        device: &Arc<Mutex<MockDevice>>,
+        features: SyncFeatures,
+    ) -> SyncConnection<DuplexStream> {
+        let (client, server) = tokio::io::duplex(1 << 20);
+        tokio::spawn(serve(server, device.clone()));
+        SyncConnection::new(client, features)
+    }
+
+    const V2: SyncFeatures = SyncFeatures {
+        stat_v2: true,
+        ls_v2: true,
+        sendrecv_v2: true,
+    };
+
+    fn mock_file(data: &[u8]) -> MockFile {
+        MockFile {
+            mode: 0o100640,
+            mtime: 1_700_000_000,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_sync_features() {
+        let features = SyncFeatures::from_features(&["shell_v2", "cmd", "stat_v2", "ls_v2"]);
+        assert!(features.stat_v2 && features.ls_v2);
+        assert!(!features.sendrecv_v2);
+        assert_eq!(
+            SyncFeatures::from_features::<&str>(&[]),
+            SyncFeatures::default()
+        );
+    }
+
+    #[test]
+    fn test_remote_paths() {
+        assert_eq!(join_remote("/sdcard", "a.txt"), "/sdcard/a.txt");
+        assert_eq!(join_remote("/sdcard/", "a.txt"), "/sdcard/a.txt");
+        assert_eq!(remote_basename("/sdcard/DCIM/"), "DCIM");
+        assert_eq!(remote_basename("photo.jpg"), "photo.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_tree_round_trip() {
+        for features in [V2, SyncFeatures::default()] {
+            let local = tempfile::tempdir().unwrap();
+            let src = local.path().join("project");
+            std::fs::create_dir_all(src.join("sub/deeper")).unwrap();
+            std::fs::write(src.join("a.txt"), b"alpha").unwrap();
+            std::fs::write(src.join("sub/b.bin"), vec![7u8; 100]).unwrap();
+            std::fs::write(src.join("sub/deeper/c.txt"), b"gamma").unwrap();
+
+            let device = Arc::new(Mutex::new(MockDevice::default()));
+            device.lock().unwrap().dirs.push("/sdcard".into());
+            let mut conn = connect(&device, features);
+
+            let mut reports = 0;
+            let stats = conn
+                .push(&src, "/sdcard", &TransferOptions::default(), &mut |_| {
+                    reports += 1
+                })
+                .await
+                .unwrap();
+            assert_eq!(stats.files, 3);
+            assert_eq!(stats.bytes, 110);
+            assert_eq!(reports, 3);
+            {
+                let device = device.lock().unwrap();
+                let b = &device.files["/sdcard/project/sub/b.bin"];
+                assert_eq!(b.data, vec![7u8; 100]);
+                assert_eq!(b.mode & 0o170000, 0o100000);
+                let mtime = local_mtime(&std::fs::metadata(src.join("sub/b.bin")).unwrap());
+                assert_eq!(b.mtime, mtime);
+                let expected = if features.sendrecv_v2 {
+                    SyncId::Snd2
+                } else {
+                    SyncId::Send
+                };
+                assert!(device.requests.contains(&expected));
+            }
+
+            let dest = local.path().join("copy");
+            let stats = conn
+                .pull(
+                    "/sdcard/project",
+                    &dest,
+                    &TransferOptions::default(),
+                    &mut |_| {},
+                )
+                .await
+                .unwrap();
+            assert_eq!(stats.files, 3);
+            assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"alpha");
+            assert_eq!(
+                std::fs::read(dest.join("sub/deeper/c.txt")).unwrap(),
+                b"gamma"
+            );
+            let pulled = std::fs::metadata(dest.join("sub/b.bin")).unwrap();
+            assert_eq!(
+                local_mtime(&pulled),
+                local_mtime(&std::fs::metadata(src.join("sub/b.bin")).unwrap())
+            );
+            conn.quit().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pull_keeps_mode_and_mtime_and_reports_progress() {
+        let device = Arc::new(Mutex::new(MockDevice::default()));
+        device
+            .lock()
+            .unwrap()
+            .files
+            .insert("/data/local/tmp/log.txt".into(), mock_file(b"0123456789"));
+        let mut conn = connect(&device, V2);
+
+        let local = tempfile::tempdir().unwrap();
+        let mut seen = Vec::new();
+        let stats = conn
+            .pull(
+                "/data/local/tmp/log.txt",
+                local.path(),
+                &TransferOptions::default(),
+                &mut |p| seen.push((p.path.to_string(), p.transferred, p.size)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(stats.bytes, 10);
+        let path = "/data/local/tmp/log.txt".to_string();
+        assert_eq!(
+            seen,
+            vec![(path.clone(), 4, 10), (path.clone(), 8, 10), (path, 10, 10)]
+        );
+
+        let metadata = std::fs::metadata(local.path().join("log.txt")).unwrap();
+        assert_eq!(local_mtime(&metadata), 1_700_000_000);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        }
+
+        // A read-only directory gets its mode only after what is inside it,
+        // and pulling again writes through read-only files.
+        {
+            let mut device = device.lock().unwrap();
+            device.dir_modes.insert("/sdcard/ro".into(), 0o555);
+            let file = MockFile {
+                mode: 0o100444,
+                ..mock_file(b"old")
+            };
+            device.files.insert("/sdcard/ro/sub/a.txt".into(), file);
+        }
+        let options = TransferOptions { sync: true };
+        let stats = conn
+            .pull("/sdcard/ro", local.path(), &options, &mut |_| {})
+            .await
+            .unwrap();
+        assert_eq!(stats.files, 1);
+        device
+            .lock()
+            .unwrap()
+            .files
+            .get_mut("/sdcard/ro/sub/a.txt")
+            .unwrap()
+            .data = b"new contents".to_vec();
+        let stats = conn
+            .pull("/sdcard/ro", local.path(), &options, &mut |_| {})
+            .await
+            .unwrap();
+        assert_eq!((stats.files, stats.skipped), (1, 0));
+        let pulled = local.path().join("ro/sub/a.txt");
+        assert_eq!(std::fs::read(&pulled).unwrap(), b"new contents");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&local.path().join("ro")), 0o555);
+            assert_eq!(mode(&local.path().join("ro/sub")), 0o755);
+            assert_eq!(mode(&pulled), 0o444);
+            // so the temporary directory can be cleaned up
+            std::fs::set_permissions(
+                local.path().join("ro"),
+                std::fs::Permissions::from_mode(0o755),
+            )
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_skips_unchanged_files() {
+        let device = Arc::new(Mutex::new(MockDevice::default()));
+        {
+            let mut device = device.lock().unwrap();
+            device
+                .files
+                .insert("/sdcard/dir/same.txt".into(), mock_file(b"same"));
+            device
+                .files
+                .insert("/sdcard/dir/changed.txt".into(), mock_file(b"old"));
+        }
+        let mut conn = connect(&device, V2);
+        let local = tempfile::tempdir().unwrap();
+        let options = TransferOptions { sync: true };
+
+        // first time everything comes over, second time nothing does
+        let stats = conn
+            .pull("/sdcard/dir", local.path(), &options, &mut |_| {})
+            .await
+            .unwrap();
+        assert_eq!((stats.files, stats.skipped), (2, 0));
+        let stats = conn
+            .pull("/sdcard/dir", local.path(), &options, &mut |_| {})
+            .await
+            .unwrap();
+        assert_eq!((stats.files, stats.skipped), (0, 2));
+
+        // a local edit is pushed back, the rest is skipped
+        let changed = local.path().join("dir/changed.txt");
+        std::fs::write(&changed, b"new contents").unwrap();
+        let stats = conn
+            .push(&local.path().join("dir"), "/sdcard", &options, &mut |_| {})
+            .await
+            .unwrap();
+        assert_eq!((stats.files, stats.skipped), (1, 1));
+        assert_eq!(
+            device.lock().unwrap().files["/sdcard/dir/changed.txt"].data,
+            b"new contents"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stat_v2_large_file_and_missing() {
+        let device = Arc::new(Mutex::new(MockDevice::default()));
+        let mut conn = connect(&device, V2);
+        assert!(!conn.stat("/missing").await.unwrap().exists());
+        let err = conn
+            .pull(
+                "/missing",
+                Path::new("/tmp"),
+                &TransferOptions::default(),
+                &mut |_| {},
+            )
+            .await;
+        assert!(matches!(err, Err(AdbError::FileNotFound(_))));
+
+        // A 5 GB size survives STA2, and would not survive STAT
+        let stat = StatV2Response {
+            mode: 0o100644,
+            size: 5 << 30,
+            ..StatV2Response::default()
+        };
+        let (client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let header = read_header(&mut server).await.unwrap();
+            assert_eq!(header.id, SyncId::Sta2);
+            read_path(&mut server, header.length).await;
+            let mut resp = b"STA2".to_vec();
+            resp.extend_from_slice(&stat.to_bytes());
+            server.write_all(&resp).await.unwrap();
+        });
+        let mut conn = SyncConnection::new(client, V2);
+        assert_eq!(conn.stat("/sdcard/big.mkv").await.unwrap().size, 5 << 30);
+    }
+
+    #[tokio::test]
+    async fn test_recv_fail_removes_partial_file() {
+        let device = Arc::new(Mutex::new(MockDevice::default()));
+        let mut conn = connect(&device, SyncFeatures::default());
+        let local = tempfile::tempdir().unwrap();
+        let path = local.path().join("gone.txt");
+        let result = conn.recv_file("/gone.txt", &path, 0, &mut |_| {}).await;
+        match result {
+            Err(AdbError::SyncError(msg)) => assert_eq!(msg, "No such file or directory"),
+            other => panic!("Expected SyncError, got {:?}", other),
+        }
+        assert!(!path.exists());
+    }
+}
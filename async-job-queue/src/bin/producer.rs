@@ -1,6 +1,7 @@
 use async_job_queue::{Job, Priority, Storage};
 use clap::{Parser, Subcommand};
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Parser)]
@@ -25,11 +26,20 @@ enum Commands {
 
         #[arg(short, long, default_value = "3")]
         max_retries: u32,
+
+        /// Fail the job if it runs longer than this many seconds
+        #[arg(short, long)]
+        timeout: Option<u64>,
     },
     Status {
         #[arg(short, long)]
         job_id: String,
     },
+    /// Cancel a pending or running job
+    Cancel {
+        #[arg(short, long)]
+        job_id: String,
+    },
     Stats,
 }
 
@@ -55,9 +65,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             payload,
             priority,
             max_retries,
+            timeout,
         } => {
             let priority = parse_priority(&priority);
-            let job = Job::new(payload.into_bytes(), priority, max_retries);
+            let mut job = Job::new(payload.into_bytes(), priority, max_retries);
+            if let Some(timeout) = timeout {
+                job = job.with_timeout(Duration::from_secs(timeout));
+            }
 
             println!("Submitting job {} with priority {}", job.id, priority);
             storage.insert(&job)?;
@@ -72,6 +86,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("Status: {}", job.status);
                     println!("Priority: {}", job.priority);
                     println!("Retries: {}/{}", job.retry_count, job.max_retries);
+                    if let Some(timeout) = job.timeout {
+                        println!("Timeout: {:?}", timeout);
+                    }
                     println!("Created: {}", job.created_at);
                     println!("Updated: {}", job.updated_at);
                     if let Some(error) = &job.error_message {
@@ -84,6 +101,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
+        Commands::Cancel { job_id } => {
+            let uuid = Uuid::parse_str(&job_id)?;
+            if storage.cancel(uuid)? {
+                println!("Cancelling job {}", uuid);
+            } else {
+                println!("Job {} has already finished", uuid);
+            }
+        }
         Commands::Stats => {
             use async_job_queue::JobStatus;
 
@@ -105,6 +130,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "  Dead Letter: {}",
                 storage.count_by_status(JobStatus::DeadLetter)?
             );
+            println!(
+                "  Cancelled: {}",
+                storage.count_by_status(JobStatus::Cancelled)?
+            );
         }
     }
 
@@ -1,6 +1,7 @@
-use async_job_queue::{JobHandler, Storage, WorkerPool};
+use async_job_queue::{async_trait, JobHandler, Storage, WorkerPool};
 use clap::Parser;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
 
 #[derive(Parser)]
@@ -12,17 +13,26 @@ struct Cli {
 
     #[arg(short, long, default_value = "4")]
     workers: usize,
+
+    /// Fail jobs that run longer than this many seconds (unless they set their own timeout)
+    #[arg(short, long)]
+    timeout: Option<u64>,
+
+    /// Seconds a job stays claimed without a heartbeat before other workers reclaim it
+    #[arg(short, long, default_value = "30", value_parser = clap::value_parser!(u64).range(1..))]
+    lease: u64,
 }
 
 struct EchoHandler;
 
+#[async_trait]
 impl JobHandler for EchoHandler {
-    fn handle(&self, payload: &[u8]) -> Result<(), String> {
+    async fn handle(&self, payload: &[u8]) -> Result<(), String> {
         let message = String::from_utf8_lossy(payload);
         info!("Processing job with payload: {}", message);
 
         // Simulate work
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        tokio::time::sleep(Duration::from_secs(2)).await;
 
         // Simulate occasional failures for testing retry logic
         if message.contains("fail") {
@@ -50,10 +60,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let storage = Arc::new(Storage::new(&cli.database)?);
     let handler = Arc::new(EchoHandler);
 
-    let pool = WorkerPool::new(storage, handler, cli.workers);
+    let mut pool = WorkerPool::new(storage, handler, cli.workers)
+        .with_lease_duration(Duration::from_secs(cli.lease));
+    if let Some(timeout) = cli.timeout {
+        pool = pool.with_job_timeout(Duration::from_secs(timeout));
+    }
 
     info!("Worker pool initialized with {} workers", cli.workers);
 
+    // Finish the jobs in flight on Ctrl-C, but don't start new ones
+    let shutdown = pool.shutdown_token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Shutting down, waiting for running jobs to finish");
+            shutdown.cancel();
+        }
+    });
+
     pool.run().await?;
 
     Ok(())
@@ -1,6 +1,8 @@
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -29,6 +31,7 @@ pub enum JobStatus {
     Completed,
     Failed,
     DeadLetter,
+    Cancelled,
 }
 
 impl fmt::Display for JobStatus {
@@ -39,6 +42,7 @@ impl fmt::Display for JobStatus {
             JobStatus::Completed => write!(f, "completed"),
             JobStatus::Failed => write!(f, "failed"),
             JobStatus::DeadLetter => write!(f, "dead_letter"),
+            JobStatus::Cancelled => write!(f, "cancelled"),
         }
     }
 }
@@ -54,6 +58,9 @@ pub struct Job {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub error_message: Option<String>,
+    /// How long the handler may run before the attempt counts as failed.
+    /// Falls back to the worker pool's job timeout when `None`.
+    pub timeout: Option<Duration>,
 }
 
 impl Job {
@@ -69,9 +76,15 @@ impl Job {
             created_at: now,
             updated_at: now,
             error_message: None,
+            timeout: None,
         }
     }
 
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn can_retry(&self) -> bool {
         self.retry_count < self.max_retries
     }
@@ -97,10 +110,20 @@ impl Job {
         self.status = JobStatus::Running;
         self.updated_at = Utc::now();
     }
+
+    pub fn mark_cancelled(&mut self) {
+        self.status = JobStatus::Cancelled;
+        self.updated_at = Utc::now();
+    }
 }
 
+/// Processes job payloads.
+///
+/// When a job times out or is cancelled, its `handle` future is dropped at
+/// its next `.await`, so handlers should await rather than block.
+#[async_trait]
 pub trait JobHandler: Send + Sync {
-    fn handle(&self, payload: &[u8]) -> Result<(), String>;
+    async fn handle(&self, payload: &[u8]) -> Result<(), String>;
 }
 
 #[cfg(test)]
@@ -129,6 +152,7 @@ mod tests {
         assert_eq!(JobStatus::Completed.to_string(), "completed");
         assert_eq!(JobStatus::Failed.to_string(), "failed");
         assert_eq!(JobStatus::DeadLetter.to_string(), "dead_letter");
+        assert_eq!(JobStatus::Cancelled.to_string(), "cancelled");
     }
 
     #[test]
@@ -142,6 +166,7 @@ mod tests {
         assert_eq!(job.retry_count, 0);
         assert_eq!(job.max_retries, 3);
         assert_eq!(job.error_message, None);
+        assert_eq!(job.timeout, None);
         assert!(job.created_at <= Utc::now());
         assert!(job.updated_at <= Utc::now());
     }
@@ -240,6 +265,27 @@ mod tests {
         assert!(job.updated_at > initial_updated_at);
     }
 
+    #[test]
+    fn test_mark_cancelled() {
+        let mut job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        job.mark_running();
+        let initial_updated_at = job.updated_at;
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        job.mark_cancelled();
+
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert_eq!(job.retry_count, 0);
+        assert!(job.updated_at > initial_updated_at);
+    }
+
+    #[test]
+    fn test_with_timeout() {
+        let job =
+            Job::new(b"test".to_vec(), Priority::Normal, 3).with_timeout(Duration::from_secs(30));
+        assert_eq!(job.timeout, Some(Duration::from_secs(30)));
+    }
+
     #[test]
     fn test_job_state_transitions() {
         let mut job = Job::new(b"test".to_vec(), Priority::Normal, 2);
@@ -300,7 +346,8 @@ mod tests {
 
     #[test]
     fn test_job_serialization() {
-        let job = Job::new(b"test payload".to_vec(), Priority::Critical, 5);
+        let job = Job::new(b"test payload".to_vec(), Priority::Critical, 5)
+            .with_timeout(Duration::from_millis(1500));
 
         // Test that Job can be serialized and deserialized
         let serialized = bincode::serialize(&job).expect("Failed to serialize");
@@ -312,13 +359,15 @@ mod tests {
         assert_eq!(job.status, deserialized.status);
         assert_eq!(job.retry_count, deserialized.retry_count);
         assert_eq!(job.max_retries, deserialized.max_retries);
+        assert_eq!(job.timeout, deserialized.timeout);
     }
 
     // Test that JobHandler trait is object-safe and works with Arc
     struct TestHandler;
 
+    #[async_trait]
     impl JobHandler for TestHandler {
-        fn handle(&self, payload: &[u8]) -> Result<(), String> {
+        async fn handle(&self, payload: &[u8]) -> Result<(), String> {
             if payload.is_empty() {
                 Err("Empty payload".to_string())
             } else {
@@ -327,12 +376,12 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_job_handler_trait() {
+    #[tokio::test]
+    async fn test_job_handler_trait() {
         let handler = TestHandler;
 
-        assert!(handler.handle(b"test").is_ok());
-        assert!(handler.handle(b"").is_err());
+        assert!(handler.handle(b"test").await.is_ok());
+        assert!(handler.handle(b"").await.is_err());
     }
 
     #[test]
@@ -341,13 +390,13 @@ mod tests {
         assert_send_sync::<TestHandler>();
     }
 
-    #[test]
-    fn test_job_handler_with_arc() {
+    #[tokio::test]
+    async fn test_job_handler_with_arc() {
         use std::sync::Arc;
 
         let handler: Arc<dyn JobHandler> = Arc::new(TestHandler);
 
-        assert!(handler.handle(b"valid").is_ok());
-        assert!(handler.handle(b"").is_err());
+        assert!(handler.handle(b"valid").await.is_ok());
+        assert!(handler.handle(b"").await.is_err());
     }
 }
@@ -2,6 +2,7 @@ mod job;
 mod storage;
 mod worker;
 
+pub use async_trait::async_trait;
 pub use job::{Job, JobHandler, JobStatus, Priority};
-pub use storage::{Storage, StorageError};
+pub use storage::{LeaseStatus, Storage, StorageError, DEFAULT_LEASE};
 pub use worker::WorkerPool;
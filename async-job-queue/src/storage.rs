@@ -2,6 +2,7 @@ use crate::job::{Job, JobStatus, Priority};
 use chrono::{DateTime, Utc};
 use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -17,10 +18,46 @@ pub enum StorageError {
     MutexPoisoned,
 }
 
+/// How long a claimed job belongs to its worker without a heartbeat.
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(30);
+
+/// What a heartbeat found out about a running job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaseStatus {
+    /// The lease was extended; keep going.
+    Held,
+    /// Someone asked for the job to be cancelled.
+    CancelRequested,
+    /// The job is no longer ours: its lease expired and it was reclaimed.
+    Lost,
+}
+
 pub struct Storage {
     conn: Arc<Mutex<Connection>>,
 }
 
+fn millis_from_now(duration: Duration) -> i64 {
+    Utc::now().timestamp_millis() + duration.as_millis() as i64
+}
+
+/// Add a column that databases created by older versions lack.
+fn add_column_if_missing(
+    conn: &Connection,
+    column: &str,
+    definition: &str,
+) -> Result<(), StorageError> {
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info('jobs') WHERE name = ?1")?
+        .exists(params![column])?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE jobs ADD COLUMN {} {}", column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
 impl Storage {
     pub fn new(db_path: &str) -> Result<Self, StorageError> {
         let conn = Connection::open(db_path)?;
@@ -35,10 +72,16 @@ impl Storage {
                 max_retries INTEGER NOT NULL,
                 created_at TEXT NOT NULL,
                 updated_at TEXT NOT NULL,
-                error_message TEXT
+                error_message TEXT,
+                timeout_ms INTEGER,
+                lease_expires_at INTEGER,
+                cancel_requested INTEGER NOT NULL DEFAULT 0
             )",
             [],
         )?;
+        add_column_if_missing(&conn, "timeout_ms", "INTEGER")?;
+        add_column_if_missing(&conn, "lease_expires_at", "INTEGER")?;
+        add_column_if_missing(&conn, "cancel_requested", "INTEGER NOT NULL DEFAULT 0")?;
 
         conn.execute(
             "CREATE INDEX IF NOT EXISTS idx_status_priority
@@ -55,8 +98,8 @@ impl Storage {
         let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
         conn.execute(
             "INSERT INTO jobs (id, payload, priority, status, retry_count, max_retries,
-                               created_at, updated_at, error_message)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                               created_at, updated_at, error_message, timeout_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
             params![
                 job.id.to_string(),
                 job.payload,
@@ -67,17 +110,40 @@ impl Storage {
                 job.created_at.to_rfc3339(),
                 job.updated_at.to_rfc3339(),
                 job.error_message,
+                job.timeout.map(|t| t.as_millis() as i64),
             ],
         )?;
         Ok(())
     }
 
+    /// Write back a job. A job that is no longer running also gives up its
+    /// lease and any pending cancellation request.
     pub fn update(&self, job: &Job) -> Result<(), StorageError> {
+        if self.write(job, None)? == 0 {
+            return Err(StorageError::NotFound(job.id));
+        }
+        Ok(())
+    }
+
+    /// Write back the outcome of the attempt that claimed `job` with
+    /// `retry_count`, like `update`. Returns `false`, writing nothing, if
+    /// that attempt no longer holds the job because it was reclaimed, as
+    /// the job may be another worker's by now.
+    pub fn finish(&self, job: &Job, retry_count: u32) -> Result<bool, StorageError> {
+        Ok(self.write(job, Some(retry_count))? == 1)
+    }
+
+    /// Write back a job, only if it is still running attempt `retry_count`
+    /// when that is given. Returns how many rows changed.
+    fn write(&self, job: &Job, retry_count: Option<u32>) -> Result<usize, StorageError> {
         let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
         let rows_affected = conn.execute(
             "UPDATE jobs SET payload = ?2, priority = ?3, status = ?4, retry_count = ?5,
-                            max_retries = ?6, updated_at = ?7, error_message = ?8
-             WHERE id = ?1",
+                            max_retries = ?6, updated_at = ?7, error_message = ?8,
+                            timeout_ms = ?9,
+                            lease_expires_at = CASE WHEN ?4 = ?10 THEN lease_expires_at END,
+                            cancel_requested = CASE WHEN ?4 = ?10 THEN cancel_requested ELSE 0 END
+             WHERE id = ?1 AND (?11 IS NULL OR (status = ?10 AND retry_count = ?11))",
             params![
                 job.id.to_string(),
                 job.payload,
@@ -87,24 +153,30 @@ impl Storage {
                 job.max_retries,
                 job.updated_at.to_rfc3339(),
                 job.error_message,
+                job.timeout.map(|t| t.as_millis() as i64),
+                JobStatus::Running as i32,
+                retry_count,
             ],
         )?;
-
-        if rows_affected == 0 {
-            return Err(StorageError::NotFound(job.id));
-        }
-
-        Ok(())
+        Ok(rows_affected)
     }
 
+    /// Claim the next pending job with the default lease.
     pub fn get_next_pending(&self) -> Result<Option<Job>, StorageError> {
+        self.claim_next(DEFAULT_LEASE)
+    }
+
+    /// Claim the next pending job, leasing it for `lease`. Unless the lease
+    /// is kept alive with `heartbeat`, the job is put back by
+    /// `reclaim_expired` once it runs out.
+    pub fn claim_next(&self, lease: Duration) -> Result<Option<Job>, StorageError> {
         let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
 
         // Atomically claim the job by updating it to Running status
         // SQLite's RETURNING clause allows us to get the updated row
         let mut stmt = conn.prepare(
             "UPDATE jobs
-             SET status = ?1, updated_at = ?2
+             SET status = ?1, updated_at = ?2, lease_expires_at = ?4
              WHERE id = (
                  SELECT id FROM jobs
                  WHERE status = ?3
@@ -112,13 +184,18 @@ impl Storage {
                  LIMIT 1
              )
              RETURNING id, payload, priority, status, retry_count, max_retries,
-                       created_at, updated_at, error_message",
+                       created_at, updated_at, error_message, timeout_ms",
         )?;
 
         let now = Utc::now().to_rfc3339();
         let job = stmt
             .query_row(
-                params![JobStatus::Running as i32, now, JobStatus::Pending as i32],
+                params![
+                    JobStatus::Running as i32,
+                    now,
+                    JobStatus::Pending as i32,
+                    millis_from_now(lease)
+                ],
                 |row| self.row_to_job(row),
             )
             .optional()?;
@@ -126,11 +203,112 @@ impl Storage {
         Ok(job)
     }
 
+    /// Extend the lease on a running job by `lease` from now.
+    ///
+    /// The job's retry count tells attempts apart: reclaiming a job either
+    /// bumps it or finishes the job, so a worker whose lease ran out finds
+    /// the job `Lost` even when another worker has claimed it since.
+    pub fn heartbeat(&self, job: &Job, lease: Duration) -> Result<LeaseStatus, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
+        let cancel_requested: Option<bool> = conn
+            .query_row(
+                "UPDATE jobs SET lease_expires_at = ?4
+                 WHERE id = ?1 AND status = ?2 AND retry_count = ?3
+                 RETURNING cancel_requested",
+                params![
+                    job.id.to_string(),
+                    JobStatus::Running as i32,
+                    job.retry_count,
+                    millis_from_now(lease)
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(match cancel_requested {
+            None => LeaseStatus::Lost,
+            Some(true) => LeaseStatus::CancelRequested,
+            Some(false) => LeaseStatus::Held,
+        })
+    }
+
+    /// Put back running jobs whose lease has expired, because their worker
+    /// crashed or hung. This counts as a failed attempt: the job goes back
+    /// to pending if it has retries left and to the dead letter queue if
+    /// not. Jobs whose cancellation was requested are cancelled instead.
+    /// Returns how many jobs were reclaimed.
+    pub fn reclaim_expired(&self) -> Result<usize, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
+        // Every CASE sees the row as it was before the update
+        let reclaimed = conn.execute(
+            "UPDATE jobs
+             SET status = CASE
+                     WHEN cancel_requested THEN ?4
+                     WHEN retry_count < max_retries THEN ?2
+                     ELSE ?3
+                 END,
+                 retry_count = CASE
+                     WHEN NOT cancel_requested AND retry_count < max_retries THEN retry_count + 1
+                     ELSE retry_count
+                 END,
+                 error_message = CASE
+                     WHEN cancel_requested THEN error_message
+                     ELSE 'Lease expired: worker stopped responding'
+                 END,
+                 lease_expires_at = NULL,
+                 cancel_requested = 0,
+                 updated_at = ?5
+             WHERE status = ?1 AND (lease_expires_at IS NULL OR lease_expires_at < ?6)",
+            params![
+                JobStatus::Running as i32,
+                JobStatus::Pending as i32,
+                JobStatus::DeadLetter as i32,
+                JobStatus::Cancelled as i32,
+                Utc::now().to_rfc3339(),
+                Utc::now().timestamp_millis(),
+            ],
+        )?;
+        Ok(reclaimed)
+    }
+
+    /// Cancel a job. A pending job is cancelled right away; a running one
+    /// is flagged, and its worker stops it at its next heartbeat. Returns
+    /// `false` if the job had already finished.
+    pub fn cancel(&self, id: Uuid) -> Result<bool, StorageError> {
+        let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
+        let rows_affected = conn.execute(
+            "UPDATE jobs
+             SET status = CASE WHEN status = ?2 THEN ?4 ELSE status END,
+                 cancel_requested = CASE WHEN status = ?3 THEN 1 ELSE cancel_requested END,
+                 updated_at = ?5
+             WHERE id = ?1 AND status IN (?2, ?3)",
+            params![
+                id.to_string(),
+                JobStatus::Pending as i32,
+                JobStatus::Running as i32,
+                JobStatus::Cancelled as i32,
+                Utc::now().to_rfc3339(),
+            ],
+        )?;
+        if rows_affected > 0 {
+            return Ok(true);
+        }
+
+        let exists = conn
+            .prepare("SELECT 1 FROM jobs WHERE id = ?1")?
+            .exists(params![id.to_string()])?;
+        if exists {
+            Ok(false)
+        } else {
+            Err(StorageError::NotFound(id))
+        }
+    }
+
     pub fn get_by_id(&self, id: Uuid) -> Result<Option<Job>, StorageError> {
         let conn = self.conn.lock().map_err(|_| StorageError::MutexPoisoned)?;
         let mut stmt = conn.prepare(
             "SELECT id, payload, priority, status, retry_count, max_retries,
-                    created_at, updated_at, error_message
+                    created_at, updated_at, error_message, timeout_ms
              FROM jobs WHERE id = ?1",
         )?;
 
@@ -157,6 +335,7 @@ impl Storage {
         let status_val: i32 = row.get(3)?;
         let created_str: String = row.get(6)?;
         let updated_str: String = row.get(7)?;
+        let timeout_ms: Option<i64> = row.get(9)?;
 
         Ok(Job {
             id: Uuid::parse_str(&id_str).unwrap(),
@@ -174,6 +353,7 @@ impl Storage {
                 2 => JobStatus::Completed,
                 3 => JobStatus::Failed,
                 4 => JobStatus::DeadLetter,
+                5 => JobStatus::Cancelled,
                 _ => JobStatus::Pending,
             },
             retry_count: row.get(4)?,
@@ -185,6 +365,7 @@ impl Storage {
                 .unwrap()
                 .with_timezone(&Utc),
             error_message: row.get(8)?,
+            timeout: timeout_ms.map(|ms| Duration::from_millis(ms as u64)),
         })
     }
 }
@@ -524,4 +705,277 @@ mod tests {
         assert_eq!(final_job.created_at, original_created_at);
         assert_eq!(final_job.payload, b"updated");
     }
+
+    #[test]
+    fn test_timeout_round_trip() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3)
+            .with_timeout(Duration::from_millis(2500));
+        storage.insert(&job).unwrap();
+
+        let mut retrieved = storage.get_by_id(job.id).unwrap().unwrap();
+        assert_eq!(retrieved.timeout, Some(Duration::from_millis(2500)));
+
+        retrieved.timeout = None;
+        storage.update(&retrieved).unwrap();
+        assert_eq!(storage.get_by_id(job.id).unwrap().unwrap().timeout, None);
+    }
+
+    #[test]
+    fn test_heartbeat_extends_lease() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        let claimed = storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            storage
+                .heartbeat(&claimed, Duration::from_secs(60))
+                .unwrap(),
+            LeaseStatus::Held
+        );
+
+        // Renewed before it ran out, so there is nothing to reclaim
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.reclaim_expired().unwrap(), 0);
+        assert_eq!(storage.count_by_status(JobStatus::Running).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_heartbeat_reports_cancel_request() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        let claimed = storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        assert!(storage.cancel(job.id).unwrap());
+        assert_eq!(
+            storage.heartbeat(&claimed, DEFAULT_LEASE).unwrap(),
+            LeaseStatus::CancelRequested
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_after_reclaim_is_lost() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        let stale = storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.reclaim_expired().unwrap(), 1);
+
+        // Another worker claims it again; the first one must not keep it
+        let fresh = storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        assert_eq!(fresh.id, stale.id);
+        assert_eq!(
+            storage.heartbeat(&stale, DEFAULT_LEASE).unwrap(),
+            LeaseStatus::Lost
+        );
+        assert_eq!(
+            storage.heartbeat(&fresh, DEFAULT_LEASE).unwrap(),
+            LeaseStatus::Held
+        );
+    }
+
+    #[test]
+    fn test_reclaim_expired() {
+        let (storage, _temp) = create_test_storage();
+
+        let retryable = Job::new(b"retryable".to_vec(), Priority::Critical, 3);
+        let exhausted = Job::new(b"exhausted".to_vec(), Priority::High, 0);
+        let leased = Job::new(b"leased".to_vec(), Priority::Low, 3);
+        storage.insert(&retryable).unwrap();
+        storage.insert(&exhausted).unwrap();
+        storage.insert(&leased).unwrap();
+
+        storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(storage.reclaim_expired().unwrap(), 2);
+
+        let retryable = storage.get_by_id(retryable.id).unwrap().unwrap();
+        assert_eq!(retryable.status, JobStatus::Pending);
+        assert_eq!(retryable.retry_count, 1);
+        assert!(retryable.error_message.unwrap().contains("Lease expired"));
+
+        let exhausted = storage.get_by_id(exhausted.id).unwrap().unwrap();
+        assert_eq!(exhausted.status, JobStatus::DeadLetter);
+        assert_eq!(exhausted.retry_count, 0);
+
+        let leased = storage.get_by_id(leased.id).unwrap().unwrap();
+        assert_eq!(leased.status, JobStatus::Running);
+    }
+
+    #[test]
+    fn test_reclaim_expired_without_lease() {
+        let (storage, _temp) = create_test_storage();
+
+        // Left running by a worker from before leases existed
+        let mut job = Job::new(b"orphan".to_vec(), Priority::Normal, 3);
+        job.status = JobStatus::Running;
+        storage.insert(&job).unwrap();
+
+        assert_eq!(storage.reclaim_expired().unwrap(), 1);
+        assert_eq!(
+            storage.get_by_id(job.id).unwrap().unwrap().status,
+            JobStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_reclaim_expired_honours_cancel_request() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        assert!(storage.cancel(job.id).unwrap());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.reclaim_expired().unwrap(), 1);
+
+        let reclaimed = storage.get_by_id(job.id).unwrap().unwrap();
+        assert_eq!(reclaimed.status, JobStatus::Cancelled);
+        assert_eq!(reclaimed.retry_count, 0);
+    }
+
+    #[test]
+    fn test_cancel_pending_job() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        assert!(storage.cancel(job.id).unwrap());
+        assert_eq!(
+            storage.get_by_id(job.id).unwrap().unwrap().status,
+            JobStatus::Cancelled
+        );
+        // It won't be claimed
+        assert!(storage.get_next_pending().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_cancel_finished_job() {
+        let (storage, _temp) = create_test_storage();
+        let mut job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        job.status = JobStatus::Completed;
+        storage.insert(&job).unwrap();
+
+        assert!(!storage.cancel(job.id).unwrap());
+        assert_eq!(
+            storage.get_by_id(job.id).unwrap().unwrap().status,
+            JobStatus::Completed
+        );
+    }
+
+    #[test]
+    fn test_cancel_nonexistent_job() {
+        let (storage, _temp) = create_test_storage();
+        let result = storage.cancel(Uuid::new_v4());
+        assert!(matches!(result, Err(StorageError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_update_releases_lease_and_cancel_request() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        let mut claimed = storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        storage.cancel(job.id).unwrap();
+        claimed.mark_failed("Oops".to_string());
+        storage.update(&claimed).unwrap();
+
+        // The next attempt starts with a clean slate
+        let retried = storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        assert_eq!(
+            storage.heartbeat(&retried, DEFAULT_LEASE).unwrap(),
+            LeaseStatus::Held
+        );
+    }
+
+    #[test]
+    fn test_finish_only_from_current_attempt() {
+        let (storage, _temp) = create_test_storage();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        // The first attempt's lease runs out and a second attempt claims it
+        let mut first = storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(storage.reclaim_expired().unwrap(), 1);
+        let mut second = storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        assert_eq!(second.retry_count, 1);
+
+        first.mark_completed();
+        assert!(!storage.finish(&first, 0).unwrap());
+        let stored = storage.get_by_id(job.id).unwrap().unwrap();
+        assert_eq!(stored.status, JobStatus::Running);
+        assert_eq!(stored.retry_count, 1);
+
+        second.mark_failed("Oops".to_string());
+        assert!(storage.finish(&second, 1).unwrap());
+        let stored = storage.get_by_id(job.id).unwrap().unwrap();
+        assert_eq!(stored.status, JobStatus::Pending);
+        assert_eq!(stored.retry_count, 2);
+
+        // Finished jobs stay finished
+        assert!(!storage.finish(&second, 1).unwrap());
+    }
+
+    #[test]
+    fn test_opens_database_without_lease_columns() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_str().unwrap();
+        {
+            let conn = Connection::open(path).unwrap();
+            conn.execute(
+                "CREATE TABLE jobs (
+                    id TEXT PRIMARY KEY,
+                    payload BLOB NOT NULL,
+                    priority INTEGER NOT NULL,
+                    status INTEGER NOT NULL,
+                    retry_count INTEGER NOT NULL,
+                    max_retries INTEGER NOT NULL,
+                    created_at TEXT NOT NULL,
+                    updated_at TEXT NOT NULL,
+                    error_message TEXT
+                )",
+                [],
+            )
+            .unwrap();
+        }
+
+        let storage = Storage::new(path).unwrap();
+        let job = Job::new(b"test".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+        let claimed = storage.claim_next(DEFAULT_LEASE).unwrap().unwrap();
+        assert_eq!(
+            storage.heartbeat(&claimed, DEFAULT_LEASE).unwrap(),
+            LeaseStatus::Held
+        );
+
+        // Opening it again leaves it be
+        assert!(Storage::new(path).is_ok());
+    }
 }
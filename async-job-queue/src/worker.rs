@@ -1,15 +1,28 @@
-use crate::job::{JobHandler, JobStatus};
-use crate::storage::Storage;
-use std::sync::Arc;
+use crate::job::{Job, JobHandler, JobStatus};
+use crate::storage::{LeaseStatus, Storage, StorageError, DEFAULT_LEASE};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::time::sleep;
+use tokio::time::{interval_at, sleep, Instant};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Shortest period for heartbeats and reclaim sweeps, however short the lease.
+const MIN_TICK: Duration = Duration::from_millis(1);
+
+/// Cancellation tokens of the jobs this pool is running, by job ID.
+type RunningJobs = Arc<Mutex<HashMap<Uuid, CancellationToken>>>;
 
 pub struct WorkerPool {
     storage: Arc<Storage>,
     handler: Arc<dyn JobHandler>,
     num_workers: usize,
     poll_interval: Duration,
+    job_timeout: Option<Duration>,
+    lease_duration: Duration,
+    shutdown: CancellationToken,
+    running: RunningJobs,
 }
 
 impl WorkerPool {
@@ -19,6 +32,10 @@ impl WorkerPool {
             handler,
             num_workers,
             poll_interval: Duration::from_secs(1),
+            job_timeout: None,
+            lease_duration: DEFAULT_LEASE,
+            shutdown: CancellationToken::new(),
+            running: RunningJobs::default(),
         }
     }
 
@@ -27,80 +44,245 @@ impl WorkerPool {
         self
     }
 
+    /// Fail attempts that run longer than `timeout`, for jobs that don't set
+    /// their own.
+    pub fn with_job_timeout(mut self, timeout: Duration) -> Self {
+        self.job_timeout = Some(timeout);
+        self
+    }
+
+    /// How long a claimed job stays ours without a heartbeat. Workers renew
+    /// the lease three times per period; jobs whose lease runs out are
+    /// reclaimed by any pool sharing the storage.
+    ///
+    /// # Panics
+    ///
+    /// If `lease` is zero, as every job would be reclaimed as soon as it
+    /// was claimed.
+    pub fn with_lease_duration(mut self, lease: Duration) -> Self {
+        assert!(!lease.is_zero(), "lease duration must be non-zero");
+        self.lease_duration = lease;
+        self
+    }
+
+    /// A token that shuts the pool down when cancelled: workers stop claiming
+    /// new jobs, and `run` returns once the jobs in flight have finished.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Cancel a job. A pending job won't run; a running one is stopped right
+    /// away if this pool runs it, and otherwise at its worker's next
+    /// heartbeat. Returns `false` if the job had already finished.
+    pub fn cancel(&self, job_id: Uuid) -> Result<bool, StorageError> {
+        let cancelled = self.storage.cancel(job_id)?;
+        if let Some(token) = self.running.lock().unwrap().get(&job_id) {
+            token.cancel();
+        }
+        Ok(cancelled)
+    }
+
+    fn worker(&self) -> Worker {
+        Worker {
+            storage: Arc::clone(&self.storage),
+            handler: Arc::clone(&self.handler),
+            poll_interval: self.poll_interval,
+            job_timeout: self.job_timeout,
+            lease_duration: self.lease_duration,
+            shutdown: self.shutdown.clone(),
+            running: Arc::clone(&self.running),
+        }
+    }
+
+    /// Run the workers until the pool is shut down.
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
         info!("Starting worker pool with {} workers", self.num_workers);
 
         let mut handles = vec![];
 
         for worker_id in 0..self.num_workers {
-            let storage = Arc::clone(&self.storage);
-            let handler = Arc::clone(&self.handler);
-            let poll_interval = self.poll_interval;
-
-            let handle = tokio::spawn(async move {
-                worker_loop(worker_id, storage, handler, poll_interval).await;
-            });
-
-            handles.push(handle);
+            handles.push(tokio::spawn(self.worker().run(worker_id)));
         }
 
+        handles.push(tokio::spawn(reclaim_loop(
+            Arc::clone(&self.storage),
+            (self.lease_duration / 2).max(MIN_TICK),
+            self.shutdown.clone(),
+        )));
+
         for handle in handles {
             handle.await?;
         }
 
+        info!("Worker pool stopped");
         Ok(())
     }
 }
 
-async fn worker_loop(
-    worker_id: usize,
+/// Periodically put back jobs whose worker died holding them.
+async fn reclaim_loop(storage: Arc<Storage>, interval: Duration, shutdown: CancellationToken) {
+    loop {
+        match storage.reclaim_expired() {
+            Ok(0) => {}
+            Ok(n) => warn!("Reclaimed {} job(s) with expired leases", n),
+            Err(e) => error!("Error reclaiming expired jobs: {}", e),
+        }
+
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = sleep(interval) => {}
+        }
+    }
+}
+
+/// How an attempt at a job ended.
+enum Outcome {
+    Finished(Result<(), String>),
+    Cancelled,
+    LeaseLost,
+}
+
+struct Worker {
     storage: Arc<Storage>,
     handler: Arc<dyn JobHandler>,
     poll_interval: Duration,
-) {
-    info!("Worker {} started", worker_id);
+    job_timeout: Option<Duration>,
+    lease_duration: Duration,
+    shutdown: CancellationToken,
+    running: RunningJobs,
+}
 
-    loop {
-        match storage.get_next_pending() {
-            Ok(Some(mut job)) => {
-                info!("Worker {} processing job {}", worker_id, job.id);
-
-                // Job is already marked as Running by get_next_pending()
-                match handler.handle(&job.payload) {
-                    Ok(()) => {
-                        job.mark_completed();
-                        info!("Worker {} completed job {}", worker_id, job.id);
-                    }
-                    Err(e) => {
-                        warn!(
-                            "Worker {} job {} failed (retry {}/{}): {}",
-                            worker_id, job.id, job.retry_count, job.max_retries, e
-                        );
-                        job.mark_failed(e);
-
-                        if job.status == JobStatus::DeadLetter {
-                            error!("Job {} moved to dead letter queue", job.id);
-                        }
+impl Worker {
+    async fn run(self, worker_id: usize) {
+        info!("Worker {} started", worker_id);
+
+        while !self.shutdown.is_cancelled() {
+            match self.storage.claim_next(self.lease_duration) {
+                Ok(Some(job)) => {
+                    let Some(job) = self.process(worker_id, job).await else {
+                        continue;
+                    };
+
+                    // Add exponential backoff for retried jobs
+                    if job.retry_count > 0 {
+                        let backoff = Duration::from_secs(2_u64.pow(job.retry_count.min(5)));
+                        self.pause(backoff).await;
                     }
                 }
-
-                if let Err(e) = storage.update(&job) {
-                    error!("Worker {} failed to update job: {}", worker_id, e);
+                Ok(None) => {
+                    // No jobs available, wait before polling again
+                    self.pause(self.poll_interval).await;
+                }
+                Err(e) => {
+                    error!("Worker {} error fetching job: {}", worker_id, e);
+                    self.pause(self.poll_interval).await;
                 }
+            }
+        }
+
+        info!("Worker {} stopped", worker_id);
+    }
 
-                // Add exponential backoff for retried jobs
-                if job.retry_count > 0 {
-                    let backoff = Duration::from_secs(2_u64.pow(job.retry_count.min(5)));
-                    sleep(backoff).await;
+    /// Sleep, unless the pool shuts down first.
+    async fn pause(&self, duration: Duration) {
+        tokio::select! {
+            _ = self.shutdown.cancelled() => {}
+            _ = sleep(duration) => {}
+        }
+    }
+
+    /// Run a claimed job and record how it went. Returns `None` if the job
+    /// was reclaimed from under us, in which case it is left alone.
+    async fn process(&self, worker_id: usize, mut job: Job) -> Option<Job> {
+        info!("Worker {} processing job {}", worker_id, job.id);
+
+        let attempt = job.retry_count;
+        let cancel = CancellationToken::new();
+        self.running.lock().unwrap().insert(job.id, cancel.clone());
+        let outcome = self.execute(&job, &cancel).await;
+        self.running.lock().unwrap().remove(&job.id);
+
+        // Job is already marked as Running by claim_next()
+        match outcome {
+            Outcome::Finished(Ok(())) => {
+                job.mark_completed();
+                info!("Worker {} completed job {}", worker_id, job.id);
+            }
+            Outcome::Finished(Err(e)) => {
+                warn!(
+                    "Worker {} job {} failed (retry {}/{}): {}",
+                    worker_id, job.id, job.retry_count, job.max_retries, e
+                );
+                job.mark_failed(e);
+
+                if job.status == JobStatus::DeadLetter {
+                    error!("Job {} moved to dead letter queue", job.id);
                 }
             }
-            Ok(None) => {
-                // No jobs available, wait before polling again
-                sleep(poll_interval).await;
+            Outcome::Cancelled => {
+                job.mark_cancelled();
+                info!("Worker {} cancelled job {}", worker_id, job.id);
             }
-            Err(e) => {
-                error!("Worker {} error fetching job: {}", worker_id, e);
-                sleep(poll_interval).await;
+            Outcome::LeaseLost => {
+                warn!(
+                    "Worker {} lost the lease on job {}, dropping it",
+                    worker_id, job.id
+                );
+                return None;
+            }
+        }
+
+        match self.storage.finish(&job, attempt) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!(
+                    "Worker {} lost the lease on job {}, discarding its result",
+                    worker_id, job.id
+                );
+                return None;
+            }
+            Err(e) => error!("Worker {} failed to update job: {}", worker_id, e),
+        }
+        Some(job)
+    }
+
+    /// Run the handler until it finishes, times out or the job is cancelled,
+    /// renewing the job's lease meanwhile. Gives up on the job once renewals
+    /// have failed for a whole lease, as it may have been reclaimed by then.
+    async fn execute(&self, job: &Job, cancel: &CancellationToken) -> Outcome {
+        let timeout = job.timeout.or(self.job_timeout);
+        let work = async {
+            match timeout {
+                Some(limit) => tokio::time::timeout(limit, self.handler.handle(&job.payload))
+                    .await
+                    .unwrap_or_else(|_| Err(format!("Timed out after {:?}", limit))),
+                None => self.handler.handle(&job.payload).await,
+            }
+        };
+        tokio::pin!(work);
+
+        let period = (self.lease_duration / 3).max(MIN_TICK);
+        let mut heartbeat = interval_at(Instant::now() + period, period);
+        let mut renewed = Instant::now();
+        loop {
+            tokio::select! {
+                result = &mut work => return Outcome::Finished(result),
+                _ = cancel.cancelled() => return Outcome::Cancelled,
+                _ = heartbeat.tick() => match self.storage.heartbeat(job, self.lease_duration) {
+                    Ok(LeaseStatus::Held) => renewed = Instant::now(),
+                    Ok(LeaseStatus::CancelRequested) => return Outcome::Cancelled,
+                    Ok(LeaseStatus::Lost) => return Outcome::LeaseLost,
+                    Err(e) => {
+                        error!("Error renewing lease on job {}: {}", job.id, e);
+                        if renewed.elapsed() >= self.lease_duration {
+                            return Outcome::LeaseLost;
+                        }
+                    }
+                },
             }
         }
     }
@@ -110,6 +292,7 @@ async fn worker_loop(
 mod tests {
     use super::*;
     use crate::job::{Job, Priority};
+    use async_trait::async_trait;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Mutex as StdMutex;
     use tempfile::NamedTempFile;
@@ -120,8 +303,9 @@ mod tests {
         call_count: Arc<AtomicUsize>,
     }
 
+    #[async_trait]
     impl JobHandler for SuccessHandler {
-        fn handle(&self, _payload: &[u8]) -> Result<(), String> {
+        async fn handle(&self, _payload: &[u8]) -> Result<(), String> {
             self.call_count.fetch_add(1, Ordering::SeqCst);
             Ok(())
         }
@@ -132,8 +316,9 @@ mod tests {
         call_count: Arc<AtomicUsize>,
     }
 
+    #[async_trait]
     impl JobHandler for FailHandler {
-        fn handle(&self, _payload: &[u8]) -> Result<(), String> {
+        async fn handle(&self, _payload: &[u8]) -> Result<(), String> {
             self.call_count.fetch_add(1, Ordering::SeqCst);
             Err("Test failure".to_string())
         }
@@ -145,8 +330,9 @@ mod tests {
         fail_times: usize,
     }
 
+    #[async_trait]
     impl JobHandler for FailNTimesHandler {
-        fn handle(&self, _payload: &[u8]) -> Result<(), String> {
+        async fn handle(&self, _payload: &[u8]) -> Result<(), String> {
             let count = self.call_count.fetch_add(1, Ordering::SeqCst);
             if count < self.fail_times {
                 Err(format!("Failure {}", count + 1))
@@ -161,13 +347,59 @@ mod tests {
         processed: Arc<StdMutex<Vec<Vec<u8>>>>,
     }
 
+    #[async_trait]
     impl JobHandler for TrackingHandler {
-        fn handle(&self, payload: &[u8]) -> Result<(), String> {
+        async fn handle(&self, payload: &[u8]) -> Result<(), String> {
             self.processed.lock().unwrap().push(payload.to_vec());
             Ok(())
         }
     }
 
+    // A lone worker polling every 10ms
+    fn test_worker(storage: Arc<Storage>, handler: Arc<dyn JobHandler>) -> Worker {
+        WorkerPool::new(storage, handler, 1)
+            .with_poll_interval(Duration::from_millis(10))
+            .worker()
+    }
+
+    // Test handler that takes its time, counting jobs started and finished
+    struct SlowHandler {
+        duration: Duration,
+        started: Arc<AtomicUsize>,
+        finished: Arc<AtomicUsize>,
+    }
+
+    impl SlowHandler {
+        fn new(duration: Duration) -> Self {
+            Self {
+                duration,
+                started: Arc::new(AtomicUsize::new(0)),
+                finished: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl JobHandler for SlowHandler {
+        async fn handle(&self, _payload: &[u8]) -> Result<(), String> {
+            self.started.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.duration).await;
+            self.finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    // Poll until `condition` holds, failing the test after two seconds
+    async fn wait_until(condition: impl Fn() -> bool) {
+        timeout(Duration::from_secs(2), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for condition");
+    }
+
     fn create_test_storage() -> (Arc<Storage>, NamedTempFile) {
         let temp_file = NamedTempFile::new().expect("Failed to create temp file");
         let storage = Arc::new(
@@ -216,7 +448,7 @@ mod tests {
         let storage_clone = Arc::clone(&storage);
         let handler_clone = Arc::clone(&handler);
         let worker_task = tokio::spawn(async move {
-            worker_loop(0, storage_clone, handler_clone, Duration::from_millis(10)).await;
+            test_worker(storage_clone, handler_clone).run(0).await;
         });
 
         // Wait for job to be processed
@@ -245,7 +477,7 @@ mod tests {
         let storage_clone = Arc::clone(&storage);
         let handler_clone = Arc::clone(&handler);
         let worker_task = tokio::spawn(async move {
-            worker_loop(0, storage_clone, handler_clone, Duration::from_millis(10)).await;
+            test_worker(storage_clone, handler_clone).run(0).await;
         });
 
         // Wait for retries to complete (initial + 2s backoff + 4s backoff + processing)
@@ -277,7 +509,7 @@ mod tests {
         let storage_clone = Arc::clone(&storage);
         let handler_clone = Arc::clone(&handler);
         let worker_task = tokio::spawn(async move {
-            worker_loop(0, storage_clone, handler_clone, Duration::from_millis(10)).await;
+            test_worker(storage_clone, handler_clone).run(0).await;
         });
 
         // Wait for job to be processed through retries (initial + 2s backoff + 4s backoff)
@@ -312,13 +544,9 @@ mod tests {
             let storage_clone = Arc::clone(&storage);
             let handler_clone = Arc::clone(&handler);
             let task = tokio::spawn(async move {
-                worker_loop(
-                    worker_id,
-                    storage_clone,
-                    handler_clone,
-                    Duration::from_millis(10),
-                )
-                .await;
+                test_worker(storage_clone, handler_clone)
+                    .run(worker_id)
+                    .await;
             });
             worker_tasks.push(task);
         }
@@ -359,7 +587,7 @@ mod tests {
         let storage_clone = Arc::clone(&storage);
         let handler_clone = Arc::clone(&handler);
         let worker_task = tokio::spawn(async move {
-            worker_loop(0, storage_clone, handler_clone, Duration::from_millis(10)).await;
+            test_worker(storage_clone, handler_clone).run(0).await;
         });
 
         // Wait for all jobs to be processed
@@ -392,7 +620,7 @@ mod tests {
         let storage_clone = Arc::clone(&storage);
         let handler_clone = Arc::clone(&handler);
         let worker_task = tokio::spawn(async move {
-            worker_loop(0, storage_clone, handler_clone, Duration::from_millis(10)).await;
+            test_worker(storage_clone, handler_clone).run(0).await;
         });
 
         // Wait for all retries (1st: immediate, 2nd: +2s, 3rd: +4s, 4th: +8s)
@@ -444,6 +672,313 @@ mod tests {
         pool_task.abort();
     }
 
+    #[tokio::test]
+    async fn test_job_timeout_fails_attempt() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_secs(10)));
+
+        let job = Job::new(b"slow".to_vec(), Priority::Normal, 0);
+        storage.insert(&job).unwrap();
+
+        let worker = WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_job_timeout(Duration::from_millis(50))
+            .worker();
+        let worker_task = tokio::spawn(worker.run(0));
+
+        wait_until(|| storage.count_by_status(JobStatus::DeadLetter).unwrap() == 1).await;
+
+        let final_job = storage.get_by_id(job.id).unwrap().unwrap();
+        assert!(final_job
+            .error_message
+            .unwrap()
+            .starts_with("Timed out after"));
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 0);
+
+        worker_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_job_timeout_overrides_pool_timeout() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_millis(200)));
+
+        // The pool would allow it, the job itself doesn't
+        let job =
+            Job::new(b"slow".to_vec(), Priority::Normal, 0).with_timeout(Duration::from_millis(20));
+        storage.insert(&job).unwrap();
+
+        let worker = WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_job_timeout(Duration::from_secs(10))
+            .worker();
+        let worker_task = tokio::spawn(worker.run(0));
+
+        wait_until(|| storage.count_by_status(JobStatus::DeadLetter).unwrap() == 1).await;
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 0);
+
+        worker_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_job() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_secs(10)));
+
+        let job = Job::new(b"long".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        let pool = Arc::new(
+            WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+                .with_poll_interval(Duration::from_millis(10)),
+        );
+        let pool_clone = Arc::clone(&pool);
+        let pool_task = tokio::spawn(async move { pool_clone.run().await.is_ok() });
+
+        wait_until(|| handler.started.load(Ordering::SeqCst) == 1).await;
+        assert!(pool.cancel(job.id).unwrap());
+
+        wait_until(|| storage.count_by_status(JobStatus::Cancelled).unwrap() == 1).await;
+        let final_job = storage.get_by_id(job.id).unwrap().unwrap();
+        assert_eq!(final_job.retry_count, 0); // Cancelling is not a failure
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 0);
+
+        // Nothing left to cancel
+        assert!(!pool.cancel(job.id).unwrap());
+
+        pool.shutdown();
+        assert!(timeout(Duration::from_secs(1), pool_task)
+            .await
+            .unwrap()
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_through_storage_stops_job_at_heartbeat() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_secs(10)));
+
+        let job = Job::new(b"long".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        // Heartbeats every 50ms
+        let worker = WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_lease_duration(Duration::from_millis(150))
+            .worker();
+        let worker_task = tokio::spawn(worker.run(0));
+
+        wait_until(|| handler.started.load(Ordering::SeqCst) == 1).await;
+        // As another process, like the producer, would
+        assert!(storage.cancel(job.id).unwrap());
+        assert_eq!(
+            storage.get_by_id(job.id).unwrap().unwrap().status,
+            JobStatus::Running
+        );
+
+        wait_until(|| storage.count_by_status(JobStatus::Cancelled).unwrap() == 1).await;
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 0);
+
+        worker_task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_keeps_long_job_leased() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_millis(300)));
+
+        let job = Job::new(b"long".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        // The job outlives its lease several times over
+        let pool = Arc::new(
+            WorkerPool::new(Arc::clone(&storage), handler.clone(), 2)
+                .with_poll_interval(Duration::from_millis(10))
+                .with_lease_duration(Duration::from_millis(60)),
+        );
+        let pool_clone = Arc::clone(&pool);
+        let pool_task = tokio::spawn(async move { pool_clone.run().await.is_ok() });
+
+        wait_until(|| storage.count_by_status(JobStatus::Completed).unwrap() == 1).await;
+
+        // Never reclaimed, so never run twice
+        assert_eq!(handler.started.load(Ordering::SeqCst), 1);
+        assert_eq!(storage.get_by_id(job.id).unwrap().unwrap().retry_count, 0);
+
+        pool.shutdown();
+        assert!(timeout(Duration::from_secs(1), pool_task)
+            .await
+            .unwrap()
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_in_flight_jobs() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_millis(200)));
+
+        for i in 0..3 {
+            let job = Job::new(format!("job{}", i).into_bytes(), Priority::Normal, 3);
+            storage.insert(&job).unwrap();
+        }
+
+        let pool = Arc::new(
+            WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+                .with_poll_interval(Duration::from_millis(10)),
+        );
+        let pool_clone = Arc::clone(&pool);
+        let pool_task = tokio::spawn(async move { pool_clone.run().await.is_ok() });
+
+        wait_until(|| handler.started.load(Ordering::SeqCst) == 1).await;
+        pool.shutdown_token().cancel();
+
+        assert!(timeout(Duration::from_secs(1), pool_task)
+            .await
+            .unwrap()
+            .unwrap());
+
+        // The running job finished, the others weren't started
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 1);
+        assert_eq!(storage.count_by_status(JobStatus::Completed).unwrap(), 1);
+        assert_eq!(storage.count_by_status(JobStatus::Pending).unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_idle_pool() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SuccessHandler {
+            call_count: Arc::new(AtomicUsize::new(0)),
+        });
+
+        // Long enough to notice if shutdown waited for it
+        let pool = WorkerPool::new(storage, handler, 3).with_poll_interval(Duration::from_secs(60));
+        let shutdown = pool.shutdown_token();
+        let pool_task = tokio::spawn(async move { pool.run().await.is_ok() });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        shutdown.cancel();
+
+        assert!(timeout(Duration::from_secs(1), pool_task)
+            .await
+            .unwrap()
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reclaims_job_from_crashed_worker() {
+        let (storage, _temp) = create_test_storage();
+        let call_count = Arc::new(AtomicUsize::new(0));
+        let handler = Arc::new(SuccessHandler {
+            call_count: Arc::clone(&call_count),
+        });
+
+        let job = Job::new(b"orphan".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        // A worker claims the job and dies without a heartbeat
+        storage
+            .claim_next(Duration::from_millis(1))
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let pool = Arc::new(
+            WorkerPool::new(Arc::clone(&storage), handler, 1)
+                .with_poll_interval(Duration::from_millis(10))
+                .with_lease_duration(Duration::from_millis(100)),
+        );
+        let pool_clone = Arc::clone(&pool);
+        let pool_task = tokio::spawn(async move { pool_clone.run().await.is_ok() });
+
+        wait_until(|| storage.count_by_status(JobStatus::Completed).unwrap() == 1).await;
+        assert_eq!(call_count.load(Ordering::SeqCst), 1);
+        // The lost attempt counts as a retry
+        assert_eq!(storage.get_by_id(job.id).unwrap().unwrap().retry_count, 1);
+
+        // Shutting down cuts the retry backoff short
+        pool.shutdown();
+        assert!(timeout(Duration::from_secs(1), pool_task)
+            .await
+            .unwrap()
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_result_of_reclaimed_attempt_is_discarded() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_millis(100)));
+
+        let job = Job::new(b"long".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+
+        // No heartbeat comes due before the handler finishes
+        let worker = WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+            .with_lease_duration(Duration::from_secs(60))
+            .worker();
+        let claimed = storage
+            .claim_next(Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+        let attempt = tokio::spawn(async move { worker.process(0, claimed).await.is_none() });
+
+        // Meanwhile the lease runs out and another worker claims the job
+        wait_until(|| handler.started.load(Ordering::SeqCst) == 1).await;
+        storage.heartbeat(&job, Duration::ZERO).unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(storage.reclaim_expired().unwrap(), 1);
+        storage
+            .claim_next(Duration::from_secs(60))
+            .unwrap()
+            .unwrap();
+
+        assert!(attempt.await.unwrap());
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 1);
+        let stored = storage.get_by_id(job.id).unwrap().unwrap();
+        assert_eq!(stored.status, JobStatus::Running);
+        assert_eq!(stored.retry_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_when_heartbeats_keep_failing() {
+        let (storage, temp) = create_test_storage();
+        let handler = Arc::new(SlowHandler::new(Duration::from_secs(10)));
+
+        let job = Job::new(b"long".to_vec(), Priority::Normal, 3);
+        storage.insert(&job).unwrap();
+        let claimed = storage
+            .claim_next(Duration::from_millis(60))
+            .unwrap()
+            .unwrap();
+
+        // Every heartbeat fails from here on
+        rusqlite::Connection::open(temp.path())
+            .unwrap()
+            .execute("DROP TABLE jobs", [])
+            .unwrap();
+
+        let worker = WorkerPool::new(Arc::clone(&storage), handler.clone(), 1)
+            .with_lease_duration(Duration::from_millis(60))
+            .worker();
+        let outcome = timeout(
+            Duration::from_secs(1),
+            worker.execute(&claimed, &CancellationToken::new()),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(outcome, Outcome::LeaseLost));
+        assert_eq!(handler.finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "lease duration must be non-zero")]
+    fn test_zero_lease_rejected() {
+        let (storage, _temp) = create_test_storage();
+        let handler = Arc::new(SuccessHandler {
+            call_count: Arc::new(AtomicUsize::new(0)),
+        });
+        let _ = WorkerPool::new(storage, handler, 1).with_lease_duration(Duration::ZERO);
+    }
+
     #[test]
     fn test_worker_pool_is_send() {
         fn assert_send<T: Send>() {}
@@ -0,0 +1,152 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use regex::Regex;
+
+use crate::parser::Parser;
+use crate::time::Bound;
+use crate::Field;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn of_class(class: &str) -> Option<Level> {
+        match class {
+            ".debug" => Some(Level::Debug),
+            ".info" => Some(Level::Info),
+            ".warning" => Some(Level::Warning),
+            ".error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warning" | "warn" => Ok(Level::Warning),
+            "error" => Ok(Level::Error),
+            _ => Err(anyhow!("unknown level {}", s)),
+        }
+    }
+}
+
+/// Which lines to print.
+///
+/// Level, source and time apply to whole entries: a line without a timestamp,
+/// like the rest of a stack trace, is shown only if the entry it continues
+/// is. `grep` looks at each line on its own.
+#[derive(Default)]
+pub struct Filter {
+    /// The least severe level to show.
+    pub level: Option<Level>,
+    pub source: Option<Regex>,
+    pub grep: Option<Regex>,
+    pub since: Option<Bound>,
+    pub until: Option<Bound>,
+    /// Whether the last entry was shown.
+    last: Option<bool>,
+}
+
+impl Filter {
+    fn filters_entries(&self) -> bool {
+        self.level.is_some()
+            || self.source.is_some()
+            || self.since.is_some()
+            || self.until.is_some()
+    }
+
+    /// `fields` is what `parser` made of `line`, if anything.
+    pub fn accept(&mut self, parser: &dyn Parser, line: &str, fields: Option<&[Field]>) -> bool {
+        let entry = match fields.and_then(|fields| parser.timestamp(fields).map(|t| (fields, t))) {
+            Some((fields, timestamp)) => {
+                let level = fields.iter().filter_map(|f| Level::of_class(f.class)).max();
+                let source = fields.iter().find(|f| f.class == ".source");
+                // timestamps that can't be compared with a bound don't rule a line out
+                let shown = self.level.is_none_or(|min| level.is_some_and(|l| l >= min))
+                    && self
+                        .source
+                        .as_ref()
+                        .is_none_or(|re| source.is_some_and(|s| re.is_match(&s.content)))
+                    && self
+                        .since
+                        .is_none_or(|b| b.compare(&timestamp) != Some(Ordering::Less))
+                    && self
+                        .until
+                        .is_none_or(|b| b.compare(&timestamp) != Some(Ordering::Greater));
+                self.last = Some(shown);
+                shown
+            }
+            None => self.last.unwrap_or(!self.filters_entries()),
+        };
+        entry && self.grep.as_ref().is_none_or(|re| re.is_match(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logcat::Logcat;
+
+    const LOG: &[&str] = &[
+        "05-17 12:34:56.101   612   612 I vold    : Vold 3.0 firing up",
+        "05-17 12:34:57.512  1054  1080 W ActivityManager: Slow operation: 52ms",
+        "05-17 12:34:58.733  1402  1402 E AndroidRuntime: FATAL EXCEPTION: main",
+        "\tat com.example.app.MainActivity.onCreate(MainActivity.java:42)",
+        "05-17 12:35:00.000  1054  1160 D BatteryService: Processing new values",
+    ];
+
+    fn shown(filter: &mut Filter) -> Vec<usize> {
+        (0..LOG.len())
+            .filter(|&i| {
+                let fields = Logcat.parse_line(LOG[i]).ok();
+                filter.accept(&Logcat, LOG[i], fields.as_deref())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(shown(&mut Filter::default()), [0, 1, 2, 3, 4]);
+
+        let mut filter = Filter {
+            level: Some(Level::Warning),
+            ..Filter::default()
+        };
+        // the stack trace goes along with the error
+        assert_eq!(shown(&mut filter), [1, 2, 3]);
+
+        let mut filter = Filter {
+            source: Some(Regex::new("^(vold|Battery)").unwrap()),
+            ..Filter::default()
+        };
+        assert_eq!(shown(&mut filter), [0, 4]);
+
+        let mut filter = Filter {
+            grep: Some(Regex::new("(?i)fatal|slow").unwrap()),
+            ..Filter::default()
+        };
+        assert_eq!(shown(&mut filter), [1, 2]);
+
+        let mut filter = Filter {
+            since: Bound::parse("12:34:57"),
+            until: Bound::parse("2023-05-17T12:34:59"),
+            ..Filter::default()
+        };
+        assert_eq!(shown(&mut filter), [1, 2, 3]);
+
+        assert_eq!("WARN".parse::<Level>().unwrap(), Level::Warning);
+        assert!("loud".parse::<Level>().is_err());
+    }
+}
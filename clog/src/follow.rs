@@ -0,0 +1,130 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How often to look for more once the end of the file is reached.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The lines of a file that is still being written, like `tail -f`. Starts
+/// from the beginning, then waits for more; a file that is truncated is read
+/// again from the top and one that is replaced, as by log rotation, is
+/// reopened.
+pub struct Follow {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// Where `reader` is, to notice truncation.
+    pos: u64,
+    inode: u64,
+    /// A line still waiting for its newline.
+    partial: String,
+}
+
+impl Follow {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let file = File::open(&path)?;
+        let inode = file.metadata()?.ino();
+        Ok(Follow {
+            path,
+            reader: BufReader::new(file),
+            pos: 0,
+            inode,
+            partial: String::new(),
+        })
+    }
+
+    /// Catches up with whatever happened to the file since it was last read
+    /// to the end.
+    fn check(&mut self) -> io::Result<()> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // between the old file going and the new one arriving
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if metadata.ino() != self.inode {
+            let file = File::open(&self.path)?;
+            self.inode = file.metadata()?.ino();
+            self.reader = BufReader::new(file);
+            self.pos = 0;
+            self.partial.clear();
+        } else if metadata.len() < self.pos {
+            self.reader.seek(SeekFrom::Start(0))?;
+            self.pos = 0;
+            self.partial.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Follow {
+    type Item = io::Result<String>;
+
+    /// Blocks until there is another complete line; never returns `None`.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.reader.read_line(&mut self.partial) {
+                Ok(n) => self.pos += n as u64,
+                Err(e) => return Some(Err(e)),
+            }
+            if self.partial.ends_with('\n') {
+                let mut line = std::mem::take(&mut self.partial);
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                return Some(Ok(line));
+            }
+            if let Err(e) = self.check() {
+                return Some(Err(e));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_follow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, "one\ntwo\r\nthr").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let mut follow = Follow::open(&path).unwrap();
+        thread::spawn(move || {
+            for line in &mut follow {
+                if tx.send(line.unwrap()).is_err() {
+                    break;
+                }
+            }
+        });
+        let next = || rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(next(), "one");
+        assert_eq!(next(), "two");
+        // nothing until the line is finished
+        assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"ee\nfour\n").unwrap();
+        assert_eq!(next(), "three");
+        assert_eq!(next(), "four");
+
+        // truncated in place
+        fs::write(&path, "five\n").unwrap();
+        assert_eq!(next(), "five");
+
+        // rotated
+        fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        fs::write(&path, "six\n").unwrap();
+        assert_eq!(next(), "six");
+    }
+}
@@ -1,7 +1,8 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use thiserror::Error;
 
+use crate::parser::{time_field, FieldStr, ParseError, Parser};
+use crate::time::Timestamp;
 use crate::Field;
 
 lazy_static! {
@@ -34,23 +35,25 @@ fn tag_to_class(t: &str) -> Option<&'static str> {
     }
 }
 
-#[derive(Error, Debug)]
-pub enum ParseError {
-    #[error("unmatched line")]
-    Unmatched,
-    #[error("missing field")]
-    MissingField,
-}
+/// Fuchsia system and kernel logs, as printed by `fx log` and the serial
+/// console.
+pub struct Fuchsia;
 
-/// To get a named field
-trait FieldStr<'t> {
-    fn field(&self, name: &str) -> Result<&'t str, ParseError>;
-}
+impl Parser for Fuchsia {
+    fn name(&self) -> &'static str {
+        "fuchsia"
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        RE_KERNEL_LOG.is_match(line)
+    }
+
+    fn parse_line<'a>(&self, line: &'a str) -> Result<Vec<Field<'a>>, ParseError> {
+        parse_line(line)
+    }
 
-/// Extend Captures to get named field with Result
-impl<'t> FieldStr<'t> for regex::Captures<'t> {
-    fn field(&self, name: &str) -> Result<&'t str, ParseError> {
-        Ok(self.name(name).ok_or(ParseError::MissingField)?.as_str())
+    fn timestamp(&self, fields: &[Field]) -> Option<Timestamp> {
+        time_field(fields)?.parse().ok().map(Timestamp::Uptime)
     }
 }
 
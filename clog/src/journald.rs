@@ -0,0 +1,100 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::parser::{time_field, FieldStr, ParseError, Parser};
+use crate::time::{self, Timestamp};
+use crate::Field;
+
+lazy_static! {
+    static ref RE_SHORT_ISO: Regex = Regex::new(concat!(
+        r"^(?P<time>\d{4}-\d\d-\d\dT\d\d:\d\d:\d\d(\.\d+)?[+-]\d{4})\s+",
+        r"(?P<host>\S+)\s+",
+        r"(?P<ident>[^\s\[:]+)(\[(?P<pid>\d+)\])?:",
+        r"\s?(?P<text>.*?)\s*$",
+    ))
+    .unwrap();
+    /// `-- Boot 2d2c...  --`, `-- No entries --` and the like.
+    static ref RE_BANNER: Regex = Regex::new(r"^-- .* --\s*$").unwrap();
+}
+
+/// `journalctl -o short-iso` and `short-iso-precise`.
+pub struct Journald;
+
+impl Parser for Journald {
+    fn name(&self) -> &'static str {
+        "journald"
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        RE_SHORT_ISO.is_match(line) || RE_BANNER.is_match(line)
+    }
+
+    fn parse_line<'a>(&self, line: &'a str) -> Result<Vec<Field<'a>>, ParseError> {
+        if let Some(cap) = RE_SHORT_ISO.captures(line) {
+            let mut ret = vec![
+                Field::pos(".time", cap.field("time")?, ""),
+                Field::pre(" ", ".thread", cap.field("host")?),
+            ];
+            if cap.name("pid").is_some() {
+                ret.extend(vec![
+                    Field::new(" ", ".source", cap.field("ident")?, "["),
+                    Field::pos(".thread", cap.field("pid")?, "]:"),
+                ]);
+            } else {
+                ret.push(Field::new(" ", ".source", cap.field("ident")?, ":"));
+            }
+            ret.push(Field::pre(" ", ".text", cap.field("text")?));
+            Ok(ret)
+        } else if RE_BANNER.is_match(line) {
+            Ok(vec![Field::pos(".debug", line.trim(), "")])
+        } else {
+            Err(ParseError::Unmatched)
+        }
+    }
+
+    fn timestamp(&self, fields: &[Field]) -> Option<Timestamp> {
+        time::parse_iso(time_field(fields)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        let r = Journald
+            .parse_line(
+                "2023-05-17T12:34:56+0800 devbox systemd[1]: Started Session 3 of user pat.",
+            )
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::pos(".time", "2023-05-17T12:34:56+0800", ""),
+                Field::pre(" ", ".thread", "devbox"),
+                Field::new(" ", ".source", "systemd", "["),
+                Field::pos(".thread", "1", "]:"),
+                Field::pre(" ", ".text", "Started Session 3 of user pat."),
+            ]
+        );
+
+        let r = Journald
+            .parse_line("2023-05-17T12:34:56.123456+0800 devbox kernel: usb 1-1: new device")
+            .unwrap();
+        assert_eq!(r[2], Field::new(" ", ".source", "kernel", ":"));
+        assert_eq!(r[3], Field::pre(" ", ".text", "usb 1-1: new device"));
+
+        let r = Journald
+            .parse_line("-- Boot 0123456789abcdef0123456789abcdef --")
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![Field::pos(
+                ".debug",
+                "-- Boot 0123456789abcdef0123456789abcdef --",
+                ""
+            )]
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use serde_json::{Map, Value};
+
+use crate::parser::{time_field, ParseError, Parser};
+use crate::time::{self, Timestamp};
+use crate::Field;
+
+// The names loggers commonly use, in order of preference
+const TIME_KEYS: &[&str] = &["timestamp", "time", "ts", "@timestamp"];
+const LEVEL_KEYS: &[&str] = &["level", "severity", "lvl", "levelname", "log.level"];
+const SOURCE_KEYS: &[&str] = &["logger", "target", "source", "module", "name"];
+const MESSAGE_KEYS: &[&str] = &["message", "msg", "@message"];
+
+fn level_to_class(level: &Value) -> &'static str {
+    match level {
+        // bunyan and pino number their levels
+        Value::Number(n) => match n.as_u64() {
+            Some(0..=20) => ".debug",
+            Some(21..=30) => ".info",
+            Some(31..=40) => ".warning",
+            _ => ".error",
+        },
+        Value::String(s) => match s.to_ascii_lowercase().as_str() {
+            "trace" | "debug" | "verbose" => ".debug",
+            "info" | "information" | "notice" => ".info",
+            "warn" | "warning" => ".warning",
+            "error" | "err" | "fatal" | "critical" | "crit" | "alert" | "emerg" | "panic" => {
+                ".error"
+            }
+            _ => ".text",
+        },
+        _ => ".text",
+    }
+}
+
+/// Removes the first of `keys` found in `object`.
+fn take(object: &mut Map<String, Value>, keys: &[&str]) -> Option<Value> {
+    keys.iter().find_map(|key| object.remove(*key))
+}
+
+fn text(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+/// One JSON object per line, as written by most structured loggers. The
+/// well-known fields come first; the rest follow as `key=value`.
+pub struct Json;
+
+impl Parser for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        line.trim_start().starts_with('{')
+            && matches!(serde_json::from_str(line), Ok(Value::Object(_)))
+    }
+
+    fn parse_line<'a>(&self, line: &'a str) -> Result<Vec<Field<'a>>, ParseError> {
+        let mut object = match serde_json::from_str(line) {
+            Ok(Value::Object(object)) => object,
+            _ => return Err(ParseError::Unmatched),
+        };
+        let time = take(&mut object, TIME_KEYS);
+        let level = take(&mut object, LEVEL_KEYS);
+        let source = take(&mut object, SOURCE_KEYS);
+        let message = take(&mut object, MESSAGE_KEYS);
+        let class = level.as_ref().map_or(".text", level_to_class);
+
+        let mut ret: Vec<Field> = vec![];
+        let sep = |ret: &Vec<Field>| if ret.is_empty() { "" } else { " " };
+        if let Some(time) = time {
+            ret.push(Field::pos(".time", text(time), ""));
+        }
+        if let Some(level) = level {
+            ret.push(Field::pre(sep(&ret), class, text(level)));
+        }
+        if let Some(source) = source {
+            ret.push(Field::new(sep(&ret), ".source", text(source), ":"));
+        }
+        if let Some(message) = message {
+            ret.push(Field::pre(sep(&ret), class, text(message)));
+        }
+        for (key, value) in object {
+            ret.push(Field::new(sep(&ret), ".thread", key, "="));
+            ret.push(Field::pos(".text", value.to_string(), ""));
+        }
+        Ok(ret)
+    }
+
+    fn timestamp(&self, fields: &[Field]) -> Option<Timestamp> {
+        let time = time_field(fields)?;
+        match time.parse::<f64>() {
+            // milliseconds, unless it would be before 1973
+            Ok(n) if n > 1e11 => Some(time::from_epoch(n / 1000.0)),
+            Ok(n) => Some(time::from_epoch(n)),
+            Err(_) => time::parse_iso(time),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        let r = Json
+            .parse_line(r#"{"ts":"2023-05-17T04:34:56Z","level":"warn","logger":"db","msg":"slow \"query\"","ms":812,"table":"users"}"#)
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::pos(".time", "2023-05-17T04:34:56Z", ""),
+                Field::pre(" ", ".warning", "warn"),
+                Field::new(" ", ".source", "db", ":"),
+                Field::pre(" ", ".warning", "slow \"query\""),
+                Field::new(" ", ".thread", "ms", "="),
+                Field::pos(".text", "812", ""),
+                Field::new(" ", ".thread", "table", "="),
+                Field::pos(".text", "\"users\"", ""),
+            ]
+        );
+
+        // pino numbers its levels
+        let r = Json
+            .parse_line(r#"{"level":50,"time":1684298096000,"msg":"boom"}"#)
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::pos(".time", "1684298096000", ""),
+                Field::pre(" ", ".error", "50"),
+                Field::pre(" ", ".error", "boom"),
+            ]
+        );
+
+        assert!(!Json.matches("[1, 2]"));
+        assert!(!Json.matches("{not json"));
+        assert!(Json.parse_line("plain").is_err());
+    }
+}
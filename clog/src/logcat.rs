@@ -0,0 +1,97 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::parser::{FieldStr, ParseError, Parser};
+use crate::time::{self, Timestamp};
+use crate::Field;
+
+lazy_static! {
+    static ref RE_THREADTIME: Regex = Regex::new(concat!(
+        r"^(?P<date>\d\d-\d\d)\s+",
+        r"(?P<time>\d\d:\d\d:\d\d\.\d{3})\s+",
+        r"(?P<pid>\d+)\s+(?P<tid>\d+)\s+",
+        r"(?P<level>[VDIWEFA])\s+",
+        r"(?P<tag>.*?)\s*:\s?",
+        r"(?P<text>.*?)\s*$",
+    ))
+    .unwrap();
+}
+
+fn level_to_class(l: &str) -> &'static str {
+    match l {
+        "V" | "D" => ".debug",
+        "I" => ".info",
+        "W" => ".warning",
+        _ => ".error",
+    }
+}
+
+/// Android `logcat -v threadtime`, the default since Android 7.
+pub struct Logcat;
+
+impl Parser for Logcat {
+    fn name(&self) -> &'static str {
+        "logcat"
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        RE_THREADTIME.is_match(line)
+    }
+
+    fn parse_line<'a>(&self, line: &'a str) -> Result<Vec<Field<'a>>, ParseError> {
+        let cap = RE_THREADTIME.captures(line).ok_or(ParseError::Unmatched)?;
+        let class = level_to_class(cap.field("level")?);
+        Ok(vec![
+            Field::pos(".time", cap.field("date")?, ""),
+            Field::pre(" ", ".time", cap.field("time")?),
+            Field::pre(" ", ".thread", cap.field("pid")?),
+            Field::pre(" ", ".thread", cap.field("tid")?),
+            Field::pre(" ", class, cap.field("level")?),
+            Field::new(" ", ".source", cap.field("tag")?, ":"),
+            Field::pre(" ", class, cap.field("text")?),
+        ])
+    }
+
+    fn timestamp(&self, fields: &[Field]) -> Option<Timestamp> {
+        match fields {
+            [date, time, ..] if date.class == ".time" => {
+                time::parse_month_day(&date.content, &time.content)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log() {
+        let r = Logcat
+            .parse_line("05-17 12:34:56.789  1234  5678 W ActivityManager: Slow operation: 52ms")
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::pos(".time", "05-17", ""),
+                Field::pre(" ", ".time", "12:34:56.789"),
+                Field::pre(" ", ".thread", "1234"),
+                Field::pre(" ", ".thread", "5678"),
+                Field::pre(" ", ".warning", "W"),
+                Field::new(" ", ".source", "ActivityManager", ":"),
+                Field::pre(" ", ".warning", "Slow operation: 52ms"),
+            ]
+        );
+
+        // tags are padded, and may have spaces of their own
+        let r = Logcat
+            .parse_line("05-17 12:34:56.790   612   640 D Wifi HAL: wifi_get_link_stats")
+            .unwrap();
+        assert_eq!(r[5], Field::new(" ", ".source", "Wifi HAL", ":"));
+        assert_eq!(r[6], Field::pre(" ", ".debug", "wifi_get_link_stats"));
+
+        assert!(!Logcat.matches("--------- beginning of main"));
+        assert!(Logcat.parse_line("--------- beginning of main").is_err());
+    }
+}
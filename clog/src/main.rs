@@ -1,34 +1,29 @@
-use anyhow::anyhow;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::process;
 
-use colored::*;
+use regex::Regex;
 
+mod filter;
+mod follow;
 mod fuchsia;
+mod journald;
+mod json;
+mod logcat;
+mod parser;
+mod syslog;
+mod theme;
+mod time;
 
-struct StyleSheet<'a> {
-    inner: HashMap<&'a str, &'a str>,
-}
-
-impl<'a> StyleSheet<'a> {
-    fn new(init: Vec<(&'a str, &'a str)>) -> Self {
-        StyleSheet {
-            inner: init.iter().cloned().collect(),
-        }
-    }
-
-    fn get(&self, class: &str) -> Result<&str, anyhow::Error> {
-        match self.inner.get(class) {
-            Some(&color) => Ok(color),
-            _ => Err(anyhow!("class not found")),
-        }
-    }
-}
+use filter::Filter;
+use parser::Parser;
+use theme::Theme;
 
 #[derive(PartialEq, Debug)]
 pub struct Field<'a> {
     prefix: &'static str,
     class: &'static str,
-    content: &'a str,
+    content: Cow<'a, str>,
     postfix: &'static str,
 }
 
@@ -36,74 +31,180 @@ impl<'a> Field<'a> {
     fn new(
         prefix: &'static str,
         class: &'static str,
-        content: &'a str,
+        content: impl Into<Cow<'a, str>>,
         postfix: &'static str,
     ) -> Self {
         Field {
             prefix,
             class,
-            content,
+            content: content.into(),
             postfix,
         }
     }
 
-    fn pos(class: &'static str, content: &'a str, postfix: &'static str) -> Self {
-        Field {
-            prefix: "",
-            class,
-            content,
-            postfix,
-        }
+    fn pos(class: &'static str, content: impl Into<Cow<'a, str>>, postfix: &'static str) -> Self {
+        Field::new("", class, content, postfix)
     }
 
-    fn pre(prefix: &'static str, class: &'static str, content: &'a str) -> Self {
-        Field {
-            prefix,
-            class,
-            content,
-            postfix: "",
-        }
+    fn pre(prefix: &'static str, class: &'static str, content: impl Into<Cow<'a, str>>) -> Self {
+        Field::new(prefix, class, content, "")
     }
 
-    fn format(&self, style_sheet: &StyleSheet) -> Result<String, anyhow::Error> {
-        Ok(format!(
+    fn format(&self, theme: &Theme, highlight: Option<&Regex>) -> String {
+        format!(
             "{}{}{}",
-            self.prefix.color(style_sheet.get(".text")?),
-            self.content.color(style_sheet.get(self.class)?),
-            self.postfix.color(style_sheet.get(".text")?)
-        ))
+            theme.get(".text").paint(self.prefix),
+            paint(theme, self.class, &self.content, highlight),
+            theme.get(".text").paint(self.postfix)
+        )
     }
 }
 
+/// Paints `text` in the style of `class`, with whatever `highlight` matches
+/// picked out in the style of `.match`.
+fn paint(theme: &Theme, class: &str, text: &str, highlight: Option<&Regex>) -> String {
+    let style = theme.get(class);
+    let highlight = match highlight {
+        Some(re) => re,
+        None => return style.paint(text).to_string(),
+    };
+    let mut out = String::new();
+    let mut last = 0;
+    for m in highlight.find_iter(text).filter(|m| !m.as_str().is_empty()) {
+        out += &style.paint(&text[last..m.start()]).to_string();
+        out += &theme.get(".match").paint(m.as_str()).to_string();
+        last = m.end();
+    }
+    out += &style.paint(&text[last..]).to_string();
+    out
+}
+
+fn usage() -> ! {
+    let names: Vec<&str> = parser::parsers().iter().map(|p| p.name()).collect();
+    eprintln!(
+        "usage: clog [--format auto|{}] [--theme dark|light|FILE] [--color auto|always|never]\n\
+         \x20           [--level LEVEL] [--source REGEX] [--grep REGEX] [--since TIME] [--until TIME]\n\
+         \x20           [-f FILE | FILE]",
+        names.join("|")
+    );
+    process::exit(2);
+}
+
+fn regex(arg: Option<String>) -> Regex {
+    let pattern = arg.unwrap_or_else(|| usage());
+    Regex::new(&pattern).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    })
+}
+
+fn bound(arg: Option<String>) -> time::Bound {
+    let arg = arg.unwrap_or_else(|| usage());
+    time::Bound::parse(&arg).unwrap_or_else(|| {
+        eprintln!("can't make sense of time {:?}", arg);
+        process::exit(2);
+    })
+}
+
 fn main() -> Result<(), anyhow::Error> {
-    let style_sheet = StyleSheet::new(vec![
-        (".text", "white"),
-        (".time", "cyan"),
-        (".source", "bright green"),
-        (".thread", "cyan"),
-        (".info", "bright white"),
-        (".warning", "magenta"),
-        (".error", "red"),
-    ]);
-
-    let mut line = String::new();
-    while let Ok(n) = std::io::stdin().read_line(&mut line) {
-        if n == 0 {
-            break;
+    let mut format = "auto".to_owned();
+    let mut theme = "dark".to_owned();
+    let mut color = "auto".to_owned();
+    let mut filter = Filter::default();
+    let mut file = None;
+    let mut follow = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().unwrap_or_else(|| usage()),
+            "--theme" => theme = args.next().unwrap_or_else(|| usage()),
+            "--color" => color = args.next().unwrap_or_else(|| usage()),
+            "--level" => {
+                let level = args.next().unwrap_or_else(|| usage());
+                filter.level = Some(level.parse().unwrap_or_else(|_| usage()));
+            }
+            "--source" => filter.source = Some(regex(args.next())),
+            "--grep" => filter.grep = Some(regex(args.next())),
+            "--since" => filter.since = Some(bound(args.next())),
+            "--until" => filter.until = Some(bound(args.next())),
+            "-f" => {
+                follow = true;
+                file = Some(args.next().unwrap_or_else(|| usage()));
+            }
+            _ if arg.starts_with('-') => usage(),
+            _ if file.is_none() => file = Some(arg),
+            _ => usage(),
         }
-        match crate::fuchsia::parse_line(&line) {
-            Ok(fields) => {
-                for field in fields {
-                    print!("{}", field.format(&style_sheet)?);
-                }
-                println!();
+    }
+    let forced = match format.as_str() {
+        "auto" => None,
+        name => Some(parser::by_name(name).unwrap_or_else(|| usage())),
+    };
+    let theme = Theme::load(&theme)?;
+    // https://no-color.org
+    let colorize = match color.as_str() {
+        "always" => true,
+        "never" => false,
+        "auto" => {
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+        _ => usage(),
+    };
+    colored::control::set_override(colorize);
+
+    let mut lines: Box<dyn Iterator<Item = std::io::Result<String>>> = match (&file, follow) {
+        (Some(path), true) => Box::new(follow::Follow::open(path)?),
+        (Some(path), false) => Box::new(BufReader::new(std::fs::File::open(path)?).lines()),
+        (None, _) => Box::new(std::io::stdin().lock().lines()),
+    };
+    // Auto-detection needs a look at the first few lines before printing any
+    let mut head = Vec::new();
+    let parser = match forced {
+        Some(parser) => parser,
+        None if follow => {
+            // don't wait for lines that may be a long time coming
+            let path = file.as_ref().unwrap();
+            for line in BufReader::new(std::fs::File::open(path)?)
+                .lines()
+                .take(parser::DETECT_LINES)
+            {
+                head.push(line?);
             }
-            Err(..) => {
-                print!("{}", line); // print as it is
+            let parser = parser::detect(&head);
+            head.clear();
+            parser
+        }
+        None => {
+            for line in lines.by_ref().take(parser::DETECT_LINES) {
+                head.push(line?);
             }
+            parser::detect(&head)
         }
-        line.clear();
+    };
+
+    for line in head.into_iter().map(Ok).chain(lines) {
+        print_line(parser.as_ref(), &mut filter, &line?, &theme);
     }
 
     Ok(())
 }
+
+fn print_line(parser: &dyn Parser, filter: &mut Filter, line: &str, theme: &Theme) {
+    let fields = parser.parse_line(line).ok();
+    if !filter.accept(parser, line, fields.as_deref()) {
+        return;
+    }
+    let highlight = filter.grep.as_ref();
+    match fields {
+        Some(fields) => {
+            for field in fields {
+                print!("{}", field.format(theme, highlight));
+            }
+            println!();
+        }
+        None => {
+            println!("{}", paint(theme, ".text", line, highlight)); // print as it is
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use thiserror::Error;
+
+use crate::time::Timestamp;
+use crate::Field;
+
+/// How many lines auto-detection looks at.
+pub const DETECT_LINES: usize = 20;
+
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("unmatched line")]
+    Unmatched,
+    #[error("missing field")]
+    MissingField,
+}
+
+/// To get a named field
+pub trait FieldStr<'t> {
+    fn field(&self, name: &str) -> Result<&'t str, ParseError>;
+}
+
+/// Extend Captures to get named field with Result
+impl<'t> FieldStr<'t> for regex::Captures<'t> {
+    fn field(&self, name: &str) -> Result<&'t str, ParseError> {
+        Ok(self.name(name).ok_or(ParseError::MissingField)?.as_str())
+    }
+}
+
+/// A log format, splitting each line into styled fields.
+pub trait Parser {
+    /// What `--format` calls it.
+    fn name(&self) -> &'static str;
+
+    /// Whether the line is unmistakably in this format, for auto-detection.
+    fn matches(&self, line: &str) -> bool;
+
+    fn parse_line<'a>(&self, line: &'a str) -> Result<Vec<Field<'a>>, ParseError>;
+
+    /// When a line was logged, given the fields `parse_line` made of it.
+    /// Lines without one are taken to continue the one before.
+    fn timestamp(&self, _fields: &[Field]) -> Option<Timestamp> {
+        None
+    }
+}
+
+/// The content of the first `.time` field.
+pub fn time_field<'f>(fields: &'f [Field]) -> Option<&'f str> {
+    fields
+        .iter()
+        .find(|f| f.class == ".time")
+        .map(|f| f.content.as_ref())
+}
+
+/// Every known format, in order of preference when detection is a tie.
+pub fn parsers() -> Vec<Box<dyn Parser>> {
+    vec![
+        Box::new(crate::fuchsia::Fuchsia),
+        Box::new(crate::logcat::Logcat),
+        Box::new(crate::syslog::Syslog),
+        Box::new(crate::journald::Journald),
+        Box::new(crate::json::Json),
+    ]
+}
+
+pub fn by_name(name: &str) -> Option<Box<dyn Parser>> {
+    parsers().into_iter().find(|p| p.name() == name)
+}
+
+/// Picks the format most of `lines` are in, or Fuchsia when none fit.
+pub fn detect<S: AsRef<str>>(lines: &[S]) -> Box<dyn Parser> {
+    let mut best: Option<(usize, Box<dyn Parser>)> = None;
+    for parser in parsers() {
+        let count = lines
+            .iter()
+            .filter(|line| parser.matches(line.as_ref()))
+            .count();
+        if count > 0 && best.as_ref().is_none_or(|(most, _)| count > *most) {
+            best = Some((count, parser));
+        }
+    }
+    match best {
+        Some((_, parser)) => parser,
+        None => Box::new(crate::fuchsia::Fuchsia),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<String> {
+        let path = format!("{}/logs/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        for (file, format) in [
+            ("fuchsia.log", "fuchsia"),
+            ("logcat.log", "logcat"),
+            ("syslog.log", "syslog"),
+            ("journald.log", "journald"),
+            ("json.log", "json"),
+        ] {
+            let lines = fixture(file);
+            assert_eq!(detect(&lines[..DETECT_LINES]).name(), format, "{}", file);
+        }
+        assert_eq!(detect(&["plain text"]).name(), "fuchsia");
+        assert_eq!(by_name("logcat").unwrap().name(), "logcat");
+        assert!(by_name("csv").is_none());
+    }
+
+    #[test]
+    fn test_fixtures_parse() {
+        for (file, format) in [
+            ("logcat.log", "logcat"),
+            ("syslog.log", "syslog"),
+            ("journald.log", "journald"),
+            ("json.log", "json"),
+        ] {
+            let parser = by_name(format).unwrap();
+            let lines = fixture(file);
+            // banners and the like are left for the caller to print as is
+            let matched: Vec<&String> = lines.iter().filter(|l| parser.matches(l)).collect();
+            assert!(matched.len() * 10 >= lines.len() * 8, "{}", file);
+            for line in matched {
+                assert!(parser.parse_line(line).is_ok(), "{}: {}", file, line);
+            }
+        }
+    }
+}
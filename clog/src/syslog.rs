@@ -0,0 +1,177 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::parser::{time_field, FieldStr, ParseError, Parser};
+use crate::time::{self, Timestamp};
+use crate::Field;
+
+lazy_static! {
+    static ref RE_RFC5424: Regex = Regex::new(concat!(
+        r"^<(?P<pri>\d{1,3})>1 ",
+        r"(?P<time>\S+) (?P<host>\S+) (?P<app>\S+) (?P<procid>\S+) (?P<msgid>\S+) ",
+        r"(?P<sd>-|(\[([^\]\\]|\\.)*\])+)",
+        r"( (?P<text>.*?))?\s*$",
+    ))
+    .unwrap();
+    /// RFC 3164, and what syslog daemons write to files: the priority is
+    /// usually dropped and the timestamp may be RFC 3339.
+    static ref RE_BSD: Regex = Regex::new(concat!(
+        r"^(<(?P<pri>\d{1,3})>)?",
+        r"(?P<time>[A-Z][a-z]{2} [ \d]\d \d\d:\d\d:\d\d",
+        r"|\d{4}-\d\d-\d\dT\d\d:\d\d:\d\d(\.\d+)?(Z|[+-]\d\d:\d\d)) ",
+        r"(?P<host>\S+) ",
+        r"(?P<tag>[^\s\[:]+)(\[(?P<pid>\d+)\])?:",
+        r"\s?(?P<text>.*?)\s*$",
+    ))
+    .unwrap();
+}
+
+/// The severity is the low three bits of the priority.
+fn pri_to_class(pri: &str) -> &'static str {
+    match pri.parse::<u8>().map(|p| p % 8) {
+        Ok(0..=3) => ".error",
+        Ok(4) => ".warning",
+        Ok(5) | Ok(6) => ".info",
+        Ok(_) => ".debug",
+        Err(_) => ".text",
+    }
+}
+
+/// Syslog messages, in RFC 5424 or the older BSD layout.
+pub struct Syslog;
+
+impl Parser for Syslog {
+    fn name(&self) -> &'static str {
+        "syslog"
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        RE_RFC5424.is_match(line) || RE_BSD.is_match(line)
+    }
+
+    fn parse_line<'a>(&self, line: &'a str) -> Result<Vec<Field<'a>>, ParseError> {
+        if let Some(cap) = RE_RFC5424.captures(line) {
+            let class = pri_to_class(cap.field("pri")?);
+            let mut ret = vec![
+                Field::new("<", class, cap.field("pri")?, ">1"),
+                Field::pre(" ", ".time", cap.field("time")?),
+                Field::pre(" ", ".thread", cap.field("host")?),
+                Field::pre(" ", ".source", cap.field("app")?),
+                Field::pre(" ", ".thread", cap.field("procid")?),
+                Field::pre(" ", ".thread", cap.field("msgid")?),
+                Field::pre(" ", ".text", cap.field("sd")?),
+            ];
+            if cap.name("text").is_some() {
+                ret.push(Field::pre(" ", class, cap.field("text")?));
+            }
+            Ok(ret)
+        } else if let Some(cap) = RE_BSD.captures(line) {
+            let mut ret = vec![];
+            let class = match cap.name("pri") {
+                Some(pri) => {
+                    let class = pri_to_class(pri.as_str());
+                    ret.push(Field::new("<", class, pri.as_str(), ">"));
+                    class
+                }
+                None => ".text",
+            };
+            ret.extend(vec![
+                Field::pos(".time", cap.field("time")?, ""),
+                Field::pre(" ", ".thread", cap.field("host")?),
+            ]);
+            if cap.name("pid").is_some() {
+                ret.extend(vec![
+                    Field::new(" ", ".source", cap.field("tag")?, "["),
+                    Field::pos(".thread", cap.field("pid")?, "]:"),
+                ]);
+            } else {
+                ret.push(Field::new(" ", ".source", cap.field("tag")?, ":"));
+            }
+            ret.push(Field::pre(" ", class, cap.field("text")?));
+            Ok(ret)
+        } else {
+            Err(ParseError::Unmatched)
+        }
+    }
+
+    fn timestamp(&self, fields: &[Field]) -> Option<Timestamp> {
+        let time = time_field(fields)?;
+        time::parse_iso(time).or_else(|| time::parse_bsd(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc5424() {
+        let r = Syslog
+            .parse_line(r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="Application"] An application event log entry"#)
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::new("<", ".info", "165", ">1"),
+                Field::pre(" ", ".time", "2003-10-11T22:14:15.003Z"),
+                Field::pre(" ", ".thread", "mymachine.example.com"),
+                Field::pre(" ", ".source", "evntslog"),
+                Field::pre(" ", ".thread", "-"),
+                Field::pre(" ", ".thread", "ID47"),
+                Field::pre(
+                    " ",
+                    ".text",
+                    r#"[exampleSDID@32473 iut="3" eventSource="Application"]"#
+                ),
+                Field::pre(" ", ".info", "An application event log entry"),
+            ]
+        );
+
+        // no message at all
+        let r = Syslog
+            .parse_line("<34>1 2003-10-11T22:14:15.003Z mymachine.example.com su - ID47 -")
+            .unwrap();
+        assert_eq!(r.len(), 7);
+        assert_eq!(r[0], Field::new("<", ".error", "34", ">1"));
+    }
+
+    #[test]
+    fn test_bsd() {
+        let r = Syslog
+            .parse_line(
+                "<12>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8",
+            )
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::new("<", ".warning", "12", ">"),
+                Field::pos(".time", "Oct 11 22:14:15", ""),
+                Field::pre(" ", ".thread", "mymachine"),
+                Field::new(" ", ".source", "su", ":"),
+                Field::pre(
+                    " ",
+                    ".warning",
+                    "'su root' failed for lonvick on /dev/pts/8"
+                ),
+            ]
+        );
+
+        let r = Syslog
+            .parse_line("May  7 09:01:02 host sshd[1042]: Accepted publickey for root")
+            .unwrap();
+        assert_eq!(
+            r,
+            vec![
+                Field::pos(".time", "May  7 09:01:02", ""),
+                Field::pre(" ", ".thread", "host"),
+                Field::new(" ", ".source", "sshd", "["),
+                Field::pos(".thread", "1042", "]:"),
+                Field::pre(" ", ".text", "Accepted publickey for root"),
+            ]
+        );
+
+        assert!(Syslog.matches("2023-05-17T12:34:56.123456+08:00 host cron[88]: (root) CMD (true)"));
+        assert!(!Syslog.matches("[00050.844] 14025:14037> INIT: cpu 0"));
+    }
+}
@@ -0,0 +1,233 @@
+//! Colors for each class of field.
+//!
+//! A theme file is TOML with a table per class, named without the leading
+//! dot, and an optional built-in theme to start from:
+//!
+//! ```toml
+//! base = "light"
+//!
+//! [error]
+//! fg = "red"
+//! bold = true
+//!
+//! [match]
+//! fg = "black"
+//! bg = "#ffd700"
+//! ```
+//!
+//! Colors are the names `colored` knows, like `"bright green"`, or `#rrggbb`.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use colored::{Color, ColoredString, Colorize};
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+}
+
+impl Style {
+    fn fg(color: Color) -> Self {
+        Style {
+            fg: Some(color),
+            ..Style::default()
+        }
+    }
+
+    fn bold(self) -> Self {
+        Style { bold: true, ..self }
+    }
+
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut s = ColoredString::from(text);
+        if let Some(fg) = self.fg {
+            s = s.color(fg);
+        }
+        if let Some(bg) = self.bg {
+            s = s.on_color(bg);
+        }
+        if self.bold {
+            s = s.bold();
+        }
+        s
+    }
+}
+
+pub struct Theme {
+    inner: HashMap<String, Style>,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme::new(vec![
+            (".text", Style::fg(Color::White)),
+            (".time", Style::fg(Color::Cyan)),
+            (".source", Style::fg(Color::BrightGreen)),
+            (".thread", Style::fg(Color::Cyan)),
+            (".debug", Style::fg(Color::BrightBlack)),
+            (".info", Style::fg(Color::BrightWhite)),
+            (".warning", Style::fg(Color::Magenta)),
+            (".error", Style::fg(Color::Red)),
+            (
+                ".match",
+                Style {
+                    bg: Some(Color::Yellow),
+                    ..Style::fg(Color::Black)
+                },
+            ),
+        ])
+    }
+
+    pub fn light() -> Self {
+        Theme::new(vec![
+            (".text", Style::fg(Color::Black)),
+            (".time", Style::fg(Color::Blue)),
+            (".source", Style::fg(Color::Green)),
+            (".thread", Style::fg(Color::Blue)),
+            (".debug", Style::fg(Color::BrightBlack)),
+            (".info", Style::fg(Color::Black).bold()),
+            (".warning", Style::fg(Color::Magenta).bold()),
+            (".error", Style::fg(Color::Red).bold()),
+            (
+                ".match",
+                Style {
+                    bg: Some(Color::BrightYellow),
+                    ..Style::fg(Color::Black)
+                },
+            ),
+        ])
+    }
+
+    fn new(init: Vec<(&str, Style)>) -> Self {
+        Theme {
+            inner: init.into_iter().map(|(c, s)| (c.to_owned(), s)).collect(),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            _ => None,
+        }
+    }
+
+    /// A built-in theme by name, or else a theme file.
+    pub fn load(name_or_path: &str) -> Result<Self, anyhow::Error> {
+        match Theme::builtin(name_or_path) {
+            Some(theme) => Ok(theme),
+            None => Theme::from_file(Path::new(name_or_path)),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading theme {}", path.display()))?;
+        Theme::parse(&text).with_context(|| format!("in theme {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let file: ThemeFile = toml::from_str(text)?;
+        let base = file.base.as_deref().unwrap_or("dark");
+        let mut theme =
+            Theme::builtin(base).ok_or_else(|| anyhow!("no built-in theme {}", base))?;
+        for (class, spec) in file.classes {
+            let style = Style {
+                fg: spec.fg.as_deref().map(parse_color).transpose()?,
+                bg: spec.bg.as_deref().map(parse_color).transpose()?,
+                bold: spec.bold,
+            };
+            theme.inner.insert(format!(".{}", class), style);
+        }
+        Ok(theme)
+    }
+
+    /// The style for `class`, falling back to that of plain text.
+    pub fn get(&self, class: &str) -> Style {
+        self.inner
+            .get(class)
+            .or_else(|| self.inner.get(".text"))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize)]
+struct ThemeFile {
+    base: Option<String>,
+    #[serde(flatten)]
+    classes: HashMap<String, StyleSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StyleSpec {
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    bold: bool,
+}
+
+fn parse_color(s: &str) -> Result<Color, anyhow::Error> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() == 6 {
+            if let Ok(rgb) = u32::from_str_radix(hex, 16) {
+                return Ok(Color::TrueColor {
+                    r: (rgb >> 16) as u8,
+                    g: (rgb >> 8) as u8,
+                    b: rgb as u8,
+                });
+            }
+        }
+    }
+    Color::from_str(s).map_err(|_| anyhow!("unknown color {:?}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let theme = Theme::parse(
+            r##"
+base = "light"
+
+[error]
+fg = "bright red"
+bg = "#102030"
+bold = true
+
+[custom]
+fg = "green"
+"##,
+        )
+        .unwrap();
+        assert_eq!(
+            theme.get(".error"),
+            Style {
+                fg: Some(Color::BrightRed),
+                bg: Some(Color::TrueColor {
+                    r: 0x10,
+                    g: 0x20,
+                    b: 0x30
+                }),
+                bold: true,
+            }
+        );
+        assert_eq!(theme.get(".custom"), Style::fg(Color::Green));
+        // untouched classes come from the base, unknown ones from .text
+        assert_eq!(theme.get(".time"), Style::fg(Color::Blue));
+        assert_eq!(theme.get(".nothing"), Style::fg(Color::Black));
+
+        assert!(Theme::parse("[error]\nfg = \"octarine\"").is_err());
+        assert!(Theme::parse("[error]\nunderline = true").is_err());
+        assert!(Theme::parse("base = \"sepia\"").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! Timestamps as the various formats write them, for `--since` and `--until`.
+//!
+//! Wall-clock times are compared as written, without converting between
+//! time zones, so a bound means what it would mean to someone reading the
+//! log. Formats that leave out the year borrow it from the bound.
+
+use std::cmp::Ordering;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref RE_DATE_TIME: Regex = Regex::new(concat!(
+        r"^(?P<year>\d{4})-(?P<month>\d\d)-(?P<day>\d\d)",
+        r"([T ](?P<time>\d\d:\d\d(:\d\d(\.\d+)?)?))?",
+        r"(Z|[+-]\d\d:?\d\d)?$",
+    ))
+    .unwrap();
+    static ref RE_TIME: Regex =
+        Regex::new(r"^(?P<hour>\d\d?):(?P<minute>\d\d)(:(?P<second>\d\d(\.\d+)?))?$").unwrap();
+    static ref RE_BSD: Regex = Regex::new(concat!(
+        r"^(?P<month>[A-Z][a-z]{2}) +(?P<day>\d\d?) ",
+        r"(?P<time>\d\d:\d\d:\d\d)$",
+    ))
+    .unwrap();
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timestamp {
+    /// Seconds since boot.
+    Uptime(f64),
+    Wall {
+        year: Option<i32>,
+        month: u32,
+        day: u32,
+        /// Seconds since midnight.
+        time: f64,
+    },
+}
+
+/// A `--since` or `--until` argument.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Uptime(f64),
+    /// Without a date, only the time of day is compared.
+    Wall {
+        date: Option<(i32, u32, u32)>,
+        time: f64,
+    },
+}
+
+fn time_of_day(s: &str) -> Option<f64> {
+    let cap = RE_TIME.captures(s)?;
+    let hour: f64 = cap["hour"].parse().ok()?;
+    let minute: f64 = cap["minute"].parse().ok()?;
+    let second: f64 = cap
+        .name("second")
+        .map_or(Ok(0.0), |s| s.as_str().parse())
+        .ok()?;
+    Some(hour * 3600.0 + minute * 60.0 + second)
+}
+
+/// `2023-05-17T12:34:56.789+08:00` and its many variations.
+pub fn parse_iso(s: &str) -> Option<Timestamp> {
+    let cap = RE_DATE_TIME.captures(s)?;
+    Some(Timestamp::Wall {
+        year: Some(cap["year"].parse().ok()?),
+        month: cap["month"].parse().ok()?,
+        day: cap["day"].parse().ok()?,
+        time: cap
+            .name("time")
+            .map_or(Some(0.0), |t| time_of_day(t.as_str()))?,
+    })
+}
+
+/// `Oct 11 22:14:15`
+pub fn parse_bsd(s: &str) -> Option<Timestamp> {
+    let cap = RE_BSD.captures(s)?;
+    let month = MONTHS.iter().position(|&m| m == &cap["month"])? as u32 + 1;
+    Some(Timestamp::Wall {
+        year: None,
+        month,
+        day: cap["day"].parse().ok()?,
+        time: time_of_day(&cap["time"])?,
+    })
+}
+
+/// Logcat's `05-17` and `12:34:56.789`.
+pub fn parse_month_day(date: &str, time: &str) -> Option<Timestamp> {
+    let (month, day) = date.split_once('-')?;
+    Some(Timestamp::Wall {
+        year: None,
+        month: month.parse().ok()?,
+        day: day.parse().ok()?,
+        time: time_of_day(time)?,
+    })
+}
+
+/// Seconds since the Unix epoch, in UTC.
+pub fn from_epoch(secs: f64) -> Timestamp {
+    let days = (secs / 86400.0).floor();
+    // Howard Hinnant's days-to-civil
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = (yoe + era * 400 + i64::from(month <= 2)) as i32;
+    Timestamp::Wall {
+        year: Some(year),
+        month,
+        day,
+        time: secs - days * 86400.0,
+    }
+}
+
+impl Bound {
+    /// Accepts `2023-05-17`, `2023-05-17T12:34[:56]`, `12:34[:56]`, or a
+    /// number of seconds since boot.
+    pub fn parse(s: &str) -> Option<Bound> {
+        if let Some(Timestamp::Wall {
+            year: Some(year),
+            month,
+            day,
+            time,
+        }) = parse_iso(s)
+        {
+            return Some(Bound::Wall {
+                date: Some((year, month, day)),
+                time,
+            });
+        }
+        if let Some(time) = time_of_day(s) {
+            return Some(Bound::Wall { date: None, time });
+        }
+        s.parse().ok().map(Bound::Uptime)
+    }
+
+    /// Where `timestamp` falls relative to this bound, if they're comparable.
+    pub fn compare(&self, timestamp: &Timestamp) -> Option<Ordering> {
+        match (*self, *timestamp) {
+            (Bound::Uptime(bound), Timestamp::Uptime(t)) => t.partial_cmp(&bound),
+            (
+                Bound::Wall {
+                    date: None,
+                    time: bound,
+                },
+                Timestamp::Wall { time, .. },
+            ) => time.partial_cmp(&bound),
+            (
+                Bound::Wall {
+                    date: Some((by, bm, bd)),
+                    time: bound,
+                },
+                Timestamp::Wall {
+                    year,
+                    month,
+                    day,
+                    time,
+                },
+            ) => match (year.unwrap_or(by), month, day).cmp(&(by, bm, bd)) {
+                Ordering::Equal => time.partial_cmp(&bound),
+                other => Some(other),
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wall(year: Option<i32>, month: u32, day: u32, time: f64) -> Option<Timestamp> {
+        Some(Timestamp::Wall {
+            year,
+            month,
+            day,
+            time,
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_iso("2023-05-17T12:34:56.5+0800"),
+            wall(Some(2023), 5, 17, 45296.5)
+        );
+        assert_eq!(
+            parse_iso("2023-05-17T12:34:56Z"),
+            wall(Some(2023), 5, 17, 45296.0)
+        );
+        assert_eq!(parse_iso("2023-05-17"), wall(Some(2023), 5, 17, 0.0));
+        assert_eq!(parse_iso("12:34:56"), None);
+        assert_eq!(parse_bsd("May  7 09:01:02"), wall(None, 5, 7, 32462.0));
+        assert_eq!(
+            parse_month_day("05-17", "12:34:56.789"),
+            wall(None, 5, 17, 45296.789)
+        );
+        assert_eq!(
+            Some(from_epoch(1684298096.0)),
+            wall(Some(2023), 5, 17, 4.0 * 3600.0 + 34.0 * 60.0 + 56.0)
+        );
+        assert_eq!(Some(from_epoch(951782400.0)), wall(Some(2000), 2, 29, 0.0));
+    }
+
+    #[test]
+    fn test_bound() {
+        let bound = Bound::parse("2023-05-17T12:00").unwrap();
+        assert_eq!(
+            bound.compare(&parse_iso("2023-05-17T11:59:59Z").unwrap()),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            bound.compare(&parse_iso("2023-05-18T00:00:00Z").unwrap()),
+            Some(Ordering::Greater)
+        );
+        // logcat has no year
+        assert_eq!(
+            bound.compare(&parse_month_day("05-17", "12:00:00.000").unwrap()),
+            Some(Ordering::Equal)
+        );
+
+        let bound = Bound::parse("12:30").unwrap();
+        assert_eq!(
+            bound.compare(&parse_bsd("Oct 11 22:14:15").unwrap()),
+            Some(Ordering::Greater)
+        );
+
+        let bound = Bound::parse("50.5").unwrap();
+        assert_eq!(
+            bound.compare(&Timestamp::Uptime(50.844)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(bound.compare(&parse_bsd("Oct 11 22:14:15").unwrap()), None);
+        assert_eq!(Bound::parse("yesterday"), None);
+    }
+}
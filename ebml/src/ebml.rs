@@ -1,7 +1,13 @@
 #![macro_use]
 
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, Write};
+
 use nom::{IResult, Needed};
 
+use crate::reader::{invalid, Element, Event, Reader};
+use crate::writer::Writer;
+
 pub fn vint(input: &[u8]) -> IResult<&[u8], u64> {
     if input.is_empty() {
         return Err(nom::Err::Incomplete(Needed::Size(1)));
@@ -47,83 +53,80 @@ pub fn vid(input: &[u8]) -> IResult<&[u8], u64> {
     Ok((&input[end..], val))
 }
 
-pub fn vsize(input: &[u8]) -> IResult<&[u8], usize> {
-    let (rest, val) = vint(input)?;
-    Ok((rest, val as usize))
-}
-
-pub fn uint(input: &[u8]) -> IResult<&[u8], u64> {
-    let (input, size) = vsize(input)?;
-    if input.len() < size {
-        return Err(nom::Err::Incomplete(::nom::Needed::Size(size)));
-    }
-    let val = input[..size]
-        .iter()
-        .fold(0u64, |acc, &b| (acc << 8) | (b as u64));
-    Ok((&input[size..], val))
-}
-
-pub fn bool(input: &[u8]) -> IResult<&[u8], bool> {
-    let (i, val) = uint(input)?;
-    Ok((i, val != 0))
-}
-
-pub fn float(input: &[u8]) -> IResult<&[u8], f64> {
-    let (input, size) = vsize(input)?;
-
-    if size == 4 {
-        let (input, val) = nom::be_f32(input)?;
-        Ok((input, val as f64))
-    } else if size == 8 {
-        let (input, val) = nom::be_f64(input)?;
-        Ok((input, val))
-    } else {
-        Ok((input, 0f64))
-    }
-}
-
-pub fn string(input: &[u8]) -> IResult<&[u8], String> {
-    let (input, size) = vsize(input)?;
-    if input.len() < size {
-        return Err(nom::Err::Incomplete(::nom::Needed::Size(size)));
-    }
-    let r = nom::take!(input, size)?;
-    Ok((r.0, String::from_utf8(r.1.to_vec()).unwrap()))
+/// How an element's data is to be read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Contains other elements.
+    Master,
+    Unsigned,
+    Signed,
+    Float,
+    String,
+    /// Nanoseconds since 2001-01-01T00:00:00 UTC.
+    Date,
+    Binary,
 }
 
-pub fn binary(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
-    let (input, size) = vsize(input)?;
-    if input.len() < size {
-        return Err(nom::Err::Incomplete(::nom::Needed::Size(size)));
-    }
-    let r = nom::take!(input, size)?;
-    Ok((r.0, r.1.to_vec()))
+/// Where an element may appear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parent {
+    /// At the top level of the file.
+    Root,
+    /// Anywhere, like Void.
+    Any,
+    /// In any of these elements.
+    In(&'static [u64]),
 }
 
-pub fn skip(input: &[u8]) -> IResult<&[u8], usize> {
-    let (input, size) = vsize(input)?;
-    let r = nom::take!(input, size)?;
-    Ok((r.0, size))
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ElementType {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub parent: Parent,
 }
 
-// parse an element from the mutable input and move the result to specified output
-macro_rules! element {
-    ($input: expr, $output: expr, $func: expr) => {{
-        let _res = $func($input)?;
-        $input = _res.0;
-        $output = _res.1;
-    }};
+/// Looks up the type of an element by its ID.
+pub type Schema = fn(u64) -> Option<ElementType>;
+
+// map element IDs to their types, falling back to another schema for the rest
+macro_rules! schema {
+    (@parent root) => { $crate::ebml::Parent::Root };
+    (@parent any) => { $crate::ebml::Parent::Any };
+    (@parent [$($parent: expr),*]) => { $crate::ebml::Parent::In(&[$($parent),*]) };
+    ($id: expr, { $($eid: literal => $name: ident: $kind: ident in $parent: tt,)* } else $fallback: expr) => {
+        match $id {
+            $($eid => Some($crate::ebml::ElementType {
+                name: stringify!($name),
+                kind: $crate::ebml::Kind::$kind,
+                parent: schema!(@parent $parent),
+            }),)*
+            _ => $fallback,
+        }
+    };
 }
 
-// skip the rest of an element after its ID field
-macro_rules! skip {
-    ($input: expr, $id: expr) => {{
-        let _res = skip($input)?;
-        $input = _res.0;
-        eprintln!("Ignore element {:x} of {:x} bytes", $id, _res.1);
-    }};
+/// The elements of the EBML header, the segment after it, and those
+/// allowed anywhere.
+pub fn element_type(id: u64) -> Option<ElementType> {
+    schema!(id, {
+        0x1A45DFA3 => EBML: Master in root,
+        0x4286 => EBMLVersion: Unsigned in [0x1A45DFA3],
+        0x42F7 => EBMLReadVersion: Unsigned in [0x1A45DFA3],
+        0x42F2 => EBMLMaxIDLength: Unsigned in [0x1A45DFA3],
+        0x42F3 => EBMLMaxSizeLength: Unsigned in [0x1A45DFA3],
+        0x4282 => DocType: String in [0x1A45DFA3],
+        0x4287 => DocTypeVersion: Unsigned in [0x1A45DFA3],
+        0x4285 => DocTypeReadVersion: Unsigned in [0x1A45DFA3],
+        0x4281 => DocTypeExtension: Master in [0x1A45DFA3],
+        0x4283 => DocTypeExtensionName: String in [0x4281],
+        0x4284 => DocTypeExtensionVersion: Unsigned in [0x4281],
+        0x18538067 => Segment: Master in root,
+        0xEC => Void: Binary in any,
+        0xBF => CRC32: Binary in any,
+    } else None)
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct EBMLHeader {
     pub version: u64,
     pub read_version: u64,
@@ -135,17 +138,10 @@ pub struct EBMLHeader {
 }
 
 impl EBMLHeader {
-    const ID: &'static [u8] = &[0x1Au8, 0x45, 0xDF, 0xA3];
-
-    pub fn parse(input: &[u8]) -> IResult<&[u8], EBMLHeader> {
-        let (input, size) = vsize(input)?;
-        if input.len() < size {
-            return Err(nom::Err::Incomplete(::nom::Needed::Size(size)));
-        }
-
-        let rest = &input[size..];
-        let mut input = &input[0..size];
+    pub const ID: u64 = 0x1A45DFA3;
 
+    /// Reads the children of the header once `reader` has started it.
+    pub fn read<R: Read + Seek>(reader: &mut Reader<R>) -> io::Result<EBMLHeader> {
         let mut header = EBMLHeader {
             version: 1,
             read_version: 1,
@@ -156,51 +152,72 @@ impl EBMLHeader {
             doc_type_read_version: 1,
         };
 
-        while !input.is_empty() {
-            let id;
-            element!(input, id, vid);
-            match id {
-                0x4286 => element!(input, header.version, uint),
-                0x42F7 => element!(input, header.read_version, uint),
-                0x42F2 => element!(input, header.max_id_length, uint),
-                0x42F3 => element!(input, header.max_size_length, uint),
-                0x4282 => element!(input, header.doc_type, string),
-                0x4287 => element!(input, header.doc_type_version, uint),
-                0x4285 => element!(input, header.doc_type_read_version, uint),
-                _ => skip!(input, id),
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x4286 => header.version = value.try_into()?,
+                    0x42F7 => header.read_version = value.try_into()?,
+                    0x42F2 => header.max_id_length = value.try_into()?,
+                    0x42F3 => header.max_size_length = value.try_into()?,
+                    0x4282 => header.doc_type = value.try_into()?,
+                    0x4287 => header.doc_type_version = value.try_into()?,
+                    0x4285 => header.doc_type_read_version = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
             }
         }
 
-        Ok((rest, header))
+        Ok(header)
     }
-}
 
-pub struct EBMLSegment<'a> {
-    pub content: &'a [u8],
+    pub fn write<W: Write + Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(EBMLHeader::ID)?;
+        writer.unsigned(0x4286, self.version)?;
+        writer.unsigned(0x42F7, self.read_version)?;
+        writer.unsigned(0x42F2, self.max_id_length)?;
+        writer.unsigned(0x42F3, self.max_size_length)?;
+        writer.string(0x4282, &self.doc_type)?;
+        writer.unsigned(0x4287, self.doc_type_version)?;
+        writer.unsigned(0x4285, self.doc_type_read_version)?;
+        writer.end()
+    }
 }
 
-impl<'a> EBMLSegment<'a> {
-    const ID: &'static [u8] = &[0x18u8, 0x53, 0x80, 0x67];
-
-    pub fn parse(input: &'_ [u8]) -> IResult<&'_ [u8], EBMLSegment<'_>> {
-        let (input, _) = nom::take_until_and_consume!(input, EBMLSegment::ID)?;
-        let (input, size) = vint(input)?;
-        let (input, content) = nom::take!(input, size)?;
+/// Where the content of the segment is. Its children are what
+/// `matroska::Level1Element::read` reads.
+pub struct EBMLSegment {
+    pub element: Element,
+}
 
-        Ok((input, EBMLSegment { content }))
-    }
+impl EBMLSegment {
+    pub const ID: u64 = 0x18538067;
 }
 
-pub fn parse(input: &'_ [u8]) -> IResult<&'_ [u8], (EBMLHeader, EBMLSegment<'_>)> {
-    let (input, _) = nom::take_until_and_consume!(input, EBMLHeader::ID)?;
-    let (input, header) = EBMLHeader::parse(input)?;
-    let (input, segment) = EBMLSegment::parse(input)?;
-    Ok((input, (header, segment)))
+/// Reads the EBML header and starts the segment after it, leaving `reader`
+/// at the segment's first child.
+pub fn read<R: Read + Seek>(reader: &mut Reader<R>) -> io::Result<(EBMLHeader, EBMLSegment)> {
+    let header = match reader.next_event()? {
+        Some(Event::Start(element)) if element.id == EBMLHeader::ID => EBMLHeader::read(reader)?,
+        _ => return Err(invalid("no EBML header")),
+    };
+    loop {
+        match reader.next_event()? {
+            Some(Event::Start(element)) if element.id == EBMLSegment::ID => {
+                return Ok((header, EBMLSegment { element }))
+            }
+            Some(Event::Start(_)) => reader.skip_element()?,
+            Some(_) => (),
+            None => return Err(invalid("no segment")),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
 
     const SINGLE_STREAM: &[u8] = include_bytes!("../assets/single_stream.mkv");
     const WEBM: &[u8] = include_bytes!("../assets/big-buck-bunny_trailer.webm");
@@ -266,20 +283,28 @@ mod tests {
         let id = vid(&bytes).unwrap().1;
         assert_eq!(id, 0x4286);
 
-        let id = vid(EBMLHeader::ID).unwrap().1;
-        assert_eq!(id, 0x1a45dfa3);
+        let bytes = vec![0x1au8, 0x45, 0xdf, 0xa3];
+        let id = vid(&bytes).unwrap().1;
+        assert_eq!(id, EBMLHeader::ID);
     }
 
     #[test]
     fn test_ebml_header() {
-        let res = parse(WEBM);
-        assert!(res.is_ok());
-        let (_, (header, _)) = res.unwrap();
+        let mut reader = Reader::new(Cursor::new(WEBM), element_type);
+        let (header, segment) = read(&mut reader).unwrap();
         assert_eq!(header.doc_type, "webm");
+        assert_eq!(segment.element.data_offset, reader.position());
 
-        let res = parse(SINGLE_STREAM);
-        assert!(res.is_ok());
-        let (_, (header, _)) = res.unwrap();
+        let mut reader = Reader::new(Cursor::new(SINGLE_STREAM), element_type);
+        let (header, segment) = read(&mut reader).unwrap();
         assert_eq!(header.doc_type, "matroska");
+        assert_eq!(header.max_id_length, 4);
+        assert_eq!(
+            segment.element.size,
+            Some(SINGLE_STREAM.len() as u64 - segment.element.data_offset)
+        );
+
+        let mut reader = Reader::new(Cursor::new(&SINGLE_STREAM[100..]), element_type);
+        assert!(read(&mut reader).is_err());
     }
 }
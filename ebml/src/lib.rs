@@ -1,2 +1,5 @@
 pub mod ebml;
 pub mod matroska;
+pub mod muxer;
+pub mod reader;
+pub mod writer;
@@ -0,0 +1,100 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::process;
+
+use ebml::matroska::element_type;
+use ebml::muxer::{remux, Edits};
+use ebml::reader::{Event, Reader, Value};
+
+fn usage() -> ! {
+    eprintln!("usage: ebml dump FILE");
+    eprintln!("       ebml remux INPUT OUTPUT [--drop-track N]... [--title TITLE]");
+    eprintln!("                               [--tag NAME[=VALUE]]...");
+    process::exit(2);
+}
+
+fn dump(path: &str) -> io::Result<()> {
+    let mut reader = Reader::new(BufReader::new(File::open(path)?), element_type);
+    reader.set_max_value_size(256);
+    while let Some(event) = reader.next_event()? {
+        let (element, value) = match event {
+            Event::Start(element) => (element, None),
+            Event::Value(element, value) => (element, Some(value)),
+            Event::End(_) => continue,
+        };
+        let indent = if value.is_some() {
+            reader.depth()
+        } else {
+            reader.depth() - 1
+        };
+        let name = element_type(element.id).map_or("Unknown", |t| t.name);
+        let size = element
+            .size
+            .map_or_else(|| "unknown size".to_string(), |size| size.to_string());
+        print!(
+            "{:indent$}{} ({:x}) @{}, {}",
+            "",
+            name,
+            element.id,
+            element.offset,
+            size,
+            indent = 2 * indent
+        );
+        match value {
+            Some(Value::Unsigned(v)) => println!(": {}", v),
+            Some(Value::Signed(v)) | Some(Value::Date(v)) => println!(": {}", v),
+            Some(Value::Float(v)) => println!(": {}", v),
+            Some(Value::String(v)) => println!(": {:?}", v),
+            Some(Value::Binary(v)) if v.len() <= 16 => println!(": {:02x?}", v),
+            _ => println!(),
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("dump") => match (args.next(), args.next()) {
+            (Some(path), None) => dump(&path),
+            _ => usage(),
+        },
+        Some("remux") => {
+            let (input, output) = match (args.next(), args.next()) {
+                (Some(input), Some(output)) => (input, output),
+                _ => usage(),
+            };
+            let mut edits = Edits::default();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--drop-track" => match args.next().and_then(|n| n.parse().ok()) {
+                        Some(number) => edits.drop_tracks.push(number),
+                        None => usage(),
+                    },
+                    "--title" => edits.title = Some(args.next().unwrap_or_else(|| usage())),
+                    "--tag" => {
+                        let tag = args.next().unwrap_or_else(|| usage());
+                        edits.tags.push(match tag.split_once('=') {
+                            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                            None => (tag, None),
+                        });
+                    }
+                    _ => usage(),
+                }
+            }
+            File::open(&input).and_then(|input| {
+                let output = File::create(&output)?;
+                remux(BufReader::new(input), BufWriter::new(output), &edits)?
+                    .into_inner()
+                    .map_err(|e| e.into_error())?;
+                Ok(())
+            })
+        }
+        _ => usage(),
+    };
+    if let Err(e) = result {
+        eprintln!("ebml: {}", e);
+        process::exit(1);
+    }
+}
@@ -1,104 +1,320 @@
-use crate::ebml;
-use crate::ebml::{binary, float, skip, string, uint, vid, vint};
-use nom::IResult;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::ops::Range;
 
+use crate::ebml::{self, vint, ElementType};
+use crate::reader::{invalid, Element, Event, Reader, Value};
+use crate::writer::{encode_vint, encode_vint_len, Writer};
+
+/// The children of the segment, and the elements of EBML itself.
+pub fn element_type(id: u64) -> Option<ElementType> {
+    schema!(id, {
+        0x114D9B74 => SeekHead: Master in [0x18538067],
+        0x4DBB => Seek: Master in [0x114D9B74],
+        0x53AB => SeekID: Binary in [0x4DBB],
+        0x53AC => SeekPosition: Unsigned in [0x4DBB],
+
+        0x1549A966 => Info: Master in [0x18538067],
+        0x73A4 => SegmentUID: Binary in [0x1549A966],
+        0x7384 => SegmentFilename: String in [0x1549A966],
+        0x3CB923 => PrevUID: Binary in [0x1549A966],
+        0x3C83AB => PrevFilename: String in [0x1549A966],
+        0x3EB923 => NextUID: Binary in [0x1549A966],
+        0x3E83BB => NextFilename: String in [0x1549A966],
+        0x4444 => SegmentFamily: Binary in [0x1549A966],
+        0x6924 => ChapterTranslate: Master in [0x1549A966],
+        0x69FC => ChapterTranslateEditionUID: Unsigned in [0x6924],
+        0x69BF => ChapterTranslateCodec: Unsigned in [0x6924],
+        0x69A5 => ChapterTranslateID: Binary in [0x6924],
+        0x2AD7B1 => TimestampScale: Unsigned in [0x1549A966],
+        0x4489 => Duration: Float in [0x1549A966],
+        0x4461 => DateUTC: Date in [0x1549A966],
+        0x7BA9 => Title: String in [0x1549A966],
+        0x4D80 => MuxingApp: String in [0x1549A966],
+        0x5741 => WritingApp: String in [0x1549A966],
+
+        0x1F43B675 => Cluster: Master in [0x18538067],
+        0xE7 => Timestamp: Unsigned in [0x1F43B675],
+        0x5854 => SilentTracks: Master in [0x1F43B675],
+        0x58D7 => SilentTrackNumber: Unsigned in [0x5854],
+        0xA7 => Position: Unsigned in [0x1F43B675],
+        0xAB => PrevSize: Unsigned in [0x1F43B675],
+        0xA3 => SimpleBlock: Binary in [0x1F43B675],
+        0xAF => EncryptedBlock: Binary in [0x1F43B675],
+        0xA0 => BlockGroup: Master in [0x1F43B675],
+        0xA1 => Block: Binary in [0xA0],
+        0xA2 => BlockVirtual: Binary in [0xA0],
+        0x75A1 => BlockAdditions: Master in [0xA0],
+        0xA6 => BlockMore: Master in [0x75A1],
+        0xEE => BlockAddID: Unsigned in [0xA6],
+        0xA5 => BlockAdditional: Binary in [0xA6],
+        0x9B => BlockDuration: Unsigned in [0xA0],
+        0xFA => ReferencePriority: Unsigned in [0xA0],
+        0xFB => ReferenceBlock: Signed in [0xA0],
+        0xFD => ReferenceVirtual: Signed in [0xA0],
+        0xA4 => CodecState: Binary in [0xA0],
+        0x75A2 => DiscardPadding: Signed in [0xA0],
+        0x8E => Slices: Master in [0xA0],
+        0xE8 => TimeSlice: Master in [0x8E],
+        0xCC => LaceNumber: Unsigned in [0xE8],
+
+        0x1654AE6B => Tracks: Master in [0x18538067],
+        0xAE => TrackEntry: Master in [0x1654AE6B],
+        0xD7 => TrackNumber: Unsigned in [0xAE],
+        0x73C5 => TrackUID: Unsigned in [0xAE],
+        0x83 => TrackType: Unsigned in [0xAE],
+        0xB9 => FlagEnabled: Unsigned in [0xAE],
+        0x88 => FlagDefault: Unsigned in [0xAE],
+        0x55AA => FlagForced: Unsigned in [0xAE],
+        0x55AB => FlagHearingImpaired: Unsigned in [0xAE],
+        0x55AC => FlagVisualImpaired: Unsigned in [0xAE],
+        0x55AD => FlagTextDescriptions: Unsigned in [0xAE],
+        0x55AE => FlagOriginal: Unsigned in [0xAE],
+        0x55AF => FlagCommentary: Unsigned in [0xAE],
+        0x9C => FlagLacing: Unsigned in [0xAE],
+        0x6DE7 => MinCache: Unsigned in [0xAE],
+        0x6DF8 => MaxCache: Unsigned in [0xAE],
+        0x23E383 => DefaultDuration: Unsigned in [0xAE],
+        0x234E7A => DefaultDecodedFieldDuration: Unsigned in [0xAE],
+        0x23314F => TrackTimestampScale: Float in [0xAE],
+        0x537F => TrackOffset: Signed in [0xAE],
+        0x55EE => MaxBlockAdditionID: Unsigned in [0xAE],
+        0x536E => Name: String in [0xAE],
+        0x22B59C => Language: String in [0xAE],
+        0x22B59D => LanguageBCP47: String in [0xAE],
+        0x86 => CodecID: String in [0xAE],
+        0x63A2 => CodecPrivate: Binary in [0xAE],
+        0x258688 => CodecName: String in [0xAE],
+        0x7446 => AttachmentLink: Unsigned in [0xAE],
+        0xAA => CodecDecodeAll: Unsigned in [0xAE],
+        0x6FAB => TrackOverlay: Unsigned in [0xAE],
+        0x56AA => CodecDelay: Unsigned in [0xAE],
+        0x56BB => SeekPreRoll: Unsigned in [0xAE],
+        0xE2 => TrackOperation: Master in [0xAE],
+        0xE0 => Video: Master in [0xAE],
+        0x9A => FlagInterlaced: Unsigned in [0xE0],
+        0x9D => FieldOrder: Unsigned in [0xE0],
+        0x53B8 => StereoMode: Unsigned in [0xE0],
+        0x53C0 => AlphaMode: Unsigned in [0xE0],
+        0xB0 => PixelWidth: Unsigned in [0xE0],
+        0xBA => PixelHeight: Unsigned in [0xE0],
+        0x54AA => PixelCropBottom: Unsigned in [0xE0],
+        0x54BB => PixelCropTop: Unsigned in [0xE0],
+        0x54CC => PixelCropLeft: Unsigned in [0xE0],
+        0x54DD => PixelCropRight: Unsigned in [0xE0],
+        0x54B0 => DisplayWidth: Unsigned in [0xE0],
+        0x54BA => DisplayHeight: Unsigned in [0xE0],
+        0x54B2 => DisplayUnit: Unsigned in [0xE0],
+        0x54B3 => AspectRatioType: Unsigned in [0xE0],
+        0x2EB524 => ColourSpace: Binary in [0xE0],
+        0x2383E3 => FrameRate: Float in [0xE0],
+        0x55B0 => Colour: Master in [0xE0],
+        0x7670 => Projection: Master in [0xE0],
+        0xE1 => Audio: Master in [0xAE],
+        0xB5 => SamplingFrequency: Float in [0xE1],
+        0x78B5 => OutputSamplingFrequency: Float in [0xE1],
+        0x9F => Channels: Unsigned in [0xE1],
+        0x7D7B => ChannelPositions: Binary in [0xE1],
+        0x6264 => BitDepth: Unsigned in [0xE1],
+        0x52F1 => Emphasis: Unsigned in [0xE1],
+        0x6D80 => ContentEncodings: Master in [0xAE],
+        0x6240 => ContentEncoding: Master in [0x6D80],
+        0x5031 => ContentEncodingOrder: Unsigned in [0x6240],
+        0x5032 => ContentEncodingScope: Unsigned in [0x6240],
+        0x5033 => ContentEncodingType: Unsigned in [0x6240],
+        0x5034 => ContentCompression: Master in [0x6240],
+        0x4254 => ContentCompAlgo: Unsigned in [0x5034],
+        0x4255 => ContentCompSettings: Binary in [0x5034],
+        0x5035 => ContentEncryption: Master in [0x6240],
+        0x47E1 => ContentEncAlgo: Unsigned in [0x5035],
+        0x47E2 => ContentEncKeyID: Binary in [0x5035],
+        0x47E7 => ContentEncAESSettings: Master in [0x5035],
+        0x47E8 => AESSettingsCipherMode: Unsigned in [0x47E7],
+        0x47E3 => ContentSignature: Binary in [0x5035],
+        0x47E4 => ContentSigKeyID: Binary in [0x5035],
+        0x47E5 => ContentSigAlgo: Unsigned in [0x5035],
+        0x47E6 => ContentSigHashAlgo: Unsigned in [0x5035],
+
+        0x1C53BB6B => Cues: Master in [0x18538067],
+        0xBB => CuePoint: Master in [0x1C53BB6B],
+        0xB3 => CueTime: Unsigned in [0xBB],
+        0xB7 => CueTrackPositions: Master in [0xBB],
+        0xF7 => CueTrack: Unsigned in [0xB7],
+        0xF1 => CueClusterPosition: Unsigned in [0xB7],
+        0xF0 => CueRelativePosition: Unsigned in [0xB7],
+        0xB2 => CueDuration: Unsigned in [0xB7],
+        0x5378 => CueBlockNumber: Unsigned in [0xB7],
+        0xEA => CueCodecState: Unsigned in [0xB7],
+        0xDB => CueReference: Master in [0xB7],
+        0x96 => CueRefTime: Unsigned in [0xDB],
+
+        0x1941A469 => Attachments: Master in [0x18538067],
+        0x61A7 => AttachedFile: Master in [0x1941A469],
+        0x467E => FileDescription: String in [0x61A7],
+        0x466E => FileName: String in [0x61A7],
+        0x4660 => FileMimeType: String in [0x61A7],
+        0x465C => FileData: Binary in [0x61A7],
+        0x46AE => FileUID: Unsigned in [0x61A7],
+        0x4675 => FileReferral: Binary in [0x61A7],
+        0x4661 => FileUsedStartTime: Unsigned in [0x61A7],
+        0x4662 => FileUsedEndTime: Unsigned in [0x61A7],
+
+        0x1043A770 => Chapters: Master in [0x18538067],
+        0x45B9 => EditionEntry: Master in [0x1043A770],
+        0x45BC => EditionUID: Unsigned in [0x45B9],
+        0x45BD => EditionFlagHidden: Unsigned in [0x45B9],
+        0x45DB => EditionFlagDefault: Unsigned in [0x45B9],
+        0x45DD => EditionFlagOrdered: Unsigned in [0x45B9],
+        0x4520 => EditionDisplay: Master in [0x45B9],
+        0x4521 => EditionString: String in [0x4520],
+        0x45E4 => EditionLanguageIETF: String in [0x4520],
+        0xB6 => ChapterAtom: Master in [0x45B9, 0xB6],
+        0x73C4 => ChapterUID: Unsigned in [0xB6],
+        0x5654 => ChapterStringUID: String in [0xB6],
+        0x91 => ChapterTimeStart: Unsigned in [0xB6],
+        0x92 => ChapterTimeEnd: Unsigned in [0xB6],
+        0x98 => ChapterFlagHidden: Unsigned in [0xB6],
+        0x4598 => ChapterFlagEnabled: Unsigned in [0xB6],
+        0x6E67 => ChapterSegmentUUID: Binary in [0xB6],
+        0x4588 => ChapterSkipType: Unsigned in [0xB6],
+        0x6EBC => ChapterSegmentEditionUID: Unsigned in [0xB6],
+        0x63C3 => ChapterPhysicalEquiv: Unsigned in [0xB6],
+        0x8F => ChapterTrack: Master in [0xB6],
+        0x89 => ChapterTrackUID: Unsigned in [0x8F],
+        0x80 => ChapterDisplay: Master in [0xB6],
+        0x85 => ChapString: String in [0x80],
+        0x437C => ChapLanguage: String in [0x80],
+        0x437D => ChapLanguageBCP47: String in [0x80],
+        0x437E => ChapCountry: String in [0x80],
+        0x6944 => ChapProcess: Master in [0xB6],
+        0x6955 => ChapProcessCodecID: Unsigned in [0x6944],
+        0x450D => ChapProcessPrivate: Binary in [0x6944],
+        0x6911 => ChapProcessCommand: Master in [0x6944],
+        0x6922 => ChapProcessTime: Unsigned in [0x6911],
+        0x6933 => ChapProcessData: Binary in [0x6911],
+
+        0x1254C367 => Tags: Master in [0x18538067],
+        0x7373 => Tag: Master in [0x1254C367],
+        0x63C0 => Targets: Master in [0x7373],
+        0x68CA => TargetTypeValue: Unsigned in [0x63C0],
+        0x63CA => TargetType: String in [0x63C0],
+        0x63C5 => TagTrackUID: Unsigned in [0x63C0],
+        0x63C9 => TagEditionUID: Unsigned in [0x63C0],
+        0x63C4 => TagChapterUID: Unsigned in [0x63C0],
+        0x63C6 => TagAttachmentUID: Unsigned in [0x63C0],
+        0x67C8 => SimpleTag: Master in [0x7373, 0x67C8],
+        0x45A3 => TagName: String in [0x67C8],
+        0x447A => TagLanguage: String in [0x67C8],
+        0x447B => TagLanguageBCP47: String in [0x67C8],
+        0x4484 => TagDefault: Unsigned in [0x67C8],
+        0x44B4 => TagDefaultBogus: Unsigned in [0x67C8],
+        0x4487 => TagString: String in [0x67C8],
+        0x4485 => TagBinary: Binary in [0x67C8],
+    } else ebml::element_type(id))
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Level1Element {
     SeekHead(SeekHead),
     Info(Box<Info>),
     Tracks(Tracks),
-    Chapters,
-    Cluster,
-    Cues,
-    Attachments,
-    Tags,
+    Chapters(Chapters),
+    Cluster(Cluster),
+    Cues(Cues),
+    Attachments(Attachments),
+    Tags(Tags),
     Void(u64),
     Unknown(u64),
 }
 
 impl Level1Element {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Level1Element> {
-        let (input, id) = vid(input)?;
-        match id {
-            0x114D9B74 => {
-                // Convert the result to the common Level1Element type
-                SeekHead::parse(input).map(|(i, val)| (i, Level1Element::SeekHead(val)))
-            }
-            0x1549A966 => {
-                Info::parse(input).map(|(i, val)| (i, Level1Element::Info(Box::new(val))))
-            }
-            0x1F43B675 => cluster(input),
-            0x1043A770 => chapters(input),
-            0x1254C367 => tags(input),
-            0x1941A469 => attachments(input),
-            0x1654AE6B => Tracks::parse(input).map(|(i, val)| (i, Level1Element::Tracks(val))),
-            0x1C53BB6B => cues(input),
-            0xEC => {
-                let (input, size) = skip(input)?;
-                Ok((input, Level1Element::Void(size as u64)))
+    /// Reads the next child of the segment, or `None` once the segment has
+    /// ended.
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Option<Level1Element>> {
+        let element = match reader.next_event()? {
+            Some(Event::Start(element)) => element,
+            Some(Event::Value(element, _)) => {
+                let size = element.size.unwrap_or_default();
+                return Ok(Some(match element.id {
+                    0xEC => Level1Element::Void(size),
+                    _ => Level1Element::Unknown(size),
+                }));
             }
+            _ => return Ok(None),
+        };
+        Level1Element::read_started(reader, &element).map(Some)
+    }
+
+    /// Reads the rest of a child of the segment once `reader` has started it.
+    pub fn read_started<R: Read + io::Seek>(
+        reader: &mut Reader<R>,
+        element: &Element,
+    ) -> io::Result<Level1Element> {
+        let level1 = match element.id {
+            0x114D9B74 => Level1Element::SeekHead(SeekHead::read(reader)?),
+            0x1549A966 => Level1Element::Info(Box::new(Info::read(reader)?)),
+            0x1654AE6B => Level1Element::Tracks(Tracks::read(reader)?),
+            0x1F43B675 => Level1Element::Cluster(Cluster::read(reader)?),
+            0x1043A770 => Level1Element::Chapters(Chapters::read(reader)?),
+            0x1254C367 => Level1Element::Tags(Tags::read(reader)?),
+            0x1941A469 => Level1Element::Attachments(Attachments::read(reader)?),
+            0x1C53BB6B => Level1Element::Cues(Cues::read(reader)?),
             _ => {
-                let (input, size) = skip(input)?;
-                Ok((input, Level1Element::Unknown(size as u64)))
+                reader.skip_element()?;
+                Level1Element::Unknown(element.size.unwrap_or_default())
             }
-        }
+        };
+        Ok(level1)
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct SeekHead {
     pub positions: Vec<Seek>,
 }
 
 impl SeekHead {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], SeekHead> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<SeekHead> {
         let mut seek_head = SeekHead::default();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0x4DBB => {
-                    let val;
-                    element!(data, val, Seek::parse);
-                    seek_head.positions.push(val);
-                }
-                _ => skip!(data, id),
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0x4DBB => seek_head.positions.push(Seek::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
             }
         }
-
-        Ok((input, seek_head))
+        Ok(seek_head)
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Seek {
     pub id: Vec<u8>,
     pub position: u64,
 }
 
 impl Seek {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Seek> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Seek> {
         let mut seek = Seek::default();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0x53AB => element!(data, seek.id, binary),
-                0x53AC => element!(data, seek.position, uint),
-                _ => skip!(data, id),
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x53AB => seek.id = value.try_into()?,
+                    0x53AC => seek.position = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
             }
         }
-
-        Ok((input, seek))
+        Ok(seek)
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Info {
     pub uid: Vec<u8>,
     pub filename: String,
@@ -109,70 +325,109 @@ pub struct Info {
     pub timecode_scale: u64,
     pub duration: f64,
     pub title: String,
-    pub date_utc: u64,
+    /// Nanoseconds since 2001-01-01T00:00:00 UTC.
+    pub date_utc: i64,
     pub muxing_app: String,
     pub writing_app: String,
 }
 
 impl Info {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Info> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
-        let mut info = Info::default();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0x73A4 => element!(data, info.uid, binary),
-                0x7384 => element!(data, info.filename, string),
-                0x3CB923 => element!(data, info.prev_uid, binary),
-                0x3C83AB => element!(data, info.prev_filename, string),
-                0x3EB923 => element!(data, info.next_uid, binary),
-                0x3C83BB => element!(data, info.next_filename, string),
-                0x2AD7B1 => element!(data, info.timecode_scale, uint),
-                0x4489 => element!(data, info.duration, float),
-                0x7BA9 => element!(data, info.title, string),
-                0x4D80 => element!(data, info.muxing_app, string),
-                0x5741 => element!(data, info.writing_app, string),
-                0x4461 => element!(data, info.date_utc, uint),
-                _ => skip!(data, id),
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Info> {
+        let mut info = Info {
+            timecode_scale: 1_000_000,
+            ..Default::default()
+        };
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x73A4 => info.uid = value.try_into()?,
+                    0x7384 => info.filename = value.try_into()?,
+                    0x3CB923 => info.prev_uid = value.try_into()?,
+                    0x3C83AB => info.prev_filename = value.try_into()?,
+                    0x3EB923 => info.next_uid = value.try_into()?,
+                    0x3E83BB => info.next_filename = value.try_into()?,
+                    0x2AD7B1 => info.timecode_scale = value.try_into()?,
+                    0x4489 => info.duration = value.try_into()?,
+                    0x7BA9 => info.title = value.try_into()?,
+                    0x4D80 => info.muxing_app = value.try_into()?,
+                    0x5741 => info.writing_app = value.try_into()?,
+                    0x4461 => info.date_utc = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
             }
         }
+        Ok(info)
+    }
 
-        Ok((input, info))
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x1549A966)?;
+        if !self.uid.is_empty() {
+            writer.binary(0x73A4, &self.uid)?;
+        }
+        if !self.filename.is_empty() {
+            writer.string(0x7384, &self.filename)?;
+        }
+        if !self.prev_uid.is_empty() {
+            writer.binary(0x3CB923, &self.prev_uid)?;
+        }
+        if !self.prev_filename.is_empty() {
+            writer.string(0x3C83AB, &self.prev_filename)?;
+        }
+        if !self.next_uid.is_empty() {
+            writer.binary(0x3EB923, &self.next_uid)?;
+        }
+        if !self.next_filename.is_empty() {
+            writer.string(0x3E83BB, &self.next_filename)?;
+        }
+        writer.unsigned(0x2AD7B1, self.timecode_scale)?;
+        if self.duration != 0.0 {
+            writer.float(0x4489, self.duration)?;
+        }
+        if self.date_utc != 0 {
+            writer.date(0x4461, self.date_utc)?;
+        }
+        if !self.title.is_empty() {
+            writer.string(0x7BA9, &self.title)?;
+        }
+        writer.string(0x4D80, &self.muxing_app)?;
+        writer.string(0x5741, &self.writing_app)?;
+        writer.end()
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Tracks {
     pub tracks: Vec<Track>,
 }
 
 impl Tracks {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Tracks> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Tracks> {
         let mut tracks = Tracks::default();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0xAE => {
-                    let t;
-                    element!(data, t, Track::parse);
-                    tracks.tracks.push(t);
-                }
-                _ => skip!(data, id),
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0xAE => tracks.tracks.push(Track::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
             }
         }
+        Ok(tracks)
+    }
 
-        Ok((input, tracks))
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x1654AE6B)?;
+        for track in &self.tracks {
+            track.write(writer)?;
+        }
+        writer.end()
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Track {
     pub number: u64,
     pub uid: u64,
@@ -201,47 +456,110 @@ impl Track {
         Track {
             enabled: true,
             default: true,
+            lacing: true,
+            timecode_scale: 1.0,
+            language: "eng".into(),
             ..Default::default()
         }
     }
 
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Track> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Track> {
         let mut track = Track::new();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0xD7 => element!(data, track.number, uint),
-                0x73C5 => element!(data, track.uid, uint),
-                0x83 => element!(data, track.typ3, uint),
-                0xB9 => element!(data, track.enabled, ebml::bool),
-                0x55AA => element!(data, track.forced, ebml::bool),
-                0x9C => element!(data, track.lacing, ebml::bool),
-                0x6DE7 => element!(data, track.min_cache, uint),
-                0x6DF8 => element!(data, track.max_cache, uint),
-                0x23E383 => element!(data, track.default_duration, uint),
-                0x23314F => element!(data, track.timecode_scale, float),
-                0x536E => element!(data, track.name, string),
-                0x22B59C => element!(data, track.language, string),
-                0x86 => element!(data, track.codec_id, string),
-                0x63A2 => element!(data, track.codec_private, binary),
-                0x258688 => element!(data, track.codec_name, string),
-                0x7446 => element!(data, track.attachment_link, uint),
-                0xE0 => element!(data, track.video, Video::parse),
-                0xE1 => element!(data, track.audio, Audio::parse),
-                0x6D80 => element!(data, track.content_encodings, ContentEncodings::parse),
-                _ => skip!(data, id),
-            }
-        }
-
-        Ok((input, track))
-    }
-}
-
-#[derive(Default)]
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0xD7 => track.number = value.try_into()?,
+                    0x73C5 => track.uid = value.try_into()?,
+                    0x83 => track.typ3 = value.try_into()?,
+                    0xB9 => track.enabled = value.try_into()?,
+                    0x88 => track.default = value.try_into()?,
+                    0x55AA => track.forced = value.try_into()?,
+                    0x9C => track.lacing = value.try_into()?,
+                    0x6DE7 => track.min_cache = value.try_into()?,
+                    0x6DF8 => track.max_cache = value.try_into()?,
+                    0x23E383 => track.default_duration = value.try_into()?,
+                    0x23314F => track.timecode_scale = value.try_into()?,
+                    0x536E => track.name = value.try_into()?,
+                    0x22B59C => track.language = value.try_into()?,
+                    0x86 => track.codec_id = value.try_into()?,
+                    0x63A2 => track.codec_private = value.try_into()?,
+                    0x258688 => track.codec_name = value.try_into()?,
+                    0x7446 => track.attachment_link = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(element)) => match element.id {
+                    0xE0 => track.video = Video::read(reader)?,
+                    0xE1 => track.audio = Audio::read(reader)?,
+                    0x6D80 => track.content_encodings = ContentEncodings::read(reader)?,
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(track)
+    }
+
+    /// Leaves out what is the same as the default.
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        let default = Track::new();
+        writer.start(0xAE)?;
+        writer.unsigned(0xD7, self.number)?;
+        writer.unsigned(0x73C5, self.uid)?;
+        writer.unsigned(0x83, self.typ3)?;
+        if self.enabled != default.enabled {
+            writer.unsigned(0xB9, self.enabled as u64)?;
+        }
+        if self.default != default.default {
+            writer.unsigned(0x88, self.default as u64)?;
+        }
+        if self.forced != default.forced {
+            writer.unsigned(0x55AA, self.forced as u64)?;
+        }
+        if self.lacing != default.lacing {
+            writer.unsigned(0x9C, self.lacing as u64)?;
+        }
+        if self.min_cache != 0 {
+            writer.unsigned(0x6DE7, self.min_cache)?;
+        }
+        if self.max_cache != 0 {
+            writer.unsigned(0x6DF8, self.max_cache)?;
+        }
+        if self.default_duration != 0 {
+            writer.unsigned(0x23E383, self.default_duration)?;
+        }
+        if self.timecode_scale != default.timecode_scale {
+            writer.float(0x23314F, self.timecode_scale)?;
+        }
+        if !self.name.is_empty() {
+            writer.string(0x536E, &self.name)?;
+        }
+        if self.language != default.language {
+            writer.string(0x22B59C, &self.language)?;
+        }
+        writer.string(0x86, &self.codec_id)?;
+        if !self.codec_private.is_empty() {
+            writer.binary(0x63A2, &self.codec_private)?;
+        }
+        if !self.codec_name.is_empty() {
+            writer.string(0x258688, &self.codec_name)?;
+        }
+        if self.attachment_link != 0 {
+            writer.unsigned(0x7446, self.attachment_link)?;
+        }
+        if self.video != default.video {
+            self.video.write(writer)?;
+        }
+        if self.audio != default.audio {
+            self.audio.write(writer)?;
+        }
+        if !self.content_encodings.encodings.is_empty() {
+            self.content_encodings.write(writer)?;
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Video {
     pub pixel_width: u64,
     pub pixel_height: u64,
@@ -255,25 +573,24 @@ pub struct Video {
 }
 
 impl Video {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
         let mut video = Video::default();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0xB0 => element!(data, video.pixel_width, uint),
-                0xBA => element!(data, video.pixel_height, uint),
-                0x54AA => element!(data, video.pixel_crop_bottom, uint),
-                0x54BB => element!(data, video.pixel_crop_top, uint),
-                0x54CC => element!(data, video.pixel_crop_left, uint),
-                0x54DD => element!(data, video.pixel_crop_right, uint),
-                0x54B0 => element!(data, video.display_width, uint),
-                0x54BA => element!(data, video.display_height, uint),
-                0x54B2 => element!(data, video.display_unit, uint),
-                _ => skip!(data, id),
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0xB0 => video.pixel_width = value.try_into()?,
+                    0xBA => video.pixel_height = value.try_into()?,
+                    0x54AA => video.pixel_crop_bottom = value.try_into()?,
+                    0x54BB => video.pixel_crop_top = value.try_into()?,
+                    0x54CC => video.pixel_crop_left = value.try_into()?,
+                    0x54DD => video.pixel_crop_right = value.try_into()?,
+                    0x54B0 => video.display_width = value.try_into()?,
+                    0x54BA => video.display_height = value.try_into()?,
+                    0x54B2 => video.display_unit = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
             }
         }
 
@@ -284,14 +601,36 @@ impl Video {
             video.display_height = video.pixel_height;
         }
 
-        Ok((input, video))
+        Ok(video)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0xE0)?;
+        writer.unsigned(0xB0, self.pixel_width)?;
+        writer.unsigned(0xBA, self.pixel_height)?;
+        for (id, crop) in [
+            (0x54AA, self.pixel_crop_bottom),
+            (0x54BB, self.pixel_crop_top),
+            (0x54CC, self.pixel_crop_left),
+            (0x54DD, self.pixel_crop_right),
+        ] {
+            if crop != 0 {
+                writer.unsigned(id, crop)?;
+            }
+        }
+        writer.unsigned(0x54B0, self.display_width)?;
+        writer.unsigned(0x54BA, self.display_height)?;
+        if self.display_unit != 0 {
+            writer.unsigned(0x54B2, self.display_unit)?;
+        }
+        writer.end()
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Audio {
-    pub sampling_frequency: u64,
-    pub output_sampling_frequency: u64,
+    pub sampling_frequency: f64,
+    pub output_sampling_frequency: f64,
     pub channels: u64,
     pub bit_depth: u64,
 }
@@ -299,130 +638,1193 @@ pub struct Audio {
 impl Audio {
     pub fn new() -> Audio {
         Audio {
-            sampling_frequency: 8000,
+            sampling_frequency: 8000.0,
             channels: 1,
             ..Default::default()
         }
     }
 
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
-        let (input, size) = vint(input)?;
-        let (input, mut data) = nom::take!(input, size)?;
-
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
         let mut audio = Audio::new();
-        while !data.is_empty() {
-            let id;
-            element!(data, id, vid);
-            match id {
-                0xB5 => element!(data, audio.sampling_frequency, uint),
-                0x78B5 => element!(data, audio.output_sampling_frequency, uint),
-                0x9F => element!(data, audio.channels, uint),
-                0x6264 => element!(data, audio.bit_depth, uint),
-                _ => skip!(data, id),
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0xB5 => audio.sampling_frequency = value.try_into()?,
+                    0x78B5 => audio.output_sampling_frequency = value.try_into()?,
+                    0x9F => audio.channels = value.try_into()?,
+                    0x6264 => audio.bit_depth = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
             }
         }
 
-        if audio.output_sampling_frequency == 0 {
+        if audio.output_sampling_frequency == 0.0 {
             audio.output_sampling_frequency = audio.sampling_frequency;
         }
 
-        Ok((input, audio))
+        Ok(audio)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0xE1)?;
+        writer.float(0xB5, self.sampling_frequency)?;
+        if self.output_sampling_frequency != self.sampling_frequency {
+            writer.float(0x78B5, self.output_sampling_frequency)?;
+        }
+        writer.unsigned(0x9F, self.channels)?;
+        if self.bit_depth != 0 {
+            writer.unsigned(0x6264, self.bit_depth)?;
+        }
+        writer.end()
     }
 }
 
-#[derive(Default)]
-pub struct ContentEncodings {}
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentEncodings {
+    pub encodings: Vec<ContentEncoding>,
+}
 
 impl ContentEncodings {
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Self> {
-        let (i, _) = ebml::skip(input)?;
-        Ok((i, Self::default()))
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut encodings = ContentEncodings::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0x6240 => encodings.encodings.push(ContentEncoding::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
+            }
+        }
+        Ok(encodings)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x6D80)?;
+        for encoding in &self.encodings {
+            encoding.write(writer)?;
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentEncoding {
+    pub order: u64,
+    pub scope: u64,
+    pub typ3: u64,
+    pub compression: Option<ContentCompression>,
+    pub encryption: Option<ContentEncryption>,
+}
+
+impl ContentEncoding {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut encoding = ContentEncoding {
+            scope: 1,
+            ..Default::default()
+        };
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x5031 => encoding.order = value.try_into()?,
+                    0x5032 => encoding.scope = value.try_into()?,
+                    0x5033 => encoding.typ3 = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(element)) => match element.id {
+                    0x5034 => encoding.compression = Some(ContentCompression::read(reader)?),
+                    0x5035 => encoding.encryption = Some(ContentEncryption::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(encoding)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x6240)?;
+        writer.unsigned(0x5031, self.order)?;
+        writer.unsigned(0x5032, self.scope)?;
+        writer.unsigned(0x5033, self.typ3)?;
+        if let Some(compression) = &self.compression {
+            writer.start(0x5034)?;
+            writer.unsigned(0x4254, compression.algo)?;
+            if !compression.settings.is_empty() {
+                writer.binary(0x4255, &compression.settings)?;
+            }
+            writer.end()?;
+        }
+        if let Some(encryption) = &self.encryption {
+            writer.start(0x5035)?;
+            writer.unsigned(0x47E1, encryption.algo)?;
+            if !encryption.key_id.is_empty() {
+                writer.binary(0x47E2, &encryption.key_id)?;
+            }
+            writer.end()?;
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentCompression {
+    /// 0 for zlib, 3 for header stripping.
+    pub algo: u64,
+    pub settings: Vec<u8>,
+}
+
+impl ContentCompression {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut compression = ContentCompression::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x4254 => compression.algo = value.try_into()?,
+                    0x4255 => compression.settings = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+        Ok(compression)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContentEncryption {
+    pub algo: u64,
+    pub key_id: Vec<u8>,
+}
+
+impl ContentEncryption {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut encryption = ContentEncryption::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x47E1 => encryption.algo = value.try_into()?,
+                    0x47E2 => encryption.key_id = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+        Ok(encryption)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cluster {
+    pub timecode: u64,
+    pub position: u64,
+    pub prev_size: u64,
+    /// From both SimpleBlocks and BlockGroups, in order.
+    pub blocks: Vec<Block>,
+}
+
+impl Cluster {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut cluster = Cluster::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0xE7 => cluster.timecode = value.try_into()?,
+                    0xA7 => cluster.position = value.try_into()?,
+                    0xAB => cluster.prev_size = value.try_into()?,
+                    0xA3 => cluster.blocks.push(Block::read(reader, &element, value)?),
+                    _ => (),
+                },
+                Some(Event::Start(element)) => match element.id {
+                    0xA0 => cluster.blocks.push(Block::read_group(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(cluster)
+    }
+}
+
+/// How the frames of a block are laid out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lacing {
+    /// A single frame.
+    No,
+    Xiph,
+    Fixed,
+    Ebml,
+}
+
+/// How much of a block too large to load is read for its header.
+const BLOCK_HEADER_SIZE: u64 = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub track: u64,
+    /// Relative to the cluster's.
+    pub timecode: i16,
+    pub keyframe: bool,
+    pub invisible: bool,
+    pub discardable: bool,
+    pub lacing: Lacing,
+    /// Where each frame is in the source.
+    pub frames: Vec<Range<u64>>,
+    /// From the BlockGroup, if the block is in one.
+    pub duration: Option<u64>,
+    pub references: Vec<i64>,
+}
+
+impl Block {
+    /// Parses the header of a SimpleBlock or Block. `data` needs to be only
+    /// as long as the header, lace sizes included.
+    pub fn parse(element: &Element, data: &[u8]) -> io::Result<Block> {
+        let short = || invalid(format!("block at {} is cut short", element.offset));
+        let (rest, track) = vint(data).map_err(|_| short())?;
+        if rest.len() < 3 {
+            return Err(short());
+        }
+        let timecode = i16::from_be_bytes([rest[0], rest[1]]);
+        let flags = rest[2];
+        let mut rest = &rest[3..];
+
+        let lacing = match flags & 0x06 {
+            0x00 => Lacing::No,
+            0x02 => Lacing::Xiph,
+            0x04 => Lacing::Fixed,
+            _ => Lacing::Ebml,
+        };
+        let count = match lacing {
+            Lacing::No => 1,
+            _ => {
+                let (&count, tail) = rest.split_first().ok_or_else(short)?;
+                rest = tail;
+                count as usize + 1
+            }
+        };
+        // the sizes of all frames but the last
+        let mut sizes = Vec::with_capacity(count);
+        match lacing {
+            Lacing::Xiph => {
+                for _ in 1..count {
+                    let mut size = 0;
+                    loop {
+                        let (&byte, tail) = rest.split_first().ok_or_else(short)?;
+                        rest = tail;
+                        size += byte as u64;
+                        if byte != 0xFF {
+                            break;
+                        }
+                    }
+                    sizes.push(size);
+                }
+            }
+            Lacing::Ebml if count > 1 => {
+                let (tail, first) = vint(rest).map_err(|_| short())?;
+                rest = tail;
+                sizes.push(first);
+                for _ in 2..count {
+                    let (tail, raw) = vint(rest).map_err(|_| short())?;
+                    // signed, offset by half the range of its length
+                    let bias = (1i64 << (7 * (rest.len() - tail.len()) - 1)) - 1;
+                    // sizes come from the file, so guard against overflow
+                    let last: Option<i64> = (*sizes.last().unwrap()).try_into().ok();
+                    let size = last
+                        .and_then(|last| last.checked_add(raw as i64))
+                        .and_then(|size| size.checked_sub(bias))
+                        .filter(|size| *size >= 0)
+                        .ok_or_else(|| {
+                            invalid(format!("bad lace size in block at {}", element.offset))
+                        })?;
+                    rest = tail;
+                    sizes.push(size as u64);
+                }
+            }
+            _ => (),
+        }
+
+        let header_len = (data.len() - rest.len()) as u64;
+        let total = element
+            .size
+            .and_then(|size| size.checked_sub(header_len))
+            .ok_or_else(short)?;
+        if lacing == Lacing::Fixed {
+            if total % count as u64 != 0 {
+                return Err(invalid(format!(
+                    "uneven fixed lacing in block at {}",
+                    element.offset
+                )));
+            }
+            sizes.resize(count - 1, total / count as u64);
+        }
+        let last = sizes
+            .iter()
+            .try_fold(0u64, |sum, size| sum.checked_add(*size))
+            .and_then(|sum| total.checked_sub(sum))
+            .ok_or_else(|| invalid(format!("lace sizes overrun block at {}", element.offset)))?;
+        sizes.push(last);
+
+        let mut start = element.data_offset + header_len;
+        let frames = sizes
+            .into_iter()
+            .map(|size| {
+                start += size;
+                start - size..start
+            })
+            .collect();
+
+        Ok(Block {
+            track,
+            timecode,
+            keyframe: flags & 0x80 != 0,
+            invisible: flags & 0x08 != 0,
+            discardable: flags & 0x01 != 0,
+            lacing,
+            frames,
+            duration: None,
+            references: Vec::new(),
+        })
+    }
+
+    /// Parses the header of a SimpleBlock or Block that `reader` has just
+    /// reported, reading it back from the source if it was too large to load.
+    pub fn read<R: Read + io::Seek>(
+        reader: &mut Reader<R>,
+        element: &Element,
+        value: Value,
+    ) -> io::Result<Block> {
+        let data = match value {
+            Value::Unread => {
+                let size = element.size.unwrap_or_default().min(BLOCK_HEADER_SIZE);
+                let mut data = vec![0u8; size as usize];
+                reader.read_at(element.data_offset, &mut data)?;
+                data
+            }
+            value => value.try_into()?,
+        };
+        Block::parse(element, &data)
+    }
+
+    /// Reads the children of a BlockGroup once `reader` has started it.
+    pub fn read_group<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Block> {
+        let mut block = None;
+        let mut duration = None;
+        let mut references = Vec::new();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0xA1 => block = Some(Block::read(reader, &element, value)?),
+                    0x9B => duration = Some(value.try_into()?),
+                    0xFB => references.push(value.try_into()?),
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+
+        let mut block = block.ok_or_else(|| invalid("block group without a block"))?;
+        // only SimpleBlocks have the flag
+        block.keyframe = references.is_empty();
+        block.duration = duration;
+        block.references = references;
+        Ok(block)
+    }
+
+    /// The data of a SimpleBlock with `frames` laced as `self.lacing` says.
+    /// `self.frames` is ignored.
+    pub fn encode<F: AsRef<[u8]>>(&self, frames: &[F]) -> io::Result<Vec<u8>> {
+        let sizes: Vec<u64> = frames.iter().map(|f| f.as_ref().len() as u64).collect();
+        let count = sizes.len();
+        if count == 0 || (self.lacing == Lacing::No && count > 1) || count > 256 {
+            return Err(invalid(format!("can't lace {} frames", count)));
+        }
+
+        let mut data = encode_vint(self.track);
+        data.extend(&self.timecode.to_be_bytes());
+        let lacing = match self.lacing {
+            Lacing::No => 0x00,
+            Lacing::Xiph => 0x02,
+            Lacing::Fixed => 0x04,
+            Lacing::Ebml => 0x06,
+        };
+        data.push(
+            lacing
+                | if self.keyframe { 0x80 } else { 0 }
+                | if self.invisible { 0x08 } else { 0 }
+                | if self.discardable { 0x01 } else { 0 },
+        );
+        if self.lacing != Lacing::No {
+            data.push((count - 1) as u8);
+        }
+        match self.lacing {
+            Lacing::Xiph => {
+                for &size in &sizes[..count - 1] {
+                    data.extend(vec![0xFF; (size / 255) as usize]);
+                    data.push((size % 255) as u8);
+                }
+            }
+            Lacing::Fixed if sizes.iter().any(|&size| size != sizes[0]) => {
+                return Err(invalid("frames of different sizes for fixed lacing"));
+            }
+            Lacing::Ebml if count > 1 => {
+                data.extend(encode_vint(sizes[0]));
+                for pair in sizes[..count - 1].windows(2) {
+                    let diff = pair[1] as i64 - pair[0] as i64;
+                    // the shortest length whose range holds it
+                    let len = (1..=8)
+                        .find(|&len| diff.abs() < 1 << (7 * len - 1))
+                        .unwrap_or(8);
+                    let bias = (1i64 << (7 * len - 1)) - 1;
+                    data.extend(encode_vint_len((diff + bias) as u64, len));
+                }
+            }
+            _ => (),
+        }
+        for frame in frames {
+            data.extend(frame.as_ref());
+        }
+        Ok(data)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cues {
+    pub points: Vec<CuePoint>,
+}
+
+impl Cues {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut cues = Cues::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0xBB => cues.points.push(CuePoint::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
+            }
+        }
+        Ok(cues)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x1C53BB6B)?;
+        for point in &self.points {
+            writer.start(0xBB)?;
+            writer.unsigned(0xB3, point.time)?;
+            for positions in &point.positions {
+                writer.start(0xB7)?;
+                writer.unsigned(0xF7, positions.track)?;
+                writer.unsigned(0xF1, positions.cluster_position)?;
+                if positions.relative_position != 0 {
+                    writer.unsigned(0xF0, positions.relative_position)?;
+                }
+                if positions.duration != 0 {
+                    writer.unsigned(0xB2, positions.duration)?;
+                }
+                if positions.block_number != 1 {
+                    writer.unsigned(0x5378, positions.block_number)?;
+                }
+                writer.end()?;
+            }
+            writer.end()?;
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CuePoint {
+    pub time: u64,
+    pub positions: Vec<CueTrackPositions>,
+}
+
+impl CuePoint {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut point = CuePoint::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) if element.id == 0xB3 => {
+                    point.time = value.try_into()?
+                }
+                Some(Event::Value(..)) => (),
+                Some(Event::Start(element)) => match element.id {
+                    0xB7 => point.positions.push(CueTrackPositions::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(point)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CueTrackPositions {
+    pub track: u64,
+    /// Relative to the start of the segment's content.
+    pub cluster_position: u64,
+    /// Of the block, relative to the start of the cluster's content.
+    pub relative_position: u64,
+    pub duration: u64,
+    pub block_number: u64,
+}
+
+impl CueTrackPositions {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut positions = CueTrackPositions {
+            block_number: 1,
+            ..Default::default()
+        };
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0xF7 => positions.track = value.try_into()?,
+                    0xF1 => positions.cluster_position = value.try_into()?,
+                    0xF0 => positions.relative_position = value.try_into()?,
+                    0xB2 => positions.duration = value.try_into()?,
+                    0x5378 => positions.block_number = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+        Ok(positions)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chapters {
+    pub editions: Vec<Edition>,
+}
+
+impl Chapters {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut chapters = Chapters::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0x45B9 => chapters.editions.push(Edition::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
+            }
+        }
+        Ok(chapters)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Edition {
+    pub uid: u64,
+    pub hidden: bool,
+    pub default: bool,
+    pub ordered: bool,
+    pub atoms: Vec<ChapterAtom>,
+}
+
+impl Edition {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut edition = Edition::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x45BC => edition.uid = value.try_into()?,
+                    0x45BD => edition.hidden = value.try_into()?,
+                    0x45DB => edition.default = value.try_into()?,
+                    0x45DD => edition.ordered = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(element)) => match element.id {
+                    0xB6 => edition.atoms.push(ChapterAtom::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(edition)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChapterAtom {
+    pub uid: u64,
+    pub string_uid: String,
+    /// In nanoseconds.
+    pub time_start: u64,
+    pub time_end: u64,
+    pub hidden: bool,
+    pub enabled: bool,
+    /// The UIDs of the tracks the chapter applies to; all of them if empty.
+    pub tracks: Vec<u64>,
+    pub displays: Vec<ChapterDisplay>,
+    /// Nested chapters.
+    pub atoms: Vec<ChapterAtom>,
+}
+
+impl ChapterAtom {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut atom = ChapterAtom {
+            enabled: true,
+            ..Default::default()
+        };
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x73C4 => atom.uid = value.try_into()?,
+                    0x5654 => atom.string_uid = value.try_into()?,
+                    0x91 => atom.time_start = value.try_into()?,
+                    0x92 => atom.time_end = value.try_into()?,
+                    0x98 => atom.hidden = value.try_into()?,
+                    0x4598 => atom.enabled = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(element)) => match element.id {
+                    0x8F => atom.tracks.extend(chapter_tracks(reader)?),
+                    0x80 => atom.displays.push(ChapterDisplay::read(reader)?),
+                    0xB6 => atom.atoms.push(ChapterAtom::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(atom)
     }
 }
 
-pub fn cluster(input: &[u8]) -> IResult<&[u8], Level1Element> {
-    let (input, size) = vint(input)?;
-    let (input, _) = nom::take!(input, size)?;
-    Ok((input, Level1Element::Cluster))
+fn chapter_tracks<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Vec<u64>> {
+    let mut tracks = Vec::new();
+    loop {
+        match reader.next_event()? {
+            Some(Event::Value(element, value)) if element.id == 0x89 => {
+                tracks.push(value.try_into()?)
+            }
+            Some(Event::Value(..)) => (),
+            Some(Event::Start(_)) => reader.skip_element()?,
+            _ => break,
+        }
+    }
+    Ok(tracks)
 }
 
-pub fn chapters(input: &[u8]) -> IResult<&[u8], Level1Element> {
-    let (input, size) = vint(input)?;
-    let (input, _) = nom::take!(input, size)?;
-    Ok((input, Level1Element::Chapters))
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChapterDisplay {
+    pub string: String,
+    pub languages: Vec<String>,
+    pub countries: Vec<String>,
 }
 
-pub fn tags(input: &[u8]) -> IResult<&[u8], Level1Element> {
-    let (input, size) = vint(input)?;
-    let (input, _) = nom::take!(input, size)?;
-    Ok((input, Level1Element::Tags))
+impl ChapterDisplay {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut display = ChapterDisplay::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x85 => display.string = value.try_into()?,
+                    0x437C => display.languages.push(value.try_into()?),
+                    0x437E => display.countries.push(value.try_into()?),
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+        if display.languages.is_empty() {
+            display.languages.push("eng".into());
+        }
+        Ok(display)
+    }
 }
 
-pub fn attachments(input: &[u8]) -> IResult<&[u8], Level1Element> {
-    let (input, size) = vint(input)?;
-    let (input, _) = nom::take!(input, size)?;
-    Ok((input, Level1Element::Attachments))
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tags {
+    pub tags: Vec<Tag>,
 }
 
-pub fn cues(input: &[u8]) -> IResult<&[u8], Level1Element> {
-    let (input, size) = vint(input)?;
-    let (input, _) = nom::take!(input, size)?;
-    Ok((input, Level1Element::Cues))
+impl Tags {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut tags = Tags::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0x7373 => tags.tags.push(Tag::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
+            }
+        }
+        Ok(tags)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x1254C367)?;
+        for tag in &self.tags {
+            writer.start(0x7373)?;
+            tag.targets.write(writer)?;
+            for simple_tag in &tag.simple_tags {
+                simple_tag.write(writer)?;
+            }
+            writer.end()?;
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tag {
+    pub targets: Targets,
+    pub simple_tags: Vec<SimpleTag>,
+}
+
+impl Tag {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut tag = Tag {
+            targets: Targets::new(),
+            ..Default::default()
+        };
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0x63C0 => tag.targets = Targets::read(reader)?,
+                    0x67C8 => tag.simple_tags.push(SimpleTag::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
+            }
+        }
+        Ok(tag)
+    }
+}
+
+/// What a tag is about: the whole segment when no UIDs are given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Targets {
+    pub type_value: u64,
+    pub typ3: String,
+    pub track_uids: Vec<u64>,
+    pub edition_uids: Vec<u64>,
+    pub chapter_uids: Vec<u64>,
+    pub attachment_uids: Vec<u64>,
+}
+
+impl Targets {
+    pub fn new() -> Self {
+        Targets {
+            type_value: 50,
+            ..Default::default()
+        }
+    }
+
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut targets = Targets::new();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x68CA => targets.type_value = value.try_into()?,
+                    0x63CA => targets.typ3 = value.try_into()?,
+                    0x63C5 => targets.track_uids.push(value.try_into()?),
+                    0x63C9 => targets.edition_uids.push(value.try_into()?),
+                    0x63C4 => targets.chapter_uids.push(value.try_into()?),
+                    0x63C6 => targets.attachment_uids.push(value.try_into()?),
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+        Ok(targets)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x63C0)?;
+        writer.unsigned(0x68CA, self.type_value)?;
+        if !self.typ3.is_empty() {
+            writer.string(0x63CA, &self.typ3)?;
+        }
+        for (id, uids) in [
+            (0x63C5, &self.track_uids),
+            (0x63C9, &self.edition_uids),
+            (0x63C4, &self.chapter_uids),
+            (0x63C6, &self.attachment_uids),
+        ] {
+            for &uid in uids {
+                writer.unsigned(id, uid)?;
+            }
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimpleTag {
+    pub name: String,
+    pub language: String,
+    pub default: bool,
+    pub string: String,
+    pub binary: Vec<u8>,
+    /// Tags about this one.
+    pub simple_tags: Vec<SimpleTag>,
+}
+
+impl SimpleTag {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut tag = SimpleTag {
+            language: "und".into(),
+            default: true,
+            ..Default::default()
+        };
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x45A3 => tag.name = value.try_into()?,
+                    0x447A => tag.language = value.try_into()?,
+                    0x4484 => tag.default = value.try_into()?,
+                    0x4487 => tag.string = value.try_into()?,
+                    0x4485 => tag.binary = value.try_into()?,
+                    _ => (),
+                },
+                Some(Event::Start(element)) => match element.id {
+                    0x67C8 => tag.simple_tags.push(SimpleTag::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                _ => break,
+            }
+        }
+        Ok(tag)
+    }
+
+    pub fn write<W: Write + io::Seek>(&self, writer: &mut Writer<W>) -> io::Result<()> {
+        writer.start(0x67C8)?;
+        writer.string(0x45A3, &self.name)?;
+        writer.string(0x447A, &self.language)?;
+        if !self.default {
+            writer.unsigned(0x4484, 0)?;
+        }
+        if !self.string.is_empty() {
+            writer.string(0x4487, &self.string)?;
+        }
+        if !self.binary.is_empty() {
+            writer.binary(0x4485, &self.binary)?;
+        }
+        for simple_tag in &self.simple_tags {
+            simple_tag.write(writer)?;
+        }
+        writer.end()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attachments {
+    pub files: Vec<AttachedFile>,
+}
+
+impl Attachments {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut attachments = Attachments::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Start(element)) => match element.id {
+                    0x61A7 => attachments.files.push(AttachedFile::read(reader)?),
+                    _ => reader.skip_element()?,
+                },
+                Some(Event::Value(..)) => (),
+                _ => break,
+            }
+        }
+        Ok(attachments)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttachedFile {
+    pub description: String,
+    pub name: String,
+    pub mime_type: String,
+    pub uid: u64,
+    /// Where the content is in the source; it isn't loaded.
+    pub data: Range<u64>,
+}
+
+impl AttachedFile {
+    pub fn read<R: Read + io::Seek>(reader: &mut Reader<R>) -> io::Result<Self> {
+        let mut file = AttachedFile::default();
+        loop {
+            match reader.next_event()? {
+                Some(Event::Value(element, value)) => match element.id {
+                    0x467E => file.description = value.try_into()?,
+                    0x466E => file.name = value.try_into()?,
+                    0x4660 => file.mime_type = value.try_into()?,
+                    0x46AE => file.uid = value.try_into()?,
+                    0x465C => file.data = element.data_offset..element.end().unwrap_or_default(),
+                    _ => (),
+                },
+                Some(Event::Start(_)) => reader.skip_element()?,
+                _ => break,
+            }
+        }
+        Ok(file)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ebml;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
 
-    const WEBM: &[u8] = include_bytes!("../assets/big-buck-bunny_trailer.webm");
+    fn open(name: &str) -> Reader<BufReader<File>> {
+        let path = format!("{}/assets/{}", env!("CARGO_MANIFEST_DIR"), name);
+        Reader::new(BufReader::new(File::open(path).unwrap()), element_type)
+    }
 
     #[test]
     fn test_webm_segment() {
-        let res = ebml::parse(WEBM);
-        assert!(res.is_ok());
-        let (_, (_, segment)) = res.unwrap();
-
-        let input = segment.content;
+        let mut reader = open("big-buck-bunny_trailer.webm");
+        let (_, segment) = ebml::read(&mut reader).unwrap();
 
-        let res = Level1Element::parse(input);
-        assert!(res.is_ok());
-        let (input, element) = res.unwrap();
-        match element {
-            Level1Element::SeekHead(_) => (),
+        match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::SeekHead(seek_head)) => assert!(!seek_head.positions.is_empty()),
             _ => panic!(),
         }
 
-        let res = Level1Element::parse(input);
-        assert!(res.is_ok());
-        let (input, element) = res.unwrap();
-        match element {
-            Level1Element::Info(_) => (),
+        match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::Info(info)) => {
+                assert_eq!(info.timecode_scale, 1_000_000);
+                assert!(info.duration > 0.0);
+            }
             _ => panic!(),
         }
 
-        let res = Level1Element::parse(input);
-        assert!(res.is_ok());
-        let (input, element) = res.unwrap();
-        match element {
-            Level1Element::Tracks(_) => (),
+        match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::Tracks(tracks)) => {
+                let video = &tracks.tracks[0];
+                assert_eq!(video.codec_id, "V_VP8");
+                assert_eq!(video.video.display_width, video.video.pixel_width);
+            }
             _ => panic!(),
         }
 
-        let res = Level1Element::parse(input);
-        assert!(res.is_ok());
-        let (input, element) = res.unwrap();
-        match element {
-            Level1Element::Cues => (),
+        let cues = match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::Cues(cues)) => cues,
+            _ => panic!(),
+        };
+        assert!(cues.points.len() > 1);
+        assert!(cues.points.windows(2).all(|p| p[0].time < p[1].time));
+
+        let offset = reader.position();
+        match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::Cluster(cluster)) => {
+                let position = cues.points[0].positions[0].cluster_position;
+                assert_eq!(segment.element.data_offset + position, offset);
+                assert_eq!(cluster.timecode, 0);
+                assert!(cluster.blocks[0].keyframe);
+                assert!(cluster.blocks.iter().any(|b| b.track == 1));
+                assert!(cluster.blocks.iter().any(|b| b.track == 2));
+                assert!(cluster
+                    .blocks
+                    .iter()
+                    .all(|b| b.frames[0].end <= reader.position()));
+            }
             _ => panic!(),
         }
 
-        let res = Level1Element::parse(input);
-        assert!(res.is_ok());
-        let (_input, element) = res.unwrap();
-        match element {
-            Level1Element::Cluster => (),
+        // another seek head at the end
+        match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::SeekHead(_)) => (),
             _ => panic!(),
         }
+
+        assert!(Level1Element::read(&mut reader).unwrap().is_none());
+        assert_eq!(Some(reader.position()), segment.element.end());
+    }
+
+    #[test]
+    fn test_mkv_segment() {
+        let mut reader = open("single_stream.mkv");
+        let (_, segment) = ebml::read(&mut reader).unwrap();
+
+        let mut tags = None;
+        let mut cluster = None;
+        let mut cues = None;
+        while let Some(element) = Level1Element::read(&mut reader).unwrap() {
+            match element {
+                Level1Element::Tags(t) => tags = Some(t),
+                Level1Element::Cluster(c) => cluster = Some(c),
+                Level1Element::Cues(c) => cues = Some(c),
+                _ => (),
+            }
+        }
+
+        let tags = tags.unwrap().tags;
+        assert_eq!(tags.len(), 2);
+        assert!(tags[0].targets.track_uids.is_empty());
+        assert_eq!(tags[0].targets.type_value, 50);
+        assert_eq!(tags[1].targets.track_uids, [1]);
+        assert_eq!(tags[1].simple_tags[0].name, "ENCODER");
+        assert_eq!(tags[1].simple_tags[0].string, "Lavc57.28.4 libx264");
+        assert_eq!(tags[1].simple_tags[0].language, "und");
+
+        let cluster = cluster.unwrap();
+        assert_eq!(cluster.timecode, 80);
+        assert_eq!(cluster.blocks.len(), 25);
+        assert!(cluster.blocks[0].keyframe);
+        let mut end = None;
+        for block in &cluster.blocks {
+            assert_eq!(block.track, 1);
+            assert_eq!(block.lacing, Lacing::No);
+            assert_eq!(block.frames.len(), 1);
+            // frames follow each other, a few bytes of header apart
+            assert!(end.is_none_or(|end| block.frames[0].start > end));
+            end = Some(block.frames[0].end);
+        }
+
+        let cues = cues.unwrap();
+        assert_eq!(cues.points.len(), 1);
+        assert_eq!(cues.points[0].time, 80);
+        assert_eq!(cues.points[0].positions[0].track, 1);
+        assert_eq!(cues.points[0].positions[0].block_number, 1);
+        assert_eq!(
+            segment.element.data_offset + cues.points[0].positions[0].cluster_position,
+            629
+        );
+    }
+
+    fn block(data: &[u8]) -> Block {
+        let element = Element {
+            id: 0xA3,
+            offset: 0,
+            data_offset: 3,
+            size: Some(data.len() as u64),
+        };
+        Block::parse(&element, data).unwrap()
+    }
+
+    fn sizes(block: &Block) -> Vec<u64> {
+        block.frames.iter().map(|f| f.end - f.start).collect()
+    }
+
+    #[test]
+    fn test_lacing() {
+        let mut data = vec![0x81, 0xFF, 0xFE, 0x80];
+        data.extend(vec![0; 10]);
+        let b = block(&data);
+        assert_eq!((b.track, b.timecode, b.keyframe), (1, -2, true));
+        assert_eq!(b.lacing, Lacing::No);
+        assert_eq!(b.frames[0], 7..17);
+
+        // 260, 10 and 7 bytes
+        let mut data = vec![0x82, 0x00, 0x10, 0x02, 0x02, 0xFF, 0x05, 0x0A];
+        data.extend(vec![0; 277]);
+        let b = block(&data);
+        assert_eq!((b.track, b.timecode, b.keyframe), (2, 16, false));
+        assert_eq!(b.lacing, Lacing::Xiph);
+        assert_eq!(sizes(&b), [260, 10, 7]);
+        assert_eq!(b.frames[0].start, 3 + 8);
+
+        // 300, 298 and 5 bytes
+        let mut data = vec![0x81, 0x00, 0x00, 0x06, 0x02, 0x41, 0x2C, 0xBD];
+        data.extend(vec![0; 603]);
+        let b = block(&data);
+        assert_eq!(b.lacing, Lacing::Ebml);
+        assert_eq!(sizes(&b), [300, 298, 5]);
+
+        let mut data = vec![0x81, 0x00, 0x00, 0x84, 0x03];
+        data.extend(vec![0; 24]);
+        let b = block(&data);
+        assert_eq!(b.lacing, Lacing::Fixed);
+        assert_eq!(sizes(&b), [6, 6, 6, 6]);
+
+        let element = Element {
+            id: 0xA3,
+            offset: 0,
+            data_offset: 3,
+            size: Some(26),
+        };
+        assert!(Block::parse(&element, &data[..26]).is_err());
+        assert!(Block::parse(&element, &[0x81, 0x00]).is_err());
+
+        // 255 EBML-laced frames, each 2^55 bytes larger than the last
+        let mut data = vec![0x81, 0x00, 0x00, 0x06, 0xFE];
+        data.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        for _ in 2..255 {
+            data.extend([0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]);
+        }
+        let element = Element {
+            id: 0xA3,
+            offset: 0,
+            data_offset: 3,
+            size: Some(data.len() as u64),
+        };
+        assert!(Block::parse(&element, &data).is_err());
+    }
+
+    // an element with a one-byte size
+    fn element(id: &[u8], data: &[&[u8]]) -> Vec<u8> {
+        let data = data.concat();
+        [id, &[0x80 | data.len() as u8], &data].concat()
+    }
+
+    #[test]
+    fn test_chapters_attachments() {
+        let nested = element(&[0xB6], &[&element(&[0x73, 0xC4], &[&[3]])]);
+        let display = element(&[0x80], &[&element(&[0x85], &[b"Intro"])]);
+        let atom = element(
+            &[0xB6],
+            &[
+                &element(&[0x73, 0xC4], &[&[2]]),
+                &element(&[0x92], &[&[0x03, 0xE8]]),
+                &display,
+                &nested,
+            ],
+        );
+        let edition = element(&[0x45, 0xB9], &[&element(&[0x45, 0xBC], &[&[1]]), &atom]);
+        let chapters = element(&[0x10, 0x43, 0xA7, 0x70], &[&edition]);
+
+        let file = element(
+            &[0x61, 0xA7],
+            &[
+                &element(&[0x46, 0x6E], &[b"cover.jpg"]),
+                &element(&[0x46, 0x60], &[b"image/jpeg"]),
+                &element(&[0x46, 0x5C], &[&[0xFF; 10]]),
+            ],
+        );
+        let attachments = element(&[0x19, 0x41, 0xA4, 0x69], &[&file]);
+
+        let source = [chapters.clone(), attachments].concat();
+        let mut reader = Reader::new(Cursor::new(source), element_type);
+
+        let chapters = match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::Chapters(chapters)) => chapters,
+            _ => panic!(),
+        };
+        let edition = &chapters.editions[0];
+        assert_eq!(edition.uid, 1);
+        let atom = &edition.atoms[0];
+        assert_eq!((atom.uid, atom.time_start, atom.time_end), (2, 0, 1000));
+        assert!(atom.enabled);
+        assert_eq!(atom.displays[0].string, "Intro");
+        assert_eq!(atom.displays[0].languages, ["eng"]);
+        assert_eq!(atom.atoms[0].uid, 3);
+
+        let attachments = match Level1Element::read(&mut reader).unwrap() {
+            Some(Level1Element::Attachments(attachments)) => attachments,
+            _ => panic!(),
+        };
+        let file = &attachments.files[0];
+        assert_eq!(file.name, "cover.jpg");
+        assert_eq!(file.mime_type, "image/jpeg");
+        let end = reader.position();
+        assert_eq!(file.data, end - 10..end);
     }
 }
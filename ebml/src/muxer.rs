@@ -0,0 +1,575 @@
+//! Writing Matroska files from the structs `matroska` reads them into, and
+//! remuxing one file into another with that.
+
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use crate::ebml::{self, EBMLHeader, EBMLSegment};
+use crate::matroska::{
+    element_type, Block, Cluster, CuePoint, CueTrackPositions, Cues, Info, Level1Element,
+    SimpleTag, Tag, Tags, Targets, Tracks,
+};
+use crate::reader::{invalid, Event, Reader};
+use crate::writer::{encode_id, Writer};
+
+/// Room kept at the start of the segment for the seek head, which can only
+/// be written once everything it points to is.
+const SEEK_HEAD_SPACE: usize = 192;
+
+/// Level-1 masters that `remux` copies without looking inside: chapters and
+/// attachments.
+const COPIED: [u64; 2] = [0x1043A770, 0x1941A469];
+
+/// Writes the header, then whatever the caller has: info and tracks first,
+/// copied masters and tags if any, then clusters. Cues for the keyframes of the first video
+/// track, or the first track if there is no video, come last, and the seek
+/// head at the start is filled in by `finish`.
+pub struct Muxer<W: Write + Seek> {
+    writer: Writer<W>,
+    /// Where the content of the segment starts, which positions are
+    /// relative to.
+    segment: u64,
+    /// The level-1 elements written so far and where.
+    seeks: Vec<(u64, u64)>,
+    cue_track: Option<u64>,
+    cues: Cues,
+    /// The timecode of the open cluster, where it is and where its data is.
+    cluster: Option<(u64, u64, u64)>,
+}
+
+impl<W: Write + Seek> Muxer<W> {
+    pub fn new(inner: W, header: &EBMLHeader) -> io::Result<Self> {
+        let mut writer = Writer::new(inner);
+        header.write(&mut writer)?;
+        writer.start(EBMLSegment::ID)?;
+        let segment = writer.position();
+        // Voids until the seek head takes their place
+        void(&mut writer, SEEK_HEAD_SPACE)?;
+        Ok(Muxer {
+            writer,
+            segment,
+            seeks: Vec::new(),
+            cue_track: None,
+            cues: Cues::default(),
+            cluster: None,
+        })
+    }
+
+    fn seek_entry(&mut self, id: u64) {
+        self.seeks.push((id, self.writer.position() - self.segment));
+    }
+
+    pub fn write_info(&mut self, info: &Info) -> io::Result<()> {
+        self.seek_entry(0x1549A966);
+        info.write(&mut self.writer)
+    }
+
+    pub fn write_tracks(&mut self, tracks: &Tracks) -> io::Result<()> {
+        self.seek_entry(0x1654AE6B);
+        self.cue_track = tracks
+            .tracks
+            .iter()
+            .find(|t| t.typ3 == 1)
+            .or_else(|| tracks.tracks.first())
+            .map(|t| t.number);
+        tracks.write(&mut self.writer)
+    }
+
+    /// Writes a level-1 master `id` whose content, `data`, was read as is
+    /// from another file.
+    pub fn write_master(&mut self, id: u64, data: &[u8]) -> io::Result<()> {
+        self.seek_entry(id);
+        self.writer.binary(id, data)
+    }
+
+    pub fn write_tags(&mut self, tags: &Tags) -> io::Result<()> {
+        self.seek_entry(0x1254C367);
+        tags.write(&mut self.writer)
+    }
+
+    /// Ends the open cluster, if any, and starts another. Block timecodes
+    /// are relative to `timecode` and must fit in 16 bits.
+    pub fn start_cluster(&mut self, timecode: u64) -> io::Result<()> {
+        self.end_cluster()?;
+        let offset = self.writer.position();
+        self.writer.start(0x1F43B675)?;
+        let data_offset = self.writer.position();
+        self.writer.unsigned(0xE7, timecode)?;
+        self.cluster = Some((timecode, offset, data_offset));
+        Ok(())
+    }
+
+    fn end_cluster(&mut self) -> io::Result<()> {
+        if self.cluster.take().is_some() {
+            self.writer.end()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a SimpleBlock of `frames` with the track, timecode, flags and
+    /// lacing of `block`. Durations and references of blocks from a
+    /// BlockGroup are not kept.
+    pub fn write_block<F: AsRef<[u8]>>(&mut self, block: &Block, frames: &[F]) -> io::Result<()> {
+        let (timecode, offset, data_offset) = self
+            .cluster
+            .ok_or_else(|| invalid("block outside a cluster"))?;
+        if block.keyframe && Some(block.track) == self.cue_track {
+            let time = (timecode as i64 + block.timecode as i64).max(0) as u64;
+            if self.cues.points.last().is_none_or(|p| p.time < time) {
+                self.cues.points.push(CuePoint {
+                    time,
+                    positions: vec![CueTrackPositions {
+                        track: block.track,
+                        cluster_position: offset - self.segment,
+                        relative_position: self.writer.position() - data_offset,
+                        duration: 0,
+                        block_number: 1,
+                    }],
+                });
+            }
+        }
+        self.writer.binary(0xA3, &block.encode(frames)?)
+    }
+
+    /// Writes the cues and the seek head, and ends the segment.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_cluster()?;
+        if !self.cues.points.is_empty() {
+            self.seek_entry(0x1C53BB6B);
+            let cues = std::mem::take(&mut self.cues);
+            cues.write(&mut self.writer)?;
+        }
+
+        // a seek head with minimal sizes, then Voids for the rest of the room
+        let mut seeks = Writer::new(Cursor::new(Vec::new()));
+        for &(id, position) in &self.seeks {
+            let mut seek = Writer::new(Cursor::new(Vec::new()));
+            seek.binary(0x53AB, &encode_id(id))?;
+            seek.unsigned(0x53AC, position)?;
+            seeks.binary(0x4DBB, &seek.into_inner()?.into_inner())?;
+        }
+        let mut space = Writer::new(Cursor::new(Vec::new()));
+        space.binary(0x114D9B74, &seeks.into_inner()?.into_inner())?;
+        let len = space.position() as usize;
+        if len + 2 > SEEK_HEAD_SPACE {
+            return Err(invalid("too many seek entries"));
+        }
+        void(&mut space, SEEK_HEAD_SPACE - len)?;
+        let space = space.into_inner()?.into_inner();
+        self.writer.write_at(self.segment, &space)?;
+
+        self.writer.into_inner()
+    }
+}
+
+/// Fills `len` bytes, at least 2, with Voids of up to 128 bytes each, whose
+/// sizes fit in one byte.
+fn void<W: Write + Seek>(writer: &mut Writer<W>, mut len: usize) -> io::Result<()> {
+    while len > 0 {
+        let mut chunk = len.min(128);
+        // no element fits in a single byte
+        if len - chunk == 1 {
+            chunk -= 1;
+        }
+        writer.binary(0xEC, &vec![0; chunk - 2])?;
+        len -= chunk;
+    }
+    Ok(())
+}
+
+/// What `remux` changes on the way.
+#[derive(Default)]
+pub struct Edits {
+    /// Track numbers to leave out, with their blocks and tags.
+    pub drop_tracks: Vec<u64>,
+    pub title: Option<String>,
+    /// Tags about the whole segment to set, or to remove if `None`.
+    pub tags: Vec<(String, Option<String>)>,
+}
+
+impl Edits {
+    fn apply(&self, info: &mut Info, tracks: &mut Tracks, tags: &mut Tags) {
+        if let Some(title) = &self.title {
+            info.title = title.clone();
+        }
+
+        let dropped: Vec<u64> = tracks
+            .tracks
+            .iter()
+            .filter(|t| self.drop_tracks.contains(&t.number))
+            .map(|t| t.uid)
+            .collect();
+        tracks
+            .tracks
+            .retain(|t| !self.drop_tracks.contains(&t.number));
+        tags.tags.retain_mut(|tag| {
+            let uids = &mut tag.targets.track_uids;
+            let only_dropped = !uids.is_empty() && uids.iter().all(|uid| dropped.contains(uid));
+            uids.retain(|uid| !dropped.contains(uid));
+            !only_dropped
+        });
+
+        for (name, value) in &self.tags {
+            let global = tags.tags.iter().position(|tag| {
+                let t = &tag.targets;
+                t.type_value == 50
+                    && t.track_uids.is_empty()
+                    && t.edition_uids.is_empty()
+                    && t.chapter_uids.is_empty()
+                    && t.attachment_uids.is_empty()
+            });
+            let index = match global {
+                Some(index) => index,
+                None => {
+                    tags.tags.push(Tag {
+                        targets: Targets::new(),
+                        simple_tags: Vec::new(),
+                    });
+                    tags.tags.len() - 1
+                }
+            };
+            let simple_tags = &mut tags.tags[index].simple_tags;
+            simple_tags.retain(|t| &t.name != name);
+            if let Some(value) = value {
+                simple_tags.push(SimpleTag {
+                    name: name.clone(),
+                    language: "und".into(),
+                    default: true,
+                    string: value.clone(),
+                    ..Default::default()
+                });
+            }
+            if simple_tags.is_empty() {
+                tags.tags.remove(index);
+            }
+        }
+    }
+}
+
+/// Copies the Matroska file in `input` to `output` with `edits`. Info,
+/// tracks, tags and clusters are carried over, and chapters and attachments
+/// copied byte for byte; cues and the seek head are made anew, and anything
+/// else is left behind.
+pub fn remux<R: Read + Seek, W: Write + Seek>(
+    mut input: R,
+    output: W,
+    edits: &Edits,
+) -> io::Result<W> {
+    let start = input.stream_position()?;
+
+    // all but the clusters, which are skipped the first time through
+    let mut reader = Reader::new(input, element_type);
+    let (header, _) = ebml::read(&mut reader)?;
+    let mut info = None;
+    let mut tracks = None;
+    let mut tags = Tags::default();
+    let mut copied = Vec::new();
+    loop {
+        match reader.next_event()? {
+            Some(Event::Start(element)) if element.id == 0x1F43B675 => reader.skip_element()?,
+            Some(Event::Start(element)) if reader.depth() == 2 && COPIED.contains(&element.id) => {
+                let size = element
+                    .size
+                    .ok_or_else(|| invalid("chapters or attachments of unknown size"))?;
+                let mut data = vec![0u8; size as usize];
+                reader.read_at(element.data_offset, &mut data)?;
+                copied.push((element.id, data));
+                reader.skip_element()?;
+            }
+            Some(Event::Start(element)) if reader.depth() == 2 => {
+                match Level1Element::read_started(&mut reader, &element)? {
+                    Level1Element::Info(i) => info = Some(*i),
+                    Level1Element::Tracks(t) => tracks = Some(t),
+                    Level1Element::Tags(t) => tags.tags.extend(t.tags),
+                    _ => (),
+                }
+            }
+            Some(Event::Start(_)) => reader.skip_element()?,
+            Some(_) => (),
+            None => break,
+        }
+    }
+    let mut info = info.ok_or_else(|| invalid("no segment info"))?;
+    let mut tracks = tracks.ok_or_else(|| invalid("no tracks"))?;
+    edits.apply(&mut info, &mut tracks, &mut tags);
+
+    let mut muxer = Muxer::new(output, &header)?;
+    muxer.write_info(&info)?;
+    muxer.write_tracks(&tracks)?;
+    for (id, data) in &copied {
+        muxer.write_master(*id, data)?;
+    }
+    if !tags.tags.is_empty() {
+        muxer.write_tags(&tags)?;
+    }
+
+    let mut input = reader.into_inner();
+    input.seek(SeekFrom::Start(start))?;
+    let mut reader = Reader::new(input, element_type);
+    ebml::read(&mut reader)?;
+    loop {
+        match reader.next_event()? {
+            Some(Event::Start(element)) if element.id == 0x1F43B675 && reader.depth() == 2 => {
+                let cluster = Cluster::read(&mut reader)?;
+                muxer.start_cluster(cluster.timecode)?;
+                for block in &cluster.blocks {
+                    if edits.drop_tracks.contains(&block.track) {
+                        continue;
+                    }
+                    let mut frames = Vec::with_capacity(block.frames.len());
+                    for range in &block.frames {
+                        let mut frame = vec![0u8; (range.end - range.start) as usize];
+                        reader.read_at(range.start, &mut frame)?;
+                        frames.push(frame);
+                    }
+                    muxer.write_block(block, &frames)?;
+                }
+            }
+            Some(Event::Start(_)) => reader.skip_element()?,
+            Some(_) => (),
+            None => break,
+        }
+    }
+    muxer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matroska::{Lacing, Track};
+
+    fn asset(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/assets/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    fn remuxed(bytes: &[u8], edits: &Edits) -> Vec<u8> {
+        remux(Cursor::new(bytes), Cursor::new(Vec::new()), edits)
+            .unwrap()
+            .into_inner()
+    }
+
+    /// A block with its frames, loaded.
+    type Frames = (Block, Vec<Vec<u8>>);
+
+    /// What remuxing keeps: the header, info, tracks and tags, and the
+    /// blocks of each cluster with their frames.
+    #[derive(Debug, PartialEq)]
+    struct Content {
+        header: EBMLHeader,
+        info: Info,
+        tracks: Tracks,
+        tags: Tags,
+        clusters: Vec<(u64, Vec<Frames>)>,
+        cues: Cues,
+    }
+
+    fn read_content(bytes: &[u8]) -> Content {
+        let mut reader = Reader::new(Cursor::new(bytes), element_type);
+        let (header, _) = ebml::read(&mut reader).unwrap();
+        let mut content = Content {
+            header,
+            info: Info::default(),
+            tracks: Tracks::default(),
+            tags: Tags::default(),
+            clusters: Vec::new(),
+            cues: Cues::default(),
+        };
+        while let Some(element) = Level1Element::read(&mut reader).unwrap() {
+            match element {
+                Level1Element::Info(info) => content.info = *info,
+                Level1Element::Tracks(tracks) => content.tracks = tracks,
+                Level1Element::Tags(tags) => content.tags = tags,
+                Level1Element::Cues(cues) => content.cues = cues,
+                Level1Element::Cluster(cluster) => {
+                    let blocks = cluster
+                        .blocks
+                        .into_iter()
+                        .map(|mut block| {
+                            let frames = block
+                                .frames
+                                .iter()
+                                .map(|r| bytes[r.start as usize..r.end as usize].to_vec())
+                                .collect();
+                            // where they are, and what a SimpleBlock can't keep
+                            block.frames.clear();
+                            block.duration = None;
+                            block.references.clear();
+                            (block, frames)
+                        })
+                        .collect();
+                    content.clusters.push((cluster.timecode, blocks));
+                }
+                _ => (),
+            }
+        }
+        content
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for name in &["single_stream.mkv", "bbb-vp9-opus.webm"] {
+            let source = asset(name);
+            let once = remuxed(&source, &Edits::default());
+            let twice = remuxed(&once, &Edits::default());
+            // nothing is lost or changed the second time around
+            assert_eq!(once, twice, "{}", name);
+
+            let (mut source, once_content) = (read_content(&source), read_content(&once));
+            // made anew
+            source.cues = once_content.cues.clone();
+            assert_eq!(source, once_content, "{}", name);
+
+            // the cues point at clusters
+            let mut reader = Reader::new(Cursor::new(&once), element_type);
+            let (_, segment) = ebml::read(&mut reader).unwrap();
+            let clusters: Vec<u64> = reader
+                .filter_map(|event| match event.unwrap() {
+                    Event::Start(element) if element.id == 0x1F43B675 => Some(element.offset),
+                    _ => None,
+                })
+                .collect();
+            assert!(!once_content.cues.points.is_empty());
+            for point in &once_content.cues.points {
+                let position = point.positions[0].cluster_position;
+                assert!(clusters.contains(&(segment.element.data_offset + position)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_edits() {
+        let source = asset("bbb-vp9-opus.webm");
+        let edits = Edits {
+            drop_tracks: vec![2],
+            title: Some("Big Buck Bunny".into()),
+            tags: vec![("ARTIST".into(), Some("Blender".into()))],
+        };
+        let edited = remuxed(&source, &edits);
+        let content = read_content(&edited);
+        assert_eq!(content.info.title, "Big Buck Bunny");
+        let numbers: Vec<u64> = content.tracks.tracks.iter().map(|t| t.number).collect();
+        assert_eq!(numbers, [1]);
+        assert!(content
+            .clusters
+            .iter()
+            .all(|(_, blocks)| blocks.iter().all(|(block, _)| block.track == 1)));
+        let tag = content.tags.tags.last().unwrap();
+        assert!(tag.targets.track_uids.is_empty());
+        assert_eq!(tag.simple_tags[0].name, "ARTIST");
+        assert_eq!(tag.simple_tags[0].string, "Blender");
+        assert_eq!(content.cues.points[0].positions[0].track, 1);
+
+        // and removed again
+        let edits = Edits {
+            tags: vec![("ARTIST".into(), None)],
+            ..Edits::default()
+        };
+        let content = read_content(&remuxed(&edited, &edits));
+        assert!(content
+            .tags
+            .tags
+            .iter()
+            .all(|tag| tag.simple_tags.iter().all(|t| t.name != "ARTIST")));
+    }
+
+    #[test]
+    fn test_chapters_and_attachments_are_kept() {
+        let mut chapters = Writer::new(Cursor::new(Vec::new()));
+        chapters.start(0x45B9).unwrap();
+        chapters.unsigned(0x45BC, 1).unwrap();
+        chapters.start(0xB6).unwrap();
+        chapters.unsigned(0x73C4, 2).unwrap();
+        chapters.unsigned(0x91, 0).unwrap();
+        let chapters = chapters.into_inner().unwrap().into_inner();
+        let mut attachments = Writer::new(Cursor::new(Vec::new()));
+        attachments.start(0x61A7).unwrap();
+        attachments.string(0x466E, "cover.jpg").unwrap();
+        attachments.string(0x4660, "image/jpeg").unwrap();
+        attachments.binary(0x465C, &[0xFF; 10]).unwrap();
+        let attachments = attachments.into_inner().unwrap().into_inner();
+
+        let source = read_content(&asset("single_stream.mkv"));
+        let mut muxer = Muxer::new(Cursor::new(Vec::new()), &source.header).unwrap();
+        muxer.write_info(&source.info).unwrap();
+        muxer.write_tracks(&source.tracks).unwrap();
+        muxer.write_master(0x1043A770, &chapters).unwrap();
+        muxer.write_master(0x1941A469, &attachments).unwrap();
+        let input = muxer.finish().unwrap().into_inner();
+
+        let output = remuxed(&input, &Edits::default());
+        let mut reader = Reader::new(Cursor::new(&output), element_type);
+        ebml::read(&mut reader).unwrap();
+        let (mut chapters, mut attachments) = (None, None);
+        while let Some(element) = Level1Element::read(&mut reader).unwrap() {
+            match element {
+                Level1Element::Chapters(c) => chapters = Some(c),
+                Level1Element::Attachments(a) => attachments = Some(a),
+                _ => (),
+            }
+        }
+        let chapters = chapters.expect("chapters were dropped");
+        assert_eq!(chapters.editions[0].uid, 1);
+        assert_eq!(chapters.editions[0].atoms[0].uid, 2);
+        let attachments = attachments.expect("attachments were dropped");
+        assert_eq!(attachments.files[0].name, "cover.jpg");
+        assert_eq!(attachments.files[0].mime_type, "image/jpeg");
+    }
+
+    #[test]
+    fn test_lacing() {
+        let mut output = Muxer::new(
+            Cursor::new(Vec::new()),
+            &EBMLHeader {
+                version: 1,
+                read_version: 1,
+                max_id_length: 4,
+                max_size_length: 8,
+                doc_type: "matroska".into(),
+                doc_type_version: 4,
+                doc_type_read_version: 2,
+            },
+        )
+        .unwrap();
+        let mut track = Track::new();
+        track.number = 1;
+        track.typ3 = 2;
+        output.write_info(&Info::default()).unwrap();
+        output
+            .write_tracks(&Tracks {
+                tracks: vec![track],
+            })
+            .unwrap();
+        output.start_cluster(1000).unwrap();
+        let frames = [vec![1u8; 300], vec![2; 20], vec![3; 600], vec![4; 7]];
+        let mut written = Vec::new();
+        for (i, &lacing) in [Lacing::Xiph, Lacing::Ebml, Lacing::Fixed, Lacing::No]
+            .iter()
+            .enumerate()
+        {
+            let frames: Vec<Vec<u8>> = match lacing {
+                Lacing::Fixed => vec![vec![5; 10]; 3],
+                Lacing::No => vec![frames[3].clone()],
+                _ => frames.to_vec(),
+            };
+            let block = Block {
+                track: 1,
+                timecode: i as i16 * 20 - 10,
+                keyframe: true,
+                invisible: false,
+                discardable: i == 3,
+                lacing,
+                frames: Vec::new(),
+                duration: None,
+                references: Vec::new(),
+            };
+            output.write_block(&block, &frames).unwrap();
+            written.push((block, frames));
+        }
+        assert!(output.write_block(&written[0].0, &[[0u8; 0]; 0]).is_err());
+        let bytes = output.finish().unwrap().into_inner();
+
+        let content = read_content(&bytes);
+        assert_eq!(content.clusters, [(1000, written)]);
+        let times: Vec<u64> = content.cues.points.iter().map(|p| p.time).collect();
+        assert_eq!(times, [990, 1010, 1030, 1050]);
+    }
+}
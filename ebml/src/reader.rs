@@ -0,0 +1,507 @@
+//! Walking the elements of an EBML document from any `Read + Seek` source,
+//! without loading more of it than the element at hand.
+//!
+//! The reader turns the document into a stream of events: the start and end
+//! of each master element, and the value of each other one. Which elements
+//! are masters, and how to read the rest, comes from a `Schema`. Elements the
+//! schema doesn't know are read as binary.
+//!
+//! Masters of unknown size, as written by live encoders, end where an
+//! element that can't be their child begins, or at the end of the source.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::ebml::{vid, vint, Kind, Parent, Schema};
+
+/// Values larger than this are left unread unless told otherwise.
+pub const DEFAULT_MAX_VALUE_SIZE: u64 = 1 << 20;
+
+pub(crate) fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Where an element is in the source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Element {
+    pub id: u64,
+    /// Of the ID.
+    pub offset: u64,
+    /// Of the data, just past the size.
+    pub data_offset: u64,
+    /// `None` for a master of unknown size.
+    pub size: Option<u64>,
+}
+
+impl Element {
+    /// Where the data ends, if the size is known.
+    pub fn end(&self) -> Option<u64> {
+        self.size.map(|size| self.data_offset + size)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    /// Nanoseconds since 2001-01-01T00:00:00 UTC.
+    Date(i64),
+    Binary(Vec<u8>),
+    /// Too large to load; it is still in the source at the element's
+    /// `data_offset`.
+    Unread,
+}
+
+macro_rules! try_from_value {
+    ($type: ty, $what: expr, $($variant: ident)|+) => {
+        impl TryFrom<Value> for $type {
+            type Error = io::Error;
+
+            fn try_from(value: Value) -> io::Result<$type> {
+                match value {
+                    $(Value::$variant(v) => Ok(v),)+
+                    _ => Err(invalid(concat!("expected ", $what))),
+                }
+            }
+        }
+    };
+}
+
+try_from_value!(u64, "an unsigned integer", Unsigned);
+try_from_value!(i64, "a signed integer or a date", Signed | Date);
+try_from_value!(f64, "a float", Float);
+try_from_value!(String, "a string", String);
+try_from_value!(Vec<u8>, "binary data", Binary);
+
+impl TryFrom<Value> for bool {
+    type Error = io::Error;
+
+    fn try_from(value: Value) -> io::Result<bool> {
+        u64::try_from(value).map(|v| v != 0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A master element begins; its children follow until its `End`.
+    Start(Element),
+    End(Element),
+    Value(Element, Value),
+}
+
+pub struct Reader<R> {
+    inner: R,
+    schema: Schema,
+    /// Where `inner` is.
+    pos: u64,
+    /// The masters that have started but not ended, innermost last.
+    open: Vec<Element>,
+    /// An element whose header was read to find that the master of unknown
+    /// size before it had ended.
+    pending: Option<Element>,
+    max_value_size: u64,
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Starts reading at the current position of `inner`, which is taken as
+    /// the start of the document.
+    pub fn new(inner: R, schema: Schema) -> Self {
+        Reader {
+            inner,
+            schema,
+            pos: 0,
+            open: Vec::new(),
+            pending: None,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+        }
+    }
+
+    /// Values larger than `size` are reported as `Value::Unread`.
+    pub fn set_max_value_size(&mut self, size: u64) {
+        self.max_value_size = size;
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// How many masters are open.
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Fills `buf` from `offset` in the source without moving the reader, as
+    /// for the data of a `Value::Unread`.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let result = self.inner.read_exact(buf);
+        self.inner.seek(SeekFrom::Start(self.pos))?;
+        result
+    }
+
+    /// The next event, or `None` at the end of the source.
+    pub fn next_event(&mut self) -> io::Result<Option<Event>> {
+        self.step(true)
+    }
+
+    /// Skips the rest of the innermost open master, seeking past it when its
+    /// size is known. No `End` is reported for it.
+    pub fn skip_element(&mut self) -> io::Result<()> {
+        let master = match self.open.last() {
+            Some(master) => *master,
+            None => return Ok(()),
+        };
+        match master.end() {
+            Some(end) => {
+                self.seek(end)?;
+                self.pending = None;
+                self.open.pop();
+            }
+            None => {
+                let depth = self.open.len();
+                while self.open.len() >= depth {
+                    if self.step(false)?.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, load: bool) -> io::Result<Option<Event>> {
+        if let Some(master) = self.open.last() {
+            if self.bound().is_some_and(|end| self.pos >= end) {
+                let master = *master;
+                self.open.pop();
+                return Ok(Some(Event::End(master)));
+            }
+        }
+
+        let element = match self.pending.take() {
+            Some(element) => element,
+            None => match self.read_element()? {
+                Some(element) => element,
+                None => {
+                    return match self.open.pop() {
+                        Some(master) if master.size.is_none() && self.bound().is_none() => {
+                            Ok(Some(Event::End(master)))
+                        }
+                        Some(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+                        None => Ok(None),
+                    }
+                }
+            },
+        };
+
+        let element_type = (self.schema)(element.id);
+        if let Some(master) = self.open.last() {
+            if master.size.is_none() {
+                let child = match element_type.map(|t| t.parent) {
+                    Some(Parent::Root) => false,
+                    Some(Parent::In(parents)) => parents.contains(&master.id),
+                    // unknown elements are taken to belong where they are
+                    Some(Parent::Any) | None => true,
+                };
+                if !child {
+                    let master = *master;
+                    self.open.pop();
+                    self.pending = Some(element);
+                    return Ok(Some(Event::End(master)));
+                }
+            }
+        }
+
+        if let (Some(end), Some(bound)) = (element.end(), self.bound()) {
+            if end > bound {
+                return Err(invalid(format!(
+                    "element {:x} at {} overruns its parent",
+                    element.id, element.offset
+                )));
+            }
+        }
+
+        match element_type.map_or(Kind::Binary, |t| t.kind) {
+            Kind::Master => {
+                self.open.push(element);
+                Ok(Some(Event::Start(element)))
+            }
+            kind => {
+                let value = self.read_value(&element, kind, load)?;
+                Ok(Some(Event::Value(element, value)))
+            }
+        }
+    }
+
+    /// Where the innermost open master of known size ends.
+    fn bound(&self) -> Option<u64> {
+        self.open.iter().rev().find_map(Element::end)
+    }
+
+    fn seek(&mut self, pos: u64) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(pos))?;
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buf)?;
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    /// The first byte of a vint, or `None` at the end of the source.
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {
+                    self.pos += 1;
+                    return Ok(Some(byte[0]));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The bytes of a vint starting with `first`.
+    fn read_vint(&mut self, first: u8, max_len: usize) -> io::Result<([u8; 8], usize)> {
+        let len = first.leading_zeros() as usize + 1;
+        if len > max_len {
+            return Err(invalid(format!("bad vint at {}", self.pos - 1)));
+        }
+        let mut buf = [0u8; 8];
+        buf[0] = first;
+        self.read_exact(&mut buf[1..len])?;
+        Ok((buf, len))
+    }
+
+    fn read_element(&mut self) -> io::Result<Option<Element>> {
+        let offset = self.pos;
+        let first = match self.read_byte()? {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+        let (buf, len) = self.read_vint(first, 4)?;
+        let id = vid(&buf[..len]).map_err(|_| invalid("bad ID"))?.1;
+
+        let first = self.read_byte()?.ok_or(io::ErrorKind::UnexpectedEof)?;
+        let (buf, len) = self.read_vint(first, 8)?;
+        let size = vint(&buf[..len]).map_err(|_| invalid("bad size"))?.1;
+        // all ones
+        let unknown = size == (1 << (7 * len)) - 1;
+
+        Ok(Some(Element {
+            id,
+            offset,
+            data_offset: self.pos,
+            size: if unknown { None } else { Some(size) },
+        }))
+    }
+
+    fn read_value(&mut self, element: &Element, kind: Kind, load: bool) -> io::Result<Value> {
+        let size = element.size.ok_or_else(|| {
+            invalid(format!(
+                "element {:x} at {} has no size",
+                element.id, element.offset
+            ))
+        })?;
+        if !load || size > self.max_value_size {
+            self.seek(element.data_offset + size)?;
+            return Ok(Value::Unread);
+        }
+        let mut data = vec![0u8; size as usize];
+        self.read_exact(&mut data)?;
+        decode(kind, data).map_err(|e| {
+            invalid(format!(
+                "element {:x} at {}: {}",
+                element.id, element.offset, e
+            ))
+        })
+    }
+}
+
+impl<R: Read + Seek> Iterator for Reader<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+fn decode(kind: Kind, mut data: Vec<u8>) -> io::Result<Value> {
+    let integer = |data: &[u8]| {
+        if data.len() > 8 {
+            return Err(invalid("integer longer than 8 bytes"));
+        }
+        Ok(data.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+    };
+    let signed = |data: &[u8]| {
+        integer(data).map(|v| {
+            // sign extension
+            let shift = 64 - 8 * data.len() as u32;
+            v.checked_shl(shift).map_or(0, |v| v as i64 >> shift)
+        })
+    };
+
+    Ok(match kind {
+        Kind::Unsigned => Value::Unsigned(integer(&data)?),
+        Kind::Signed => Value::Signed(signed(&data)?),
+        Kind::Date => Value::Date(signed(&data)?),
+        Kind::Float => Value::Float(match data.len() {
+            0 => 0.0,
+            4 => f32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64,
+            8 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(&data);
+                f64::from_be_bytes(bytes)
+            }
+            _ => return Err(invalid("float neither 4 nor 8 bytes")),
+        }),
+        Kind::String => {
+            // may be padded with zeros
+            while data.last() == Some(&0) {
+                data.pop();
+            }
+            Value::String(String::from_utf8(data).map_err(invalid)?)
+        }
+        Kind::Binary => Value::Binary(data),
+        Kind::Master => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matroska;
+    use std::io::Cursor;
+
+    const WEBM: &[u8] = include_bytes!("../assets/bbb-vp9-opus.webm");
+
+    fn element(id: &[u8], size: &[u8], data: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = [id, size].concat();
+        bytes.extend(data.concat());
+        bytes
+    }
+
+    #[test]
+    fn test_events() {
+        let reader = Reader::new(Cursor::new(WEBM), matroska::element_type);
+        let mut open = Vec::new();
+        let mut last = None;
+        for event in reader {
+            match event.unwrap() {
+                Event::Start(element) => open.push(element),
+                Event::End(element) => assert_eq!(open.pop(), Some(element)),
+                Event::Value(element, value) => {
+                    assert!(last.is_none_or(|offset| element.offset > offset));
+                    last = Some(element.offset);
+                    if element.id == 0x4282 {
+                        assert_eq!(value, Value::String("webm".into()));
+                    }
+                }
+            }
+        }
+        assert!(open.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_size() {
+        let timestamp = element(&[0xE7], &[0x81], &[&[0x10]]);
+        let block = element(&[0xA3], &[0x85], &[&[0x81, 0, 0, 0x80, 0xAA]]);
+        let cluster = element(&[0x1F, 0x43, 0xB6, 0x75], &[0xFF], &[&timestamp, &block]);
+        let cues = element(&[0x1C, 0x53, 0xBB, 0x6B], &[0x80], &[]);
+        let segment = element(
+            &[0x18, 0x53, 0x80, 0x67],
+            &[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+            &[&cluster, &cluster, &cues],
+        );
+
+        let reader = Reader::new(Cursor::new(segment), matroska::element_type);
+        let events: Vec<String> = reader
+            .map(|event| match event.unwrap() {
+                Event::Start(e) => format!("<{:x}@{}", e.id, e.offset),
+                Event::End(e) => format!(">{:x}", e.id),
+                Event::Value(e, _) => format!("{:x}", e.id),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "<18538067@0",
+                "<1f43b675@12",
+                "e7",
+                "a3",
+                ">1f43b675",
+                "<1f43b675@27",
+                "e7",
+                "a3",
+                ">1f43b675",
+                "<1c53bb6b@42",
+                ">1c53bb6b",
+                ">18538067",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_skip() {
+        let mut reader = Reader::new(Cursor::new(WEBM), matroska::element_type);
+        let mut clusters = 0;
+        while let Some(event) = reader.next_event().unwrap() {
+            if let Event::Start(element) = event {
+                if element.id == 0x1F43B675 {
+                    reader.skip_element().unwrap();
+                    assert_eq!(Some(reader.position()), element.end());
+                    clusters += 1;
+                }
+            }
+        }
+        assert!(clusters > 0);
+
+        let mut reader = Reader::new(Cursor::new(WEBM), matroska::element_type);
+        reader.set_max_value_size(16);
+        let unread = reader
+            .filter_map(|event| match event.unwrap() {
+                Event::Value(element, Value::Unread) => Some(element.id),
+                _ => None,
+            })
+            .next();
+        assert!(unread.is_some());
+    }
+
+    #[test]
+    fn test_invalid() {
+        // the child claims more than its parent has
+        let info = element(&[0x15, 0x49, 0xA9, 0x66], &[0x83], &[&[0x7B, 0xA9, 0x85]]);
+        let mut reader = Reader::new(Cursor::new(info), matroska::element_type);
+        assert!(matches!(reader.next_event(), Ok(Some(Event::Start(_)))));
+        assert!(reader.next_event().is_err());
+
+        // truncated
+        let info = element(&[0x15, 0x49, 0xA9, 0x66], &[0x88], &[&[0x7B, 0xA9, 0x81]]);
+        let mut reader = Reader::new(Cursor::new(info), matroska::element_type);
+        assert!(reader.next_event().is_ok());
+        assert!(reader.next_event().is_err());
+
+        assert_eq!(
+            decode(Kind::Signed, vec![0xFF, 0xFE]).unwrap(),
+            Value::Signed(-2)
+        );
+        assert_eq!(decode(Kind::Signed, vec![]).unwrap(), Value::Signed(0));
+        assert_eq!(
+            decode(Kind::String, b"abc\0\0".to_vec()).unwrap(),
+            Value::String("abc".into())
+        );
+        assert!(decode(Kind::Float, vec![0; 3]).is_err());
+    }
+}
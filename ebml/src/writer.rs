@@ -0,0 +1,278 @@
+//! Writing EBML elements, the other way around from `reader`.
+//!
+//! Masters of known size get an eight-byte size that is filled in when they
+//! end, which is why the output has to be `Seek` too. Masters of unknown
+//! size, for output that is never to be seeked, need no such patching.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::reader::{invalid, Value};
+
+/// The bytes of an element ID, which keeps its length marker.
+pub fn encode_id(id: u64) -> Vec<u8> {
+    let len = (1..=4).find(|&len| id < 1 << (8 * len)).unwrap_or(8);
+    id.to_be_bytes()[8 - len..].to_vec()
+}
+
+/// `value` as a vint of exactly `len` bytes.
+pub fn encode_vint_len(value: u64, len: usize) -> Vec<u8> {
+    let marked = value | 1 << (7 * len);
+    marked.to_be_bytes()[8 - len..].to_vec()
+}
+
+/// `value` as the shortest vint that holds it. All ones is kept for unknown
+/// sizes, so 127 takes two bytes.
+pub fn encode_vint(value: u64) -> Vec<u8> {
+    let len = (1..=8)
+        .find(|&len| value < (1 << (7 * len)) - 1)
+        .unwrap_or(8);
+    encode_vint_len(value, len)
+}
+
+/// The size of a master of unknown size.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+struct Open {
+    /// Where the size is to be filled in, if it is known.
+    size_offset: Option<u64>,
+    data_offset: u64,
+}
+
+pub struct Writer<W> {
+    inner: W,
+    /// Where `inner` is.
+    pos: u64,
+    open: Vec<Open>,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    /// Starts writing at the current position of `inner`, which is taken as
+    /// the start of the document.
+    pub fn new(inner: W) -> Self {
+        Writer {
+            inner,
+            pos: 0,
+            open: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// The end of any masters still open is the end of the output.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        while !self.open.is_empty() {
+            self.end()?;
+        }
+        Ok(self.inner)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Writes raw bytes at `offset` without moving the writer, as to fill
+    /// in space reserved earlier.
+    pub fn write_at(&mut self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let result = self.inner.write_all(bytes);
+        self.inner.seek(SeekFrom::Start(self.pos))?;
+        result
+    }
+
+    /// Starts a master whose size is filled in by `end`.
+    pub fn start(&mut self, id: u64) -> io::Result<()> {
+        self.write(&encode_id(id))?;
+        let size_offset = self.pos;
+        self.write(&encode_vint_len(0, 8))?;
+        self.open.push(Open {
+            size_offset: Some(size_offset),
+            data_offset: self.pos,
+        });
+        Ok(())
+    }
+
+    /// Starts a master of unknown size.
+    pub fn start_unknown(&mut self, id: u64) -> io::Result<()> {
+        self.write(&encode_id(id))?;
+        self.write(&UNKNOWN_SIZE)?;
+        self.open.push(Open {
+            size_offset: None,
+            data_offset: self.pos,
+        });
+        Ok(())
+    }
+
+    /// Ends the innermost open master.
+    pub fn end(&mut self) -> io::Result<()> {
+        let open = self
+            .open
+            .pop()
+            .ok_or_else(|| invalid("no element to end"))?;
+        if let Some(size_offset) = open.size_offset {
+            let size = self.pos - open.data_offset;
+            self.write_at(size_offset, &encode_vint_len(size, 8))?;
+        }
+        Ok(())
+    }
+
+    pub fn binary(&mut self, id: u64, data: &[u8]) -> io::Result<()> {
+        self.write(&encode_id(id))?;
+        self.write(&encode_vint(data.len() as u64))?;
+        self.write(data)
+    }
+
+    pub fn unsigned(&mut self, id: u64, value: u64) -> io::Result<()> {
+        // at least one byte, since none at all means the default
+        let len = (1..8).find(|&len| value >> (8 * len) == 0).unwrap_or(8);
+        self.binary(id, &value.to_be_bytes()[8 - len..])
+    }
+
+    pub fn signed(&mut self, id: u64, value: i64) -> io::Result<()> {
+        let len = (1..8)
+            .find(|&len| {
+                let bits = 8 * len - 1;
+                (-(1i64 << bits)..1i64 << bits).contains(&value)
+            })
+            .unwrap_or(8);
+        self.binary(id, &value.to_be_bytes()[8 - len..])
+    }
+
+    pub fn float(&mut self, id: u64, value: f64) -> io::Result<()> {
+        self.binary(id, &value.to_be_bytes())
+    }
+
+    pub fn string(&mut self, id: u64, value: &str) -> io::Result<()> {
+        self.binary(id, value.as_bytes())
+    }
+
+    /// `value` is in nanoseconds since 2001-01-01T00:00:00 UTC.
+    pub fn date(&mut self, id: u64, value: i64) -> io::Result<()> {
+        self.binary(id, &value.to_be_bytes())
+    }
+
+    pub fn value(&mut self, id: u64, value: &Value) -> io::Result<()> {
+        match value {
+            Value::Unsigned(v) => self.unsigned(id, *v),
+            Value::Signed(v) => self.signed(id, *v),
+            Value::Float(v) => self.float(id, *v),
+            Value::String(v) => self.string(id, v),
+            Value::Date(v) => self.date(id, *v),
+            Value::Binary(v) => self.binary(id, v),
+            Value::Unread => Err(invalid(format!("no data for element {:x}", id))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matroska;
+    use crate::reader::{Event, Reader};
+    use std::io::Cursor;
+
+    const MKV: &[u8] = include_bytes!("../assets/single_stream.mkv");
+
+    /// The elements with their depth and value, but not where they are.
+    fn tree(bytes: &[u8]) -> Vec<(usize, u64, Option<Value>)> {
+        let mut reader = Reader::new(Cursor::new(bytes), matroska::element_type);
+        reader.set_max_value_size(u64::MAX);
+        let mut tree = Vec::new();
+        while let Some(event) = reader.next_event().unwrap() {
+            match event {
+                Event::Start(element) => tree.push((reader.depth(), element.id, None)),
+                Event::Value(element, value) => {
+                    tree.push((reader.depth(), element.id, Some(value)))
+                }
+                Event::End(_) => (),
+            }
+        }
+        tree
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode_id(0xEC), [0xEC]);
+        assert_eq!(encode_id(0x4286), [0x42, 0x86]);
+        assert_eq!(encode_id(0x1A45DFA3), [0x1A, 0x45, 0xDF, 0xA3]);
+        assert_eq!(encode_vint(0), [0x80]);
+        assert_eq!(encode_vint(126), [0xFE]);
+        assert_eq!(encode_vint(127), [0x40, 0x7F]);
+        assert_eq!(encode_vint(0x123456789), [0x09, 0x23, 0x45, 0x67, 0x89]);
+        assert_eq!(encode_vint_len(1, 3), [0x20, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_values() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        writer.start(0x1549A966).unwrap();
+        writer.unsigned(0x2AD7B1, 0).unwrap();
+        writer.unsigned(0x2AD7B1, 1_000_000).unwrap();
+        writer.signed(0xFB, -129).unwrap();
+        writer.signed(0xFB, 127).unwrap();
+        writer.float(0x4489, 1.5).unwrap();
+        writer.date(0x4461, -1).unwrap();
+        writer.string(0x7BA9, "Title").unwrap();
+        writer.end().unwrap();
+        let bytes = writer.into_inner().unwrap().into_inner();
+
+        let values: Vec<Value> = tree(&bytes).into_iter().filter_map(|n| n.2).collect();
+        assert_eq!(
+            values,
+            [
+                Value::Unsigned(0),
+                Value::Unsigned(1_000_000),
+                Value::Signed(-129),
+                Value::Signed(127),
+                Value::Float(1.5),
+                Value::Date(-1),
+                Value::String("Title".into()),
+            ]
+        );
+        // one byte each for 0 and 127, two for -129
+        assert_eq!(bytes.len(), 12 + 5 + 7 + 4 + 3 + 11 + 11 + 8);
+    }
+
+    #[test]
+    fn test_unknown_size() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        writer.start_unknown(0x18538067).unwrap();
+        writer.start_unknown(0x1F43B675).unwrap();
+        writer.unsigned(0xE7, 5).unwrap();
+        writer.end().unwrap();
+        writer.start(0x1F43B675).unwrap();
+        writer.unsigned(0xE7, 6).unwrap();
+        let bytes = writer.into_inner().unwrap().into_inner();
+
+        let mut reader = Reader::new(Cursor::new(&bytes), matroska::element_type);
+        let sizes: Vec<Option<u64>> = std::iter::from_fn(|| reader.next_event().unwrap())
+            .filter_map(|event| match event {
+                Event::Start(element) => Some(element.size),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sizes, [None, None, Some(3)]);
+        assert!(Writer::new(Cursor::new(Vec::new())).end().is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        let mut reader = Reader::new(Cursor::new(MKV), matroska::element_type);
+        reader.set_max_value_size(u64::MAX);
+        while let Some(event) = reader.next_event().unwrap() {
+            match event {
+                Event::Start(element) if element.size.is_some() => writer.start(element.id),
+                Event::Start(element) => writer.start_unknown(element.id),
+                Event::End(_) => writer.end(),
+                Event::Value(element, value) => writer.value(element.id, &value),
+            }
+            .unwrap();
+        }
+        let bytes = writer.into_inner().unwrap().into_inner();
+        assert_eq!(tree(&bytes), tree(MKV));
+    }
+}
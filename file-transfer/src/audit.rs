@@ -0,0 +1,59 @@
+//! A record of who changed what, appended to a file as JSON lines.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::auth::now;
+
+#[derive(Debug, Serialize)]
+pub struct Event<'a> {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub who: &'a str,
+    pub peer: Option<String>,
+    pub action: &'a str,
+    pub file: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+pub struct Audit {
+    file: Mutex<File>,
+}
+
+impl Audit {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Audit> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Audit {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(
+        &self,
+        who: &str,
+        peer: Option<String>,
+        action: &str,
+        file: &str,
+        size: Option<u64>,
+    ) {
+        let event = Event {
+            time: now(),
+            who,
+            peer,
+            action,
+            file,
+            size,
+        };
+        let mut line = serde_json::to_string(&event).expect("events serialize");
+        line.push('\n');
+        // Losing an audit line shouldn't fail the request it describes.
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            println!("Audit log error: {}", e);
+        }
+    }
+}
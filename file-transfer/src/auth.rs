@@ -0,0 +1,340 @@
+//! Bearer tokens and signed share links.
+//!
+//! Tokens are read from a file with one token per line:
+//!
+//! ```text
+//! # name  token          scopes             quota
+//! alice   s3cr3t-alice   read,write,delete  1G
+//! ci      0123abcd       read,write         500M
+//! viewer  look-only      read
+//! ```
+//!
+//! The quota is a byte count with an optional `K`, `M` or `G` suffix; without
+//! one the token may store any amount. When no token file is loaded, every
+//! request is let through.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::future::{ready, Ready};
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    Read,
+    Write,
+    Delete,
+}
+
+impl Scope {
+    fn parse(text: &str) -> Option<Scope> {
+        match text {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            "delete" => Some(Scope::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// Who is making a request and what they may do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Bytes this caller may keep stored, if limited.
+    pub quota: Option<u64>,
+}
+
+impl Caller {
+    fn anonymous() -> Caller {
+        Caller {
+            name: "anonymous".to_owned(),
+            scopes: Vec::new(),
+            quota: None,
+        }
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), AuthError> {
+        if self.scopes.contains(&scope) {
+            Ok(())
+        } else if self.scopes.is_empty() {
+            Err(AuthError::Unauthorized)
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+impl FromRequest for Caller {
+    type Error = AuthError;
+    type Future = Ready<Result<Caller, AuthError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let auth = req
+            .app_data::<web::Data<Auth>>()
+            .expect("Auth is registered as app data");
+        ready(auth.authenticate(req))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// No token, or one we don't know.
+    Unauthorized,
+    /// A valid token without the needed scope.
+    Forbidden,
+    /// A share link whose signature doesn't match or whose time has passed.
+    InvalidLink,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Unauthorized => write!(f, "a valid bearer token is required"),
+            AuthError::Forbidden => write!(f, "token lacks the required scope"),
+            AuthError::InvalidLink => write!(f, "share link is invalid or expired"),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden | AuthError::InvalidLink => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if *self == AuthError::Unauthorized {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.body(self.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+pub struct Auth {
+    /// Callers by token; `None` lets everyone do everything.
+    tokens: Option<HashMap<String, Caller>>,
+    share_key: Vec<u8>,
+}
+
+impl Auth {
+    /// No tokens required.
+    pub fn open(share_key: &[u8]) -> Auth {
+        Auth {
+            tokens: None,
+            share_key: share_key.to_vec(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, share_key: &[u8]) -> io::Result<Auth> {
+        let text = fs::read_to_string(path)?;
+        Auth::parse(&text, share_key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(text: &str, share_key: &[u8]) -> Result<Auth, TokenError> {
+        let mut tokens = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| TokenError {
+                line: i + 1,
+                message,
+            };
+            let (token, caller) = parse_token(line).map_err(error)?;
+            if tokens.insert(token, caller).is_some() {
+                return Err(error("token listed twice".to_owned()));
+            }
+        }
+        Ok(Auth {
+            tokens: Some(tokens),
+            share_key: share_key.to_vec(),
+        })
+    }
+
+    fn authenticate(&self, req: &HttpRequest) -> Result<Caller, AuthError> {
+        let tokens = match &self.tokens {
+            Some(tokens) => tokens,
+            None => {
+                return Ok(Caller {
+                    scopes: vec![Scope::Read, Scope::Write, Scope::Delete],
+                    ..Caller::anonymous()
+                })
+            }
+        };
+        let value = match req.headers().get(header::AUTHORIZATION) {
+            Some(value) => value.to_str().map_err(|_| AuthError::Unauthorized)?,
+            None => return Ok(Caller::anonymous()),
+        };
+        let token = value
+            .strip_prefix("Bearer ")
+            .ok_or(AuthError::Unauthorized)?;
+        tokens
+            .get(token.trim())
+            .cloned()
+            .ok_or(AuthError::Unauthorized)
+    }
+
+    fn mac(&self, name: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.share_key).expect("HMAC takes keys of any length");
+        mac.update(format!("/{}\n{}", name, expires).as_bytes());
+        mac
+    }
+
+    /// The signature that lets anyone download `name` until `expires`,
+    /// in seconds since the Unix epoch.
+    pub fn sign(&self, name: &str, expires: u64) -> String {
+        let signature = self.mac(name, expires).finalize().into_bytes();
+        format!("{:x}", signature)
+    }
+
+    pub fn verify(&self, name: &str, expires: u64, signature: &str) -> Result<(), AuthError> {
+        if expires < now() {
+            return Err(AuthError::InvalidLink);
+        }
+        let signature = decode_hex(signature).ok_or(AuthError::InvalidLink)?;
+        self.mac(name, expires)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::InvalidLink)
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_token(line: &str) -> Result<(String, Caller), String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, token, scopes, quota) = match words[..] {
+        [name, token, scopes] => (name, token, scopes, None),
+        [name, token, scopes, quota] => (name, token, scopes, Some(quota)),
+        _ => return Err("expected: name token scopes [quota]".to_owned()),
+    };
+    let scopes = scopes
+        .split(',')
+        .map(|scope| Scope::parse(scope).ok_or_else(|| format!("unknown scope `{}`", scope)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let quota = quota
+        .map(|quota| parse_size(quota).ok_or_else(|| format!("bad quota `{}`", quota)))
+        .transpose()?;
+    let caller = Caller {
+        name: name.to_owned(),
+        scopes,
+        quota,
+    };
+    Ok((token.to_owned(), caller))
+}
+
+fn parse_size(text: &str) -> Option<u64> {
+    let (digits, unit) = match text.char_indices().last()? {
+        (i, 'K' | 'k') => (&text[..i], 1 << 10),
+        (i, 'M' | 'm') => (&text[..i], 1 << 20),
+        (i, 'G' | 'g') => (&text[..i], 1 << 30),
+        _ => (text, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let auth = Auth::parse(
+            "# comment
+             alice  s3cr3t  read,write,delete  1G
+             viewer look    read # trailing comment",
+            b"key",
+        )
+        .unwrap();
+        let tokens = auth.tokens.unwrap();
+        assert_eq!(
+            tokens["s3cr3t"],
+            Caller {
+                name: "alice".to_owned(),
+                scopes: vec![Scope::Read, Scope::Write, Scope::Delete],
+                quota: Some(1 << 30),
+            }
+        );
+        assert_eq!(tokens["look"].quota, None);
+
+        assert_eq!(
+            Auth::parse("a b read\nc d admin", b"").err().unwrap().line,
+            2
+        );
+        assert!(Auth::parse("a b read 12X", b"").is_err());
+        assert!(Auth::parse("a b", b"").is_err());
+        assert!(Auth::parse("a b read\nc b write", b"").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("4k"), Some(4096));
+        assert_eq!(parse_size("500M"), Some(500 << 20));
+        assert_eq!(parse_size("G"), None);
+        assert_eq!(parse_size("99999999999G"), None);
+    }
+
+    #[test]
+    fn test_signatures() {
+        let auth = Auth::open(b"secret");
+        let expires = now() + 60;
+        let signature = auth.sign("a.txt", expires);
+        assert_eq!(auth.verify("a.txt", expires, &signature), Ok(()));
+        assert!(auth.verify("b.txt", expires, &signature).is_err());
+        assert!(auth.verify("a.txt", expires + 1, &signature).is_err());
+        assert!(auth.verify("a.txt", expires, "zz").is_err());
+        assert!(Auth::open(b"other")
+            .verify("a.txt", expires, &signature)
+            .is_err());
+
+        let past = now() - 1;
+        let signature = auth.sign("a.txt", past);
+        assert_eq!(
+            auth.verify("a.txt", past, &signature),
+            Err(AuthError::InvalidLink)
+        );
+    }
+}
@@ -0,0 +1,603 @@
+use std::io::SeekFrom;
+use std::ops::Range;
+
+use actix_web::body::SizedStream;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::http::Method;
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
+use futures::stream::{self, StreamExt};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::audit::Audit;
+use crate::auth::{self, Auth, Caller, Scope};
+use crate::store::{Store, StoreError};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_SHARE_TTL: u64 = 60 * 60;
+const MAX_SHARE_TTL: u64 = 7 * 24 * 60 * 60;
+
+/// Routes for the file API. Expects `Store`, `Auth` and `Audit` as app data.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/").route(web::get().to(list_files)))
+        .service(web::resource("/share/{filename}").route(web::post().to(share_file)))
+        .service(
+            web::resource("/{filename}")
+                .route(web::delete().to(delete_file))
+                .route(web::get().to(download_file))
+                .route(web::head().to(download_file))
+                .route(web::put().to(upload_file))
+                .route(web::post().to(upload_file))
+                .route(web::patch().to(resume_upload)),
+        );
+}
+
+#[derive(Deserialize)]
+struct SignedLink {
+    expires: Option<u64>,
+    signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ShareOptions {
+    /// Seconds the link stays valid.
+    ttl: Option<u64>,
+}
+
+fn peer(req: &HttpRequest) -> Option<String> {
+    req.peer_addr().map(|addr| addr.to_string())
+}
+
+async fn list_files(caller: Caller, store: web::Data<Store>) -> Result<HttpResponse, Error> {
+    caller.require(Scope::Read)?;
+    Ok(HttpResponse::Ok().json(store.list().await?))
+}
+
+async fn delete_file(
+    req: HttpRequest,
+    caller: Caller,
+    path: web::Path<String>,
+    store: web::Data<Store>,
+    audit: web::Data<Audit>,
+) -> Result<HttpResponse, Error> {
+    caller.require(Scope::Delete)?;
+    let filename = path.into_inner();
+    store.delete(&filename).await?;
+    audit.record(&caller.name, peer(&req), "delete", &filename, None);
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Hands out a link that downloads a file without a token until it expires.
+async fn share_file(
+    req: HttpRequest,
+    caller: Caller,
+    path: web::Path<String>,
+    options: web::Query<ShareOptions>,
+    store: web::Data<Store>,
+    auth: web::Data<Auth>,
+    audit: web::Data<Audit>,
+) -> Result<HttpResponse, Error> {
+    caller.require(Scope::Read)?;
+    let filename = path.into_inner();
+    store.entry(&filename).await?;
+    let ttl = options.ttl.unwrap_or(DEFAULT_SHARE_TTL).min(MAX_SHARE_TTL);
+    let expires = auth::now() + ttl;
+    let url = format!(
+        "/{}?expires={}&signature={}",
+        utf8_percent_encode(&filename, NON_ALPHANUMERIC),
+        expires,
+        auth.sign(&filename, expires)
+    );
+    audit.record(&caller.name, peer(&req), "share", &filename, None);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "url": url, "expires": expires })))
+}
+
+async fn download_file(
+    req: HttpRequest,
+    caller: Caller,
+    path: web::Path<String>,
+    link: web::Query<SignedLink>,
+    store: web::Data<Store>,
+    auth: web::Data<Auth>,
+) -> Result<HttpResponse, Error> {
+    let filename = path.into_inner();
+    match (link.expires, &link.signature) {
+        (Some(expires), Some(signature)) => auth.verify(&filename, expires, signature)?,
+        _ => caller.require(Scope::Read)?,
+    }
+    let (mut file, entry) = match store.open_file(&filename).await {
+        Ok(found) => found,
+        // lets a client find out where to resume an unfinished upload
+        Err(StoreError::NotFound) if req.method() == Method::HEAD => {
+            return match store.upload_offset(&filename).await? {
+                Some(offset) => Ok(HttpResponse::NoContent()
+                    .insert_header(("Upload-Offset", offset.to_string()))
+                    .finish()),
+                None => Err(StoreError::NotFound.into()),
+            };
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let etag = entry.etag();
+    let last_modified = httpdate::fmt_http_date(entry.modified_time());
+    if none_match(req.headers(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::LAST_MODIFIED, last_modified))
+            .finish());
+    }
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((header::ETAG, etag.as_str()))
+        .insert_header((header::LAST_MODIFIED, last_modified))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .content_type("application/octet-stream");
+
+    let range = match req.headers().get(header::RANGE) {
+        Some(value) if if_range_matches(req.headers(), &etag) => {
+            value.to_str().ok().and_then(|v| parse_range(v, entry.size))
+        }
+        _ => None,
+    };
+    let range = match range {
+        None => 0..entry.size,
+        Some(Ok(range)) => {
+            response.status(actix_web::http::StatusCode::PARTIAL_CONTENT);
+            response.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, entry.size),
+            ));
+            range
+        }
+        Some(Err(())) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", entry.size)))
+                .finish());
+        }
+    };
+
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let length = range.end - range.start;
+    let body = stream::unfold((file, length), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut buf = vec![0u8; remaining.min(CHUNK_SIZE as u64) as usize];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(web::Bytes::from(buf)), (file, remaining - n as u64)))
+            }
+            Err(e) => Some((Err(e), (file, 0))),
+        }
+    });
+    Ok(response.body(SizedStream::new(length, body.boxed_local())))
+}
+
+/// Room left in the caller's quota for `filename`, which the upload replaces.
+async fn room(caller: &Caller, store: &Store, filename: &str) -> Result<Option<u64>, Error> {
+    Ok(match caller.quota {
+        Some(quota) => Some(quota.saturating_sub(store.usage(&caller.name, filename).await?)),
+        None => None,
+    })
+}
+
+async fn upload_file(
+    req: HttpRequest,
+    caller: Caller,
+    mut payload: web::Payload,
+    path: web::Path<String>,
+    store: web::Data<Store>,
+    audit: web::Data<Audit>,
+) -> Result<HttpResponse, Error> {
+    caller.require(Scope::Write)?;
+    let filename = path.into_inner();
+    let limit = room(&caller, &store, &filename).await?;
+    let mut upload = store.create(&filename, &caller.name).await?.limit(limit);
+    while let Some(chunk) = payload.next().await {
+        upload.write(&chunk?).await?;
+    }
+    let size = upload.finish().await?;
+    audit.record(&caller.name, peer(&req), "upload", &filename, Some(size));
+    let entry = store.entry(&filename).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, entry.etag()))
+        .finish())
+}
+
+/// Appends a chunk to a resumable upload. `Upload-Offset` says where the chunk
+/// goes and `Upload-Length` how large the whole file will be; the file appears
+/// once every byte has arrived.
+async fn resume_upload(
+    req: HttpRequest,
+    caller: Caller,
+    mut payload: web::Payload,
+    path: web::Path<String>,
+    store: web::Data<Store>,
+    audit: web::Data<Audit>,
+) -> Result<HttpResponse, Error> {
+    caller.require(Scope::Write)?;
+    let offset = number_header(req.headers(), "Upload-Offset");
+    let length = number_header(req.headers(), "Upload-Length");
+    let (offset, length) = match (offset, length) {
+        (Some(offset), Some(length)) => (offset, length),
+        _ => {
+            return Ok(HttpResponse::BadRequest()
+                .body("Upload-Offset and Upload-Length headers are required"))
+        }
+    };
+    let filename = path.into_inner();
+    if room(&caller, &store, &filename)
+        .await?
+        .is_some_and(|room| length > room)
+    {
+        return Err(StoreError::QuotaExceeded.into());
+    }
+    let mut upload = store
+        .resume(&filename, offset, length, &caller.name)
+        .await?;
+    while let Some(chunk) = payload.next().await {
+        upload.write(&chunk?).await?;
+    }
+    let offset = upload.finish().await?;
+    if offset == length {
+        audit.record(&caller.name, peer(&req), "upload", &filename, Some(length));
+    }
+    Ok(HttpResponse::NoContent()
+        .insert_header(("Upload-Offset", offset.to_string()))
+        .finish())
+}
+
+pub async fn invalid_resource(req: HttpRequest) -> impl Responder {
+    println!("Invalid URI: \"{}\"", req.uri());
+    HttpResponse::NotFound()
+}
+
+fn number_header(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Whether `If-None-Match` lists `etag`, compared weakly as RFC 9110 asks.
+fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Whether a `Range` should be honoured given `If-Range`; dates are not
+/// compared, so only an ETag validator can keep the range.
+fn if_range_matches(headers: &HeaderMap, etag: &str) -> bool {
+    match headers.get(header::IF_RANGE) {
+        None => true,
+        Some(value) => value.to_str().is_ok_and(|v| v.trim() == etag),
+    }
+}
+
+/// Parses a single `bytes=` range against a file of `size` bytes. `None`
+/// means the header should be ignored, which includes multiple ranges;
+/// `Some(Err(()))` means it can't be satisfied.
+fn parse_range(value: &str, size: u64) -> Option<Result<Range<u64>, ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 {
+            return Some(Err(()));
+        }
+        size.saturating_sub(suffix)..size
+    } else {
+        let first: u64 = first.parse().ok()?;
+        let end = match last {
+            "" => size,
+            last => {
+                let last: u64 = last.parse().ok()?;
+                if last < first {
+                    return None;
+                }
+                size.min(last.saturating_add(1))
+            }
+        };
+        first..end
+    };
+    if range.start >= size {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::dev::{Service, ServiceResponse};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{
+        call_and_read_body, call_and_read_body_json, call_service, init_service, read_body,
+        TestRequest,
+    };
+    use actix_web::App;
+    use std::path::Path;
+
+    const TOKENS: &str = "alice  alice-token  read,write,delete  16
+                          viewer view-token   read";
+
+    async fn service(
+        dir: &Path,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = Error> {
+        let auth = Auth::parse(TOKENS, b"test key").unwrap();
+        init_service(
+            App::new()
+                .app_data(web::Data::new(Store::open(dir).unwrap()))
+                .app_data(web::Data::new(auth))
+                .app_data(web::Data::new(Audit::open(dir.join(".audit.log")).unwrap()))
+                .configure(configure),
+        )
+        .await
+    }
+
+    fn request(method: Method, uri: &str, token: Option<&str>) -> TestRequest {
+        let req = TestRequest::default().method(method).uri(uri);
+        match token {
+            Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+            None => req,
+        }
+    }
+
+    fn put(uri: &str, token: &str, body: &'static [u8]) -> actix_http::Request {
+        request(Method::PUT, uri, Some(token))
+            .set_payload(body)
+            .to_request()
+    }
+
+    fn get(uri: &str, token: Option<&str>) -> actix_http::Request {
+        request(Method::GET, uri, token).to_request()
+    }
+
+    #[actix_web::test]
+    async fn test_scopes() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = service(dir.path()).await;
+
+        let resp = call_service(&app, get("/", None)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            resp.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+            "Bearer"
+        );
+        let resp = call_service(&app, get("/", Some("guess"))).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = call_service(&app, put("/a.txt", "view-token", b"no")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = call_service(&app, put("/a.txt", "alice-token", b"hello")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = call_service(&app, get("/a.txt", Some("view-token"))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_body(resp).await, "hello");
+        let listing: serde_json::Value =
+            call_and_read_body_json(&app, get("/", Some("view-token"))).await;
+        assert_eq!(listing[0]["name"], "a.txt");
+        assert_eq!(listing[0]["owner"], "alice");
+
+        let delete = |token| request(Method::DELETE, "/a.txt", Some(token)).to_request();
+        let resp = call_service(&app, delete("view-token")).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = call_service(&app, delete("alice-token")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = call_service(&app, get("/a.txt", Some("view-token"))).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = service(dir.path()).await;
+
+        let resp = call_service(&app, put("/a", "alice-token", b"0123456789")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = call_service(&app, put("/b", "alice-token", b"0123456789")).await;
+        assert_eq!(resp.status(), StatusCode::INSUFFICIENT_STORAGE);
+        let resp = call_service(&app, get("/b", Some("alice-token"))).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // replacing a file frees what it used
+        let resp = call_service(&app, put("/a", "alice-token", b"0123456789abcdef")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = call_service(
+            &app,
+            request(Method::PATCH, "/c", Some("alice-token"))
+                .insert_header(("Upload-Offset", "0"))
+                .insert_header(("Upload-Length", "1"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::INSUFFICIENT_STORAGE);
+    }
+
+    #[actix_web::test]
+    async fn test_share_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = service(dir.path()).await;
+        call_service(&app, put("/a%20b.txt", "alice-token", b"shared")).await;
+
+        let resp = call_service(
+            &app,
+            request(Method::POST, "/share/a%20b.txt?ttl=60", None).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let share: serde_json::Value = call_and_read_body_json(
+            &app,
+            request(Method::POST, "/share/a%20b.txt?ttl=60", Some("view-token")).to_request(),
+        )
+        .await;
+        let url = share["url"].as_str().unwrap();
+        assert!(url.starts_with("/a%20b%2Etxt?expires="));
+        let resp = call_service(&app, get(url, None)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_body(resp).await, "shared");
+
+        let tampered = url.replace("expires=", "expires=1");
+        let resp = call_service(&app, get(&tampered, None)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let auth = Auth::parse(TOKENS, b"test key").unwrap();
+        let expired = auth::now() - 1;
+        let url = format!(
+            "/a%20b.txt?expires={}&signature={}",
+            expired,
+            auth.sign("a b.txt", expired)
+        );
+        let resp = call_service(&app, get(&url, None)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = service(dir.path()).await;
+        call_service(&app, put("/a", "alice-token", b"12345")).await;
+        call_service(
+            &app,
+            request(Method::DELETE, "/a", Some("alice-token")).to_request(),
+        )
+        .await;
+
+        let log = std::fs::read_to_string(dir.path().join(".audit.log")).unwrap();
+        let events: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["who"], "alice");
+        assert_eq!(events[0]["action"], "upload");
+        assert_eq!(events[0]["file"], "a");
+        assert_eq!(events[0]["size"], 5);
+        assert_eq!(events[1]["action"], "delete");
+    }
+
+    #[actix_web::test]
+    async fn test_ranges_and_etags() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = service(dir.path()).await;
+        let resp = call_service(&app, put("/a", "alice-token", b"0123456789")).await;
+        let etag = resp
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        let resp = call_service(
+            &app,
+            request(Method::GET, "/a", Some("view-token"))
+                .insert_header((header::RANGE, "bytes=2-4"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 2-4/10"
+        );
+        assert_eq!(read_body(resp).await, "234");
+
+        let resp = call_service(
+            &app,
+            request(Method::GET, "/a", Some("view-token"))
+                .insert_header((header::RANGE, "bytes=10-"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let resp = call_service(
+            &app,
+            request(Method::GET, "/a", Some("view-token"))
+                .insert_header((header::IF_NONE_MATCH, etag))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[actix_web::test]
+    async fn test_resumable_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = service(dir.path()).await;
+        let patch = |offset: &str, body: &'static [u8]| {
+            request(Method::PATCH, "/r", Some("alice-token"))
+                .insert_header(("Upload-Offset", offset))
+                .insert_header(("Upload-Length", "6"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let resp = call_service(&app, patch("0", b"abc")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "3");
+        let resp = call_service(
+            &app,
+            request(Method::HEAD, "/r", Some("view-token")).to_request(),
+        )
+        .await;
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "3");
+
+        let resp = call_service(&app, patch("2", b"cdef")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "3");
+
+        let resp = call_service(&app, patch("3", b"def")).await;
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "6");
+        let body = call_and_read_body(&app, get("/r", Some("view-token"))).await;
+        assert_eq!(body, "abcdef");
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok(0..100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(Ok(900..1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok(900..1000)));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some(Ok(0..1000)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some(Ok(990..1000)));
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 1000),
+            Some(Ok(0..1000))
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-", 0), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=x-1", 1000), None);
+    }
+
+    #[test]
+    fn test_none_match() {
+        let mut headers = HeaderMap::new();
+        assert!(!none_match(&headers, "\"abc\""));
+        headers.insert(
+            header::IF_NONE_MATCH,
+            header::HeaderValue::from_static("\"xyz\", W/\"abc\""),
+        );
+        assert!(none_match(&headers, "\"abc\""));
+        assert!(!none_match(&headers, "\"def\""));
+        headers.insert(header::IF_NONE_MATCH, header::HeaderValue::from_static("*"));
+        assert!(none_match(&headers, "\"def\""));
+    }
+}
@@ -1,94 +1,73 @@
-use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
-use futures::stream::StreamExt; // Removed TryStreamExt and future::{Future, ok}
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+mod audit;
+mod auth;
+mod handlers;
+mod store;
 
-// Define the shared store type alias
-type FileStore = Arc<Mutex<HashMap<String, web::Bytes>>>;
+use actix_web::{web, App, HttpServer};
+use std::env;
+use std::path::PathBuf;
+use std::process;
 
-async fn delete_file(path: web::Path<String>, store: web::Data<FileStore>) -> impl Responder {
-    let filename = path.into_inner();
-    let mut store_guard = store.lock().unwrap();
-    if store_guard.remove(&filename).is_some() {
-        HttpResponse::Ok().finish()
-    } else {
-        HttpResponse::NotFound().finish()
-    }
-}
-
-async fn download_file(path: web::Path<String>, store: web::Data<FileStore>) -> impl Responder {
-    let filename = path.into_inner();
-    let store_guard = store.lock().unwrap();
-    match store_guard.get(&filename) {
-        Some(file_bytes) => HttpResponse::Ok()
-            .content_type("application/octet-stream")
-            .body(file_bytes.clone()),
-        None => HttpResponse::NotFound().finish(),
-    }
-}
-
-async fn upload_specified_file(
-    mut payload: web::Payload,
-    path: web::Path<String>,
-    store: web::Data<FileStore>,
-) -> Result<HttpResponse, Error> {
-    let filename = path.into_inner();
-    let mut body = web::BytesMut::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk?;
-        body.extend_from_slice(&chunk);
-    }
+use crate::audit::Audit;
+use crate::auth::Auth;
+use crate::store::Store;
 
-    let mut store_guard = store.lock().unwrap();
-    store_guard.insert(filename, body.freeze());
-    Ok(HttpResponse::Ok().finish())
-}
-
-// upload_new_file is essentially the same as upload_specified_file for in-memory store
-// as PUT and POST on the same resource path will just overwrite.
-async fn upload_new_file(
-    mut payload: web::Payload,
-    path: web::Path<String>,
-    store: web::Data<FileStore>,
-) -> Result<HttpResponse, Error> {
-    let filename = path.into_inner();
-    let mut body = web::BytesMut::new();
-    while let Some(chunk) = payload.next().await {
-        let chunk = chunk?;
-        body.extend_from_slice(&chunk);
-    }
-
-    let mut store_guard = store.lock().unwrap();
-    store_guard.insert(filename, body.freeze());
-    Ok(HttpResponse::Ok().finish())
-}
-
-async fn invalid_resource(req: HttpRequest) -> impl Responder {
-    println!("Invalid URI: \"{}\"", req.uri());
-    HttpResponse::NotFound()
+fn usage() -> ! {
+    eprintln!("usage: file_transfer [--root dir] [--tokens file] [--share-key key] [--audit file] [address]");
+    eprintln!("The storage root defaults to $FILE_TRANSFER_ROOT, then ./files.");
+    eprintln!("Share links are signed with $FILE_TRANSFER_SHARE_KEY unless a key is given;");
+    eprintln!("without either, a random key is used and links die with the server.");
+    process::exit(2);
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let server_address = "127.0.0.1:8080";
-    println!("Listening at address {}...", server_address);
+    let mut server_address = "127.0.0.1:8080".to_owned();
+    let mut root = env::var("FILE_TRANSFER_ROOT").unwrap_or_else(|_| "files".to_owned());
+    let mut tokens = None;
+    let mut share_key = env::var("FILE_TRANSFER_SHARE_KEY").ok();
+    let mut audit_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = args.next().unwrap_or_else(|| usage()),
+            "--tokens" => tokens = Some(args.next().unwrap_or_else(|| usage())),
+            "--share-key" => share_key = Some(args.next().unwrap_or_else(|| usage())),
+            "--audit" => audit_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            _ if arg.starts_with('-') => usage(),
+            _ => server_address = arg,
+        }
+    }
 
-    // Initialize the shared file store
-    let file_store_data =
-        web::Data::new(Arc::new(Mutex::new(HashMap::<String, web::Bytes>::new())));
+    // Shared by every worker
+    let store = web::Data::new(Store::open(&root)?);
+    let share_key = match share_key {
+        Some(key) => key.into_bytes(),
+        None => rand::random::<[u8; 32]>().to_vec(),
+    };
+    let auth = web::Data::new(match tokens {
+        Some(path) => Auth::load(&path, &share_key)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)))?,
+        None => {
+            println!("No token file given, so anyone may read, write and delete files!");
+            Auth::open(&share_key)
+        }
+    });
+    let audit_path = audit_path.unwrap_or_else(|| store.root().join(".audit.log"));
+    let audit = web::Data::new(Audit::open(&audit_path)?);
+    println!(
+        "Listening at address {}, storing files in {}...",
+        server_address,
+        store.root().display()
+    );
 
     HttpServer::new(move || {
-        // Closure needs to be move due to file_store_data
         App::new()
-            .app_data(file_store_data.clone()) // Add shared state
-            .service(
-                web::resource("/{filename}")
-                    .route(web::delete().to(delete_file))
-                    .route(web::get().to(download_file))
-                    .route(web::put().to(upload_specified_file))
-                    .route(web::post().to(upload_new_file)),
-            )
-            .default_service(web::route().to(invalid_resource))
+            .app_data(store.clone())
+            .app_data(auth.clone())
+            .app_data(audit.clone())
+            .configure(handlers::configure)
+            .default_service(web::route().to(handlers::invalid_resource))
     })
     .bind(server_address)?
     .run()
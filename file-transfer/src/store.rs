@@ -0,0 +1,604 @@
+//! Files kept on disk under a storage root.
+//!
+//! Whole uploads are streamed into a hidden temporary file and renamed into
+//! place once complete, so readers never see half-written files. Resumable
+//! uploads accumulate in `.partial/` until they reach their declared length.
+//! Who uploaded each file is kept in `.owners.json` so quotas survive restarts.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs as std_fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+const PARTIAL_DIR: &str = ".partial";
+const OWNERS_FILE: &str = ".owners.json";
+
+#[derive(Debug)]
+pub enum StoreError {
+    InvalidName,
+    NotFound,
+    /// Another resumable upload of the same file is in progress.
+    Busy,
+    /// A resumable upload continued from the wrong offset; holds the right one.
+    OffsetMismatch(u64),
+    /// More data was sent than the upload declared.
+    TooLarge,
+    /// The uploader's quota doesn't leave room for the file.
+    QuotaExceeded,
+    Io(io::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::InvalidName => write!(f, "invalid file name"),
+            StoreError::NotFound => write!(f, "file not found"),
+            StoreError::Busy => write!(f, "upload already in progress"),
+            StoreError::OffsetMismatch(offset) => write!(f, "upload is at offset {}", offset),
+            StoreError::TooLarge => write!(f, "upload exceeds its declared length"),
+            StoreError::QuotaExceeded => write!(f, "storage quota exceeded"),
+            StoreError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> StoreError {
+        match e.kind() {
+            io::ErrorKind::NotFound => StoreError::NotFound,
+            _ => StoreError::Io(e),
+        }
+    }
+}
+
+impl ResponseError for StoreError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StoreError::InvalidName => StatusCode::BAD_REQUEST,
+            StoreError::NotFound => StatusCode::NOT_FOUND,
+            StoreError::Busy | StoreError::OffsetMismatch(_) => StatusCode::CONFLICT,
+            StoreError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            StoreError::QuotaExceeded => StatusCode::INSUFFICIENT_STORAGE,
+            StoreError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            StoreError::OffsetMismatch(offset) => {
+                response.insert_header(("Upload-Offset", offset.to_string()));
+            }
+            StoreError::Io(e) => println!("Storage error: {}", e),
+            _ => {}
+        }
+        response.body(self.to_string())
+    }
+}
+
+/// A stored file as reported by the listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch.
+    pub modified: u64,
+    pub sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl Entry {
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.sha256)
+    }
+
+    pub fn modified_time(&self) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_secs(self.modified)
+    }
+}
+
+struct CachedDigest {
+    modified: SystemTime,
+    size: u64,
+    sha256: String,
+}
+
+pub struct Store {
+    root: PathBuf,
+    /// SHA-256 digests, reused until the file's size or mtime changes.
+    digests: Mutex<HashMap<String, CachedDigest>>,
+    /// Names with a resumable upload being written.
+    uploading: Mutex<HashSet<String>>,
+    /// Who uploaded each file, complete or partial.
+    owners: Mutex<HashMap<String, String>>,
+    temp_counter: AtomicU64,
+}
+
+impl Store {
+    pub fn open<P: Into<PathBuf>>(root: P) -> io::Result<Store> {
+        let root = root.into();
+        std_fs::create_dir_all(root.join(PARTIAL_DIR))?;
+        let owners = match std_fs::read(root.join(OWNERS_FILE)) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Store {
+            root,
+            digests: Mutex::new(HashMap::new()),
+            uploading: Mutex::new(HashSet::new()),
+            owners: Mutex::new(owners),
+            temp_counter: AtomicU64::new(0),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, StoreError> {
+        if is_valid_name(name) {
+            Ok(self.root.join(name))
+        } else {
+            Err(StoreError::InvalidName)
+        }
+    }
+
+    fn partial_path(&self, name: &str) -> Result<PathBuf, StoreError> {
+        self.path(name)?;
+        Ok(self.root.join(PARTIAL_DIR).join(name))
+    }
+
+    /// Every stored file, sorted by name. Temporary and partial files are left out.
+    pub async fn list(&self) -> Result<Vec<Entry>, StoreError> {
+        let mut entries = Vec::new();
+        let mut dir = fs::read_dir(&self.root).await?;
+        while let Some(item) = dir.next_entry().await? {
+            let name = match item.file_name().into_string() {
+                Ok(name) if is_valid_name(&name) => name,
+                _ => continue,
+            };
+            match self.entry(&name).await {
+                Ok(entry) => entries.push(entry),
+                // removed or replaced by a directory while listing
+                Err(StoreError::NotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    pub async fn entry(&self, name: &str) -> Result<Entry, StoreError> {
+        let path = self.path(name)?;
+        let metadata = fs::metadata(&path).await?;
+        if !metadata.is_file() {
+            return Err(StoreError::NotFound);
+        }
+        let modified = metadata.modified()?;
+        let size = metadata.len();
+        let sha256 = self.digest(name, path, modified, size).await?;
+        Ok(Entry {
+            name: name.to_owned(),
+            size,
+            modified: modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            sha256,
+            owner: self.owners.lock().unwrap().get(name).cloned(),
+        })
+    }
+
+    fn set_owner(&self, name: &str, owner: Option<&str>) -> io::Result<()> {
+        let mut owners = self.owners.lock().unwrap();
+        let changed = match owner {
+            Some(owner) => {
+                owners.insert(name.to_owned(), owner.to_owned()).as_deref() != Some(owner)
+            }
+            None => owners.remove(name).is_some(),
+        };
+        if !changed {
+            return Ok(());
+        }
+        let json = serde_json::to_vec_pretty(&*owners).map_err(io::Error::other)?;
+        let temp = self.root.join(format!("{}.tmp", OWNERS_FILE));
+        std_fs::write(&temp, json)?;
+        std_fs::rename(temp, self.root.join(OWNERS_FILE))
+    }
+
+    /// Bytes stored by `owner`, counting unfinished uploads but leaving out
+    /// `except`, a file about to be replaced.
+    pub async fn usage(&self, owner: &str, except: &str) -> Result<u64, StoreError> {
+        let names: Vec<String> = self
+            .owners
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, who)| who.as_str() == owner && name.as_str() != except)
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut total = 0;
+        for name in names {
+            for path in [self.path(&name)?, self.partial_path(&name)?] {
+                match fs::metadata(path).await {
+                    Ok(metadata) => total += metadata.len(),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    async fn digest(
+        &self,
+        name: &str,
+        path: PathBuf,
+        modified: SystemTime,
+        size: u64,
+    ) -> io::Result<String> {
+        if let Some(cached) = self.digests.lock().unwrap().get(name) {
+            if cached.modified == modified && cached.size == size {
+                return Ok(cached.sha256.clone());
+            }
+        }
+        let sha256 = actix_web::web::block(move || hash_file(&path))
+            .await
+            .map_err(io::Error::other)??;
+        self.remember(name, modified, size, sha256.clone());
+        Ok(sha256)
+    }
+
+    fn remember(&self, name: &str, modified: SystemTime, size: u64, sha256: String) {
+        self.digests.lock().unwrap().insert(
+            name.to_owned(),
+            CachedDigest {
+                modified,
+                size,
+                sha256,
+            },
+        );
+    }
+
+    /// Opens a stored file for reading along with its listing entry.
+    pub async fn open_file(&self, name: &str) -> Result<(File, Entry), StoreError> {
+        let entry = self.entry(name).await?;
+        let file = File::open(self.path(name)?).await?;
+        Ok((file, entry))
+    }
+
+    /// Removes a file along with any unfinished upload of it.
+    pub async fn delete(&self, name: &str) -> Result<(), StoreError> {
+        let complete = fs::remove_file(self.path(name)?).await;
+        let partial = fs::remove_file(self.partial_path(name)?).await;
+        self.digests.lock().unwrap().remove(name);
+        self.set_owner(name, None)?;
+        match (complete, partial) {
+            (Ok(()), _) | (_, Ok(())) => Ok(()),
+            (Err(e), _) => Err(e.into()),
+        }
+    }
+
+    /// Starts replacing `name` with new contents from `owner`.
+    pub async fn create(&self, name: &str, owner: &str) -> Result<Upload<'_>, StoreError> {
+        let target = self.path(name)?;
+        let temp = self.root.join(format!(
+            ".upload-{}-{}",
+            std::process::id(),
+            self.temp_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&temp).await?;
+        Ok(Upload {
+            store: self,
+            name: name.to_owned(),
+            path: temp,
+            target,
+            owner: owner.to_owned(),
+            file,
+            written: 0,
+            length: None,
+            limit: None,
+            hasher: Some(Sha256::new()),
+            finished: false,
+            lock: None,
+        })
+    }
+
+    /// How much of a resumable upload has arrived, if one is in progress.
+    pub async fn upload_offset(&self, name: &str) -> Result<Option<u64>, StoreError> {
+        match fs::metadata(self.partial_path(name)?).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Continues a resumable upload of `length` bytes at `offset`, which must
+    /// be where the previous chunk ended, or zero to start over.
+    pub async fn resume(
+        &self,
+        name: &str,
+        offset: u64,
+        length: u64,
+        owner: &str,
+    ) -> Result<Upload<'_>, StoreError> {
+        let target = self.path(name)?;
+        let partial = self.partial_path(name)?;
+        let lock = UploadLock::acquire(self, name)?;
+        let current = self.upload_offset(name).await?.unwrap_or(0);
+        if offset != 0 && offset != current {
+            return Err(StoreError::OffsetMismatch(current));
+        }
+        if offset > length {
+            return Err(StoreError::TooLarge);
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .truncate(false)
+            .open(&partial)
+            .await?;
+        if offset == 0 {
+            file.set_len(0).await?;
+        }
+        self.set_owner(name, Some(owner))?;
+        Ok(Upload {
+            store: self,
+            name: name.to_owned(),
+            path: partial,
+            target,
+            owner: owner.to_owned(),
+            file,
+            written: offset,
+            length: Some(length),
+            limit: None,
+            hasher: None,
+            finished: false,
+            lock: Some(lock),
+        })
+    }
+}
+
+/// Whether `name` can be stored: a single path component that isn't hidden.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', '\0'])
+        && !name.chars().any(char::is_control)
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = std_fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+struct UploadLock<'a> {
+    store: &'a Store,
+    name: String,
+}
+
+impl<'a> UploadLock<'a> {
+    fn acquire(store: &'a Store, name: &str) -> Result<UploadLock<'a>, StoreError> {
+        if !store.uploading.lock().unwrap().insert(name.to_owned()) {
+            return Err(StoreError::Busy);
+        }
+        Ok(UploadLock {
+            store,
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl Drop for UploadLock<'_> {
+    fn drop(&mut self) {
+        self.store.uploading.lock().unwrap().remove(&self.name);
+    }
+}
+
+/// An upload being written. Dropping an unfinished whole upload discards it;
+/// a resumable upload keeps whatever arrived.
+pub struct Upload<'a> {
+    store: &'a Store,
+    name: String,
+    path: PathBuf,
+    target: PathBuf,
+    owner: String,
+    file: File,
+    written: u64,
+    length: Option<u64>,
+    /// Most bytes this upload may add to the store.
+    limit: Option<u64>,
+    hasher: Option<Sha256>,
+    finished: bool,
+    lock: Option<UploadLock<'a>>,
+}
+
+impl Upload<'_> {
+    /// Caps how many bytes the upload may hold, failing it with
+    /// `QuotaExceeded` beyond that.
+    pub fn limit(mut self, limit: Option<u64>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), StoreError> {
+        let written = self.written + chunk.len() as u64;
+        if self.length.is_some_and(|length| written > length) {
+            return Err(StoreError::TooLarge);
+        }
+        if self.limit.is_some_and(|limit| written > limit) {
+            return Err(StoreError::QuotaExceeded);
+        }
+        self.file.write_all(chunk).await?;
+        self.written = written;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(chunk);
+        }
+        Ok(())
+    }
+
+    /// Completes the upload if all of it has arrived, returning the number
+    /// of bytes received so far.
+    pub async fn finish(mut self) -> Result<u64, StoreError> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        if self.length.is_some_and(|length| self.written < length) {
+            self.finished = true;
+            return Ok(self.written);
+        }
+        fs::rename(&self.path, &self.target).await?;
+        self.finished = true;
+        self.store.set_owner(&self.name, Some(&self.owner))?;
+        let metadata = fs::metadata(&self.target).await?;
+        match self.hasher.take() {
+            Some(hasher) => self.store.remember(
+                &self.name,
+                metadata.modified()?,
+                metadata.len(),
+                format!("{:x}", hasher.finalize()),
+            ),
+            None => {
+                self.store.digests.lock().unwrap().remove(&self.name);
+            }
+        }
+        Ok(self.written)
+    }
+}
+
+impl Drop for Upload<'_> {
+    fn drop(&mut self) {
+        if !self.finished && self.lock.is_none() {
+            let _ = std_fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_names() {
+        assert!(is_valid_name("report.pdf"));
+        assert!(is_valid_name("with space and ünïcode"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("."));
+        assert!(!is_valid_name(".."));
+        assert!(!is_valid_name(".partial"));
+        assert!(!is_valid_name("../etc/passwd"));
+        assert!(!is_valid_name("a/b"));
+        assert!(!is_valid_name("a\\b"));
+        assert!(!is_valid_name("line\nbreak"));
+        assert!(!is_valid_name(&"x".repeat(256)));
+    }
+
+    #[actix_web::test]
+    async fn test_create_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::open(dir.path()).unwrap();
+
+        let mut upload = store.create("hello.txt", "alice").await.unwrap();
+        upload.write(b"hello ").await.unwrap();
+        upload.write(b"world").await.unwrap();
+        assert_eq!(upload.finish().await.unwrap(), 11);
+
+        // an abandoned upload leaves nothing behind
+        let mut upload = store.create("gone.txt", "alice").await.unwrap();
+        upload.write(b"partial").await.unwrap();
+        drop(upload);
+
+        let entries = store.list().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "hello.txt");
+        assert_eq!(entries[0].size, 11);
+        assert_eq!(
+            entries[0].sha256,
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        assert_eq!(entries[0].owner.as_deref(), Some("alice"));
+        assert_eq!(std_fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        // ownership survives a restart
+        let store = Store::open(dir.path()).unwrap();
+        assert_eq!(store.usage("alice", "").await.unwrap(), 11);
+        assert_eq!(store.usage("alice", "hello.txt").await.unwrap(), 0);
+        store.delete("hello.txt").await.unwrap();
+        assert_eq!(store.usage("alice", "").await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn test_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::open(dir.path()).unwrap();
+        let mut upload = store.create("a", "alice").await.unwrap().limit(Some(4));
+        upload.write(b"1234").await.unwrap();
+        assert!(matches!(
+            upload.write(b"5").await,
+            Err(StoreError::QuotaExceeded)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::open(dir.path()).unwrap();
+
+        let mut upload = store.resume("big.bin", 0, 10, "bob").await.unwrap();
+        upload.write(b"01234").await.unwrap();
+        assert!(matches!(
+            store.resume("big.bin", 5, 10, "bob").await,
+            Err(StoreError::Busy)
+        ));
+        assert_eq!(upload.finish().await.unwrap(), 5);
+        assert_eq!(store.upload_offset("big.bin").await.unwrap(), Some(5));
+        assert_eq!(store.usage("bob", "").await.unwrap(), 5);
+        assert!(matches!(
+            store.entry("big.bin").await,
+            Err(StoreError::NotFound)
+        ));
+
+        assert!(matches!(
+            store.resume("big.bin", 3, 10, "bob").await,
+            Err(StoreError::OffsetMismatch(5))
+        ));
+        let mut upload = store.resume("big.bin", 5, 10, "bob").await.unwrap();
+        assert!(matches!(
+            upload.write(b"56789!").await,
+            Err(StoreError::TooLarge)
+        ));
+        upload.write(b"56789").await.unwrap();
+        assert_eq!(upload.finish().await.unwrap(), 10);
+
+        assert_eq!(store.upload_offset("big.bin").await.unwrap(), None);
+        assert_eq!(
+            std_fs::read(dir.path().join("big.bin")).unwrap(),
+            b"0123456789"
+        );
+        assert_eq!(store.entry("big.bin").await.unwrap().size, 10);
+    }
+}
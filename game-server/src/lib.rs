@@ -0,0 +1,6 @@
+//! The game simulation and its wire protocol, kept apart from the networking
+//! so they can be driven without sockets.
+
+pub mod protocol;
+pub mod server;
+pub mod world;
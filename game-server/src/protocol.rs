@@ -0,0 +1,224 @@
+//! Messages exchanged with clients, as JSON text frames.
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::Rect;
+
+/// What a client sends.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Enter a room, creating it if need be.
+    Join {
+        room: String,
+    },
+    /// Take back the entity of a dropped connection.
+    Resume {
+        token: String,
+    },
+    /// Go back to the lobby, giving up the entity.
+    Leave,
+    Input(Input),
+    /// The latest snapshot received, which later ones may be relative to.
+    Ack {
+        tick: u64,
+    },
+}
+
+/// The keys a client held during one tick. Each input is applied for exactly
+/// one tick, in `seq` order, so a client can replay the ones the server hasn't
+/// acknowledged yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Input {
+    pub seq: u32,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl Input {
+    /// The direction of travel, each axis in `-1..=1`.
+    pub fn direction(&self) -> (i32, i32) {
+        (
+            self.right as i32 - self.left as i32,
+            self.down as i32 - self.up as i32,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EntityState {
+    pub id: u32,
+    pub position: Position,
+}
+
+/// The static parts of the world a client needs to draw it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WorldInfo {
+    pub width: i32,
+    pub height: i32,
+    pub entity_size: i32,
+    pub obstacles: Vec<Rect>,
+    pub tick_rate: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RoomSummary {
+    pub name: String,
+    pub players: usize,
+}
+
+/// What the server sends.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Sent on connecting and after leaving a room.
+    Lobby {
+        rooms: Vec<RoomSummary>,
+    },
+    /// Sent on joining or resuming. Keep `token` to resume after a dropped
+    /// connection.
+    Welcome {
+        id: u32,
+        room: String,
+        token: String,
+        world: WorldInfo,
+    },
+    Snapshot(Snapshot),
+    Error {
+        message: String,
+    },
+}
+
+/// The entities in a room, either in full or as the changes since an earlier
+/// snapshot the client acknowledged.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub tick: u64,
+    /// The last input of the receiving client that has been applied.
+    pub ack: u32,
+    /// The tick this snapshot is relative to, or `None` when it's complete.
+    pub baseline: Option<u64>,
+    /// Entities that are new or have changed since the baseline.
+    pub entities: Vec<EntityState>,
+    /// Entities that have gone since the baseline.
+    pub removed: Vec<u32>,
+}
+
+impl Snapshot {
+    /// This snapshot, which must be complete, as changes from `base`, the
+    /// entities at tick `baseline`.
+    pub fn delta(&self, baseline: u64, base: &[EntityState]) -> Snapshot {
+        let entities = self
+            .entities
+            .iter()
+            .filter(|e| !base.contains(e))
+            .cloned()
+            .collect();
+        let removed = base
+            .iter()
+            .filter(|b| !self.entities.iter().any(|e| e.id == b.id))
+            .map(|b| b.id)
+            .collect();
+        Snapshot {
+            tick: self.tick,
+            ack: self.ack,
+            baseline: Some(baseline),
+            entities,
+            removed,
+        }
+    }
+
+    /// The complete list of entities, given those at the baseline tick.
+    pub fn apply(&self, base: &[EntityState]) -> Vec<EntityState> {
+        let mut entities: Vec<EntityState> = match self.baseline {
+            Some(_) => base
+                .iter()
+                .filter(|b| !self.removed.contains(&b.id))
+                .filter(|b| !self.entities.iter().any(|e| e.id == b.id))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        entities.extend(self.entities.iter().cloned());
+        entities.sort_by_key(|e| e.id);
+        entities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_message() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"type":"input","seq":7,"up":true,"right":true}"#).unwrap();
+        let ClientMessage::Input(input) = message else {
+            panic!("not an input: {:?}", message);
+        };
+        assert_eq!(input.seq, 7);
+        assert_eq!(input.direction(), (1, -1));
+
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(r#"{"type":"join","room":"red"}"#).unwrap(),
+            ClientMessage::Join {
+                room: "red".to_owned()
+            }
+        );
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type":"teleport"}"#).is_err());
+        assert!(serde_json::from_str::<ClientMessage>("R").is_err());
+    }
+
+    #[test]
+    fn test_server_message() {
+        let snapshot = ServerMessage::Snapshot(Snapshot {
+            tick: 3,
+            ack: 2,
+            baseline: None,
+            entities: vec![entity(1, 10, 20)],
+            removed: Vec::new(),
+        });
+        assert_eq!(
+            serde_json::to_string(&snapshot).unwrap(),
+            r#"{"type":"snapshot","tick":3,"ack":2,"baseline":null,"entities":[{"id":1,"position":{"x":10,"y":20}}],"removed":[]}"#
+        );
+    }
+
+    fn entity(id: u32, x: i32, y: i32) -> EntityState {
+        EntityState {
+            id,
+            position: Position { x, y },
+        }
+    }
+
+    #[test]
+    fn test_delta() {
+        let base = vec![entity(1, 0, 0), entity(2, 50, 0), entity(3, 90, 0)];
+        let full = Snapshot {
+            tick: 9,
+            ack: 4,
+            baseline: None,
+            entities: vec![entity(1, 0, 0), entity(2, 55, 0), entity(4, 0, 50)],
+            removed: Vec::new(),
+        };
+        let delta = full.delta(7, &base);
+        assert_eq!(delta.baseline, Some(7));
+        assert_eq!(delta.entities, vec![entity(2, 55, 0), entity(4, 0, 50)]);
+        assert_eq!(delta.removed, vec![3]);
+        assert_eq!(delta.apply(&base), full.entities);
+        assert_eq!(full.apply(&base), full.entities);
+
+        // nothing changed, nothing sent
+        let still = full.delta(9, &full.entities);
+        assert!(still.entities.is_empty() && still.removed.is_empty());
+    }
+}
@@ -0,0 +1,425 @@
+//! Rooms of players over WebSocket.
+//!
+//! Every connection starts in the lobby and can join a room, each of which
+//! runs its own [`World`]. Snapshots go out once a tick as deltas against the
+//! last one the client acknowledged. A player whose connection drops keeps
+//! their entity for a grace period and can take it back with the token from
+//! their welcome.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::protocol::{ClientMessage, EntityState, RoomSummary, ServerMessage};
+use crate::world::{self, InputError, World};
+
+/// Snapshots remembered per client as possible baselines.
+const HISTORY: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub world: world::Config,
+    /// How long a dropped player's entity waits to be resumed.
+    pub grace_period: Duration,
+    /// Messages that may wait for a slow client. Snapshots that don't fit
+    /// are skipped rather than holding up everyone else.
+    pub queue_len: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            world: world::Config::default(),
+            grace_period: Duration::from_secs(30),
+            queue_len: 16,
+        }
+    }
+}
+
+/// One WebSocket, identified so a stale connection can't act for a player
+/// that has since resumed elsewhere.
+#[derive(Clone)]
+struct Connection {
+    id: u64,
+    outbound: mpsc::Sender<Message>,
+}
+
+struct Member {
+    token: String,
+    connection: Option<Connection>,
+    /// When the connection went away, if it has.
+    dropped: Option<Instant>,
+    /// Complete entity lists recently sent, oldest first.
+    sent: VecDeque<(u64, Vec<EntityState>)>,
+    /// The latest of `sent` the client has acknowledged.
+    acked: Option<u64>,
+}
+
+impl Member {
+    fn attach(&mut self, connection: &Connection) {
+        self.connection = Some(connection.clone());
+        self.dropped = None;
+        // a new connection has seen nothing yet
+        self.sent.clear();
+        self.acked = None;
+    }
+
+    fn detach(&mut self, now: Instant) {
+        self.connection = None;
+        self.dropped = Some(now);
+    }
+
+    fn is_on(&self, connection: &Connection) -> bool {
+        self.connection
+            .as_ref()
+            .is_some_and(|c| c.id == connection.id)
+    }
+}
+
+struct Room {
+    world: World,
+    members: BTreeMap<u32, Member>,
+}
+
+/// Where a connection is playing: a room name and entity id.
+type Seat = (String, u32);
+
+struct State {
+    config: ServerConfig,
+    rooms: BTreeMap<String, Room>,
+    tokens: HashMap<String, Seat>,
+    next_entity: u32,
+    next_connection: u64,
+}
+
+impl State {
+    fn new(config: ServerConfig) -> State {
+        State {
+            config,
+            rooms: BTreeMap::new(),
+            tokens: HashMap::new(),
+            next_entity: 0,
+            next_connection: 0,
+        }
+    }
+
+    fn lobby(&self) -> ServerMessage {
+        ServerMessage::Lobby {
+            rooms: self
+                .rooms
+                .iter()
+                .map(|(name, room)| RoomSummary {
+                    name: name.clone(),
+                    players: room.members.len(),
+                })
+                .collect(),
+        }
+    }
+
+    fn welcome(&self, (room, id): &Seat) -> ServerMessage {
+        let joined = &self.rooms[room];
+        ServerMessage::Welcome {
+            id: *id,
+            room: room.clone(),
+            token: joined.members[id].token.clone(),
+            world: joined.world.info(),
+        }
+    }
+
+    /// The member sitting in `seat`, provided `connection` is still the one
+    /// playing it.
+    fn member(&mut self, seat: Option<&Seat>, connection: &Connection) -> Option<&mut Member> {
+        let (room, id) = seat?;
+        let member = self.rooms.get_mut(room)?.members.get_mut(id)?;
+        member.is_on(connection).then_some(member)
+    }
+
+    fn handle(
+        &mut self,
+        connection: &Connection,
+        seat: &mut Option<Seat>,
+        message: ClientMessage,
+    ) -> Option<ServerMessage> {
+        match message {
+            ClientMessage::Join { room } => Some(self.join(connection, seat, room)),
+            ClientMessage::Resume { token } => Some(self.resume(connection, seat, &token)),
+            ClientMessage::Leave => {
+                if let Some(current) = seat.take() {
+                    if self.member(Some(&current), connection).is_some() {
+                        self.remove(&current);
+                    }
+                }
+                Some(self.lobby())
+            }
+            ClientMessage::Input(input) => {
+                let (room, id) = seat.as_ref()?;
+                self.member(seat.as_ref(), connection)?;
+                match self.rooms.get_mut(room)?.world.submit(*id, input) {
+                    // dropped inputs show up as a lagging ack on the client
+                    Ok(()) | Err(InputError::RateLimited) | Err(InputError::OutOfOrder) => None,
+                    Err(InputError::UnknownEntity) => Some(error("not in a room")),
+                }
+            }
+            ClientMessage::Ack { tick } => {
+                let member = self.member(seat.as_ref(), connection)?;
+                if member.sent.iter().any(|(sent, _)| *sent == tick)
+                    && member.acked.is_none_or(|acked| acked < tick)
+                {
+                    member.acked = Some(tick);
+                    // nothing older will be a baseline again
+                    member.sent.retain(|(sent, _)| *sent >= tick);
+                }
+                None
+            }
+        }
+    }
+
+    fn join(
+        &mut self,
+        connection: &Connection,
+        seat: &mut Option<Seat>,
+        name: String,
+    ) -> ServerMessage {
+        if seat.is_some() {
+            return error("already in a room");
+        }
+        if name.is_empty() || name.len() > 32 {
+            return error("room names are 1 to 32 bytes");
+        }
+        let id = self.next_entity + 1;
+        let config = &self.config.world;
+        let room = self.rooms.entry(name.clone()).or_insert_with(|| Room {
+            world: World::new(config.clone()),
+            members: BTreeMap::new(),
+        });
+        if room.world.spawn(id).is_none() {
+            if room.members.is_empty() {
+                self.rooms.remove(&name);
+            }
+            return error("room is full");
+        }
+        self.next_entity = id;
+
+        let token = new_token();
+        let mut member = Member {
+            token: token.clone(),
+            connection: None,
+            dropped: None,
+            sent: VecDeque::new(),
+            acked: None,
+        };
+        member.attach(connection);
+        room.members.insert(id, member);
+        self.tokens.insert(token, (name.clone(), id));
+        println!("client {} joined {} as {}", connection.id, name, id);
+        let joined = (name, id);
+        let welcome = self.welcome(&joined);
+        *seat = Some(joined);
+        welcome
+    }
+
+    fn resume(
+        &mut self,
+        connection: &Connection,
+        seat: &mut Option<Seat>,
+        token: &str,
+    ) -> ServerMessage {
+        if seat.is_some() {
+            return error("already in a room");
+        }
+        let resumed = match self.tokens.get(token) {
+            Some(resumed) => resumed.clone(),
+            None => return error("unknown or expired token"),
+        };
+        let (room, id) = &resumed;
+        // whoever held it before is shut out from here on
+        if let Some(member) = self.rooms.get_mut(room).and_then(|r| r.members.get_mut(id)) {
+            member.attach(connection);
+        }
+        println!("client {} resumed {} in {}", connection.id, id, room);
+        let welcome = self.welcome(&resumed);
+        *seat = Some(resumed);
+        welcome
+    }
+
+    fn remove(&mut self, (name, id): &Seat) {
+        if let Some(room) = self.rooms.get_mut(name) {
+            if let Some(member) = room.members.remove(id) {
+                self.tokens.remove(&member.token);
+            }
+            room.world.remove(*id);
+            if room.members.is_empty() {
+                self.rooms.remove(name);
+            }
+        }
+    }
+
+    fn disconnect(&mut self, connection: &Connection, seat: &Option<Seat>, now: Instant) {
+        if let Some(member) = self.member(seat.as_ref(), connection) {
+            member.detach(now);
+        }
+    }
+
+    /// Steps every room, queues each connected player a snapshot and lets go
+    /// of players that have been gone too long.
+    fn tick(&mut self, now: Instant) {
+        let mut expired = Vec::new();
+        for (name, room) in &mut self.rooms {
+            room.world.step();
+            for (&id, member) in &mut room.members {
+                let connection = match &member.connection {
+                    Some(connection) => connection,
+                    None => {
+                        if member
+                            .dropped
+                            .is_some_and(|dropped| now - dropped >= self.config.grace_period)
+                        {
+                            expired.push((name.clone(), id));
+                        }
+                        continue;
+                    }
+                };
+
+                let full = room.world.snapshot(id);
+                let baseline = member
+                    .acked
+                    .and_then(|acked| member.sent.iter().find(|(tick, _)| *tick == acked));
+                let snapshot = match baseline {
+                    Some((tick, base)) => full.delta(*tick, base),
+                    None => full.clone(),
+                };
+                match connection
+                    .outbound
+                    .try_send(encode(&ServerMessage::Snapshot(snapshot)))
+                {
+                    Ok(()) => {
+                        member.sent.push_back((full.tick, full.entities));
+                        if member.sent.len() > HISTORY {
+                            member.sent.pop_front();
+                        }
+                    }
+                    // the next one covers the same ground
+                    Err(mpsc::error::TrySendError::Full(_)) => {}
+                    Err(mpsc::error::TrySendError::Closed(_)) => member.detach(now),
+                }
+            }
+        }
+        for seat in expired {
+            println!("entity {} in {} timed out", seat.1, seat.0);
+            self.remove(&seat);
+        }
+    }
+}
+
+fn error(message: &str) -> ServerMessage {
+    ServerMessage::Error {
+        message: message.to_owned(),
+    }
+}
+
+fn encode(message: &ServerMessage) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap().into())
+}
+
+fn new_token() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Runs the game on `listener` until accepting fails.
+pub async fn serve(listener: TcpListener, config: ServerConfig) -> Result<(), Error> {
+    let tick_rate = config.world.tick_rate;
+    // Only ever locked briefly and never across an await, so a plain mutex will do
+    let state = Arc::new(Mutex::new(State::new(config)));
+
+    // Spawn the simulation loop
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1) / tick_rate);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                interval.tick().await;
+                state.lock().unwrap().tick(Instant::now());
+            }
+        });
+    }
+
+    // Accept connections
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        println!("client addr: {}", addr);
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(state, stream).await {
+                eprintln!("WebSocket handshake error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(state: Arc<Mutex<State>>, stream: TcpStream) -> Result<(), Error> {
+    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    let (mut sink, mut stream) = ws_stream.split();
+
+    // Everything bound for the socket goes through this queue, so the
+    // simulation loop never waits on a slow client.
+    let (outbound, mut queued) = mpsc::channel(state.lock().unwrap().config.queue_len);
+    tokio::spawn(async move {
+        while let Some(message) = queued.recv().await {
+            if sink.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let (connection, lobby) = {
+        let mut state = state.lock().unwrap();
+        state.next_connection += 1;
+        let connection = Connection {
+            id: state.next_connection,
+            outbound: outbound.clone(),
+        };
+        (connection, state.lobby())
+    };
+    println!("new client {}", connection.id);
+    let mut seat = None;
+    if outbound.send(encode(&lobby)).await.is_err() {
+        return Ok(());
+    }
+
+    while let Some(Ok(msg)) = stream.next().await {
+        let txt = match msg {
+            Message::Text(txt) => txt,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let reply = match serde_json::from_str(&txt) {
+            Ok(message) => state
+                .lock()
+                .unwrap()
+                .handle(&connection, &mut seat, message),
+            Err(e) => Some(error(&format!("bad message: {}", e))),
+        };
+        if let Some(reply) = reply {
+            if outbound.send(encode(&reply)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    // Client disconnected; its entity waits a while in case it comes back
+    state
+        .lock()
+        .unwrap()
+        .disconnect(&connection, &seat, Instant::now());
+    println!("client {} disconnected", connection.id);
+    Ok(())
+}
@@ -0,0 +1,451 @@
+//! The authoritative game state, advanced one fixed tick at a time.
+//!
+//! Nothing here knows about sockets or clocks, so tests can feed inputs and
+//! step the world by hand and always get the same result.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{EntityState, Input, Position, Snapshot, WorldInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Rect {
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub width: i32,
+    pub height: i32,
+    /// Entities are squares this many pixels across.
+    pub entity_size: i32,
+    /// Pixels an entity moves per tick along each axis.
+    pub speed: i32,
+    pub tick_rate: u32,
+    pub obstacles: Vec<Rect>,
+    /// Inputs a client may have waiting to be applied.
+    pub max_queued_inputs: usize,
+    pub max_inputs_per_second: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            width: 1000,
+            height: 500,
+            entity_size: 100,
+            speed: 10,
+            tick_rate: 20,
+            obstacles: vec![
+                Rect {
+                    x: 300,
+                    y: 150,
+                    w: 50,
+                    h: 200,
+                },
+                Rect {
+                    x: 650,
+                    y: 0,
+                    w: 50,
+                    h: 150,
+                },
+            ],
+            max_queued_inputs: 10,
+            // a little slack over one per tick for jittery connections
+            max_inputs_per_second: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputError {
+    UnknownEntity,
+    /// The sequence number isn't newer than one already accepted.
+    OutOfOrder,
+    /// Too many inputs this second, or too many waiting.
+    RateLimited,
+}
+
+#[derive(Debug)]
+struct Entity {
+    position: Position,
+    inputs: VecDeque<Input>,
+    /// Highest sequence number accepted.
+    last_seq: u32,
+    /// Highest sequence number applied.
+    ack: u32,
+    window_start: u64,
+    window_inputs: u32,
+}
+
+pub struct World {
+    config: Config,
+    tick: u64,
+    entities: BTreeMap<u32, Entity>,
+}
+
+impl World {
+    pub fn new(config: Config) -> World {
+        World {
+            config,
+            tick: 0,
+            entities: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn info(&self) -> WorldInfo {
+        WorldInfo {
+            width: self.config.width,
+            height: self.config.height,
+            entity_size: self.config.entity_size,
+            obstacles: self.config.obstacles.clone(),
+            tick_rate: self.config.tick_rate,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn position(&self, id: u32) -> Option<Position> {
+        self.entities.get(&id).map(|e| e.position)
+    }
+
+    fn rect(&self, position: Position) -> Rect {
+        Rect {
+            x: position.x,
+            y: position.y,
+            w: self.config.entity_size,
+            h: self.config.entity_size,
+        }
+    }
+
+    /// Whether entity `id` would overlap a wall, an obstacle or another
+    /// entity at `position`.
+    fn blocked(&self, id: u32, position: Position) -> bool {
+        let rect = self.rect(position);
+        let size = self.config.entity_size;
+        rect.x < 0
+            || rect.y < 0
+            || rect.x + size > self.config.width
+            || rect.y + size > self.config.height
+            || self.config.obstacles.iter().any(|o| o.intersects(&rect))
+            || self
+                .entities
+                .iter()
+                .any(|(&other, e)| other != id && self.rect(e.position).intersects(&rect))
+    }
+
+    /// Adds an entity at the first free spot, scanning rows from the top left.
+    /// Returns where it went, or `None` if the world is full.
+    pub fn spawn(&mut self, id: u32) -> Option<Position> {
+        let step = (self.config.entity_size / 2).max(1);
+        let spot = (0..=self.config.height - self.config.entity_size)
+            .step_by(step as usize)
+            .flat_map(|y| {
+                (0..=self.config.width - self.config.entity_size)
+                    .step_by(step as usize)
+                    .map(move |x| Position { x, y })
+            })
+            .find(|&position| !self.blocked(id, position))?;
+        self.entities.insert(
+            id,
+            Entity {
+                position: spot,
+                inputs: VecDeque::new(),
+                last_seq: 0,
+                ack: 0,
+                window_start: self.tick,
+                window_inputs: 0,
+            },
+        );
+        Some(spot)
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.entities.remove(&id);
+    }
+
+    /// Queues an input to be applied on a coming tick. Sequence numbers start
+    /// at 1 and must increase, though gaps are fine.
+    pub fn submit(&mut self, id: u32, input: Input) -> Result<(), InputError> {
+        let tick = self.tick;
+        let window = u64::from(self.config.tick_rate);
+        let entity = self
+            .entities
+            .get_mut(&id)
+            .ok_or(InputError::UnknownEntity)?;
+        if input.seq <= entity.last_seq {
+            return Err(InputError::OutOfOrder);
+        }
+        if tick >= entity.window_start + window {
+            entity.window_start = tick;
+            entity.window_inputs = 0;
+        }
+        if entity.window_inputs >= self.config.max_inputs_per_second
+            || entity.inputs.len() >= self.config.max_queued_inputs
+        {
+            return Err(InputError::RateLimited);
+        }
+        entity.window_inputs += 1;
+        entity.last_seq = input.seq;
+        entity.inputs.push_back(input);
+        Ok(())
+    }
+
+    /// Advances one tick, applying at most one queued input per entity in
+    /// order of id.
+    pub fn step(&mut self) {
+        self.tick += 1;
+        let ids: Vec<u32> = self.entities.keys().copied().collect();
+        for id in ids {
+            let input = match self.entities.get_mut(&id).unwrap().inputs.pop_front() {
+                Some(input) => input,
+                None => continue,
+            };
+            let position = self.travel(id, input.direction());
+            let entity = self.entities.get_mut(&id).unwrap();
+            entity.position = position;
+            entity.ack = input.seq;
+        }
+    }
+
+    /// Where entity `id` ends up moving in `direction` for one tick: as far
+    /// as it can along each axis before bumping into something.
+    fn travel(&self, id: u32, (dx, dy): (i32, i32)) -> Position {
+        let mut position = self.entities[&id].position;
+        for (step_x, step_y) in [(dx, 0), (0, dy)] {
+            if step_x == 0 && step_y == 0 {
+                continue;
+            }
+            for _ in 0..self.config.speed {
+                let next = Position {
+                    x: position.x + step_x,
+                    y: position.y + step_y,
+                };
+                if self.blocked(id, next) {
+                    break;
+                }
+                position = next;
+            }
+        }
+        position
+    }
+
+    /// The whole world as client `id` should see it.
+    pub fn snapshot(&self, id: u32) -> Snapshot {
+        Snapshot {
+            tick: self.tick,
+            ack: self.entities.get(&id).map_or(0, |e| e.ack),
+            baseline: None,
+            entities: self
+                .entities
+                .iter()
+                .map(|(&id, e)| EntityState {
+                    id,
+                    position: e.position,
+                })
+                .collect(),
+            removed: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            width: 100,
+            height: 100,
+            entity_size: 10,
+            speed: 5,
+            tick_rate: 10,
+            obstacles: vec![Rect {
+                x: 50,
+                y: 0,
+                w: 10,
+                h: 10,
+            }],
+            max_queued_inputs: 4,
+            max_inputs_per_second: 6,
+        }
+    }
+
+    fn right(seq: u32) -> Input {
+        Input {
+            seq,
+            right: true,
+            ..Input::default()
+        }
+    }
+
+    fn at(x: i32, y: i32) -> Option<Position> {
+        Some(Position { x, y })
+    }
+
+    #[test]
+    fn test_spawn() {
+        let mut world = World::new(Config {
+            width: 20,
+            height: 10,
+            obstacles: Vec::new(),
+            ..config()
+        });
+        assert_eq!(world.spawn(1), at(0, 0));
+        assert_eq!(world.spawn(2), at(10, 0));
+        assert_eq!(world.spawn(3), None);
+        world.remove(1);
+        assert_eq!(world.spawn(3), at(0, 0));
+    }
+
+    #[test]
+    fn test_movement_and_ack() {
+        let mut world = World::new(config());
+        world.spawn(1);
+        world.submit(1, right(1)).unwrap();
+        world.submit(1, right(2)).unwrap();
+        world.step();
+        // one input per tick
+        assert_eq!(world.position(1), at(5, 0));
+        assert_eq!(world.snapshot(1).ack, 1);
+        world.step();
+        world.step();
+        assert_eq!(world.position(1), at(10, 0));
+        let snapshot = world.snapshot(1);
+        assert_eq!((snapshot.tick, snapshot.ack), (3, 2));
+
+        let diagonal = Input {
+            seq: 3,
+            down: true,
+            left: true,
+            ..Input::default()
+        };
+        world.submit(1, diagonal).unwrap();
+        world.step();
+        assert_eq!(world.position(1), at(5, 5));
+    }
+
+    #[test]
+    fn test_bounds_and_obstacles() {
+        let mut world = World::new(Config {
+            max_inputs_per_second: 100,
+            ..config()
+        });
+        world.spawn(1);
+        let up = Input {
+            seq: 1,
+            up: true,
+            left: true,
+            ..Input::default()
+        };
+        world.submit(1, up).unwrap();
+        world.step();
+        assert_eq!(world.position(1), at(0, 0));
+
+        // stops flush against the obstacle at x = 50
+        for seq in 2..=12 {
+            world.submit(1, right(seq)).unwrap();
+            world.step();
+        }
+        assert_eq!(world.position(1), at(40, 0));
+    }
+
+    #[test]
+    fn test_entity_collision() {
+        let mut world = World::new(Config {
+            obstacles: Vec::new(),
+            ..config()
+        });
+        world.spawn(1);
+        world.spawn(2);
+        assert_eq!(world.position(2), at(10, 0));
+        world.submit(1, right(1)).unwrap();
+        world.step();
+        assert_eq!(world.position(1), at(0, 0));
+
+        // sliding along the other entity still works
+        let down_right = Input {
+            seq: 2,
+            down: true,
+            right: true,
+            ..Input::default()
+        };
+        world.submit(1, down_right).unwrap();
+        world.step();
+        assert_eq!(world.position(1), at(0, 5));
+    }
+
+    #[test]
+    fn test_input_validation() {
+        let mut world = World::new(config());
+        assert_eq!(world.submit(1, right(1)), Err(InputError::UnknownEntity));
+        world.spawn(1);
+        assert_eq!(world.submit(1, right(0)), Err(InputError::OutOfOrder));
+        world.submit(1, right(5)).unwrap();
+        assert_eq!(world.submit(1, right(5)), Err(InputError::OutOfOrder));
+        assert_eq!(world.submit(1, right(3)), Err(InputError::OutOfOrder));
+
+        // the queue holds four
+        for seq in 6..=8 {
+            world.submit(1, right(seq)).unwrap();
+        }
+        assert_eq!(world.submit(1, right(9)), Err(InputError::RateLimited));
+
+        // six per second, where a second is ten ticks
+        for seq in 9..=10 {
+            world.step();
+            world.submit(1, right(seq)).unwrap();
+        }
+        world.step();
+        assert_eq!(world.submit(1, right(11)), Err(InputError::RateLimited));
+        for _ in 0..7 {
+            world.step();
+        }
+        assert_eq!(world.tick(), 10);
+        world.submit(1, right(11)).unwrap();
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let run = || {
+            let mut world = World::new(config());
+            for id in 1..=3 {
+                world.spawn(id);
+            }
+            for tick in 1..=30u32 {
+                for id in 1..=3 {
+                    let input = Input {
+                        seq: tick,
+                        up: (tick + id) % 3 == 0,
+                        down: (tick * id) % 4 == 1,
+                        left: tick % 5 == id,
+                        right: (tick + id) % 2 == 0,
+                    };
+                    let _ = world.submit(id, input);
+                }
+                world.step();
+            }
+            world.snapshot(1)
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use game_server::protocol::{
+    ClientMessage, EntityState, Input, RoomSummary, ServerMessage, Snapshot,
+};
+use game_server::server::{self, ServerConfig};
+
+async fn start(config: ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server::serve(listener, config));
+    addr
+}
+
+/// A test client that keeps track of the world the way a real one would.
+struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// Complete entity lists by tick, for resolving deltas.
+    states: HashMap<u64, Vec<EntityState>>,
+}
+
+impl Client {
+    async fn connect(addr: SocketAddr) -> Client {
+        let (ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        let mut client = Client {
+            ws,
+            states: HashMap::new(),
+        };
+        assert!(matches!(client.recv().await, ServerMessage::Lobby { .. }));
+        client
+    }
+
+    async fn send(&mut self, message: ClientMessage) {
+        let json = serde_json::to_string(&message).unwrap();
+        self.ws.send(Message::Text(json.into())).await.unwrap();
+    }
+
+    async fn recv(&mut self) -> ServerMessage {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), self.ws.next())
+                .await
+                .expect("timed out waiting for the server")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// The next reply that isn't a snapshot.
+    async fn reply(&mut self) -> ServerMessage {
+        loop {
+            match self.recv().await {
+                ServerMessage::Snapshot(_) => continue,
+                message => return message,
+            }
+        }
+    }
+
+    async fn join(&mut self, room: &str) -> (u32, String) {
+        self.send(ClientMessage::Join {
+            room: room.to_owned(),
+        })
+        .await;
+        match self.reply().await {
+            ServerMessage::Welcome { id, token, .. } => (id, token),
+            message => panic!("expected a welcome, got {:?}", message),
+        }
+    }
+
+    /// Receives a snapshot, acknowledges it and returns it along with the
+    /// entities it describes.
+    async fn snapshot(&mut self) -> (Snapshot, Vec<EntityState>) {
+        let snapshot = loop {
+            if let ServerMessage::Snapshot(snapshot) = self.recv().await {
+                break snapshot;
+            }
+        };
+        let base = match snapshot.baseline {
+            Some(baseline) => self.states[&baseline].clone(),
+            None => Vec::new(),
+        };
+        let entities = snapshot.apply(&base);
+        self.states.insert(snapshot.tick, entities.clone());
+        self.send(ClientMessage::Ack {
+            tick: snapshot.tick,
+        })
+        .await;
+        (snapshot, entities)
+    }
+
+    /// Reads snapshots until the entities satisfy `done`.
+    async fn wait_for(&mut self, done: impl Fn(&[EntityState]) -> bool) -> Vec<EntityState> {
+        loop {
+            let (_, entities) = self.snapshot().await;
+            if done(&entities) {
+                return entities;
+            }
+        }
+    }
+}
+
+fn ids(entities: &[EntityState]) -> Vec<u32> {
+    entities.iter().map(|e| e.id).collect()
+}
+
+#[tokio::test]
+async fn test_rooms() {
+    let addr = start(ServerConfig::default()).await;
+    let mut a = Client::connect(addr).await;
+    let mut b = Client::connect(addr).await;
+    let mut c = Client::connect(addr).await;
+    let (a_id, _) = a.join("red").await;
+    let (b_id, _) = b.join("red").await;
+    let (c_id, _) = c.join("blue").await;
+
+    a.wait_for(|e| ids(e) == [a_id, b_id]).await;
+    b.wait_for(|e| ids(e) == [a_id, b_id]).await;
+    c.wait_for(|e| ids(e) == [c_id]).await;
+
+    c.send(ClientMessage::Join {
+        room: "red".to_owned(),
+    })
+    .await;
+    assert!(matches!(c.reply().await, ServerMessage::Error { .. }));
+
+    // leaving gives up the entity, and an empty room goes away
+    c.send(ClientMessage::Leave).await;
+    let rooms = match c.reply().await {
+        ServerMessage::Lobby { rooms } => rooms,
+        message => panic!("expected the lobby, got {:?}", message),
+    };
+    assert_eq!(
+        rooms,
+        vec![RoomSummary {
+            name: "red".to_owned(),
+            players: 2,
+        }]
+    );
+
+    b.send(ClientMessage::Leave).await;
+    a.wait_for(|e| ids(e) == [a_id]).await;
+}
+
+#[tokio::test]
+async fn test_delta_snapshots() {
+    let addr = start(ServerConfig::default()).await;
+    let mut a = Client::connect(addr).await;
+    let mut b = Client::connect(addr).await;
+    let (a_id, _) = a.join("red").await;
+
+    let (first, _) = a.snapshot().await;
+    assert_eq!(first.baseline, None);
+    assert_eq!(ids(&first.entities), [a_id]);
+
+    // once acknowledged, a world where nothing happens costs nothing
+    let (quiet, entities) = loop {
+        let (snapshot, entities) = a.snapshot().await;
+        if snapshot.baseline.is_some() {
+            break (snapshot, entities);
+        }
+    };
+    assert!(quiet.entities.is_empty() && quiet.removed.is_empty());
+    assert_eq!(ids(&entities), [a_id]);
+
+    // a newcomer shows up on its own
+    let (b_id, _) = b.join("red").await;
+    let arrival = loop {
+        let (snapshot, _) = a.snapshot().await;
+        if !snapshot.entities.is_empty() {
+            break snapshot;
+        }
+    };
+    assert!(arrival.baseline.is_some());
+    assert_eq!(ids(&arrival.entities), [b_id]);
+
+    // and so does a mover, seen from the other side
+    b.wait_for(|e| e.len() == 2).await;
+    let start = a.states[&arrival.tick][0].position;
+    a.send(ClientMessage::Input(Input {
+        seq: 1,
+        down: true,
+        ..Input::default()
+    }))
+    .await;
+    let moved = loop {
+        let (snapshot, _) = b.snapshot().await;
+        if !snapshot.entities.is_empty() {
+            break snapshot;
+        }
+    };
+    assert!(moved.baseline.is_some());
+    assert_eq!(ids(&moved.entities), [a_id]);
+    assert!(moved.entities[0].position.y > start.y);
+}
+
+#[tokio::test]
+async fn test_reconnect() {
+    let addr = start(ServerConfig {
+        grace_period: Duration::from_millis(500),
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut a = Client::connect(addr).await;
+    let mut b = Client::connect(addr).await;
+    let (a_id, token) = a.join("red").await;
+    let (b_id, _) = b.join("red").await;
+    b.wait_for(|e| ids(e) == [a_id, b_id]).await;
+
+    drop(a);
+    let mut a = Client::connect(addr).await;
+    a.send(ClientMessage::Resume {
+        token: token.clone(),
+    })
+    .await;
+    match a.reply().await {
+        ServerMessage::Welcome { id, room, .. } => {
+            assert_eq!(id, a_id);
+            assert_eq!(room, "red");
+        }
+        message => panic!("expected a welcome, got {:?}", message),
+    }
+    // a resumed connection starts over from a full snapshot
+    let (snapshot, entities) = a.snapshot().await;
+    assert_eq!(snapshot.baseline, None);
+    assert_eq!(ids(&entities), [a_id, b_id]);
+
+    // gone for longer than the grace period, the entity goes too
+    drop(a);
+    b.wait_for(|e| ids(e) == [b_id]).await;
+    let mut a = Client::connect(addr).await;
+    a.send(ClientMessage::Resume { token }).await;
+    assert!(matches!(a.reply().await, ServerMessage::Error { .. }));
+}
+
+#[tokio::test]
+async fn test_slow_client() {
+    let addr = start(ServerConfig {
+        queue_len: 2,
+        ..ServerConfig::default()
+    })
+    .await;
+    let mut a = Client::connect(addr).await;
+    let mut slow = Client::connect(addr).await;
+    let (a_id, _) = a.join("red").await;
+    let (slow_id, _) = slow.join("red").await;
+
+    // one client reading nothing doesn't hold back the ticks for others
+    let start = a.snapshot().await.0.tick;
+    let mut last = start;
+    while last < start + 20 {
+        last = a.snapshot().await.0.tick;
+    }
+    let entities = a.wait_for(|e| e.len() == 2).await;
+    assert_eq!(ids(&entities), [a_id, slow_id]);
+
+    // and when it does catch up it can still make sense of what it gets
+    let entities = slow.wait_for(|e| e.len() == 2).await;
+    assert_eq!(ids(&entities), [a_id, slow_id]);
+}
@@ -0,0 +1,322 @@
+//! Commands the shell runs itself, because they act on its own state.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+
+use crate::exec::Io;
+use crate::job::State;
+use crate::shell::Shell;
+use crate::vars::is_name;
+
+pub type Builtin = fn(&mut Shell, &[String], &Io) -> io::Result<i32>;
+
+/// The names `lookup` knows.
+pub const NAMES: &[&str] = &["alias", "bg", "cd", "exit", "export", "fg", "jobs", "pwd"];
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    match name {
+        "jobs" => Some(jobs),
+        "fg" => Some(fg),
+        "bg" => Some(bg),
+        "cd" => Some(cd),
+        "pwd" => Some(pwd),
+        "export" => Some(export),
+        "alias" => Some(alias),
+        "exit" => Some(exit),
+        _ => None,
+    }
+}
+
+/// Where a builtin writes its output.
+fn stdout(io: &Io) -> io::Result<Box<dyn Write>> {
+    Ok(match &io.stdout {
+        Some(fd) => Box::new(File::from(fd.try_clone()?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+fn error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Quote `value` so that it reads back as it is.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// `%n` or `n` picks job n, no argument the most recent job.
+fn job_spec(args: &[String]) -> io::Result<Option<usize>> {
+    match args.first() {
+        None => Ok(None),
+        Some(spec) => spec.trim_start_matches('%').parse().map(Some).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: no such job", spec),
+            )
+        }),
+    }
+}
+
+fn no_such_job(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no such job", name))
+}
+
+fn jobs(shell: &mut Shell, _: &[String], io: &Io) -> io::Result<i32> {
+    let mut out = stdout(io)?;
+    for job in shell.jobs.iter() {
+        writeln!(out, "{}", job)?;
+    }
+    Ok(0)
+}
+
+fn fg(shell: &mut Shell, args: &[String], io: &Io) -> io::Result<i32> {
+    let mut job = shell
+        .jobs
+        .take(job_spec(args)?)
+        .ok_or_else(|| no_such_job("fg"))?;
+    writeln!(stdout(io)?, "{}", job.command)?;
+    job.state = State::Running;
+    job.signal(libc::SIGCONT);
+    Ok(shell.foreground(job))
+}
+
+fn bg(shell: &mut Shell, args: &[String], io: &Io) -> io::Result<i32> {
+    let job = shell
+        .jobs
+        .get_mut(job_spec(args)?)
+        .ok_or_else(|| no_such_job("bg"))?;
+    job.state = State::Running;
+    job.signal(libc::SIGCONT);
+    if !job.command.ends_with('&') {
+        job.command.push_str(" &");
+    }
+    writeln!(stdout(io)?, "[{}] {}", job.id, job.command)?;
+    Ok(0)
+}
+
+fn cd(shell: &mut Shell, args: &[String], io: &Io) -> io::Result<i32> {
+    let dir = match args.first().map(String::as_str) {
+        None => shell
+            .vars
+            .get("HOME")
+            .ok_or_else(|| error("cd: HOME not set".to_owned()))?
+            .to_owned(),
+        Some("-") => {
+            let dir = shell
+                .vars
+                .get("OLDPWD")
+                .ok_or_else(|| error("cd: OLDPWD not set".to_owned()))?
+                .to_owned();
+            writeln!(stdout(io)?, "{}", dir)?;
+            dir
+        }
+        Some(dir) => dir.to_owned(),
+    };
+    let old = env::current_dir()?;
+    env::set_current_dir(&dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("cd: {}: {}", dir, e)))?;
+    let new = env::current_dir()?;
+    shell.vars.set("OLDPWD", old.to_string_lossy().into_owned());
+    shell.vars.set("PWD", new.to_string_lossy().into_owned());
+    Ok(0)
+}
+
+fn pwd(_: &mut Shell, _: &[String], io: &Io) -> io::Result<i32> {
+    writeln!(stdout(io)?, "{}", env::current_dir()?.display())?;
+    Ok(0)
+}
+
+/// `export name[=value]...`, or list the exported variables.
+fn export(shell: &mut Shell, args: &[String], io: &Io) -> io::Result<i32> {
+    if args.is_empty() {
+        let mut out = stdout(io)?;
+        for (name, value) in shell.vars.exported() {
+            writeln!(out, "export {}={}", name, quote(value))?;
+        }
+        return Ok(0);
+    }
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            return Err(error(format!("export: {}: not a valid identifier", arg)));
+        }
+        if let Some(value) = value {
+            shell.vars.set(name, value.to_owned());
+        }
+        shell.vars.export(name);
+    }
+    Ok(0)
+}
+
+/// `alias name[=value]...`, or list the aliases.
+fn alias(shell: &mut Shell, args: &[String], io: &Io) -> io::Result<i32> {
+    let mut out = stdout(io)?;
+    if args.is_empty() {
+        for (name, value) in &shell.aliases {
+            writeln!(out, "alias {}={}", name, quote(value))?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) => {
+                shell.aliases.insert(name.to_owned(), value.to_owned());
+            }
+            None => match shell.aliases.get(arg) {
+                Some(value) => writeln!(out, "alias {}={}", arg, quote(value))?,
+                None => {
+                    eprintln!("alias: {}: not found", arg);
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(status)
+}
+
+fn exit(shell: &mut Shell, args: &[String], _: &Io) -> io::Result<i32> {
+    let status = match args.first() {
+        None => shell.last_status,
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| error(format!("exit: {}: numeric argument required", arg)))?,
+    };
+    shell.exit = Some(status);
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// The working directory belongs to the whole test process, so tests
+    /// that change it or depend on it take turns.
+    static CWD: Mutex<()> = Mutex::new(());
+
+    /// Holds `CWD` and changes back to where it started when dropped.
+    struct CwdGuard {
+        start: PathBuf,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl CwdGuard {
+        fn lock() -> CwdGuard {
+            let lock = CWD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            CwdGuard {
+                start: env::current_dir().unwrap(),
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for CwdGuard {
+        fn drop(&mut self) {
+            let _ = env::set_current_dir(&self.start);
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(NAMES.iter().all(|name| lookup(name).is_some()));
+    }
+
+    #[test]
+    fn test_cd_and_pwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().canonicalize().unwrap();
+        let cwd = CwdGuard::lock();
+        let start = cwd.start.clone();
+        let mut shell = Shell::new(false);
+
+        shell.execute(&format!("cd {}; pwd > out", dir.display()));
+        assert_eq!(
+            fs::read_to_string(dir.join("out")).unwrap().trim(),
+            dir.to_str().unwrap()
+        );
+        assert_eq!(shell.vars.get("PWD"), dir.to_str());
+        assert_eq!(shell.vars.get("OLDPWD"), start.to_str());
+
+        shell.execute("cd - > /dev/null");
+        assert_eq!(env::current_dir().unwrap(), start);
+        assert_eq!(shell.last_status, 0);
+
+        shell.execute(&format!("cd {}", dir.join("missing").display()));
+        assert_ne!(shell.last_status, 0);
+        assert_eq!(env::current_dir().unwrap(), start);
+    }
+
+    #[test]
+    fn test_variables_and_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let mut shell = Shell::new(false);
+
+        shell.execute("LOCAL=1; SHARED=\"$LOCAL 2\"; export SHARED NEW='it''s'");
+        assert_eq!(shell.vars.get("LOCAL"), Some("1"));
+        assert_eq!(shell.vars.get("SHARED"), Some("1 2"));
+        let exported: Vec<_> = shell.vars.exported().map(|(name, _)| name).collect();
+        assert!(exported.contains(&"SHARED") && exported.contains(&"NEW"));
+        assert!(!exported.contains(&"LOCAL"));
+
+        shell.execute(&format!("export > {}", out.display()));
+        let listing = fs::read_to_string(&out).unwrap();
+        assert!(listing.contains("export SHARED='1 2'\n"));
+        assert!(listing.contains("export NEW='its'\n"));
+
+        shell.execute("export 1BAD=x");
+        assert_ne!(shell.last_status, 0);
+    }
+
+    #[test]
+    fn test_command_substitution_of_builtin() {
+        let _cwd = CwdGuard::lock();
+        let mut shell = Shell::new(false);
+        shell.execute("HERE=$(pwd)");
+        assert_eq!(
+            shell.vars.get("HERE").map(Into::into),
+            Some(env::current_dir().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        let _cwd = CwdGuard::lock();
+        let mut shell = Shell::new(false);
+
+        shell.execute("alias here=pwd 'pwd=pwd' ll='ls -l'");
+        shell.execute(&format!("here > {}", path("here")));
+        assert_eq!(
+            fs::read_to_string(path("here")).unwrap().trim(),
+            env::current_dir().unwrap().to_str().unwrap()
+        );
+        // an alias does not expand within itself
+        shell.execute(&format!("pwd > {}", path("pwd")));
+        assert_eq!(shell.last_status, 0);
+
+        shell.execute(&format!("alias > {}", path("aliases")));
+        assert_eq!(
+            fs::read_to_string(path("aliases")).unwrap(),
+            "alias here='pwd'\nalias ll='ls -l'\nalias pwd='pwd'\n"
+        );
+        shell.execute("alias missing");
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut shell = Shell::new(false);
+        shell.execute("exit 3; AFTER=1");
+        assert_eq!(shell.exit, Some(3));
+        assert_eq!(shell.vars.get("AFTER"), None);
+    }
+}
@@ -0,0 +1,123 @@
+//! Tab completion of commands on `$PATH` and of file names.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper, Result};
+
+use crate::builtin;
+
+#[derive(Default)]
+pub struct ShellHelper {
+    files: FilenameCompleter,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| c.is_whitespace() || "|&;<>()".contains(c))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        if !command_position(&line[..start]) || prefix.contains('/') {
+            return self.files.complete(line, pos, ctx);
+        }
+        let path = env::var("PATH").unwrap_or_default();
+        let candidates = commands(prefix, &path)
+            .into_iter()
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: format!("{} ", name),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+/// Whether a word following `before` would be the name of a command.
+fn command_position(before: &str) -> bool {
+    let before = before.trim_end();
+    before.is_empty() || before.ends_with(['|', '&', ';', '(', '{'])
+}
+
+/// Builtins and executables on `path` whose names start with `prefix`.
+fn commands(prefix: &str, path: &str) -> BTreeSet<String> {
+    let mut names: BTreeSet<String> = builtin::NAMES
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) {
+                continue;
+            }
+            let executable = entry
+                .path()
+                .metadata()
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if executable {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_position() {
+        assert!(command_position(""));
+        assert!(command_position("ls | "));
+        assert!(command_position("a && "));
+        assert!(command_position("a;"));
+        assert!(!command_position("ls "));
+        assert!(!command_position("cat <"));
+    }
+
+    #[test]
+    fn test_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let create = |name: &str, mode: u32| {
+            let path = dir.path().join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        create("exp-tool", 0o755);
+        create("exp-data", 0o644);
+        create("other", 0o755);
+        fs::create_dir(dir.path().join("exp-dir")).unwrap();
+
+        let path = format!("/nonexistent:{}", dir.path().display());
+        assert_eq!(
+            commands("ex", &path).into_iter().collect::<Vec<_>>(),
+            vec!["exit", "exp-tool", "export"]
+        );
+    }
+}
@@ -0,0 +1,991 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::rc::Rc;
+
+use crate::builtin;
+use crate::expand::{expand, expand_string, Word};
+use crate::job::{Job, Process};
+use crate::parser::cmdline;
+use crate::shell::Shell;
+
+/// Where the standard streams of a command go; `None` inherits the shell's.
+#[derive(Default)]
+pub struct Io {
+    pub stdin: Option<OwnedFd>,
+    pub stdout: Option<OwnedFd>,
+    pub stderr: Option<OwnedFd>,
+}
+
+impl Io {
+    pub fn try_clone(&self) -> io::Result<Io> {
+        let clone = |fd: &Option<OwnedFd>| fd.as_ref().map(|fd| fd.try_clone()).transpose();
+        Ok(Io {
+            stdin: clone(&self.stdin)?,
+            stdout: clone(&self.stdout)?,
+            stderr: clone(&self.stderr)?,
+        })
+    }
+
+    /// A new handle on whatever `fd` currently refers to.
+    fn dup(&self, fd: i32) -> io::Result<OwnedFd> {
+        let (own, inherited) = match fd {
+            0 => (&self.stdin, io::stdin().as_fd().try_clone_to_owned()),
+            1 => (&self.stdout, io::stdout().as_fd().try_clone_to_owned()),
+            _ => (&self.stderr, io::stderr().as_fd().try_clone_to_owned()),
+        };
+        match own {
+            Some(fd) => fd.try_clone(),
+            None => inherited,
+        }
+    }
+
+    fn set(&mut self, fd: i32, to: OwnedFd) {
+        match fd {
+            0 => self.stdin = Some(to),
+            1 => self.stdout = Some(to),
+            _ => self.stderr = Some(to),
+        }
+    }
+
+    fn redirect(&mut self, redirect: &Redirect, shell: &mut Shell) -> io::Result<()> {
+        let target = |path: &Word, shell: &mut Shell| match expand(path, shell)?.as_slice() {
+            [path] => Ok(path.clone()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: ambiguous redirect", path),
+            )),
+        };
+        let mut options = OpenOptions::new();
+        let (fd, path) = match redirect {
+            Redirect::Read { fd, path } => (fd, options.read(true).open(target(path, shell)?)),
+            Redirect::Write { fd, path } => (
+                fd,
+                options
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(target(path, shell)?),
+            ),
+            Redirect::Append { fd, path } => (
+                fd,
+                options.append(true).create(true).open(target(path, shell)?),
+            ),
+            Redirect::Duplicate { fd, to } => {
+                let to = self.dup(*to)?;
+                self.set(*fd, to);
+                return Ok(());
+            }
+        };
+        let file = path.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", redirect, e)))?;
+        self.set(*fd, file.into());
+        Ok(())
+    }
+}
+
+pub trait Executable: fmt::Display + fmt::Debug {
+    /// Start the processes connected to `io` without waiting for them. With
+    /// job control they all join process group `pgid`, which the first one
+    /// leads when it is `None`.
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>>;
+
+    /// Run in the foreground to completion and return the exit status.
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        let mut pgid = None;
+        let processes = self.spawn(shell, io, &mut pgid)?;
+        Ok(shell.foreground(Job::new(pgid, processes, self.to_string())))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Redirect {
+    Read { fd: i32, path: Word },
+    Write { fd: i32, path: Word },
+    Append { fd: i32, path: Word },
+    Duplicate { fd: i32, to: i32 },
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = |fd: i32, default: i32| {
+            if fd == default {
+                String::new()
+            } else {
+                fd.to_string()
+            }
+        };
+        match self {
+            Redirect::Read { fd, path } => write!(f, "{}<{}", prefix(*fd, 0), path),
+            Redirect::Write { fd, path } => write!(f, "{}>{}", prefix(*fd, 1), path),
+            Redirect::Append { fd, path } => write!(f, "{}>>{}", prefix(*fd, 1), path),
+            Redirect::Duplicate { fd, to } => write!(f, "{}>&{}", prefix(*fd, 1), to),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Command {
+    pub program: Word,
+    pub args: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl Command {
+    /// What the command stands for when its name is an alias.
+    fn alias(&self, shell: &Shell) -> Option<io::Result<(String, Box<dyn Executable>)>> {
+        let name = self.program.literal()?;
+        let mut line = shell.aliases.get(name)?.clone();
+        for arg in &self.args {
+            line.push_str(&format!(" {}", arg));
+        }
+        for redirect in &self.redirects {
+            line.push_str(&format!(" {}", redirect));
+        }
+        Some(match cmdline(line.as_bytes()) {
+            Ok((_, exe)) => Ok((name.to_owned(), exe)),
+            Err(why) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("alias {}: {}", name, why),
+            )),
+        })
+    }
+}
+
+/// Run `f` with alias `name` set aside, so that `alias ls='ls -F'` does not
+/// recurse.
+fn without_alias<T>(shell: &mut Shell, name: &str, f: impl FnOnce(&mut Shell) -> T) -> T {
+    let value = shell.aliases.remove(name);
+    let result = f(shell);
+    if let Some(value) = value {
+        shell.aliases.insert(name.to_owned(), value);
+    }
+    result
+}
+
+impl Command {
+    /// Start the command like `spawn`, except that a shell function runs
+    /// to completion in this shell when `inline` is set rather than in a
+    /// subshell of its own.
+    fn start(
+        &self,
+        shell: &mut Shell,
+        mut io: Io,
+        pgid: &mut Option<i32>,
+        inline: bool,
+    ) -> io::Result<Vec<Process>> {
+        if let Some(alias) = self.alias(shell) {
+            let (name, exe) = alias?;
+            return without_alias(shell, &name, |shell| exe.spawn(shell, io, pgid));
+        }
+        for redirect in &self.redirects {
+            io.redirect(redirect, shell)?;
+        }
+        let mut argv = expand(&self.program, shell)?;
+        for arg in &self.args {
+            argv.extend(expand(arg, shell)?);
+        }
+        if argv.is_empty() {
+            // the command expanded to nothing
+            return Ok(vec![Process::Exited(0)]);
+        }
+        let program = argv.remove(0);
+        if let Some(body) = shell.functions.get(&program).cloned() {
+            let call = Call {
+                name: program,
+                body,
+                args: argv,
+            };
+            if !inline {
+                return call.spawn(shell, io, pgid);
+            }
+            return Ok(vec![Process::Exited(call.run(shell, io)?)]);
+        }
+        if let Some(builtin) = builtin::lookup(&program) {
+            let status = builtin(shell, &argv, &io).unwrap_or_else(|why| {
+                eprintln!("{}", why);
+                1
+            });
+            return Ok(vec![Process::Exited(status)]);
+        }
+
+        let mut command = std::process::Command::new(&program);
+        command.args(&argv);
+        command.env_clear().envs(shell.vars.exported());
+        if let Some(fd) = io.stdin {
+            command.stdin(Stdio::from(fd));
+        }
+        if let Some(fd) = io.stdout {
+            command.stdout(Stdio::from(fd));
+        }
+        if let Some(fd) = io.stderr {
+            command.stderr(Stdio::from(fd));
+        }
+        if shell.interactive {
+            command.process_group(pgid.unwrap_or(0));
+            // the shell ignores job control signals, which would otherwise
+            // be inherited across exec
+            unsafe {
+                command.pre_exec(|| {
+                    for signal in &[
+                        libc::SIGINT,
+                        libc::SIGQUIT,
+                        libc::SIGTSTP,
+                        libc::SIGTTIN,
+                        libc::SIGTTOU,
+                    ] {
+                        libc::signal(*signal, libc::SIG_DFL);
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let child = command
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", program, e)))?;
+        let pid = child.id() as i32;
+        if shell.interactive && pgid.is_none() {
+            *pgid = Some(pid);
+        }
+        Ok(vec![Process::Running(pid)])
+    }
+}
+
+impl Executable for Command {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        self.start(shell, io, pgid, false)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        if let Some(alias) = self.alias(shell) {
+            let (name, exe) = alias?;
+            return without_alias(shell, &name, |shell| exe.run(shell, io));
+        }
+        let mut pgid = None;
+        let processes = self.start(shell, io, &mut pgid, true)?;
+        Ok(shell.foreground(Job::new(pgid, processes, self.to_string())))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        for redirect in &self.redirects {
+            write!(f, " {}", redirect)?;
+        }
+        Ok(())
+    }
+}
+
+/// A call to a shell function, with its arguments already expanded.
+#[derive(Debug)]
+struct Call {
+    name: String,
+    body: Rc<dyn Executable>,
+    args: Vec<String>,
+}
+
+impl Executable for Call {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        let saved = std::mem::replace(&mut shell.positional, self.args.clone());
+        let status = self.body.run(shell, io);
+        shell.positional = saved;
+        status
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    pub left: Command,
+    pub right: Box<dyn Executable>,
+}
+
+impl Executable for Pipeline {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        let (reader, writer) = io::pipe()?;
+        let left_io = Io {
+            stdin: io.stdin,
+            stdout: Some(writer.into()),
+            stderr: io.stderr.as_ref().map(|fd| fd.try_clone()).transpose()?,
+        };
+        // `left_io` is dropped by the time the right side is spawned, so the
+        // shell no longer holds the write end and the reader sees EOF
+        let mut processes = self.left.spawn(shell, left_io, pgid)?;
+        let right_io = Io {
+            stdin: Some(reader.into()),
+            stdout: io.stdout,
+            stderr: io.stderr,
+        };
+        processes.extend(self.right.spawn(shell, right_io, pgid)?);
+        Ok(processes)
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} | {}", self.left, self.right)
+    }
+}
+
+/// Start `exe` in a forked copy of the shell, as compound commands, function
+/// calls and assignments run when they are in the background or in a
+/// pipeline. What it does to variables, functions and the like stays in the
+/// copy.
+fn subshell(
+    exe: &dyn Executable,
+    shell: &mut Shell,
+    io: Io,
+    pgid: &mut Option<i32>,
+) -> io::Result<Vec<Process>> {
+    // anything still buffered would otherwise be written twice
+    io::Write::flush(&mut io::stdout())?;
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        let status = run_subshell(exe, shell, io, *pgid);
+        let _ = io::Write::flush(&mut io::stdout());
+        unsafe { libc::_exit(status) }
+    }
+    if shell.interactive {
+        // set the group from both sides so neither can act before it is
+        let group = pgid.unwrap_or(pid);
+        unsafe {
+            libc::setpgid(pid, group);
+        }
+        *pgid = Some(group);
+    }
+    Ok(vec![Process::Running(pid)])
+}
+
+/// The child side of `subshell`, returning the exit status.
+fn run_subshell(exe: &dyn Executable, shell: &mut Shell, io: Io, pgid: Option<i32>) -> i32 {
+    if shell.interactive {
+        unsafe {
+            libc::setpgid(0, pgid.unwrap_or(0));
+            for signal in &[
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTSTP,
+                libc::SIGTTIN,
+                libc::SIGTTOU,
+            ] {
+                libc::signal(*signal, libc::SIG_DFL);
+            }
+        }
+        // only the parent shell does job control
+        shell.interactive = false;
+    }
+    for (fd, own) in [(0, &io.stdin), (1, &io.stdout), (2, &io.stderr)] {
+        if let Some(own) = own {
+            if unsafe { libc::dup2(own.as_raw_fd(), fd) } < 0 {
+                eprintln!("{}", io::Error::last_os_error());
+                return 1;
+            }
+        }
+    }
+    drop(io);
+    close_other_fds();
+    match exe.run(shell, Io::default()) {
+        Ok(status) => shell.exit.unwrap_or(status),
+        Err(why) => {
+            eprintln!("{}", why);
+            1
+        }
+    }
+}
+
+/// Close every descriptor but the standard ones. Without an exec nothing
+/// else closes them, and a subshell holding the read end of its own pipeline
+/// would keep the writer from ever seeing EPIPE.
+fn close_other_fds() {
+    let fds: Vec<i32> = match std::fs::read_dir("/dev/fd") {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => return,
+    };
+    for fd in fds.into_iter().filter(|&fd| fd > 2) {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
+
+/// `left && right`
+#[derive(Debug)]
+pub struct And {
+    pub left: Box<dyn Executable>,
+    pub right: Box<dyn Executable>,
+}
+
+impl Executable for And {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        match self.left.run(shell, io.try_clone()?)? {
+            0 => self.right.run(shell, io),
+            status => Ok(status),
+        }
+    }
+}
+
+impl fmt::Display for And {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} && {}", self.left, self.right)
+    }
+}
+
+/// `left || right`
+#[derive(Debug)]
+pub struct Or {
+    pub left: Box<dyn Executable>,
+    pub right: Box<dyn Executable>,
+}
+
+impl Executable for Or {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        match self.left.run(shell, io.try_clone()?)? {
+            0 => Ok(0),
+            _ if shell.exit.is_some() => Ok(shell.last_status),
+            _ => self.right.run(shell, io),
+        }
+    }
+}
+
+impl fmt::Display for Or {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} || {}", self.left, self.right)
+    }
+}
+
+/// Commands separated by `;` or `&`
+#[derive(Debug)]
+pub struct Sequence {
+    pub items: Vec<Box<dyn Executable>>,
+}
+
+impl Executable for Sequence {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        let mut status = 0;
+        for item in &self.items {
+            status = match item.run(shell, io.try_clone()?) {
+                Ok(status) => status,
+                Err(why) => {
+                    // like a command that failed to start, keep going
+                    eprintln!("{}", why);
+                    127
+                }
+            };
+            shell.last_status = status;
+            if shell.exit.is_some() {
+                break;
+            }
+        }
+        Ok(status)
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            let text = item.to_string();
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", text)?;
+            if i + 1 < self.items.len() && !text.ends_with('&') {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `name=value`
+#[derive(Debug)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+impl Executable for Assignment {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, _: Io) -> io::Result<i32> {
+        let value = expand_string(&self.value, shell)?;
+        shell.vars.set(&self.name, value);
+        Ok(0)
+    }
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+/// `job &`
+#[derive(Debug)]
+pub struct Background {
+    pub job: Box<dyn Executable>,
+}
+
+impl Executable for Background {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        let mut pgid = None;
+        let processes = self.job.spawn(shell, io, &mut pgid)?;
+        let job = Job::new(pgid, processes, self.to_string());
+        let leader = job.leader();
+        let id = shell.jobs.add(job);
+        eprintln!("[{}] {}", id, leader);
+        Ok(0)
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} &", self.job)
+    }
+}
+
+/// `exe` as written before a keyword, which needs a `;` unless it ends in `&`.
+fn terminated(exe: &dyn Executable) -> String {
+    let text = exe.to_string();
+    if text.ends_with('&') {
+        text
+    } else {
+        format!("{};", text)
+    }
+}
+
+/// `if list; then list; [elif list; then list;]... [else list;] fi`
+#[derive(Debug)]
+pub struct If {
+    pub branches: Vec<(Box<dyn Executable>, Box<dyn Executable>)>,
+    pub otherwise: Option<Box<dyn Executable>>,
+}
+
+impl Executable for If {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        for (condition, body) in &self.branches {
+            if condition.run(shell, io.try_clone()?)? == 0 {
+                return body.run(shell, io);
+            }
+        }
+        match &self.otherwise {
+            Some(body) => body.run(shell, io),
+            None => Ok(0),
+        }
+    }
+}
+
+impl fmt::Display for If {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (condition, body)) in self.branches.iter().enumerate() {
+            let keyword = if i == 0 { "if" } else { "elif" };
+            write!(
+                f,
+                "{} {} then {} ",
+                keyword,
+                terminated(condition.as_ref()),
+                terminated(body.as_ref())
+            )?;
+        }
+        if let Some(body) = &self.otherwise {
+            write!(f, "else {} ", terminated(body.as_ref()))?;
+        }
+        write!(f, "fi")
+    }
+}
+
+/// `while list; do list; done`
+#[derive(Debug)]
+pub struct While {
+    pub condition: Box<dyn Executable>,
+    pub body: Box<dyn Executable>,
+}
+
+impl Executable for While {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        let mut status = 0;
+        while shell.exit.is_none() && self.condition.run(shell, io.try_clone()?)? == 0 {
+            status = self.body.run(shell, io.try_clone()?)?;
+        }
+        Ok(status)
+    }
+}
+
+impl fmt::Display for While {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "while {} do {} done",
+            terminated(self.condition.as_ref()),
+            terminated(self.body.as_ref())
+        )
+    }
+}
+
+/// `for name [in words]; do list; done`, which goes over the positional
+/// parameters without `in`.
+#[derive(Debug)]
+pub struct For {
+    pub name: String,
+    pub words: Option<Vec<Word>>,
+    pub body: Box<dyn Executable>,
+}
+
+impl Executable for For {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        let values = match &self.words {
+            Some(words) => {
+                let mut values = Vec::new();
+                for word in words {
+                    values.extend(expand(word, shell)?);
+                }
+                values
+            }
+            None => shell.positional.clone(),
+        };
+        let mut status = 0;
+        for value in values {
+            if shell.exit.is_some() {
+                break;
+            }
+            shell.vars.set(&self.name, value);
+            status = self.body.run(shell, io.try_clone()?)?;
+        }
+        Ok(status)
+    }
+}
+
+impl fmt::Display for For {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "for {}", self.name)?;
+        if let Some(words) = &self.words {
+            write!(f, " in")?;
+            for word in words {
+                write!(f, " {}", word)?;
+            }
+        }
+        write!(f, "; do {} done", terminated(self.body.as_ref()))
+    }
+}
+
+/// `{ list; }`
+#[derive(Debug)]
+pub struct Group {
+    pub list: Box<dyn Executable>,
+}
+
+impl Executable for Group {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, io: Io) -> io::Result<i32> {
+        self.list.run(shell, io)
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{ {} }}", terminated(self.list.as_ref()))
+    }
+}
+
+/// A compound command whose output goes somewhere else, as in
+/// `while read line; do ...; done < file`.
+#[derive(Debug)]
+pub struct Redirected {
+    pub exe: Box<dyn Executable>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl Executable for Redirected {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, mut io: Io) -> io::Result<i32> {
+        for redirect in &self.redirects {
+            io.redirect(redirect, shell)?;
+        }
+        self.exe.run(shell, io)
+    }
+}
+
+impl fmt::Display for Redirected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.exe)?;
+        for redirect in &self.redirects {
+            write!(f, " {}", redirect)?;
+        }
+        Ok(())
+    }
+}
+
+/// `name() body`, which defines a function to be called like a command.
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub body: Rc<dyn Executable>,
+}
+
+impl Executable for Function {
+    fn spawn(&self, shell: &mut Shell, io: Io, pgid: &mut Option<i32>) -> io::Result<Vec<Process>> {
+        subshell(self, shell, io, pgid)
+    }
+
+    fn run(&self, shell: &mut Shell, _: Io) -> io::Result<i32> {
+        shell.functions.insert(self.name.clone(), self.body.clone());
+        Ok(0)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}() {}", self.name, self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::cmdline;
+    use std::fs;
+
+    fn run(shell: &mut Shell, line: &str) -> i32 {
+        let (_, exe) = cmdline(line.as_bytes()).unwrap();
+        exe.run(shell, Io::default()).unwrap()
+    }
+
+    #[test]
+    fn test_redirections() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        let mut shell = Shell::new(false);
+
+        run(&mut shell, &format!("echo one > {}", path("out")));
+        run(&mut shell, &format!("echo two >> {}", path("out")));
+        assert_eq!(fs::read_to_string(path("out")).unwrap(), "one\ntwo\n");
+
+        run(
+            &mut shell,
+            &format!("sort -r < {} > {}", path("out"), path("sorted")),
+        );
+        assert_eq!(fs::read_to_string(path("sorted")).unwrap(), "two\none\n");
+
+        run(
+            &mut shell,
+            &format!("ls {} 2>&1 > {}", path("missing"), path("stdout")),
+        );
+        assert_eq!(fs::read_to_string(path("stdout")).unwrap(), "");
+        run(
+            &mut shell,
+            &format!("ls {} > {} 2>&1", path("missing"), path("both")),
+        );
+        assert!(fs::read_to_string(path("both"))
+            .unwrap()
+            .contains("missing"));
+
+        run(
+            &mut shell,
+            &format!("ls {} 2>&1 | wc -l > {}", path("missing"), path("count")),
+        );
+        assert_eq!(fs::read_to_string(path("count")).unwrap().trim(), "1");
+    }
+
+    #[test]
+    fn test_exit_status_control_flow() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let out = out.to_str().unwrap();
+        let mut shell = Shell::new(false);
+
+        assert_eq!(run(&mut shell, "true && false"), 1);
+        assert_eq!(run(&mut shell, "false || true"), 0);
+        assert_eq!(run(&mut shell, "false | true"), 0);
+        assert_eq!(run(&mut shell, "true | false"), 1);
+        run(
+            &mut shell,
+            &format!(
+                "false && echo no >> {0} || echo yes >> {0}; echo done >> {0}",
+                out
+            ),
+        );
+        assert_eq!(fs::read_to_string(out).unwrap(), "yes\ndone\n");
+    }
+
+    #[test]
+    fn test_background_jobs() {
+        let mut shell = Shell::new(false);
+        assert_eq!(run(&mut shell, "sleep 0.2 & true"), 0);
+        assert_eq!(shell.jobs.iter().count(), 1);
+        assert!(shell.jobs.reap().is_empty());
+
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let done = shell.jobs.reap();
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].command, "sleep 0.2 &");
+        assert_eq!(shell.jobs.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_compound_commands_in_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        let mut shell = Shell::new(false);
+
+        assert_eq!(
+            run(&mut shell, &format!("true && echo hi > {} &", path("and"))),
+            0
+        );
+        assert_eq!(
+            run(
+                &mut shell,
+                &format!("for w in a b; do x=$w; echo $x >> {}; done &", path("for"))
+            ),
+            0
+        );
+        assert_eq!(shell.jobs.iter().count(), 2);
+        let start = std::time::Instant::now();
+        let mut done = Vec::new();
+        while done.len() < 2 {
+            assert!(
+                start.elapsed().as_secs() < 5,
+                "background jobs never finished"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            done.extend(shell.jobs.reap());
+        }
+        assert!(done.iter().all(|job| job.status == 0));
+        assert_eq!(fs::read_to_string(path("and")).unwrap(), "hi\n");
+        assert_eq!(fs::read_to_string(path("for")).unwrap(), "a\nb\n");
+
+        // what a background job sets stays in its subshell
+        assert_eq!(shell.vars.get("x"), None);
+    }
+
+    #[test]
+    fn test_if_while_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let out = out.to_str().unwrap();
+        let mut shell = Shell::new(false);
+
+        shell.execute(&format!(
+            "if false; then echo no; elif true; then echo elif; else echo else; fi > {0}
+             if false; then echo no; fi; echo $? >> {0}
+             for word in a 'b c'; do echo $word >> {0}; done
+             n=
+             while test \"$n\" != xxx; do n=x$n; done; echo $n >> {0}",
+            out
+        ));
+        assert_eq!(fs::read_to_string(out).unwrap(), "elif\n0\na\nb c\nxxx\n");
+    }
+
+    #[test]
+    fn test_functions() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let out = out.to_str().unwrap();
+        let mut shell = Shell::new(false);
+        shell.positional = vec!["outer".to_owned()];
+
+        shell.execute(&format!(
+            "greet() {{
+                 echo $# $1-$2 >> {0}
+                 false
+             }}
+             greet hello 'big world'; echo $? $1 >> {0}
+             each() {{ for arg; do echo [$arg] >> {0}; done; }}
+             each x y",
+            out
+        ));
+        assert_eq!(
+            fs::read_to_string(out).unwrap(),
+            "2 hello-big world\n1 outer\n[x]\n[y]\n"
+        );
+    }
+
+    #[test]
+    fn test_functions_in_background_and_pipelines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        let mut shell = Shell::new(false);
+
+        // the shell goes on while the function runs in its own process
+        let start = std::time::Instant::now();
+        shell.execute(&format!(
+            "f() {{ sleep 0.5; echo $1 > {}; }}; f bg &",
+            path("bg")
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_millis(400));
+        let jobs: Vec<_> = shell.jobs.iter().collect();
+        assert_eq!(jobs.len(), 1);
+        assert_ne!(jobs[0].leader(), 0);
+        while shell.jobs.reap().is_empty() {
+            assert!(start.elapsed().as_secs() < 5, "function never finished");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(fs::read_to_string(path("bg")).unwrap(), "bg\n");
+
+        // a producer that never stops on its own ends when the reader does
+        shell.execute(&format!(
+            "produce() {{ yes $1; }}; produce y | head -1 > {}",
+            path("pipe")
+        ));
+        assert_eq!(fs::read_to_string(path("pipe")).unwrap(), "y\n");
+        assert!(start.elapsed().as_secs() < 5);
+    }
+}
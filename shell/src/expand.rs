@@ -0,0 +1,515 @@
+//! Words as typed on the command line and their expansion into arguments.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    /// Literal text; quoted text is neither split nor globbed.
+    Text { text: String, quoted: bool },
+    /// `$name` or `${name}`
+    Parameter { name: String, quoted: bool },
+    /// `$(command)`
+    Substitution { source: String, quoted: bool },
+}
+
+/// A word made of the pieces it was typed as, e.g. `~/"$dir"/*.rs`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Word(pub Vec<Segment>);
+
+impl Word {
+    /// The word as typed when it is plain unquoted text.
+    pub fn literal(&self) -> Option<&str> {
+        match self.0.as_slice() {
+            [Segment::Text {
+                text,
+                quoted: false,
+            }] => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Word {
+    fn from(text: &str) -> Word {
+        Word(vec![Segment::Text {
+            text: text.to_owned(),
+            quoted: false,
+        }])
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Text {
+                    text,
+                    quoted: false,
+                } => write!(f, "{}", text)?,
+                Segment::Text { text, quoted: true } if !text.contains('\'') => {
+                    write!(f, "'{}'", text)?
+                }
+                Segment::Text { text, quoted: true } => {
+                    write!(f, "\"")?;
+                    for c in text.chars() {
+                        if "\\\"$`".contains(c) {
+                            write!(f, "\\")?;
+                        }
+                        write!(f, "{}", c)?;
+                    }
+                    write!(f, "\"")?;
+                }
+                Segment::Parameter { name, quoted } => {
+                    let braces = match self.0.get(i + 1) {
+                        Some(Segment::Text {
+                            text,
+                            quoted: false,
+                        }) => text.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+                        _ => false,
+                    };
+                    match (quoted, braces) {
+                        (true, _) => write!(f, "\"${}\"", name)?,
+                        (false, true) => write!(f, "${{{}}}", name)?,
+                        (false, false) => write!(f, "${}", name)?,
+                    }
+                }
+                Segment::Substitution {
+                    source,
+                    quoted: true,
+                } => write!(f, "\"$({})\"", source)?,
+                Segment::Substitution {
+                    source,
+                    quoted: false,
+                } => write!(f, "$({})", source)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What expansion needs to know about the shell.
+pub trait Context {
+    fn parameter(&self, name: &str) -> Option<String>;
+
+    /// Run `source` and return what it wrote to standard output.
+    fn substitute(&mut self, source: &str) -> io::Result<String>;
+}
+
+/// Expand `word` into arguments: tilde, parameters and command substitution
+/// first, then field splitting and globbing of whatever was not quoted.
+pub fn expand(word: &Word, context: &mut dyn Context) -> io::Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = Field::default();
+    for (i, segment) in word.0.iter().enumerate() {
+        match segment {
+            Segment::Text { text, quoted } => {
+                let rest = match tilde(text, i == 0 && !quoted, context) {
+                    Some((home, rest)) => {
+                        field.push(&home, true);
+                        rest
+                    }
+                    None => text,
+                };
+                field.push(rest, *quoted);
+            }
+            Segment::Parameter { name, quoted } => {
+                let value = context.parameter(name).unwrap_or_default();
+                field.expansion(&value, *quoted, &mut fields);
+            }
+            Segment::Substitution { source, quoted } => {
+                let value = context.substitute(source)?;
+                field.expansion(value.trim_end_matches('\n'), *quoted, &mut fields);
+            }
+        }
+    }
+    field.end(&mut fields);
+
+    let mut args = Vec::new();
+    for field in fields {
+        let matches = if field.glob {
+            glob(&field.pattern)
+        } else {
+            Vec::new()
+        };
+        if matches.is_empty() {
+            args.push(field.text);
+        } else {
+            args.extend(matches);
+        }
+    }
+    Ok(args)
+}
+
+/// Expand `word` into a single string, without splitting or globbing, as
+/// for the value of an assignment.
+pub fn expand_string(word: &Word, context: &mut dyn Context) -> io::Result<String> {
+    let mut value = String::new();
+    for (i, segment) in word.0.iter().enumerate() {
+        match segment {
+            Segment::Text { text, quoted } => match tilde(text, i == 0 && !quoted, context) {
+                Some((home, rest)) => {
+                    value.push_str(&home);
+                    value.push_str(rest);
+                }
+                None => value.push_str(text),
+            },
+            Segment::Parameter { name, .. } => {
+                value.push_str(&context.parameter(name).unwrap_or_default())
+            }
+            Segment::Substitution { source, .. } => {
+                value.push_str(context.substitute(source)?.trim_end_matches('\n'))
+            }
+        }
+    }
+    Ok(value)
+}
+
+/// `~` or `~/...` at the start of a word stands for `$HOME`.
+fn tilde<'a>(text: &'a str, leading: bool, context: &dyn Context) -> Option<(String, &'a str)> {
+    if !leading || !(text == "~" || text.starts_with("~/")) {
+        return None;
+    }
+    Some((context.parameter("HOME")?, &text[1..]))
+}
+
+/// An argument being put together, along with the glob pattern it makes.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    glob: bool,
+    quoted: bool,
+}
+
+impl Field {
+    fn push(&mut self, text: &str, quoted: bool) {
+        self.text.push_str(text);
+        if quoted {
+            self.quoted = true;
+            for c in text.chars() {
+                if "*?[]\\".contains(c) {
+                    self.pattern.push('\\');
+                }
+                self.pattern.push(c);
+            }
+        } else {
+            self.glob |= text.contains(['*', '?', '[']);
+            self.pattern.push_str(text);
+        }
+    }
+
+    /// Add the value of an expansion, which splits into several fields at
+    /// whitespace unless it is quoted.
+    fn expansion(&mut self, value: &str, quoted: bool, fields: &mut Vec<Field>) {
+        if quoted {
+            self.push(value, true);
+            return;
+        }
+        if value.starts_with(char::is_whitespace) {
+            self.end(fields);
+        }
+        for (i, piece) in value.split_whitespace().enumerate() {
+            if i > 0 {
+                self.end(fields);
+            }
+            self.push(piece, false);
+        }
+        if value.ends_with(char::is_whitespace) {
+            self.end(fields);
+        }
+    }
+
+    /// Finish this field; an unquoted one that expanded to nothing vanishes.
+    fn end(&mut self, fields: &mut Vec<Field>) {
+        let field = mem::take(self);
+        if field.quoted || !field.text.is_empty() {
+            fields.push(field);
+        }
+    }
+}
+
+fn has_magic(pattern: &str) -> bool {
+    let mut escaped = false;
+    for c in pattern.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_owned()
+    } else if path.ends_with('/') {
+        format!("{}{}", path, name)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+/// Paths matching `pattern`, sorted. A name starting with `.` only matches
+/// a pattern that starts with `.` too.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_owned()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    for component in rest.split('/') {
+        let mut next = Vec::new();
+        for path in &paths {
+            if !has_magic(component) {
+                let candidate = join(path, &unescape(component));
+                if Path::new(&candidate).symlink_metadata().is_ok() {
+                    next.push(candidate);
+                }
+                continue;
+            }
+            let dir = if path.is_empty() { "." } else { path };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let pattern: Vec<char> = component.chars().collect();
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                let name_chars: Vec<char> = name.chars().collect();
+                if matches(&pattern, &name_chars) {
+                    next.push(join(path, &name));
+                }
+            }
+        }
+        paths = next;
+    }
+    paths.sort();
+    paths
+}
+
+/// Whether `name` matches a pattern of `*`, `?`, `[...]` and `\` escapes.
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| matches(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        Some('[') => match class(&pattern[1..], name.first().copied()) {
+            Some((true, rest)) => matches(rest, &name[1..]),
+            Some((false, _)) => false,
+            // no closing bracket, so a plain `[`
+            None => name.first() == Some(&'[') && matches(&pattern[1..], &name[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            name.first() == Some(&pattern[1]) && matches(&pattern[2..], &name[1..])
+        }
+        Some(c) => name.first() == Some(c) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Match `c` against the bracket expression that `pattern` starts with and
+/// return the rest of the pattern after it.
+fn class(pattern: &[char], c: Option<char>) -> Option<(bool, &[char])> {
+    let (negate, mut i) = match pattern.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+    let start = i;
+    let mut found = false;
+    loop {
+        let first = *pattern.get(i)?;
+        if first == ']' && i > start {
+            return Some((c.is_some() && found != negate, &pattern[i + 1..]));
+        }
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&last)) if last != ']' => {
+                found |= c.is_some_and(|c| first <= c && c <= last);
+                i += 3;
+            }
+            _ => {
+                found |= c == Some(first);
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::words;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct FakeContext {
+        vars: HashMap<String, String>,
+        commands: Vec<String>,
+    }
+
+    impl Context for FakeContext {
+        fn parameter(&self, name: &str) -> Option<String> {
+            self.vars.get(name).cloned()
+        }
+
+        fn substitute(&mut self, source: &str) -> io::Result<String> {
+            self.commands.push(source.to_owned());
+            Ok(format!("<{}>\n\n", source))
+        }
+    }
+
+    fn context() -> FakeContext {
+        let mut context = FakeContext::default();
+        for (name, value) in [
+            ("HOME", "/home/me"),
+            ("name", "world"),
+            ("list", " a  b "),
+            ("star", "*"),
+            ("empty", ""),
+        ] {
+            context.vars.insert(name.to_owned(), value.to_owned());
+        }
+        context
+    }
+
+    fn expand_line(context: &mut FakeContext, line: &str) -> Vec<String> {
+        let (_, words) = words(line.as_bytes()).unwrap();
+        let mut args = Vec::new();
+        for word in &words {
+            args.extend(expand(word, context).unwrap());
+        }
+        args
+    }
+
+    #[test]
+    fn test_quotes_and_escapes() {
+        let mut context = context();
+        assert_eq!(
+            expand_line(&mut context, r#"echo "a \"b\" \$c \\ \d" 'it''s' \'"#),
+            vec!["echo", r#"a "b" $c \ \d"#, "its", "'"]
+        );
+        assert_eq!(
+            expand_line(&mut context, r#"echo "" ''"#),
+            vec!["echo", "", ""]
+        );
+    }
+
+    #[test]
+    fn test_parameters() {
+        let mut context = context();
+        assert_eq!(
+            expand_line(&mut context, r#"echo $name ${name}s "$name!" $missing $"#),
+            vec!["echo", "world", "worlds", "world!", "$"]
+        );
+        assert_eq!(
+            expand_line(&mut context, r#"echo x${list}y "$list" $empty "$empty""#),
+            vec!["echo", "x", "a", "b", "y", " a  b ", ""]
+        );
+    }
+
+    #[test]
+    fn test_tilde() {
+        let mut context = context();
+        assert_eq!(
+            expand_line(&mut context, "echo ~ ~/src a~ '~' ~me"),
+            vec!["echo", "/home/me", "/home/me/src", "a~", "~", "~me"]
+        );
+    }
+
+    #[test]
+    fn test_command_substitution() {
+        let mut context = context();
+        assert_eq!(
+            expand_line(&mut context, r#"echo $(ls -l | wc -l) "$(echo (a) ')')""#),
+            vec!["echo", "<ls", "-l", "|", "wc", "-l>", "<echo (a) ')'>"]
+        );
+        assert_eq!(context.commands, vec!["ls -l | wc -l", "echo (a) ')'"]);
+    }
+
+    #[test]
+    fn test_assignment_value() {
+        let mut context = context();
+        let (_, words) = words(b"~/$list$(pwd)*").unwrap();
+        assert_eq!(
+            expand_string(&words[0], &mut context).unwrap(),
+            "/home/me/ a  b <pwd>*"
+        );
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for name in ["a.rs", "b.rs", "c.txt", ".hidden.rs", "[x].rs"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/d.rs"), "").unwrap();
+
+        let mut context = context();
+        let line = format!("ls {0}/*.rs {0}/?.txt {0}/*/*.rs {0}/*.none", root);
+        assert_eq!(
+            expand_line(&mut context, &line),
+            vec![
+                "ls".to_owned(),
+                format!("{}/[x].rs", root),
+                format!("{}/a.rs", root),
+                format!("{}/b.rs", root),
+                format!("{}/c.txt", root),
+                format!("{}/sub/d.rs", root),
+                format!("{}/*.none", root),
+            ]
+        );
+        assert_eq!(
+            expand_line(&mut context, &format!("ls {0}/[ab].rs '{0}/*.rs'", root)),
+            vec![
+                "ls".to_owned(),
+                format!("{}/a.rs", root),
+                format!("{}/b.rs", root),
+                format!("{}/*.rs", root),
+            ]
+        );
+        assert_eq!(
+            expand_line(&mut context, &format!("ls {}/$star.txt", root)),
+            vec!["ls".to_owned(), format!("{}/c.txt", root)]
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let m = |pattern: &str, name: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let name: Vec<char> = name.chars().collect();
+            matches(&pattern, &name)
+        };
+        assert!(m("*", "anything"));
+        assert!(m("a*b*c", "aXbYc"));
+        assert!(!m("a*b", "aXbY"));
+        assert!(m("?.rs", "a.rs"));
+        assert!(!m("?.rs", "ab.rs"));
+        assert!(m("[a-c]x", "bx"));
+        assert!(!m("[!a-c]x", "bx"));
+        assert!(m("[]]", "]"));
+        assert!(m("[ab", "[ab"));
+        assert!(m("\\*", "*"));
+        assert!(!m("\\*", "a"));
+    }
+}
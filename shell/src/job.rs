@@ -0,0 +1,190 @@
+use std::fmt;
+
+/// A process started for a command; builtins finish before they return.
+#[derive(Debug)]
+pub enum Process {
+    Running(i32),
+    Exited(i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+    Done,
+}
+
+/// The processes started for one pipeline.
+#[derive(Debug)]
+pub struct Job {
+    /// Number in the job table, 0 until the job is added to it.
+    pub id: usize,
+    pub pgid: Option<i32>,
+    pub command: String,
+    pub state: State,
+    /// Exit status of the last process in the pipeline.
+    pub status: i32,
+    /// Processes not yet reaped.
+    pids: Vec<i32>,
+    last: Option<i32>,
+}
+
+impl Job {
+    pub fn new(pgid: Option<i32>, processes: Vec<Process>, command: String) -> Job {
+        let (last, status) = match processes.last() {
+            Some(Process::Running(pid)) => (Some(*pid), 0),
+            Some(Process::Exited(status)) => (None, *status),
+            None => (None, 0),
+        };
+        let pids: Vec<i32> = processes
+            .iter()
+            .filter_map(|p| match p {
+                Process::Running(pid) => Some(*pid),
+                Process::Exited(_) => None,
+            })
+            .collect();
+        let state = if pids.is_empty() {
+            State::Done
+        } else {
+            State::Running
+        };
+        Job {
+            id: 0,
+            pgid,
+            command,
+            state,
+            status,
+            pids,
+            last,
+        }
+    }
+
+    /// The pid announced when a job goes into the background.
+    pub fn leader(&self) -> i32 {
+        self.pgid
+            .or_else(|| self.pids.first().copied())
+            .unwrap_or(0)
+    }
+
+    /// Wait until every process has exited or one of them has stopped.
+    pub fn wait(&mut self) {
+        self.update(libc::WUNTRACED);
+    }
+
+    /// Note any processes that exited, stopped or continued, without blocking.
+    pub fn poll(&mut self) {
+        self.update(libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED);
+    }
+
+    fn update(&mut self, options: i32) {
+        while let Some(&pid) = self.pids.first() {
+            let mut status = 0;
+            let waited = unsafe { libc::waitpid(pid, &mut status, options) };
+            if waited == 0 {
+                // still running
+                return;
+            }
+            if waited < 0 {
+                // not our child any more
+                self.pids.remove(0);
+                continue;
+            }
+            if libc::WIFSTOPPED(status) {
+                self.state = State::Stopped;
+                self.status = 128 + libc::WSTOPSIG(status);
+                return;
+            }
+            if libc::WIFCONTINUED(status) {
+                self.state = State::Running;
+                continue;
+            }
+            let code = if libc::WIFSIGNALED(status) {
+                128 + libc::WTERMSIG(status)
+            } else {
+                libc::WEXITSTATUS(status)
+            };
+            if self.last == Some(pid) {
+                self.status = code;
+            }
+            self.pids.remove(0);
+        }
+        self.state = State::Done;
+    }
+
+    /// Send `signal` to every process of the job.
+    pub fn signal(&self, signal: i32) {
+        match self.pgid {
+            Some(pgid) => unsafe {
+                libc::kill(-pgid, signal);
+            },
+            None => {
+                for pid in &self.pids {
+                    unsafe {
+                        libc::kill(*pid, signal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Running => "Running".to_owned(),
+            State::Stopped => "Stopped".to_owned(),
+            State::Done if self.status == 0 => "Done".to_owned(),
+            State::Done => format!("Exit {}", self.status),
+        };
+        write!(f, "[{}]  {:<10}{}", self.id, state, self.command)
+    }
+}
+
+/// Jobs in the background or stopped.
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+}
+
+impl JobTable {
+    /// Add `job` under a fresh number unless it already has one.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        }
+        let id = job.id;
+        let at = self.jobs.iter().position(|j| j.id > id);
+        self.jobs.insert(at.unwrap_or(self.jobs.len()), job);
+        id
+    }
+
+    /// Remove the job numbered `id`, or the most recent one.
+    pub fn take(&mut self, id: Option<usize>) -> Option<Job> {
+        let at = match id {
+            Some(id) => self.jobs.iter().position(|j| j.id == id)?,
+            None => self.jobs.len().checked_sub(1)?,
+        };
+        Some(self.jobs.remove(at))
+    }
+
+    pub fn get_mut(&mut self, id: Option<usize>) -> Option<&mut Job> {
+        match id {
+            Some(id) => self.jobs.iter_mut().find(|j| j.id == id),
+            None => self.jobs.last_mut(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.jobs.iter()
+    }
+
+    /// Update every job and remove the ones that are done.
+    pub fn reap(&mut self) -> Vec<Job> {
+        for job in &mut self.jobs {
+            job.poll();
+        }
+        let (done, jobs) = self.jobs.drain(..).partition(|j| j.state == State::Done);
+        self.jobs = jobs;
+        done
+    }
+}
@@ -1,124 +1,99 @@
-use std::io::Error;
-use std::process::{Child, Stdio};
-use std::str::from_utf8;
-
-use nom::{
-    branch::alt,
-    bytes::complete::{is_a, is_not, tag, take_until},
-    character::complete::space0,
-    combinator::map,
-    multi::many1,
-    sequence::{delimited, tuple},
-    IResult,
-};
+mod builtin;
+mod complete;
+mod exec;
+mod expand;
+mod job;
+mod parser;
+mod shell;
+mod vars;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use rustyline::error::ReadlineError;
 use rustyline::{Editor, Result as RustylineResult};
 
-trait Executable {
-    fn execute(&self, cin: Stdio, cout: Stdio) -> Result<Vec<Child>, Error>;
-}
-
-#[derive(Debug)]
-struct Command {
-    program: String,
-    args: Vec<String>,
-}
+use crate::complete::ShellHelper;
+use crate::shell::Shell;
 
-impl Command {
-    fn execute(&self, cin: Stdio, cout: Stdio) -> Result<Child, Error> {
-        std::process::Command::new(&self.program)
-            .stdin(cin)
-            .stdout(cout)
-            .args(&self.args)
-            .spawn()
+fn history_path(shell: &Shell) -> Option<PathBuf> {
+    match shell.vars.get("HISTFILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => shell
+            .vars
+            .get("HOME")
+            .map(|home| PathBuf::from(home).join(".shell_history")),
     }
 }
 
-impl Executable for Command {
-    fn execute(&self, cin: Stdio, cout: Stdio) -> Result<Vec<Child>, Error> {
-        Ok(vec![self.execute(cin, cout)?])
+fn interactive(mut shell: Shell) -> RustylineResult<i32> {
+    let mut rl = Editor::<ShellHelper>::new()?;
+    rl.set_helper(Some(ShellHelper::default()));
+    let history = history_path(&shell);
+    if let Some(path) = &history {
+        // there is no history yet the first time round
+        let _ = rl.load_history(path);
     }
-}
-
-struct Pipeline {
-    left: Command,
-    right: Box<dyn Executable>,
-}
-
-impl Executable for Pipeline {
-    fn execute(&self, cin: Stdio, cout: Stdio) -> Result<Vec<Child>, Error> {
-        let mut left = self.left.execute(cin, Stdio::piped())?;
-        let right = self
-            .right
-            .execute(Stdio::from(left.stdout.take().unwrap()), cout)?;
-        let mut children = vec![left];
-        children.extend(right);
-        Ok(children)
+    while shell.exit.is_none() {
+        shell.notify();
+        match rl.readline("> ") {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    rl.add_history_entry(line.as_str());
+                }
+                shell.execute(&line);
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(why) => return Err(why),
+        }
     }
-}
-
-// Parser functions
-fn pipe(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    is_a("|")(input)
-}
-
-fn unquoted_arg(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    is_not(" \t\r\n'|")(input)
-}
-
-fn single_quoted_arg(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    delimited(tag("'"), take_until("'"), tag("'"))(input)
-}
-
-fn arg(input: &[u8]) -> IResult<&[u8], &[u8]> {
-    delimited(space0, alt((unquoted_arg, single_quoted_arg)), space0)(input)
-}
-
-fn command(input: &[u8]) -> IResult<&[u8], Command> {
-    map(many1(arg), |args| {
-        let args: Vec<&str> = args.iter().map(|bytes| from_utf8(bytes).unwrap()).collect();
-        Command {
-            program: args.first().unwrap().to_string(),
-            args: args[1..].iter().map(|str| str.to_string()).collect(),
+    if let Some(path) = &history {
+        if let Err(why) = rl.save_history(path) {
+            eprintln!("{}: {}", path.display(), why);
         }
-    })(input)
-}
-
-fn pipeline(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
-    map(tuple((command, pipe, cmdline)), |(left, _, right)| {
-        Box::new(Pipeline { left, right }) as Box<dyn Executable>
-    })(input)
+    }
+    Ok(shell.exit.unwrap_or(shell.last_status))
 }
 
-fn cmdline(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
-    alt((
-        pipeline,
-        map(command, |c| Box::new(c) as Box<dyn Executable>),
-    ))(input)
+/// Run `source` as a script named `name` with `args` as `$1`, `$2`...
+fn script(name: String, source: &str, args: Vec<String>) -> i32 {
+    let mut shell = Shell::new(false);
+    shell.name = name;
+    shell.positional = args;
+    shell.execute(source);
+    shell.exit.unwrap_or(shell.last_status)
 }
 
-fn parse_and_execute(line: &str) {
-    if line.trim().is_empty() {
-        return;
-    }
-    match cmdline(line.as_bytes()) {
-        Ok((_, exe)) => match exe.execute(Stdio::inherit(), Stdio::inherit()) {
-            Err(why) => eprintln!("Failed to execute: {}", why),
-            Ok(children) => {
-                for mut child in children {
-                    let _ = child.wait();
+fn main() -> RustylineResult<()> {
+    let mut args = env::args().skip(1);
+    let status = match args.next().as_deref() {
+        // `shell -c commands [name [args...]]`
+        Some("-c") => {
+            let commands = match args.next() {
+                Some(commands) => commands,
+                None => {
+                    eprintln!("-c: option requires an argument");
+                    process::exit(2);
                 }
+            };
+            let name = args.next().unwrap_or_else(|| "shell".to_owned());
+            script(name, &commands, args.collect())
+        }
+        // `shell script.sh [args...]`
+        Some(path) => match fs::read_to_string(path) {
+            Ok(source) => script(path.to_owned(), &source, args.collect()),
+            Err(why) => {
+                eprintln!("{}: {}", path, why);
+                127
             }
         },
-        Err(why) => eprintln!("Failed to parse: {}", why),
-    }
-}
-
-fn main() -> RustylineResult<()> {
-    let mut rl = Editor::<()>::new()?;
-    loop {
-        let readline = rl.readline("> ");
-        if let Ok(line) = readline {
-            parse_and_execute(&line);
+        None => {
+            let tty = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+            interactive(Shell::new(tty))?
         }
-    }
+    };
+    process::exit(status)
 }
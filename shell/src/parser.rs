@@ -0,0 +1,621 @@
+use std::str::from_utf8;
+
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag, take_until, take_while},
+    character::complete::{one_of, satisfy, space0},
+    combinator::{all_consuming, eof, map, not, opt, peek, recognize, value},
+    error::{Error, ErrorKind},
+    multi::{many0, many1, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+
+use std::rc::Rc;
+
+use crate::exec::{
+    And, Assignment, Background, Command, Executable, For, Function, Group, If, Or, Pipeline,
+    Redirect, Redirected, Sequence, While,
+};
+use crate::expand::{Segment, Word};
+
+/// Words that open or close compound commands where a command could start.
+const RESERVED: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "while", "for", "do", "done", "{", "}",
+];
+
+// Parser functions
+fn comment(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    preceded(tag("#"), take_while(|b| b != b'\n'))(input)
+}
+
+/// Blanks, and a comment up to the end of the line.
+fn blank(input: &[u8]) -> IResult<&[u8], ()> {
+    value((), pair(space0, opt(comment)))(input)
+}
+
+/// Any number of empty or comment lines.
+fn linebreak(input: &[u8]) -> IResult<&[u8], ()> {
+    value((), pair(many0(pair(blank, tag("\n"))), blank))(input)
+}
+
+fn pipe(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    delimited(space0, terminated(tag("|"), not(tag("|"))), linebreak)(input)
+}
+
+fn unquoted_text(input: &[u8]) -> IResult<&[u8], Segment> {
+    map(is_not(" \t\r\n'\"\\$|&;<>()"), |bytes| text(bytes, false))(input)
+}
+
+fn single_quoted(input: &[u8]) -> IResult<&[u8], Segment> {
+    map(delimited(tag("'"), take_until("'"), tag("'")), |bytes| {
+        text(bytes, true)
+    })(input)
+}
+
+fn text(bytes: &[u8], quoted: bool) -> Segment {
+    Segment::Text {
+        text: from_utf8(bytes).unwrap().to_string(),
+        quoted,
+    }
+}
+
+/// The next character, however many bytes it takes.
+fn any_char(input: &[u8]) -> IResult<&[u8], char> {
+    let valid = match from_utf8(&input[..input.len().min(4)]) {
+        Ok(valid) => valid,
+        Err(e) => from_utf8(&input[..e.valid_up_to()]).unwrap(),
+    };
+    match valid.chars().next() {
+        Some(c) => Ok((&input[c.len_utf8()..], c)),
+        None => Err(nom::Err::Error(Error::new(input, ErrorKind::Char))),
+    }
+}
+
+/// `\c` outside quotes stands for `c` itself.
+fn escaped(input: &[u8]) -> IResult<&[u8], Segment> {
+    map(preceded(tag("\\"), any_char), |c| Segment::Text {
+        text: c.to_string(),
+        quoted: true,
+    })(input)
+}
+
+/// Within double quotes only `\$`, `\``, `\"`, `\\` and `\newline` are escapes.
+fn double_quoted_escape(input: &[u8]) -> IResult<&[u8], Segment> {
+    map(preceded(tag("\\"), any_char), |c| Segment::Text {
+        text: match c {
+            '$' | '`' | '"' | '\\' => c.to_string(),
+            '\n' => String::new(),
+            c => format!("\\{}", c),
+        },
+        quoted: true,
+    })(input)
+}
+
+fn name(input: &[u8]) -> IResult<&[u8], String> {
+    map(
+        recognize(pair(
+            satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+            take_while(|b: u8| b.is_ascii_alphanumeric() || b == b'_'),
+        )),
+        |bytes| from_utf8(bytes).unwrap().to_string(),
+    )(input)
+}
+
+/// A variable name, or one of the special parameters `$0`..`$9`, `$?` and `$#`.
+fn parameter_name(input: &[u8]) -> IResult<&[u8], String> {
+    alt((name, map(one_of("0123456789?#"), |c| c.to_string())))(input)
+}
+
+/// `$name` or `${name}`
+fn parameter(quoted: bool) -> impl FnMut(&[u8]) -> IResult<&[u8], Segment> {
+    move |input| {
+        map(
+            preceded(
+                tag("$"),
+                alt((
+                    delimited(tag("{"), parameter_name, tag("}")),
+                    parameter_name,
+                )),
+            ),
+            |name| Segment::Parameter { name, quoted },
+        )(input)
+    }
+}
+
+/// `$(command)`, where the command may hold parentheses and quotes of its own.
+fn substitution(quoted: bool) -> impl FnMut(&[u8]) -> IResult<&[u8], Segment> {
+    move |input| {
+        let (body, _) = tag("$(")(input)?;
+        let mut depth = 0;
+        let mut quote = None;
+        let mut i = 0;
+        while i < body.len() {
+            match (quote, body[i]) {
+                (Some(b'"'), b'\\') => i += 1,
+                (Some(q), b) if b == q => quote = None,
+                (Some(_), _) => {}
+                (None, b'\'') | (None, b'"') => quote = Some(body[i]),
+                (None, b'\\') => i += 1,
+                (None, b'(') => depth += 1,
+                (None, b')') if depth == 0 => {
+                    let source = from_utf8(&body[..i]).unwrap().to_string();
+                    return Ok((&body[i + 1..], Segment::Substitution { source, quoted }));
+                }
+                (None, b')') => depth -= 1,
+                (None, _) => {}
+            }
+            i += 1;
+        }
+        Err(nom::Err::Error(Error::new(input, ErrorKind::TakeUntil)))
+    }
+}
+
+/// A `$` that starts no expansion is just a dollar sign.
+fn dollar(quoted: bool) -> impl FnMut(&[u8]) -> IResult<&[u8], Segment> {
+    move |input| map(tag("$"), |bytes| text(bytes, quoted))(input)
+}
+
+fn double_quoted(input: &[u8]) -> IResult<&[u8], Vec<Segment>> {
+    map(
+        delimited(
+            tag("\""),
+            many0(alt((
+                double_quoted_escape,
+                substitution(true),
+                parameter(true),
+                dollar(true),
+                map(is_not("\"\\$"), |bytes| text(bytes, true)),
+            ))),
+            tag("\""),
+        ),
+        |mut segments| {
+            // `""` is still an (empty) argument
+            if segments.is_empty() {
+                segments.push(text(b"", true));
+            }
+            segments
+        },
+    )(input)
+}
+
+fn word(input: &[u8]) -> IResult<&[u8], Word> {
+    map(
+        // a word cannot start a comment
+        preceded(
+            not(tag("#")),
+            many1(alt((
+                map(
+                    alt((
+                        unquoted_text,
+                        single_quoted,
+                        escaped,
+                        substitution(false),
+                        parameter(false),
+                        dollar(false),
+                    )),
+                    |segment| vec![segment],
+                ),
+                double_quoted,
+            ))),
+        ),
+        |pieces| {
+            let mut segments: Vec<Segment> = Vec::new();
+            for segment in pieces.into_iter().flatten() {
+                // join up text that was typed in pieces, like `'a'"b"`
+                if let (
+                    Some(Segment::Text {
+                        text: last,
+                        quoted: last_quoted,
+                    }),
+                    Segment::Text { text, quoted },
+                ) = (segments.last_mut(), &segment)
+                {
+                    if last_quoted == quoted {
+                        last.push_str(text);
+                        continue;
+                    }
+                }
+                segments.push(segment);
+            }
+            Word(segments)
+        },
+    )(input)
+}
+
+/// Words separated by blanks, as in `for name in words`.
+fn word_list(input: &[u8]) -> IResult<&[u8], Vec<Word>> {
+    many0(delimited(space0, word, space0))(input)
+}
+
+#[cfg(test)]
+pub fn words(input: &[u8]) -> IResult<&[u8], Vec<Word>> {
+    all_consuming(word_list)(input)
+}
+
+fn fd(input: &[u8]) -> IResult<&[u8], i32> {
+    map(one_of("012"), |c| c.to_digit(10).unwrap() as i32)(input)
+}
+
+/// `[n]>file`, `[n]>>file`, `[n]<file` and `[n]>&m`
+fn redirect(input: &[u8]) -> IResult<&[u8], Redirect> {
+    alt((
+        map(tuple((opt(fd), tag(">&"), fd)), |(fd, _, to)| {
+            Redirect::Duplicate {
+                fd: fd.unwrap_or(1),
+                to,
+            }
+        }),
+        map(
+            tuple((opt(fd), tag(">>"), space0, word)),
+            |(fd, _, _, path)| Redirect::Append {
+                fd: fd.unwrap_or(1),
+                path,
+            },
+        ),
+        map(
+            tuple((opt(fd), tag(">"), space0, word)),
+            |(fd, _, _, path)| Redirect::Write {
+                fd: fd.unwrap_or(1),
+                path,
+            },
+        ),
+        map(
+            tuple((opt(fd), tag("<"), space0, word)),
+            |(fd, _, _, path)| Redirect::Read {
+                fd: fd.unwrap_or(0),
+                path,
+            },
+        ),
+    ))(input)
+}
+
+enum Part {
+    Arg(Word),
+    Redirect(Redirect),
+}
+
+fn part(input: &[u8]) -> IResult<&[u8], Part> {
+    delimited(
+        space0,
+        alt((map(redirect, Part::Redirect), map(word, Part::Arg))),
+        space0,
+    )(input)
+}
+
+fn command(input: &[u8]) -> IResult<&[u8], Command> {
+    let (rest, parts) = many1(part)(input)?;
+    let mut args = Vec::new();
+    let mut redirects = Vec::new();
+    for part in parts {
+        match part {
+            Part::Arg(arg) => args.push(arg),
+            Part::Redirect(redirect) => redirects.push(redirect),
+        }
+    }
+    if args.is_empty() {
+        // redirections alone make no command
+        return Err(nom::Err::Error(Error::new(input, ErrorKind::Many1)));
+    }
+    if let Some(reserved) = args[0].literal() {
+        if RESERVED.contains(&reserved) {
+            return Err(nom::Err::Error(Error::new(input, ErrorKind::Tag)));
+        }
+    }
+    Ok((
+        rest,
+        Command {
+            program: args.remove(0),
+            args,
+            redirects,
+        },
+    ))
+}
+
+fn pipeline(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(separated_list1(pipe, command), |mut commands| {
+        let last = commands.pop().unwrap();
+        commands
+            .into_iter()
+            .rev()
+            .fold(Box::new(last) as Box<dyn Executable>, |right, left| {
+                Box::new(Pipeline { left, right }) as Box<dyn Executable>
+            })
+    })(input)
+}
+
+#[derive(Clone, Copy)]
+enum Connector {
+    And,
+    Or,
+}
+
+fn connector(input: &[u8]) -> IResult<&[u8], Connector> {
+    delimited(
+        space0,
+        alt((
+            value(Connector::And, tag("&&")),
+            value(Connector::Or, tag("||")),
+        )),
+        linebreak,
+    )(input)
+}
+
+/// `name=value` on its own
+fn assignment(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        terminated(
+            tuple((name, tag("="), opt(word))),
+            peek(preceded(
+                space0,
+                alt((eof, tag(";"), tag("&"), tag("||"), tag("\n"), tag("#"))),
+            )),
+        ),
+        |(name, _, value)| {
+            Box::new(Assignment {
+                name,
+                value: value.unwrap_or_default(),
+            }) as Box<dyn Executable>
+        },
+    )(input)
+}
+
+/// `word` where a command could start, followed by a delimiter.
+fn keyword(word: &'static str) -> impl FnMut(&[u8]) -> IResult<&[u8], &[u8]> {
+    move |input| {
+        delimited(
+            space0,
+            terminated(
+                tag(word),
+                peek(alt((eof, recognize(one_of(" \t\r\n;&|<>()"))))),
+            ),
+            space0,
+        )(input)
+    }
+}
+
+/// The lists inside compound commands, which may span lines.
+fn compound_list(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    delimited(linebreak, list, linebreak)(input)
+}
+
+/// `if list; then list; [elif list; then list;]... [else list;] fi`
+fn if_clause(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        tuple((
+            keyword("if"),
+            compound_list,
+            keyword("then"),
+            compound_list,
+            many0(tuple((
+                keyword("elif"),
+                compound_list,
+                keyword("then"),
+                compound_list,
+            ))),
+            opt(preceded(keyword("else"), compound_list)),
+            keyword("fi"),
+        )),
+        |(_, condition, _, body, elifs, otherwise, _)| {
+            let mut branches = vec![(condition, body)];
+            branches.extend(elifs.into_iter().map(|(_, c, _, b)| (c, b)));
+            Box::new(If {
+                branches,
+                otherwise,
+            }) as Box<dyn Executable>
+        },
+    )(input)
+}
+
+/// `while list; do list; done`
+fn while_clause(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        tuple((
+            keyword("while"),
+            compound_list,
+            keyword("do"),
+            compound_list,
+            keyword("done"),
+        )),
+        |(_, condition, _, body, _)| Box::new(While { condition, body }) as Box<dyn Executable>,
+    )(input)
+}
+
+/// `for name [in words]; do list; done`
+fn for_clause(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        tuple((
+            keyword("for"),
+            name,
+            opt(preceded(keyword("in"), word_list)),
+            blank,
+            opt(alt((tag(";"), tag("\n")))),
+            linebreak,
+            keyword("do"),
+            compound_list,
+            keyword("done"),
+        )),
+        |(_, name, words, _, _, _, _, body, _)| {
+            Box::new(For { name, words, body }) as Box<dyn Executable>
+        },
+    )(input)
+}
+
+/// `{ list; }`
+fn group(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        delimited(keyword("{"), compound_list, keyword("}")),
+        |list| Box::new(Group { list }) as Box<dyn Executable>,
+    )(input)
+}
+
+/// A compound command, with any redirections that apply to all of it.
+fn compound_command(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        pair(
+            alt((if_clause, while_clause, for_clause, group)),
+            many0(delimited(space0, redirect, space0)),
+        ),
+        |(exe, redirects)| {
+            if redirects.is_empty() {
+                exe
+            } else {
+                Box::new(Redirected { exe, redirects })
+            }
+        },
+    )(input)
+}
+
+/// `name() compound-command`
+fn function(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(
+        tuple((
+            preceded(space0, name),
+            space0,
+            tag("()"),
+            linebreak,
+            compound_command,
+        )),
+        |(name, _, _, _, body)| {
+            Box::new(Function {
+                name,
+                body: Rc::from(body),
+            }) as Box<dyn Executable>
+        },
+    )(input)
+}
+
+fn and_or(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    let item = |input| alt((function, compound_command, assignment, pipeline))(input);
+    map(pair(item, many0(pair(connector, item))), |(first, rest)| {
+        rest.into_iter()
+            .fold(first, |left, (connector, right)| match connector {
+                Connector::And => Box::new(And { left, right }),
+                Connector::Or => Box::new(Or { left, right }),
+            })
+    })(input)
+}
+
+/// `;` or a newline runs in sequence, `&` runs what precedes it in the
+/// background.
+fn terminator(input: &[u8]) -> IResult<&[u8], bool> {
+    delimited(
+        blank,
+        alt((
+            value(false, tag(";")),
+            value(false, tag("\n")),
+            value(true, terminated(tag("&"), not(tag("&")))),
+        )),
+        linebreak,
+    )(input)
+}
+
+fn list(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    map(many1(pair(and_or, opt(terminator))), |items| {
+        let mut items: Vec<Box<dyn Executable>> = items
+            .into_iter()
+            .map(|(job, background)| {
+                if background == Some(true) {
+                    Box::new(Background { job }) as Box<dyn Executable>
+                } else {
+                    job
+                }
+            })
+            .collect();
+        if items.len() == 1 {
+            items.pop().unwrap()
+        } else {
+            Box::new(Sequence { items })
+        }
+    })(input)
+}
+
+/// A command line or a whole script; nothing but blanks and comments makes
+/// an empty sequence.
+pub fn cmdline(input: &[u8]) -> IResult<&[u8], Box<dyn Executable>> {
+    all_consuming(delimited(
+        linebreak,
+        map(opt(list), |list| {
+            list.unwrap_or_else(|| Box::new(Sequence { items: Vec::new() }))
+        }),
+        linebreak,
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> String {
+        format!("{}", cmdline(line.as_bytes()).unwrap().1)
+    }
+
+    #[test]
+    fn test_pipeline() {
+        assert_eq!(parse("ls -l | grep 'a b'|wc"), "ls -l | grep 'a b' | wc");
+    }
+
+    #[test]
+    fn test_redirections() {
+        assert_eq!(
+            parse("sort < in.txt > out.txt 2>&1"),
+            "sort <in.txt >out.txt 2>&1"
+        );
+        assert_eq!(parse("make 2>> log >&2"), "make 2>>log >&2");
+        assert_eq!(parse("echo a>b"), "echo a >b");
+        assert_eq!(parse("echo 2 > b"), "echo 2 >b");
+        assert!(cmdline(b"> out.txt").is_err());
+    }
+
+    #[test]
+    fn test_connectors() {
+        assert_eq!(
+            parse("true && echo yes || echo no; echo done"),
+            "true && echo yes || echo no; echo done"
+        );
+        assert_eq!(parse("a | b && c"), "a | b && c");
+        assert!(cmdline(b"a && ").is_err());
+        assert!(cmdline(b"a ||| b").is_err());
+    }
+
+    #[test]
+    fn test_background() {
+        assert_eq!(parse("sleep 10 &"), "sleep 10 &");
+        assert_eq!(parse("sleep 10 & echo hi;"), "sleep 10 & echo hi");
+        assert_eq!(parse("a && b &"), "a && b &");
+    }
+
+    #[test]
+    fn test_comments_and_newlines() {
+        assert_eq!(
+            parse("# setup\n\necho a # first\necho '#' b#c\n\n"),
+            "echo a; echo '#' b#c"
+        );
+        assert_eq!(parse("a &&\n  b |\n c"), "a && b | c");
+        assert_eq!(parse("  # nothing\n"), "");
+    }
+
+    #[test]
+    fn test_compound_commands() {
+        assert_eq!(
+            parse("if test -f x; then echo yes; elif true\nthen :; else echo no; fi"),
+            "if test -f x; then echo yes; elif true; then :; else echo no; fi"
+        );
+        assert_eq!(
+            parse("while read line\ndo\n  echo $line &\ndone"),
+            "while read line; do echo $line & done"
+        );
+        assert_eq!(
+            parse("for f in *.rs \"$1\"; do wc $f; done; for arg\ndo echo $arg; done"),
+            "for f in *.rs \"$1\"; do wc $f; done; for arg; do echo $arg; done"
+        );
+        assert_eq!(
+            parse("greet() {\n  echo hello $1 $?\n}"),
+            "greet() { echo hello $1 $?; }"
+        );
+        assert_eq!(parse("echo if then fi"), "echo if then fi");
+        assert!(cmdline(b"if true; then echo; done").is_err());
+        assert!(cmdline(b"then echo").is_err());
+        assert!(cmdline(b"while true; do echo; }").is_err());
+    }
+}
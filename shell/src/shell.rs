@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+use std::rc::Rc;
+use std::thread;
+
+use crate::exec::{Executable, Io};
+use crate::expand::Context;
+use crate::job::{Job, JobTable, State};
+use crate::parser::cmdline;
+use crate::vars::Variables;
+
+/// State that outlives a single command line.
+pub struct Shell {
+    pub jobs: JobTable,
+    /// Whether the shell controls a terminal and does job control.
+    pub interactive: bool,
+    /// Exit status of the last foreground command, as in `$?`.
+    pub last_status: i32,
+    pub vars: Variables,
+    pub aliases: BTreeMap<String, String>,
+    pub functions: HashMap<String, Rc<dyn Executable>>,
+    /// `$0`, the name of the shell or script.
+    pub name: String,
+    /// `$1`, `$2` and so on.
+    pub positional: Vec<String>,
+    /// Set by `exit` to the status the shell should exit with.
+    pub exit: Option<i32>,
+    pgid: i32,
+}
+
+impl Shell {
+    pub fn new(interactive: bool) -> Shell {
+        let pgid = unsafe {
+            if interactive {
+                // stay in the foreground while jobs come and go
+                for signal in &[libc::SIGTTOU, libc::SIGTTIN, libc::SIGTSTP, libc::SIGQUIT] {
+                    libc::signal(*signal, libc::SIG_IGN);
+                }
+                libc::setpgid(0, 0);
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            }
+            libc::getpgrp()
+        };
+        Shell {
+            jobs: JobTable::default(),
+            interactive,
+            last_status: 0,
+            vars: Variables::from_env(),
+            aliases: BTreeMap::new(),
+            functions: HashMap::new(),
+            name: "shell".to_owned(),
+            positional: Vec::new(),
+            exit: None,
+            pgid,
+        }
+    }
+
+    /// Parse and run one line of input.
+    pub fn execute(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let status = match cmdline(line.as_bytes()) {
+            Ok((_, exe)) => match exe.run(self, Io::default()) {
+                Ok(status) => status,
+                Err(why) => {
+                    eprintln!("Failed to execute: {}", why);
+                    127
+                }
+            },
+            Err(why) => {
+                eprintln!("Failed to parse: {}", why);
+                2
+            }
+        };
+        self.last_status = status;
+    }
+
+    /// Wait for `job` with the terminal handed over to it. A job that stops
+    /// is put in the job table instead.
+    pub fn foreground(&mut self, mut job: Job) -> i32 {
+        if job.state == State::Done {
+            self.last_status = job.status;
+            return job.status;
+        }
+        let pgid = job.pgid.filter(|_| self.interactive);
+        if let Some(pgid) = pgid {
+            unsafe {
+                libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+            }
+        }
+        job.wait();
+        if pgid.is_some() {
+            unsafe {
+                libc::tcsetpgrp(libc::STDIN_FILENO, self.pgid);
+            }
+        }
+        let status = job.status;
+        if job.state == State::Stopped {
+            let id = self.jobs.add(job);
+            eprintln!();
+            eprintln!("{}", self.jobs.get_mut(Some(id)).unwrap());
+        }
+        self.last_status = status;
+        status
+    }
+
+    /// Run `line` and return what it writes to standard output.
+    pub fn capture(&mut self, line: &str) -> io::Result<String> {
+        let (_, exe) = cmdline(line.as_bytes()).map_err(|why| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", line, why))
+        })?;
+        let (reader, writer) = io::pipe()?;
+        // read as the command writes, or it would block on a full pipe
+        let output = thread::spawn(move || {
+            let mut reader = reader;
+            let mut output = String::new();
+            reader.read_to_string(&mut output).map(|_| output)
+        });
+        let io = Io {
+            stdout: Some(writer.into()),
+            ..Io::default()
+        };
+        self.last_status = exe.run(self, io)?;
+        output.join().unwrap()
+    }
+
+    /// Report background jobs that have finished since the last call.
+    pub fn notify(&mut self) {
+        for job in self.jobs.reap() {
+            eprintln!("{}", job);
+        }
+    }
+}
+
+impl Context for Shell {
+    fn parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.last_status.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "0" => Some(self.name.clone()),
+            _ => match name.parse::<usize>() {
+                Ok(n) => self.positional.get(n - 1).cloned(),
+                Err(_) => self.vars.get(name).map(str::to_owned),
+            },
+        }
+    }
+
+    fn substitute(&mut self, source: &str) -> io::Result<String> {
+        self.capture(source)
+    }
+}
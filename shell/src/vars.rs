@@ -0,0 +1,74 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Shell variables, the exported ones making up the environment of commands.
+#[derive(Default)]
+pub struct Variables {
+    values: BTreeMap<String, String>,
+    exported: BTreeSet<String>,
+}
+
+impl Variables {
+    /// Start from the shell's own environment, all of it exported.
+    pub fn from_env() -> Variables {
+        let mut vars = Variables::default();
+        for (name, value) in std::env::vars() {
+            vars.set(&name, value);
+            vars.export(&name);
+        }
+        vars
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.values.insert(name.to_owned(), value);
+    }
+
+    /// Mark `name` for export; it is exported empty if it is not yet set.
+    pub fn export(&mut self, name: &str) {
+        self.values.entry(name.to_owned()).or_default();
+        self.exported.insert(name.to_owned());
+    }
+
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.exported
+            .iter()
+            .map(move |name| (name.as_str(), self.values[name].as_str()))
+    }
+}
+
+/// Whether `name` can be a variable name.
+pub fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        let mut vars = Variables::default();
+        vars.set("LOCAL", "1".to_owned());
+        vars.set("SHARED", "2".to_owned());
+        vars.export("SHARED");
+        vars.export("EMPTY");
+        assert_eq!(
+            vars.exported().collect::<Vec<_>>(),
+            vec![("EMPTY", ""), ("SHARED", "2")]
+        );
+        assert_eq!(vars.get("LOCAL"), Some("1"));
+        assert_eq!(vars.get("MISSING"), None);
+    }
+
+    #[test]
+    fn test_is_name() {
+        assert!(is_name("_a1"));
+        assert!(!is_name("1a"));
+        assert!(!is_name("a-b"));
+        assert!(!is_name(""));
+    }
+}
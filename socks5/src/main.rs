@@ -1,74 +1,56 @@
-#[cfg(test)]
-use strum::IntoEnumIterator;
+mod protocol;
+mod rules;
+mod server;
 
-#[cfg(test)]
-#[derive(Debug, Clone, Copy, strum_macros::EnumIter)]
-enum ResponseCode {
-    Success = 0x00,
-    Failure = 0x01,
-    RuleFailure = 0x02,
-    NetworkUnreachable = 0x03,
-    HostUnreachable = 0x04,
-    ConnectionRefused = 0x05,
-    TtlExpired = 0x06,
-    CommandNotSupported = 0x07,
-    AddrTypeNotSupported = 0x08,
-}
+use std::env;
+use std::io;
+use std::process;
+use std::time::Duration;
 
-#[cfg(test)]
-struct SocksReply {
-    // The SOCKS request information is sent by the client as soon as it has
-    // established a connection to the SOCKS server, and completed the
-    // authentication negotiations.  The server evaluates the request, and
-    // returns a reply formed as follows:
-    //
-    //         +----+-----+-------+------+----------+----------+
-    //         |VER | REP |  RSV  | ATYP | BND.ADDR | BND.PORT |
-    //         +----+-----+-------+------+----------+----------+
-    //         | 1  |  1  | X'00' |  1   | Variable |    2     |
-    //         +----+-----+-------+------+----------+----------+
-    //
-    //     Where:
-    //
-    //         o  VER    protocol version: X'05'
-    //         o  REP    Reply field:
-    //             o  X'00' succeeded
-    //             o  X'01' general SOCKS server failure
-    //             o  X'02' connection not allowed by ruleset
-    //             o  X'03' Network unreachable
-    //             o  X'04' Host unreachable
-    //             o  X'05' Connection refused
-    //             o  X'06' TTL expired
-    //             o  X'07' Command not supported
-    //             o  X'08' Address type not supported
-    //             o  X'09' to X'FF' unassigned
-    //         o  RSV    RESERVED
-    //         o  ATYP   address type of following address
-    //
-    //            o  IP V4 address: X'01'
-    //            o  DOMAINNAME: X'03'
-    //            o  IP V6 address: X'04'
-    //         o  BND.ADDR       server bound address
-    //         o  BND.PORT       server bound port in network octet order
-    buf: [u8; 10],
-}
+use tokio::net::TcpListener;
 
-#[cfg(test)]
-impl SocksReply {
-    fn new(status: ResponseCode) -> Self {
-        let buf = [5, status as u8, 0, 1, 0, 0, 0, 0, 0, 0];
-        Self { buf }
-    }
+use crate::rules::Rules;
+use crate::server::Server;
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: socks5 [address] [--user name:password]... [--rules file] [--connect-timeout secs]"
+    );
+    process::exit(2);
 }
 
-#[cfg(test)]
-#[test]
-fn test_socks_reply() {
-    for rc in ResponseCode::iter() {
-        let reply = SocksReply::new(rc);
-        assert_eq!(reply.buf[0], 5);
-        assert_eq!(reply.buf[1], rc as u8)
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut addr = "127.0.0.1:1080".to_owned();
+    let mut server = Server::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--user" => {
+                let user = args.next().unwrap_or_else(|| usage());
+                let (name, password) = user.split_once(':').unwrap_or_else(|| usage());
+                server = server.user(name, password);
+            }
+            "--rules" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                server = server.rules(
+                    Rules::load(&path)
+                        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?,
+                );
+            }
+            "--connect-timeout" => {
+                let secs: u64 = args
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .unwrap_or_else(|| usage());
+                server = server.connect_timeout(Duration::from_secs(secs));
+            }
+            _ if arg.starts_with('-') => usage(),
+            _ => addr = arg,
+        }
     }
-}
 
-fn main() {}
+    let listener = TcpListener::bind(&addr).await?;
+    println!("SOCKS5 proxy listening on {}", listener.local_addr()?);
+    server.serve(listener).await
+}
@@ -0,0 +1,343 @@
+//! Messages of SOCKS5 (RFC 1928) and its username/password
+//! authentication (RFC 1929).
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const VERSION: u8 = 0x05;
+pub const AUTH_VERSION: u8 = 0x01;
+
+pub const METHOD_NO_AUTH: u8 = 0x00;
+pub const METHOD_USER_PASS: u8 = 0x02;
+pub const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+pub const AUTH_SUCCESS: u8 = 0x00;
+pub const AUTH_FAILURE: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(strum_macros::EnumIter))]
+pub enum ResponseCode {
+    Success = 0x00,
+    Failure = 0x01,
+    RuleFailure = 0x02,
+    NetworkUnreachable = 0x03,
+    HostUnreachable = 0x04,
+    ConnectionRefused = 0x05,
+    TtlExpired = 0x06,
+    CommandNotSupported = 0x07,
+    AddrTypeNotSupported = 0x08,
+}
+
+impl From<&io::Error> for ResponseCode {
+    /// The reply for a failed attempt to reach the destination.
+    fn from(e: &io::Error) -> ResponseCode {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => ResponseCode::ConnectionRefused,
+            io::ErrorKind::NetworkUnreachable => ResponseCode::NetworkUnreachable,
+            io::ErrorKind::HostUnreachable => ResponseCode::HostUnreachable,
+            io::ErrorKind::TimedOut => ResponseCode::TtlExpired,
+            _ => ResponseCode::Failure,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The client does not speak the protocol.
+    Protocol(&'static str),
+    /// The request is refused with this reply.
+    Refused(ResponseCode),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Protocol(what) => write!(f, "protocol error: {}", what),
+            Error::Refused(code) => write!(f, "refused: {:?}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Connect = 0x01,
+    Bind = 0x02,
+    UdpAssociate = 0x03,
+}
+
+/// A destination as the client names it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Address {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Address {
+    /// Read `ATYP | ADDR | PORT`.
+    pub async fn read_from<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Address, Error> {
+        let atyp = reader.read_u8().await?;
+        let address = match atyp {
+            ATYP_IPV4 => {
+                let mut ip = [0u8; 4];
+                reader.read_exact(&mut ip).await?;
+                Address::Ip(SocketAddr::new(Ipv4Addr::from(ip).into(), 0))
+            }
+            ATYP_IPV6 => {
+                let mut ip = [0u8; 16];
+                reader.read_exact(&mut ip).await?;
+                Address::Ip(SocketAddr::new(Ipv6Addr::from(ip).into(), 0))
+            }
+            ATYP_DOMAIN => {
+                let len = reader.read_u8().await?;
+                let mut name = vec![0u8; len as usize];
+                reader.read_exact(&mut name).await?;
+                let name = String::from_utf8(name)
+                    .map_err(|_| Error::Refused(ResponseCode::HostUnreachable))?;
+                Address::Domain(name, 0)
+            }
+            _ => return Err(Error::Refused(ResponseCode::AddrTypeNotSupported)),
+        };
+        let port = reader.read_u16().await?;
+        Ok(address.with_port(port))
+    }
+
+    /// Append `ATYP | ADDR | PORT`.
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            Address::Ip(SocketAddr::V4(addr)) => {
+                buf.push(ATYP_IPV4);
+                buf.extend_from_slice(&addr.ip().octets());
+            }
+            Address::Ip(SocketAddr::V6(addr)) => {
+                buf.push(ATYP_IPV6);
+                buf.extend_from_slice(&addr.ip().octets());
+            }
+            Address::Domain(name, _) => {
+                buf.push(ATYP_DOMAIN);
+                buf.push(name.len() as u8);
+                buf.extend_from_slice(name.as_bytes());
+            }
+        }
+        buf.extend_from_slice(&self.port().to_be_bytes());
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            Address::Ip(addr) => addr.port(),
+            Address::Domain(_, port) => *port,
+        }
+    }
+
+    fn with_port(self, port: u16) -> Address {
+        match self {
+            Address::Ip(addr) => Address::Ip(SocketAddr::new(addr.ip(), port)),
+            Address::Domain(name, _) => Address::Domain(name, port),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Ip(addr) => write!(f, "{}", addr),
+            Address::Domain(name, port) => write!(f, "{}:{}", name, port),
+        }
+    }
+}
+
+/// What the client asks for once authenticated.
+#[derive(Debug)]
+pub struct Request {
+    pub command: Command,
+    pub address: Address,
+}
+
+impl Request {
+    //     +----+-----+-------+------+----------+----------+
+    //     |VER | CMD |  RSV  | ATYP | DST.ADDR | DST.PORT |
+    //     +----+-----+-------+------+----------+----------+
+    //     | 1  |  1  | X'00' |  1   | Variable |    2     |
+    //     +----+-----+-------+------+----------+----------+
+    pub async fn read_from<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Request, Error> {
+        let mut header = [0u8; 3];
+        reader.read_exact(&mut header).await?;
+        if header[0] != VERSION {
+            return Err(Error::Protocol("bad version in request"));
+        }
+        let address = Address::read_from(reader).await?;
+        let command = match header[1] {
+            0x01 => Command::Connect,
+            0x02 => Command::Bind,
+            0x03 => Command::UdpAssociate,
+            _ => return Err(Error::Refused(ResponseCode::CommandNotSupported)),
+        };
+        Ok(Request { command, address })
+    }
+}
+
+/// Split a UDP ASSOCIATE datagram into its destination and payload.
+///
+///     +----+------+------+----------+----------+----------+
+///     |RSV | FRAG | ATYP | DST.ADDR | DST.PORT |   DATA   |
+///     +----+------+------+----------+----------+----------+
+///     | 2  |  1   |  1   | Variable |    2     | Variable |
+///     +----+------+------+----------+----------+----------+
+pub async fn parse_datagram(datagram: &[u8]) -> Result<(Address, &[u8]), Error> {
+    if datagram.len() < 3 {
+        return Err(Error::Protocol("short datagram"));
+    }
+    if datagram[2] != 0 {
+        return Err(Error::Protocol("fragmented datagrams are not supported"));
+    }
+    let mut rest = &datagram[3..];
+    let address = Address::read_from(&mut rest).await?;
+    Ok((address, rest))
+}
+
+/// Wrap `payload` from `source` for delivery to a UDP ASSOCIATE client.
+pub fn datagram(source: &Address, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = vec![0, 0, 0];
+    source.write_to(&mut datagram);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+pub struct SocksReply {
+    // The SOCKS request information is sent by the client as soon as it has
+    // established a connection to the SOCKS server, and completed the
+    // authentication negotiations.  The server evaluates the request, and
+    // returns a reply formed as follows:
+    //
+    //         +----+-----+-------+------+----------+----------+
+    //         |VER | REP |  RSV  | ATYP | BND.ADDR | BND.PORT |
+    //         +----+-----+-------+------+----------+----------+
+    //         | 1  |  1  | X'00' |  1   | Variable |    2     |
+    //         +----+-----+-------+------+----------+----------+
+    //
+    //     Where:
+    //
+    //         o  VER    protocol version: X'05'
+    //         o  REP    Reply field:
+    //             o  X'00' succeeded
+    //             o  X'01' general SOCKS server failure
+    //             o  X'02' connection not allowed by ruleset
+    //             o  X'03' Network unreachable
+    //             o  X'04' Host unreachable
+    //             o  X'05' Connection refused
+    //             o  X'06' TTL expired
+    //             o  X'07' Command not supported
+    //             o  X'08' Address type not supported
+    //             o  X'09' to X'FF' unassigned
+    //         o  RSV    RESERVED
+    //         o  ATYP   address type of following address
+    //
+    //            o  IP V4 address: X'01'
+    //            o  DOMAINNAME: X'03'
+    //            o  IP V6 address: X'04'
+    //         o  BND.ADDR       server bound address
+    //         o  BND.PORT       server bound port in network octet order
+    pub buf: Vec<u8>,
+}
+
+impl SocksReply {
+    pub fn new(status: ResponseCode) -> Self {
+        Self::bound(
+            status,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        )
+    }
+
+    pub fn bound(status: ResponseCode, addr: SocketAddr) -> Self {
+        let mut buf = vec![VERSION, status as u8, 0];
+        Address::Ip(addr).write_to(&mut buf);
+        Self { buf }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn test_socks_reply() {
+        for rc in ResponseCode::iter() {
+            let reply = SocksReply::new(rc);
+            assert_eq!(reply.buf[0], 5);
+            assert_eq!(reply.buf[1], rc as u8)
+        }
+        let reply = SocksReply::bound(ResponseCode::Success, "[::1]:1080".parse().unwrap());
+        assert_eq!(reply.buf.len(), 4 + 16 + 2);
+        assert_eq!(reply.buf[3], ATYP_IPV6);
+        assert_eq!(&reply.buf[20..], &[0x04, 0x38]);
+    }
+
+    #[tokio::test]
+    async fn test_address_round_trip() {
+        for address in [
+            Address::Ip("10.0.0.1:80".parse().unwrap()),
+            Address::Ip("[2001:db8::1]:443".parse().unwrap()),
+            Address::Domain("example.com".to_owned(), 8080),
+        ] {
+            let mut buf = Vec::new();
+            address.write_to(&mut buf);
+            assert_eq!(Address::read_from(&mut &buf[..]).await.unwrap(), address);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        let request = Request::read_from(&mut &[5, 1, 0, 1, 127, 0, 0, 1, 0, 80][..])
+            .await
+            .unwrap();
+        assert_eq!(request.command, Command::Connect);
+        assert_eq!(
+            request.address,
+            Address::Ip("127.0.0.1:80".parse().unwrap())
+        );
+
+        let unknown_atyp = Request::read_from(&mut &[5, 1, 0, 9, 0, 0][..]).await;
+        assert!(matches!(
+            unknown_atyp,
+            Err(Error::Refused(ResponseCode::AddrTypeNotSupported))
+        ));
+        let unknown_command = Request::read_from(&mut &[5, 9, 0, 1, 0, 0, 0, 0, 0, 0][..]).await;
+        assert!(matches!(
+            unknown_command,
+            Err(Error::Refused(ResponseCode::CommandNotSupported))
+        ));
+        let bad_version = Request::read_from(&mut &[4, 1, 0, 1, 0, 0, 0, 0, 0, 0][..]).await;
+        assert!(matches!(bad_version, Err(Error::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn test_datagram() {
+        let source = Address::Domain("dns.example".to_owned(), 53);
+        let wrapped = datagram(&source, b"query");
+        let (address, payload) = parse_datagram(&wrapped).await.unwrap();
+        assert_eq!(address, source);
+        assert_eq!(payload, b"query");
+
+        let mut fragment = wrapped.clone();
+        fragment[2] = 1;
+        assert!(parse_datagram(&fragment).await.is_err());
+        assert!(parse_datagram(&[0, 0]).await.is_err());
+    }
+}
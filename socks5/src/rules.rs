@@ -0,0 +1,331 @@
+//! Access control. A rule file holds one rule per line, the first rule that
+//! matches a request deciding it:
+//!
+//! ```text
+//! # action  [from CIDR] [to HOST] [port RANGE] [user NAME]
+//! deny  to *.internal.example.com
+//! allow from 10.0.0.0/8 port 80-443
+//! allow user alice
+//! ```
+//!
+//! `HOST` is a CIDR or a name pattern where `*` matches any run of
+//! characters. Once a rule file is loaded, a request that no rule matches is
+//! denied.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::protocol::Address;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+/// An IP network such as `192.168.0.0/16`; a bare address is a network of one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(text: &str) -> Option<Cidr> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse().ok()?)),
+            None => (text.parse::<IpAddr>().ok()?, None),
+        };
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        if prefix > bits {
+            return None;
+        }
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(net: &[u8], ip: &[u8], prefix: u8) -> bool {
+    let (whole, rest) = ((prefix / 8) as usize, prefix % 8);
+    if net[..whole] != ip[..whole] {
+        return false;
+    }
+    rest == 0 || (net[whole] ^ ip[whole]) & (0xff << (8 - rest)) == 0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostPattern {
+    Network(Cidr),
+    Name(String),
+}
+
+impl HostPattern {
+    fn parse(text: &str) -> HostPattern {
+        match Cidr::parse(text) {
+            Some(cidr) => HostPattern::Network(cidr),
+            None => HostPattern::Name(text.to_ascii_lowercase()),
+        }
+    }
+
+    fn matches(&self, address: &Address) -> bool {
+        match (self, address) {
+            (HostPattern::Network(cidr), Address::Ip(addr)) => cidr.contains(addr.ip()),
+            (HostPattern::Network(_), Address::Domain(..)) => false,
+            (HostPattern::Name(pattern), Address::Ip(addr)) => {
+                wildcard(pattern, &addr.ip().to_string())
+            }
+            (HostPattern::Name(pattern), Address::Domain(name, _)) => {
+                wildcard(pattern, &name.to_ascii_lowercase())
+            }
+        }
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of characters.
+fn wildcard(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            text.starts_with(prefix)
+                && (0..=text.len() - prefix.len())
+                    .filter(|&i| text.is_char_boundary(prefix.len() + i))
+                    .any(|i| wildcard(rest, &text[prefix.len() + i..]))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub action: Action,
+    pub from: Option<Cidr>,
+    pub to: Option<HostPattern>,
+    pub ports: Option<RangeInclusive<u16>>,
+    pub user: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, client: IpAddr, destination: &Address, user: Option<&str>) -> bool {
+        self.from.as_ref().is_none_or(|from| from.contains(client))
+            && self.to.as_ref().is_none_or(|to| to.matches(destination))
+            && self
+                .ports
+                .as_ref()
+                .is_none_or(|ports| ports.contains(&destination.port()))
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|name| user == Some(name.as_str()))
+    }
+}
+
+#[derive(Debug)]
+pub struct RuleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[derive(Debug, Default)]
+pub struct Rules {
+    rules: Option<Vec<Rule>>,
+}
+
+impl Rules {
+    /// Rules that let every request through.
+    pub fn allow_all() -> Rules {
+        Rules::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Rules> {
+        let text = fs::read_to_string(path)?;
+        Rules::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Rules, RuleError> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            rules.push(parse_rule(line).map_err(|message| RuleError {
+                line: i + 1,
+                message,
+            })?);
+        }
+        Ok(Rules { rules: Some(rules) })
+    }
+
+    /// Whether `client`, logged in as `user` if at all, may reach `destination`.
+    pub fn allows(&self, client: IpAddr, destination: &Address, user: Option<&str>) -> bool {
+        let rules = match &self.rules {
+            Some(rules) => rules,
+            None => return true,
+        };
+        rules
+            .iter()
+            .find(|rule| rule.matches(client, destination, user))
+            .is_some_and(|rule| rule.action == Action::Allow)
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let mut words = line.split_whitespace();
+    let action = match words.next() {
+        Some("allow") => Action::Allow,
+        Some("deny") => Action::Deny,
+        Some(other) => return Err(format!("expected allow or deny, found `{}`", other)),
+        None => unreachable!("blank lines are skipped"),
+    };
+    let mut rule = Rule {
+        action,
+        from: None,
+        to: None,
+        ports: None,
+        user: None,
+    };
+    while let Some(key) = words.next() {
+        let value = words
+            .next()
+            .ok_or_else(|| format!("`{}` needs a value", key))?;
+        match key {
+            "from" => {
+                rule.from =
+                    Some(Cidr::parse(value).ok_or_else(|| format!("bad network `{}`", value))?)
+            }
+            "to" => rule.to = Some(HostPattern::parse(value)),
+            "port" => rule.ports = Some(parse_ports(value)?),
+            "user" => rule.user = Some(value.to_owned()),
+            _ => return Err(format!("unknown condition `{}`", key)),
+        }
+    }
+    Ok(rule)
+}
+
+fn parse_ports(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let bad = || format!("bad port range `{}`", text);
+    let (first, last) = match text.split_once('-') {
+        Some((first, last)) => (first, last),
+        None => (text, text),
+    };
+    let first: u16 = first.parse().map_err(|_| bad())?;
+    let last: u16 = last.parse().map_err(|_| bad())?;
+    if first > last {
+        return Err(bad());
+    }
+    Ok(first..=last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn to(text: &str) -> Address {
+        match text.parse() {
+            Ok(addr) => Address::Ip(addr),
+            Err(_) => {
+                let (name, port) = text.rsplit_once(':').unwrap();
+                Address::Domain(name.to_owned(), port.parse().unwrap())
+            }
+        }
+    }
+
+    #[test]
+    fn test_cidr() {
+        let net = Cidr::parse("192.168.0.0/16").unwrap();
+        assert!(net.contains(ip("192.168.4.2")));
+        assert!(!net.contains(ip("192.169.0.1")));
+        assert!(net.contains(ip("::ffff:192.168.1.1")));
+        assert!(!net.contains(ip("::1")));
+
+        let odd = Cidr::parse("10.0.0.0/12").unwrap();
+        assert!(odd.contains(ip("10.15.255.255")));
+        assert!(!odd.contains(ip("10.16.0.0")));
+
+        assert!(Cidr::parse("fd00::/8").unwrap().contains(ip("fd12::1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+        assert!(Cidr::parse("1.2.3.4").unwrap().contains(ip("1.2.3.4")));
+        assert_eq!(Cidr::parse("1.2.3.4/33"), None);
+        assert_eq!(Cidr::parse("example.com"), None);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        assert!(wildcard("*.example.com", "www.example.com"));
+        assert!(!wildcard("*.example.com", "example.com"));
+        assert!(wildcard("*", "anything"));
+        assert!(wildcard("a*b*c", "a12b34c"));
+        assert!(!wildcard("a*b*c", "a12b34"));
+        assert!(wildcard("exact", "exact"));
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let rules = Rules::parse(
+            "# comments and blank lines are skipped
+
+             deny  to *.internal.example.com   # trailing comment
+             allow from 10.0.0.0/8 port 80-443
+             allow to 192.0.2.0/24 user alice
+             deny from 10.0.0.0/8",
+        )
+        .unwrap();
+        let inside = ip("10.1.2.3");
+        assert!(rules.allows(inside, &to("www.example.com:443"), None));
+        assert!(!rules.allows(inside, &to("db.internal.example.com:443"), None));
+        assert!(!rules.allows(inside, &to("www.example.com:22"), None));
+        assert!(rules.allows(inside, &to("192.0.2.7:22"), Some("alice")));
+        assert!(!rules.allows(inside, &to("192.0.2.7:22"), Some("bob")));
+        // nothing matches
+        assert!(!rules.allows(ip("203.0.113.9"), &to("www.example.com:80"), None));
+    }
+
+    #[test]
+    fn test_allow_all() {
+        let rules = Rules::allow_all();
+        assert!(rules.allows(ip("203.0.113.9"), &to("anywhere.example:1"), None));
+        let empty = Rules::parse("# nothing\n").unwrap();
+        assert!(!empty.allows(ip("203.0.113.9"), &to("anywhere.example:1"), None));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = Rules::parse("allow\npermit to x").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(Rules::parse("allow from nowhere").is_err());
+        assert!(Rules::parse("allow port 90-80").is_err());
+        assert!(Rules::parse("allow port").is_err());
+        assert!(Rules::parse("allow colour blue").is_err());
+    }
+}
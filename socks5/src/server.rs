@@ -0,0 +1,621 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use crate::protocol::{
+    datagram, parse_datagram, Address, Command, Error, Request, ResponseCode, SocksReply,
+    AUTH_FAILURE, AUTH_SUCCESS, AUTH_VERSION, METHOD_NONE_ACCEPTABLE, METHOD_NO_AUTH,
+    METHOD_USER_PASS, VERSION,
+};
+use crate::rules::Rules;
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a BIND waits for the incoming connection.
+const BIND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bytes relayed from the client and to it.
+type Traffic = (u64, u64);
+
+pub struct Server {
+    /// Username to password; when empty no authentication is asked for.
+    users: HashMap<String, String>,
+    rules: Rules,
+    connect_timeout: Duration,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            users: HashMap::new(),
+            rules: Rules::allow_all(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+
+    pub fn rules(mut self, rules: Rules) -> Server {
+        self.rules = rules;
+        self
+    }
+
+    /// Require clients to log in, `name` being one of the users allowed.
+    pub fn user(mut self, name: &str, password: &str) -> Server {
+        self.users.insert(name.to_owned(), password.to_owned());
+        self
+    }
+
+    /// Give up on a CONNECT whose target hasn't answered after `connect_timeout`.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Server {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Serve every client that connects to `listener`, each on its own task.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = server.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle(stream, peer).await {
+                    eprintln!("{}: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, mut client: TcpStream, peer: SocketAddr) -> Result<(), Error> {
+        let user = self.negotiate(&mut client).await?;
+        let request = match Request::read_from(&mut client).await {
+            Ok(request) => request,
+            Err(Error::Refused(code)) => return refuse(&mut client, code).await,
+            Err(e) => return Err(e),
+        };
+        // the address of an association is the client's own, its datagrams
+        // are checked one by one instead
+        if request.command != Command::UdpAssociate
+            && !self
+                .rules
+                .allows(peer.ip(), &request.address, user.as_deref())
+        {
+            return refuse(&mut client, ResponseCode::RuleFailure).await;
+        }
+        let (up, down) = match request.command {
+            Command::Connect => self.connect(client, &request.address).await?,
+            Command::Bind => self.bind(client, peer, user.as_deref()).await?,
+            Command::UdpAssociate => {
+                self.udp_associate(client, peer, &request.address, user.as_deref())
+                    .await?
+            }
+        };
+        println!(
+            "{} {} {:?} {}: {} bytes up, {} bytes down",
+            peer,
+            user.as_deref().unwrap_or("-"),
+            request.command,
+            request.address,
+            up,
+            down
+        );
+        Ok(())
+    }
+
+    /// Agree on an authentication method and go through it, returning the
+    /// user who logged in if any.
+    async fn negotiate(&self, client: &mut TcpStream) -> Result<Option<String>, Error> {
+        //     +----+----------+----------+
+        //     |VER | NMETHODS | METHODS  |
+        //     +----+----------+----------+
+        //     | 1  |    1     | 1 to 255 |
+        //     +----+----------+----------+
+        if client.read_u8().await? != VERSION {
+            return Err(Error::Protocol("bad version in greeting"));
+        }
+        let mut methods = vec![0u8; client.read_u8().await? as usize];
+        client.read_exact(&mut methods).await?;
+
+        let wanted = if self.users.is_empty() {
+            METHOD_NO_AUTH
+        } else {
+            METHOD_USER_PASS
+        };
+        if !methods.contains(&wanted) {
+            client.write_all(&[VERSION, METHOD_NONE_ACCEPTABLE]).await?;
+            return Err(Error::Protocol("no acceptable authentication method"));
+        }
+        client.write_all(&[VERSION, wanted]).await?;
+        if wanted == METHOD_USER_PASS {
+            return self.authenticate(client).await.map(Some);
+        }
+        Ok(None)
+    }
+
+    async fn authenticate(&self, client: &mut TcpStream) -> Result<String, Error> {
+        //     +----+------+----------+------+----------+
+        //     |VER | ULEN |  UNAME   | PLEN |  PASSWD  |
+        //     +----+------+----------+------+----------+
+        //     | 1  |  1   | 1 to 255 |  1   | 1 to 255 |
+        //     +----+------+----------+------+----------+
+        if client.read_u8().await? != AUTH_VERSION {
+            return Err(Error::Protocol("bad version in authentication"));
+        }
+        let mut name = vec![0u8; client.read_u8().await? as usize];
+        client.read_exact(&mut name).await?;
+        let mut password = vec![0u8; client.read_u8().await? as usize];
+        client.read_exact(&mut password).await?;
+
+        let name = String::from_utf8_lossy(&name);
+        let allowed = self
+            .users
+            .get(name.as_ref())
+            .is_some_and(|expected| expected.as_bytes() == password.as_slice());
+        if !allowed {
+            // the client must close the connection after a failure
+            client.write_all(&[AUTH_VERSION, AUTH_FAILURE]).await?;
+            return Err(Error::Protocol("authentication failed"));
+        }
+        client.write_all(&[AUTH_VERSION, AUTH_SUCCESS]).await?;
+        Ok(name.into_owned())
+    }
+
+    /// CONNECT: reach `address` and relay between it and the client.
+    async fn connect(&self, mut client: TcpStream, address: &Address) -> Result<Traffic, Error> {
+        let mut target = match timeout(self.connect_timeout, connect(address)).await {
+            Ok(Ok(target)) => target,
+            Ok(Err(e)) => {
+                client
+                    .write_all(&SocksReply::new(ResponseCode::from(&e)).buf)
+                    .await?;
+                return Err(e.into());
+            }
+            Err(_) => return refuse(&mut client, ResponseCode::TtlExpired).await,
+        };
+        let reply = SocksReply::bound(ResponseCode::Success, target.local_addr()?);
+        client.write_all(&reply.buf).await?;
+        Ok(copy_bidirectional(&mut client, &mut target).await?)
+    }
+
+    /// BIND: accept one connection for the client, as the data connection
+    /// of active-mode FTP, and relay between the two.
+    async fn bind(
+        &self,
+        mut client: TcpStream,
+        peer: SocketAddr,
+        user: Option<&str>,
+    ) -> Result<Traffic, Error> {
+        let listener = TcpListener::bind(SocketAddr::new(client.local_addr()?.ip(), 0)).await?;
+        let reply = SocksReply::bound(ResponseCode::Success, listener.local_addr()?);
+        client.write_all(&reply.buf).await?;
+
+        let (mut incoming, from) = match timeout(BIND_TIMEOUT, listener.accept()).await {
+            Ok(Ok(accepted)) => accepted,
+            Ok(Err(e)) => {
+                client
+                    .write_all(&SocksReply::new(ResponseCode::from(&e)).buf)
+                    .await?;
+                return Err(e.into());
+            }
+            Err(_) => return refuse(&mut client, ResponseCode::TtlExpired).await,
+        };
+        if !self.rules.allows(peer.ip(), &Address::Ip(from), user) {
+            return refuse(&mut client, ResponseCode::RuleFailure).await;
+        }
+        // the second reply tells who connected
+        client
+            .write_all(&SocksReply::bound(ResponseCode::Success, from).buf)
+            .await?;
+        Ok(copy_bidirectional(&mut client, &mut incoming).await?)
+    }
+
+    /// UDP ASSOCIATE: relay datagrams between the client and whoever it
+    /// addresses for as long as the TCP connection stays open.
+    async fn udp_associate(
+        &self,
+        mut client: TcpStream,
+        peer: SocketAddr,
+        requested: &Address,
+        user: Option<&str>,
+    ) -> Result<Traffic, Error> {
+        let relay = UdpSocket::bind(SocketAddr::new(client.local_addr()?.ip(), 0)).await?;
+        let reply = SocksReply::bound(ResponseCode::Success, relay.local_addr()?);
+        client.write_all(&reply.buf).await?;
+
+        // the client may say where its datagrams come from, or leave it to
+        // the first one that arrives from its host
+        let mut client_udp = match requested {
+            Address::Ip(addr) if addr.port() != 0 && !addr.ip().is_unspecified() => Some(*addr),
+            Address::Ip(addr) if addr.port() != 0 => Some(SocketAddr::new(peer.ip(), addr.port())),
+            _ => None,
+        };
+        let (mut up, mut down) = (0, 0);
+        let mut buf = vec![0u8; 65535];
+        let mut control = [0u8; 64];
+        loop {
+            tokio::select! {
+                read = client.read(&mut control) => match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => continue,
+                },
+                received = relay.recv_from(&mut buf) => {
+                    // one bad datagram is no reason to end the association
+                    let (len, from) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            eprintln!("{}: UDP relay: {}", peer, e);
+                            continue;
+                        }
+                    };
+                    let from_client = match client_udp {
+                        Some(addr) => addr == from,
+                        None => from.ip() == peer.ip(),
+                    };
+                    if from_client {
+                        client_udp = Some(from);
+                        let (destination, payload) = match parse_datagram(&buf[..len]).await {
+                            Ok(parsed) => parsed,
+                            Err(_) => continue,
+                        };
+                        if !self.rules.allows(peer.ip(), &destination, user) {
+                            continue;
+                        }
+                        let target = match resolve(&destination).await {
+                            Ok(addrs) => addrs.into_iter().next(),
+                            Err(_) => None,
+                        };
+                        if let Some(target) = target {
+                            match relay.send_to(payload, target).await {
+                                Ok(sent) => up += sent as u64,
+                                Err(e) => eprintln!("{}: UDP relay to {}: {}", peer, target, e),
+                            }
+                        }
+                    } else if let Some(client_udp) = client_udp {
+                        let wrapped = datagram(&Address::Ip(from), &buf[..len]);
+                        match relay.send_to(&wrapped, client_udp).await {
+                            Ok(_) => down += len as u64,
+                            Err(e) => eprintln!("{}: UDP relay: {}", peer, e),
+                        }
+                    }
+                }
+            }
+        }
+        Ok((up, down))
+    }
+}
+
+async fn resolve(address: &Address) -> io::Result<Vec<SocketAddr>> {
+    match address {
+        Address::Ip(addr) => Ok(vec![*addr]),
+        Address::Domain(name, port) => Ok(lookup_host((name.as_str(), *port))
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::HostUnreachable, e))?
+            .collect()),
+    }
+}
+
+async fn connect(address: &Address) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::HostUnreachable, "no addresses");
+    for addr in resolve(address).await? {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Answer the request with a failure and end the session.
+async fn refuse<T>(client: &mut TcpStream, code: ResponseCode) -> Result<T, Error> {
+    client.write_all(&SocksReply::new(code).buf).await?;
+    Err(Error::Refused(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start(server: Server) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server.serve(listener));
+        addr
+    }
+
+    /// A server that sends back whatever it receives.
+    async fn echo_server(at: &str) -> Option<SocketAddr> {
+        let listener = TcpListener::bind(at).await.ok()?;
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        Some(addr)
+    }
+
+    /// Greet the proxy offering `methods` and return the one it picks.
+    async fn greet(stream: &mut TcpStream, methods: &[u8]) -> u8 {
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await.unwrap();
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], VERSION);
+        reply[1]
+    }
+
+    async fn login(stream: &mut TcpStream, name: &str, password: &str) -> u8 {
+        let mut request = vec![AUTH_VERSION, name.len() as u8];
+        request.extend_from_slice(name.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await.unwrap();
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[0], AUTH_VERSION);
+        reply[1]
+    }
+
+    /// Send a request and return the reply code and bound address.
+    async fn request(stream: &mut TcpStream, command: u8, address: &Address) -> (u8, Address) {
+        let mut request = vec![VERSION, command, 0];
+        address.write_to(&mut request);
+        stream.write_all(&request).await.unwrap();
+        let mut header = [0u8; 3];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], VERSION);
+        let bound = Address::read_from(stream).await.unwrap();
+        (header[1], bound)
+    }
+
+    async fn assert_echoes(stream: &mut TcpStream) {
+        stream.write_all(b"hello through the proxy").await.unwrap();
+        let mut echoed = [0u8; 23];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello through the proxy");
+    }
+
+    #[tokio::test]
+    async fn test_connect_ipv4() {
+        let echo = echo_server("127.0.0.1:0").await.unwrap();
+        let proxy = start(Server::new()).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        assert_eq!(greet(&mut stream, &[METHOD_NO_AUTH]).await, METHOD_NO_AUTH);
+        let (code, bound) = request(&mut stream, 1, &Address::Ip(echo)).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        assert!(matches!(bound, Address::Ip(addr) if addr.port() != 0));
+        assert_echoes(&mut stream).await;
+    }
+
+    #[tokio::test]
+    async fn test_connect_ipv6() {
+        let echo = match echo_server("[::1]:0").await {
+            Some(echo) => echo,
+            // no IPv6 loopback here
+            None => return,
+        };
+        let proxy = start(Server::new()).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let (code, _) = request(&mut stream, 1, &Address::Ip(echo)).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        assert_echoes(&mut stream).await;
+    }
+
+    #[tokio::test]
+    async fn test_connect_domain() {
+        let echo = echo_server("127.0.0.1:0").await.unwrap();
+        let proxy = start(Server::new()).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let localhost = Address::Domain("localhost".to_owned(), echo.port());
+        let (code, _) = request(&mut stream, 1, &localhost).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        assert_echoes(&mut stream).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let nowhere = Address::Domain("nowhere.invalid".to_owned(), 80);
+        let (code, _) = request(&mut stream, 1, &nowhere).await;
+        assert_eq!(code, ResponseCode::HostUnreachable as u8);
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // a listener that never accepts, with its backlog full, leaves
+        // further connections waiting for an answer to their SYN
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let full = socket.listen(0).unwrap();
+        let target = full.local_addr().unwrap();
+        let _queued = TcpStream::connect(target).await.unwrap();
+        let proxy = start(Server::new().connect_timeout(Duration::from_millis(100))).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let (code, _) = request(&mut stream, 1, &Address::Ip(target)).await;
+        assert_eq!(code, ResponseCode::TtlExpired as u8);
+    }
+
+    #[tokio::test]
+    async fn test_connection_refused() {
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let proxy = start(Server::new()).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let (code, _) = request(&mut stream, 1, &Address::Ip(closed)).await;
+        assert_eq!(code, ResponseCode::ConnectionRefused as u8);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_requests() {
+        let proxy = start(Server::new()).await;
+        let target = Address::Ip("127.0.0.1:9".parse().unwrap());
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let (code, _) = request(&mut stream, 0x09, &target).await;
+        assert_eq!(code, ResponseCode::CommandNotSupported as u8);
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        stream
+            .write_all(&[VERSION, 1, 0, 0x07, 0, 0])
+            .await
+            .unwrap();
+        let mut reply = [0u8; 10];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], ResponseCode::AddrTypeNotSupported as u8);
+    }
+
+    #[tokio::test]
+    async fn test_username_password() {
+        let echo = echo_server("127.0.0.1:0").await.unwrap();
+        let proxy = start(Server::new().user("alice", "secret")).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        assert_eq!(
+            greet(&mut stream, &[METHOD_NO_AUTH]).await,
+            METHOD_NONE_ACCEPTABLE
+        );
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        let offered = [METHOD_NO_AUTH, METHOD_USER_PASS];
+        assert_eq!(greet(&mut stream, &offered).await, METHOD_USER_PASS);
+        assert_eq!(login(&mut stream, "alice", "wrong").await, AUTH_FAILURE);
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &offered).await;
+        assert_eq!(login(&mut stream, "alice", "secret").await, AUTH_SUCCESS);
+        let (code, _) = request(&mut stream, 1, &Address::Ip(echo)).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        assert_echoes(&mut stream).await;
+    }
+
+    #[tokio::test]
+    async fn test_rules() {
+        let echo = echo_server("127.0.0.1:0").await.unwrap();
+        let rules = format!("allow port {}\ndeny user mallory", echo.port());
+        let proxy = start(Server::new().rules(Rules::parse(&rules).unwrap())).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let (code, _) = request(&mut stream, 1, &Address::Ip(echo)).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        assert_echoes(&mut stream).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let elsewhere = Address::Ip(SocketAddr::new(echo.ip(), echo.port() ^ 1));
+        let (code, _) = request(&mut stream, 1, &elsewhere).await;
+        assert_eq!(code, ResponseCode::RuleFailure as u8);
+    }
+
+    #[tokio::test]
+    async fn test_udp_associate() {
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, from) = echo.recv_from(&mut buf).await.unwrap();
+                echo.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+        let rules = format!("allow to {}\nallow to ::1\n", echo_addr.ip());
+        let proxy = start(Server::new().rules(Rules::parse(&rules).unwrap())).await;
+
+        let mut control = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut control, &[METHOD_NO_AUTH]).await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let me = Address::Ip(socket.local_addr().unwrap());
+        let (code, relay) = request(&mut control, 3, &me).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        let relay = match relay {
+            Address::Ip(relay) => relay,
+            other => panic!("relay at {}", other),
+        };
+
+        let query = datagram(&Address::Ip(echo_addr), b"dns query");
+        socket.send_to(&query, relay).await.unwrap();
+        let mut buf = [0u8; 512];
+        let (len, from) = timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(from, relay);
+        let (source, payload) = parse_datagram(&buf[..len]).await.unwrap();
+        assert_eq!(source, Address::Ip(echo_addr));
+        assert_eq!(payload, b"dns query");
+
+        // denied destinations are dropped
+        let blocked = datagram(&Address::Ip("192.0.2.1:53".parse().unwrap()), b"x");
+        socket.send_to(&blocked, relay).await.unwrap();
+        assert!(
+            timeout(Duration::from_millis(200), socket.recv_from(&mut buf))
+                .await
+                .is_err()
+        );
+
+        // an IPv4 relay cannot send to IPv6, and carries on after failing to
+        let unsendable = datagram(&Address::Ip("[::1]:9".parse().unwrap()), b"x");
+        socket.send_to(&unsendable, relay).await.unwrap();
+        socket.send_to(&query, relay).await.unwrap();
+        let (len, _) = timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let (_, payload) = parse_datagram(&buf[..len]).await.unwrap();
+        assert_eq!(payload, b"dns query");
+    }
+
+    #[tokio::test]
+    async fn test_bind() {
+        let proxy = start(Server::new()).await;
+
+        let mut stream = TcpStream::connect(proxy).await.unwrap();
+        greet(&mut stream, &[METHOD_NO_AUTH]).await;
+        let expected = Address::Ip("127.0.0.1:0".parse().unwrap());
+        let (code, bound) = request(&mut stream, 2, &expected).await;
+        assert_eq!(code, ResponseCode::Success as u8);
+        let bound = match bound {
+            Address::Ip(bound) => bound,
+            other => panic!("bound at {}", other),
+        };
+
+        let mut incoming = TcpStream::connect(bound).await.unwrap();
+        let mut header = [0u8; 3];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[1], ResponseCode::Success as u8);
+        let from = Address::read_from(&mut stream).await.unwrap();
+        assert_eq!(from, Address::Ip(incoming.local_addr().unwrap()));
+
+        incoming.write_all(b"from the server").await.unwrap();
+        let mut received = [0u8; 15];
+        stream.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"from the server");
+        stream.write_all(b"back").await.unwrap();
+        let mut received = [0u8; 4];
+        incoming.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"back");
+    }
+}
@@ -1,37 +1,100 @@
 use std::io;
-use std::net::UdpSocket;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::Duration;
 
-use crate::tftp::{LockStep, Packet, Receiver, Sender};
+use crate::tftp::{LockStep, Mode, Packet, Receiver, Sender, TransferOptions};
+use crate::transfer::{Transfer, DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT};
 
-pub struct Client {}
+pub struct Client {
+    timeout: Duration,
+    max_retries: u32,
+    options: TransferOptions,
+    mode: Mode,
+}
 
 impl Client {
     pub fn new() -> Client {
-        Client {}
+        Client {
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            options: TransferOptions::default(),
+            mode: Mode::Octet,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Client {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Options to request from the server. `tsize` only needs to be `Some`
+    /// to be requested; the actual value is filled in per transfer.
+    pub fn options(mut self, options: TransferOptions) -> Client {
+        self.options = options;
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Client {
+        self.mode = mode;
+        self
     }
 
     pub fn upload(&self, file: &str, to: &str) -> io::Result<()> {
-        let mut sender = Sender::new(file)?;
+        self.put(file, file, to)
+    }
+
+    pub fn download(&self, file: &str, from: &str) -> io::Result<()> {
+        self.get(file, file, from)
+    }
+
+    pub fn put(&self, local: &str, remote: &str, to: &str) -> io::Result<()> {
+        let sender = Sender::new(local)?.mode(self.mode);
+        let mut options = self.options;
+        if options.tsize.is_some() {
+            options.tsize = Some(sender.size());
+        }
         self.execute(
             to,
-            &mut sender,
+            &mut sender.requested(options),
             &Packet::WriteRequest {
-                filename: file.to_owned(),
-                mode: "octet".to_owned(),
+                filename: remote.to_owned(),
+                mode: self.mode.as_str().to_owned(),
+                options,
             },
         )
     }
 
-    pub fn download(&self, file: &str, from: &str) -> io::Result<()> {
-        let mut receiver = Receiver::new(file)?;
+    pub fn get(&self, remote: &str, local: &str, from: &str) -> io::Result<()> {
+        let mut options = self.options;
+        if options.tsize.is_some() {
+            options.tsize = Some(0);
+        }
+        let mut receiver = Receiver::new(local)?.mode(self.mode).requested(options);
         self.execute(
             from,
             &mut receiver,
             &Packet::ReadRequest {
-                filename: file.to_owned(),
-                mode: "octet".to_owned(),
+                filename: remote.to_owned(),
+                mode: self.mode.as_str().to_owned(),
+                options,
             },
-        )
+        )?;
+        match receiver.options().tsize {
+            Some(tsize) if tsize != receiver.received_bytes()? => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected {} bytes but received {}",
+                    tsize,
+                    receiver.received_bytes()?
+                ),
+            )),
+            _ => Ok(()),
+        }
     }
 
     fn execute<T: LockStep>(
@@ -40,19 +103,15 @@ impl Client {
         lock_stepper: &mut T,
         req: &Packet,
     ) -> io::Result<()> {
-        // send initial request and get origin address of response
+        let server = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no server address"))?;
         let socket = UdpSocket::bind("0.0.0.0:0")?;
-        socket.send_to(req.to_bytes().as_slice(), addr)?;
-
-        let mut buf = [0u8; 1024];
-        while !lock_stepper.done() {
-            let (size, org) = socket.recv_from(&mut buf)?;
-            if let Some(packet) = Packet::from(&buf[..size]) {
-                if let Some(reply) = lock_stepper.process(&packet) {
-                    socket.send_to(reply.to_bytes().as_slice(), org)?;
-                }
-            }
-        }
-        Ok(())
+        // the origin address of the first response becomes the peer TID
+        Transfer::unlocked(&socket, server)
+            .timeout(self.timeout)
+            .max_retries(self.max_retries)
+            .run(lock_stepper, req)
     }
 }
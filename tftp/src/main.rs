@@ -1,11 +1,16 @@
+use std::time::Duration;
+
 use anyhow::{anyhow, Error};
 
 use crate::client::Client;
 use crate::server::Server;
+use crate::tftp::{Mode, TransferOptions};
 
 mod client;
+mod netascii;
 mod server;
 mod tftp;
+mod transfer;
 
 const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:34254";
 
@@ -13,21 +18,19 @@ fn main() -> Result<(), Error> {
     let command = std::env::args()
         .nth(1)
         .ok_or_else(|| anyhow!("No command"))?;
-    let filename = std::env::args()
-        .nth(2)
-        .ok_or_else(|| anyhow!("No filename"))?;
+    let argument = std::env::args().nth(2);
+    let filename = || argument.clone().ok_or_else(|| anyhow!("No filename"));
+    let client = || parse_client(std::env::args().skip(3));
     match command.as_ref() {
         "upload" => {
-            Client::new().upload(filename.as_ref(), DEFAULT_SERVER_ADDR)?;
+            client()?.upload(filename()?.as_ref(), DEFAULT_SERVER_ADDR)?;
         }
         "download" => {
-            Client::new().download(filename.as_ref(), DEFAULT_SERVER_ADDR)?;
-        }
-        "send" => {
-            Server::new().send(filename.as_ref(), DEFAULT_SERVER_ADDR)?;
+            client()?.download(filename()?.as_ref(), DEFAULT_SERVER_ADDR)?;
         }
-        "recv" => {
-            Server::new().recv(filename.as_ref(), DEFAULT_SERVER_ADDR)?;
+        "serve" => {
+            let root = argument.clone().unwrap_or_else(|| ".".to_owned());
+            parse_server(root, std::env::args().skip(3))?.serve(DEFAULT_SERVER_ADDR)?;
         }
         "" => {
             println!("no command is given.");
@@ -39,33 +42,166 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
+/// Parse `--netascii`, `--blksize N`, `--windowsize N`, `--timeout SECS`
+/// and `--tsize`, which are negotiated with the server, and `--rexmt MS`
+/// and `--retries N`, which only set how patient the client is.
+fn parse_client<I: Iterator<Item = String>>(mut args: I) -> Result<Client, Error> {
+    let mut client = Client::new();
+    let mut options = TransferOptions::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("No value for {}", arg));
+        match arg.as_ref() {
+            "--rexmt" => client = client.timeout(Duration::from_millis(value()?.parse()?)),
+            "--retries" => client = client.max_retries(value()?.parse()?),
+            "--blksize" => options.blksize = Some(value()?.parse()?),
+            "--windowsize" => options.windowsize = Some(value()?.parse()?),
+            "--timeout" => options.timeout = Some(value()?.parse()?),
+            "--tsize" => options.tsize = Some(0),
+            "--netascii" => client = client.mode(Mode::NetAscii),
+            _ => return Err(anyhow!("invalid option: {}", arg)),
+        }
+    }
+    Ok(client.options(options))
+}
+
+/// Parse `--rexmt MS`, `--retries N` and `--max-windowsize N`.
+fn parse_server<I: Iterator<Item = String>>(root: String, mut args: I) -> Result<Server, Error> {
+    let mut server = Server::new(root);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("No value for {}", arg));
+        match arg.as_ref() {
+            "--rexmt" => server = server.timeout(Duration::from_millis(value()?.parse()?)),
+            "--retries" => server = server.max_retries(value()?.parse()?),
+            "--max-windowsize" => server = server.max_windowsize(value()?.parse()?),
+            _ => return Err(anyhow!("invalid option: {}", arg)),
+        }
+    }
+    Ok(server)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
     use crate::client::Client;
     use crate::server::Server;
-    use crate::DEFAULT_SERVER_ADDR;
+    use crate::tftp::{Mode, TransferOptions};
+
+    fn start(root: &std::path::Path) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let server = Server::new(root).timeout(Duration::from_millis(100));
+        std::thread::spawn(move || server.serve_on(socket));
+        addr
+    }
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(str::to_owned)
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert!(crate::parse_client(args("--rexmt 250 --retries 8 --tsize")).is_ok());
+        assert!(crate::parse_client(args("--retries")).is_err());
+        assert!(crate::parse_server(".".into(), args("--rexmt 250 --max-windowsize 16")).is_ok());
+        assert!(crate::parse_server(".".into(), args("--max-windowsize lots")).is_err());
+        assert!(crate::parse_server(".".into(), args("--verbose")).is_err());
+    }
 
     #[test]
-    #[ignore]
     fn test_download() {
-        let server = std::thread::spawn(|| {
-            let server = Server::new();
-            let _ = server.send("README.md", DEFAULT_SERVER_ADDR);
-        });
-        let client = Client::new();
-        let _ = client.download("README-downloaded.md", DEFAULT_SERVER_ADDR);
-        let _ = server.join();
+        let root = tempfile::tempdir().unwrap();
+        fs::copy("README.md", root.path().join("README.md")).unwrap();
+        let server = start(root.path());
+
+        let local = root.path().join("README-downloaded.md");
+        let client = Client::new().timeout(Duration::from_millis(100));
+        client
+            .get("README.md", local.to_str().unwrap(), &server)
+            .unwrap();
+        assert_eq!(fs::read(local).unwrap(), fs::read("README.md").unwrap());
     }
 
     #[test]
-    #[ignore]
     fn test_upload() {
-        let server = std::thread::spawn(|| {
-            let server = Server::new();
-            let _ = server.recv("README-uploaded.md", DEFAULT_SERVER_ADDR);
-        });
+        let root = tempfile::tempdir().unwrap();
+        let server = start(root.path());
+
+        let client = Client::new().max_retries(3);
+        client
+            .put("README.md", "README-uploaded.md", &server)
+            .unwrap();
+        assert_eq!(
+            fs::read(root.path().join("README-uploaded.md")).unwrap(),
+            fs::read("README.md").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_download_missing() {
+        let root = tempfile::tempdir().unwrap();
+        let server = start(root.path());
+
+        let local = root.path().join("missing-downloaded.txt");
         let client = Client::new();
-        let _ = client.upload("README.md", DEFAULT_SERVER_ADDR);
-        let _ = server.join();
+        assert!(client
+            .get("missing.txt", local.to_str().unwrap(), &server)
+            .is_err());
+    }
+
+    #[test]
+    fn test_negotiated_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+        let image: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let local = root.path().join("firmware.bin");
+        fs::write(&local, &image).unwrap();
+        let server = start(root.path());
+
+        let client = Client::new()
+            .timeout(Duration::from_millis(100))
+            .options(TransferOptions {
+                blksize: Some(1428),
+                tsize: Some(0),
+                timeout: Some(1),
+                windowsize: Some(8),
+            });
+        client
+            .put(local.to_str().unwrap(), "uploaded.bin", &server)
+            .unwrap();
+        let downloaded = root.path().join("downloaded.bin");
+        client
+            .get("uploaded.bin", downloaded.to_str().unwrap(), &server)
+            .unwrap();
+        assert_eq!(fs::read(downloaded).unwrap(), image);
+    }
+
+    #[test]
+    fn test_netascii_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+        let text = b"first line\nsecond\rline\n".repeat(100);
+        let local = root.path().join("notes.txt");
+        fs::write(&local, &text).unwrap();
+        let server = start(root.path());
+
+        let client = Client::new()
+            .timeout(Duration::from_millis(100))
+            .mode(Mode::NetAscii)
+            .options(TransferOptions {
+                tsize: Some(0),
+                ..TransferOptions::default()
+            });
+        client
+            .put(local.to_str().unwrap(), "uploaded.txt", &server)
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(fs::read(root.path().join("uploaded.txt")).unwrap(), text);
+
+        let downloaded = root.path().join("downloaded.txt");
+        client
+            .get("uploaded.txt", downloaded.to_str().unwrap(), &server)
+            .unwrap();
+        assert_eq!(fs::read(downloaded).unwrap(), text);
     }
 }
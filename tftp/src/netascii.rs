@@ -0,0 +1,150 @@
+//! Translation between local text and netascii, the NVT ASCII of RFC 764
+//! where every line ends in CR LF and a bare CR travels as CR NUL.
+
+use std::io::{self, Read};
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const NUL: u8 = 0;
+
+/// Reads local text from `inner` as netascii.
+pub struct Encoder<R> {
+    inner: R,
+    pending: Option<u8>,
+}
+
+impl<R: Read> Encoder<R> {
+    pub fn new(inner: R) -> Encoder<R> {
+        Encoder {
+            inner,
+            pending: None,
+        }
+    }
+}
+
+impl<R: Read> Read for Encoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            if let Some(b) = self.pending.take() {
+                buf[n] = b;
+                n += 1;
+                continue;
+            }
+            let mut byte = [0u8];
+            if self.inner.read(&mut byte)? == 0 {
+                break;
+            }
+            buf[n] = match byte[0] {
+                LF => {
+                    self.pending = Some(LF);
+                    CR
+                }
+                CR => {
+                    self.pending = Some(NUL);
+                    CR
+                }
+                b => b,
+            };
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// Turns netascii back into local text, one DATA block at a time. A CR that
+/// ends a block is held until the next block tells what it stands for.
+#[derive(Default)]
+pub struct Decoder {
+    pending_cr: bool,
+}
+
+impl Decoder {
+    pub fn decode(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut text = Vec::with_capacity(data.len());
+        for &b in data {
+            if self.pending_cr {
+                self.pending_cr = false;
+                match b {
+                    LF => {
+                        text.push(LF);
+                        continue;
+                    }
+                    NUL => {
+                        text.push(CR);
+                        continue;
+                    }
+                    // not valid netascii, keep the CR as it is
+                    _ => text.push(CR),
+                }
+            }
+            if b == CR {
+                self.pending_cr = true;
+            } else {
+                text.push(b);
+            }
+        }
+        text
+    }
+
+    /// Whatever is still held back once the last block has been decoded.
+    pub fn finish(&mut self) -> Vec<u8> {
+        if std::mem::take(&mut self.pending_cr) {
+            vec![CR]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(text: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        Encoder::new(text).read_to_end(&mut encoded).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(b"a\nb\rc\r\n"), b"a\r\nb\r\0c\r\0\r\n");
+        assert_eq!(encode(b""), b"");
+    }
+
+    #[test]
+    fn test_encode_small_reads() {
+        let mut encoder = Encoder::new(&b"\n\n"[..]);
+        let mut buf = [0u8; 1];
+        let mut encoded = Vec::new();
+        while encoder.read(&mut buf).unwrap() > 0 {
+            encoded.push(buf[0]);
+        }
+        assert_eq!(encoded, b"\r\n\r\n");
+    }
+
+    #[test]
+    fn test_decode_across_blocks() {
+        let mut decoder = Decoder::default();
+        assert_eq!(decoder.decode(b"a\r"), b"a");
+        assert_eq!(decoder.decode(b"\nb\r"), b"\nb");
+        assert_eq!(decoder.decode(b"\0c\rx"), b"\rc\rx");
+        assert_eq!(decoder.decode(b"\r"), b"");
+        assert_eq!(decoder.finish(), b"\r");
+        assert_eq!(decoder.finish(), b"");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = b"line one\nline two\r\nbare\rcr\n\n\r\r";
+        let encoded = encode(text);
+        for split in 0..encoded.len() {
+            let mut decoder = Decoder::default();
+            let mut decoded = decoder.decode(&encoded[..split]);
+            decoded.extend(decoder.decode(&encoded[split..]));
+            decoded.extend(decoder.finish());
+            assert_eq!(decoded, text);
+        }
+    }
+}
@@ -1,36 +1,527 @@
 use std::io;
-use std::net::UdpSocket;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
-use crate::tftp::{LockStep, Packet, Receiver, Sender};
+use crate::tftp::{ErrorCode, LockStep, Mode, Packet, Receiver, Sender, TransferOptions};
+use crate::transfer::{Transfer, DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT};
 
-pub struct Server {}
+/// Largest window granted to clients, bounding the DATA held in memory per
+/// transfer to this many blocks.
+const DEFAULT_MAX_WINDOW_SIZE: u16 = 64;
+
+#[derive(Clone)]
+pub struct Server {
+    root: PathBuf,
+    timeout: Duration,
+    max_retries: u32,
+    max_windowsize: u16,
+}
 
 impl Server {
-    pub fn new() -> Server {
-        Server {}
+    pub fn new<P: Into<PathBuf>>(root: P) -> Server {
+        Server {
+            root: root.into(),
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            max_windowsize: DEFAULT_MAX_WINDOW_SIZE,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Server {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Server {
+        self.max_retries = max_retries;
+        self
     }
 
-    pub fn send(&self, file: &str, at: &str) -> std::io::Result<()> {
-        let mut sender = Sender::new(file)?;
-        self.serve(at, &mut sender)
+    pub fn max_windowsize(mut self, max_windowsize: u16) -> Server {
+        self.max_windowsize = max_windowsize;
+        self
     }
 
-    pub fn recv(&self, file: &str, at: &str) -> std::io::Result<()> {
-        let mut receiver = Receiver::new(file)?;
-        self.serve(at, &mut receiver)
+    pub fn serve(&self, at: &str) -> io::Result<()> {
+        self.serve_on(UdpSocket::bind(at)?)
     }
 
-    fn serve<T: LockStep>(&self, addr: &str, lock_stepper: &mut T) -> io::Result<()> {
-        let socket = UdpSocket::bind(addr)?;
+    /// Accept requests on `socket` forever, running every transfer on its own
+    /// thread and ephemeral port.
+    pub fn serve_on(&self, socket: UdpSocket) -> io::Result<()> {
+        let local = socket.local_addr()?;
         let mut buf = [0u8; 1024];
-        while !lock_stepper.done() {
-            let (size, org) = socket.recv_from(&mut buf)?;
-            if let Some(packet) = Packet::from(&buf[..size]) {
-                if let Some(reply) = lock_stepper.process(&packet) {
-                    socket.send_to(reply.to_bytes().as_slice(), org)?;
+        loop {
+            let (size, org) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e),
+            };
+            match Packet::from(&buf[..size]) {
+                Ok(request @ Packet::ReadRequest { .. })
+                | Ok(request @ Packet::WriteRequest { .. }) => {
+                    let server = self.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = server.handle(local, org, &request) {
+                            eprintln!("transfer with {} failed: {}", org, e);
+                        }
+                    });
                 }
+                Ok(_) => {
+                    let stray = Packet::error(ErrorCode::UnknownTid, "unknown transfer ID");
+                    if let Err(e) = socket.send_to(&stray.to_bytes(), org) {
+                        eprintln!("error reply to {} failed: {}", org, e);
+                    }
+                }
+                Err(e) => {
+                    let reply = Packet::error(ErrorCode::IllegalOp, &e.to_string());
+                    if let Err(e) = socket.send_to(&reply.to_bytes(), org) {
+                        eprintln!("error reply to {} failed: {}", org, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle(&self, local: SocketAddr, peer: SocketAddr, request: &Packet) -> io::Result<()> {
+        let socket = UdpSocket::bind(SocketAddr::new(local.ip(), 0))?;
+        let transfer = Transfer::new(&socket, peer)
+            .timeout(self.timeout)
+            .max_retries(self.max_retries);
+
+        let mode = match request {
+            Packet::ReadRequest { mode, .. } | Packet::WriteRequest { mode, .. } => mode,
+            _ => return Ok(()),
+        };
+        let mode = match Mode::parse(mode) {
+            Some(mode) => mode,
+            None => {
+                let msg = format!("unsupported mode {}", mode);
+                transfer.reject(ErrorCode::IllegalOp, &msg)?;
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        };
+
+        match request {
+            Packet::ReadRequest {
+                filename, options, ..
+            } => match self.resolve(filename).and_then(Sender::new) {
+                Ok(sender) => {
+                    let acked = options.accept(Some(sender.size()), self.max_windowsize);
+                    let sender = sender.mode(mode).negotiated(acked);
+                    self.run(transfer, sender, request, acked)
+                }
+                Err(e) => reject(&transfer, &e),
+            },
+            Packet::WriteRequest {
+                filename, options, ..
+            } => match self.resolve(filename).and_then(Receiver::new) {
+                Ok(receiver) => {
+                    let acked = options.accept(None, self.max_windowsize);
+                    let receiver = receiver.mode(mode).negotiated(acked);
+                    self.run(transfer, receiver, request, acked)
+                }
+                Err(e) => reject(&transfer, &e),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Answer the request with an OACK if any option was accepted, or else
+    /// start the transfer right away as plain RFC 1350 does.
+    fn run<T: LockStep>(
+        &self,
+        mut transfer: Transfer,
+        mut lock_stepper: T,
+        request: &Packet,
+        acked: TransferOptions,
+    ) -> io::Result<()> {
+        let first = if acked.is_empty() {
+            lock_stepper.process(request)
+        } else {
+            Some(Packet::OptionAck { options: acked })
+        };
+        match first {
+            Some(first) => transfer.run(&mut lock_stepper, &first),
+            None => transfer.reject(ErrorCode::NotDefined, "cannot start transfer"),
+        }
+    }
+
+    /// Map a requested filename into the root directory, refusing anything
+    /// that would escape it.
+    fn resolve(&self, filename: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(filename);
+        let escapes = relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if filename.is_empty() || escapes {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "path escapes the server root",
+            ));
+        }
+
+        let root = self.root.canonicalize()?;
+        let path = root.join(relative);
+        // follow symlinks of whatever already exists, the file itself for
+        // reads or its directory for writes
+        let existing = if path.exists() {
+            path.canonicalize()?
+        } else {
+            match path.parent() {
+                Some(parent) => parent.canonicalize()?,
+                None => path.clone(),
+            }
+        };
+        if !existing.starts_with(&root) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "path escapes the server root",
+            ));
+        }
+        Ok(path)
+    }
+}
+
+fn reject(transfer: &Transfer, err: &io::Error) -> io::Result<()> {
+    let code = match err.kind() {
+        io::ErrorKind::NotFound => ErrorCode::FileNotFound,
+        io::ErrorKind::PermissionDenied => ErrorCode::AccessViolation,
+        io::ErrorKind::AlreadyExists => ErrorCode::FileAlreadyExists,
+        io::ErrorKind::StorageFull => ErrorCode::DiskFull,
+        _ => ErrorCode::NotDefined,
+    };
+    transfer.reject(code, &err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tftp::{BLOCK_SIZE, MAX_PACKET_SIZE};
+    use std::fs;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn start(root: &Path) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = Server::new(root).timeout(TIMEOUT).max_retries(3);
+        thread::spawn(move || server.serve_on(socket));
+        addr
+    }
+
+    fn client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        socket
+    }
+
+    fn recv(socket: &UdpSocket) -> (Packet, SocketAddr) {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let (size, org) = socket.recv_from(&mut buf).unwrap();
+        (Packet::from(&buf[..size]).unwrap(), org)
+    }
+
+    fn rrq(filename: &str) -> Vec<u8> {
+        Packet::ReadRequest {
+            filename: filename.to_owned(),
+            mode: "octet".to_owned(),
+            options: TransferOptions::default(),
+        }
+        .to_bytes()
+    }
+
+    fn ack(block_num: u16) -> Vec<u8> {
+        Packet::Ack { block_num }.to_bytes()
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Download `filename`, ignoring (dropping) the first copy of every
+    /// block number listed in `drop`.
+    fn download(server: SocketAddr, filename: &str, drop: &[u16]) -> Vec<u8> {
+        let socket = client();
+        socket.send_to(&rrq(filename), server).unwrap();
+        let mut dropped = Vec::new();
+        let mut received = Vec::new();
+        let mut expected = 1;
+        loop {
+            let (packet, tid) = recv(&socket);
+            assert_ne!(tid, server, "transfer must use its own TID");
+            let (block_num, data) = match packet {
+                Packet::Data { block_num, data } => (block_num, data),
+                p => panic!("unexpected {:?}", p),
+            };
+            if drop.contains(&block_num) && !dropped.contains(&block_num) {
+                dropped.push(block_num);
+                continue;
+            }
+            if block_num == expected {
+                received.extend_from_slice(&data);
+                expected += 1;
+            }
+            socket.send_to(&ack(block_num), tid).unwrap();
+            if block_num + 1 == expected && data.len() < BLOCK_SIZE {
+                return received;
             }
         }
-        Ok(())
+    }
+
+    #[test]
+    fn test_download_with_drops() {
+        let root = tempfile::tempdir().unwrap();
+        let data = content(3 * BLOCK_SIZE + 7);
+        fs::write(root.path().join("image.bin"), &data).unwrap();
+        let server = start(root.path());
+
+        assert_eq!(download(server, "image.bin", &[1, 3]), data);
+    }
+
+    #[test]
+    fn test_concurrent_downloads() {
+        let root = tempfile::tempdir().unwrap();
+        let a = content(5 * BLOCK_SIZE);
+        let b = content(2 * BLOCK_SIZE + 100);
+        fs::write(root.path().join("a.bin"), &a).unwrap();
+        fs::write(root.path().join("b.bin"), &b).unwrap();
+        let server = start(root.path());
+
+        let first = thread::spawn(move || download(server, "a.bin", &[2]));
+        let second = thread::spawn(move || download(server, "b.bin", &[]));
+        assert_eq!(second.join().unwrap(), b);
+        assert_eq!(first.join().unwrap(), a);
+    }
+
+    #[test]
+    fn test_upload_with_lost_ack() {
+        let root = tempfile::tempdir().unwrap();
+        let server = start(root.path());
+        let data = content(BLOCK_SIZE + 10);
+
+        let socket = client();
+        let wrq = Packet::WriteRequest {
+            filename: "upload.bin".to_owned(),
+            mode: "octet".to_owned(),
+            options: TransferOptions::default(),
+        };
+        socket.send_to(&wrq.to_bytes(), server).unwrap();
+        let (packet, tid) = recv(&socket);
+        assert_eq!(packet, Packet::Ack { block_num: 0 });
+
+        let blocks: Vec<_> = data.chunks(BLOCK_SIZE).collect();
+        let first = Packet::Data {
+            block_num: 1,
+            data: blocks[0].to_vec(),
+        };
+        socket.send_to(&first.to_bytes(), tid).unwrap();
+        // pretend the ACK was lost: the server must retransmit it
+        assert_eq!(recv(&socket).0, Packet::Ack { block_num: 1 });
+        assert_eq!(recv(&socket).0, Packet::Ack { block_num: 1 });
+
+        let last = Packet::Data {
+            block_num: 2,
+            data: blocks[1].to_vec(),
+        };
+        socket.send_to(&last.to_bytes(), tid).unwrap();
+        assert_eq!(recv(&socket).0, Packet::Ack { block_num: 2 });
+        // the final ACK is lost too, so the last block is sent again while
+        // the server is still dallying
+        socket.send_to(&last.to_bytes(), tid).unwrap();
+        assert_eq!(recv(&socket).0, Packet::Ack { block_num: 2 });
+
+        thread::sleep(TIMEOUT * 3);
+        assert_eq!(fs::read(root.path().join("upload.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn test_unknown_tid() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a.bin"), content(2 * BLOCK_SIZE)).unwrap();
+        let server = start(root.path());
+
+        let socket = client();
+        socket.send_to(&rrq("a.bin"), server).unwrap();
+        let (_, tid) = recv(&socket);
+
+        let stray = client();
+        stray.send_to(&ack(1), tid).unwrap();
+        assert_eq!(
+            recv(&stray).0,
+            Packet::error(ErrorCode::UnknownTid, "unknown transfer ID")
+        );
+
+        stray.send_to(&ack(1), server).unwrap();
+        assert_eq!(
+            recv(&stray).0,
+            Packet::error(ErrorCode::UnknownTid, "unknown transfer ID")
+        );
+    }
+
+    #[test]
+    fn test_gives_up_after_retries() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a.bin"), content(10)).unwrap();
+        let server = start(root.path());
+
+        let socket = client();
+        socket.send_to(&rrq("a.bin"), server).unwrap();
+        // the original DATA plus three retransmissions, then silence
+        for _ in 0..4 {
+            assert!(matches!(recv(&socket).0, Packet::Data { block_num: 1, .. }));
+        }
+        socket.set_read_timeout(Some(TIMEOUT * 3)).unwrap();
+        let mut buf = [0u8; 1024];
+        assert!(socket.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_path_traversal() {
+        let parent = tempfile::tempdir().unwrap();
+        let root = parent.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(parent.path().join("secret.txt"), b"secret").unwrap();
+        let server = start(&root);
+
+        for filename in &["../secret.txt", "/etc/passwd", "sub/../../secret.txt"] {
+            let socket = client();
+            socket.send_to(&rrq(filename), server).unwrap();
+            match recv(&socket).0 {
+                Packet::Error { error_code, .. } => {
+                    assert_eq!(error_code, ErrorCode::AccessViolation as u16)
+                }
+                p => panic!("unexpected {:?}", p),
+            }
+        }
+
+        let socket = client();
+        socket.send_to(&rrq("missing.txt"), server).unwrap();
+        match recv(&socket).0 {
+            Packet::Error { error_code, .. } => {
+                assert_eq!(error_code, ErrorCode::FileNotFound as u16)
+            }
+            p => panic!("unexpected {:?}", p),
+        }
+    }
+
+    #[test]
+    fn test_option_negotiation() {
+        let root = tempfile::tempdir().unwrap();
+        let data = content(10 * 1024 + 1);
+        fs::write(root.path().join("image.bin"), &data).unwrap();
+        let server = start(root.path());
+
+        let socket = client();
+        let request = Packet::ReadRequest {
+            filename: "image.bin".to_owned(),
+            mode: "octet".to_owned(),
+            options: TransferOptions {
+                blksize: Some(1024),
+                tsize: Some(0),
+                timeout: Some(1),
+                windowsize: Some(1000),
+            },
+        };
+        socket.send_to(&request.to_bytes(), server).unwrap();
+        let (packet, tid) = recv(&socket);
+        assert_eq!(
+            packet,
+            Packet::OptionAck {
+                options: TransferOptions {
+                    blksize: Some(1024),
+                    tsize: Some(data.len() as u64),
+                    timeout: Some(1),
+                    windowsize: Some(DEFAULT_MAX_WINDOW_SIZE),
+                },
+            }
+        );
+
+        // the whole file fits in one window, sent without waiting for ACKs
+        socket.send_to(&ack(0), tid).unwrap();
+        let mut received = Vec::new();
+        for block_num in 1..=11 {
+            match recv(&socket).0 {
+                Packet::Data { block_num: n, data } => {
+                    assert_eq!(n, block_num);
+                    received.extend_from_slice(&data);
+                }
+                p => panic!("unexpected {:?}", p),
+            }
+        }
+        assert_eq!(received, data);
+        socket.send_to(&ack(11), tid).unwrap();
+    }
+
+    #[test]
+    fn test_write_request_options() {
+        let root = tempfile::tempdir().unwrap();
+        let server = start(root.path());
+
+        let socket = client();
+        let options = TransferOptions {
+            blksize: Some(16),
+            tsize: Some(20),
+            ..TransferOptions::default()
+        };
+        let request = Packet::WriteRequest {
+            filename: "small.bin".to_owned(),
+            mode: "octet".to_owned(),
+            options,
+        };
+        socket.send_to(&request.to_bytes(), server).unwrap();
+        let (packet, tid) = recv(&socket);
+        assert_eq!(packet, Packet::OptionAck { options });
+
+        let data = content(20);
+        for (i, chunk) in data.chunks(16).enumerate() {
+            let block_num = i as u16 + 1;
+            let packet = Packet::Data {
+                block_num,
+                data: chunk.to_vec(),
+            };
+            socket.send_to(&packet.to_bytes(), tid).unwrap();
+            assert_eq!(recv(&socket).0, Packet::Ack { block_num });
+        }
+        thread::sleep(TIMEOUT * 3);
+        assert_eq!(fs::read(root.path().join("small.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rejects_unknown_mode_and_malformed_requests() {
+        let root = tempfile::tempdir().unwrap();
+        let server = start(root.path());
+
+        let socket = client();
+        let request = Packet::WriteRequest {
+            filename: "letter.txt".to_owned(),
+            mode: "mail".to_owned(),
+            options: TransferOptions::default(),
+        };
+        socket.send_to(&request.to_bytes(), server).unwrap();
+        assert_eq!(
+            recv(&socket).0,
+            Packet::error(ErrorCode::IllegalOp, "unsupported mode mail")
+        );
+        assert!(!root.path().join("letter.txt").exists());
+
+        socket.send_to(b"\x00\x01truncated", server).unwrap();
+        assert_eq!(
+            recv(&socket).0,
+            Packet::error(ErrorCode::IllegalOp, "unterminated string")
+        );
+        socket.send_to(b"\x00", server).unwrap();
+        assert_eq!(
+            recv(&socket).0,
+            Packet::error(ErrorCode::IllegalOp, "truncated packet")
+        );
+
+        // the server is still up
+        fs::write(root.path().join("a.bin"), content(10)).unwrap();
+        assert_eq!(download(server, "a.bin", &[]), content(10));
     }
 }
@@ -1,8 +1,18 @@
 use bytes::{Buf, BufMut, BytesMut};
+use std::collections::VecDeque;
+use std::fmt;
 use std::fs::File;
-use std::io::{Cursor, Read, Write};
+use std::io::{BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::time::Duration;
 
-const BLOCK_SIZE: usize = 512;
+use crate::netascii;
+
+pub const BLOCK_SIZE: usize = 512;
+/// Largest block size allowed by RFC 2348.
+pub const MAX_BLOCK_SIZE: u16 = 65464;
+/// Largest datagram any negotiated transfer can produce.
+pub const MAX_PACKET_SIZE: usize = 4 + MAX_BLOCK_SIZE as usize;
 
 enum OpCode {
     Rrq = 1,
@@ -10,6 +20,7 @@ enum OpCode {
     Data,
     Ack,
     Error,
+    Oack,
     Invalid,
 }
 
@@ -21,13 +32,14 @@ impl From<u16> for OpCode {
             3 => OpCode::Data,
             4 => OpCode::Ack,
             5 => OpCode::Error,
+            6 => OpCode::Oack,
             _ => OpCode::Invalid,
         }
     }
 }
 
-#[allow(dead_code)]
-enum ErrorCode {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
     NotDefined = 0,
     FileNotFound = 1,
     AccessViolation = 2,
@@ -36,66 +48,281 @@ enum ErrorCode {
     UnknownTid = 5,
     FileAlreadyExists = 6,
     NoSuchUser = 7,
+    OptionNegotiation = 8,
+}
+
+impl ErrorCode {
+    pub fn from_u16(code: u16) -> Option<ErrorCode> {
+        match code {
+            0 => Some(ErrorCode::NotDefined),
+            1 => Some(ErrorCode::FileNotFound),
+            2 => Some(ErrorCode::AccessViolation),
+            3 => Some(ErrorCode::DiskFull),
+            4 => Some(ErrorCode::IllegalOp),
+            5 => Some(ErrorCode::UnknownTid),
+            6 => Some(ErrorCode::FileAlreadyExists),
+            7 => Some(ErrorCode::NoSuchUser),
+            8 => Some(ErrorCode::OptionNegotiation),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            ErrorCode::NotDefined => "not defined",
+            ErrorCode::FileNotFound => "file not found",
+            ErrorCode::AccessViolation => "access violation",
+            ErrorCode::DiskFull => "disk full or allocation exceeded",
+            ErrorCode::IllegalOp => "illegal TFTP operation",
+            ErrorCode::UnknownTid => "unknown transfer ID",
+            ErrorCode::FileAlreadyExists => "file already exists",
+            ErrorCode::NoSuchUser => "no such user",
+            ErrorCode::OptionNegotiation => "option negotiation failed",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Transfer modes of RFC 1350, short of the obsolete "mail".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    NetAscii,
+    Octet,
+}
+
+impl Mode {
+    pub fn parse(mode: &str) -> Option<Mode> {
+        match mode.to_ascii_lowercase().as_ref() {
+            "netascii" => Some(Mode::NetAscii),
+            "octet" => Some(Mode::Octet),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::NetAscii => "netascii",
+            Mode::Octet => "octet",
+        }
+    }
+}
+
+/// Options of RFC 2347 carried by RRQ, WRQ and OACK packets. Unknown or
+/// malformed options are dropped while parsing, as the RFC allows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransferOptions {
+    /// RFC 2348
+    pub blksize: Option<u16>,
+    /// RFC 2349
+    pub tsize: Option<u64>,
+    /// RFC 2349, in seconds
+    pub timeout: Option<u8>,
+    /// RFC 7440
+    pub windowsize: Option<u16>,
+}
+
+impl TransferOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == TransferOptions::default()
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.blksize.map_or(BLOCK_SIZE, usize::from)
+    }
+
+    pub fn window_size(&self) -> usize {
+        self.windowsize.map_or(1, usize::from)
+    }
+
+    /// The options a server acknowledges for this request. `tsize` is the
+    /// size of the file being read, or `None` to echo the client's value.
+    pub fn accept(&self, tsize: Option<u64>, max_windowsize: u16) -> TransferOptions {
+        TransferOptions {
+            blksize: self.blksize.map(|b| b.min(MAX_BLOCK_SIZE)),
+            tsize: self.tsize.map(|t| tsize.unwrap_or(t)),
+            timeout: self.timeout,
+            windowsize: self.windowsize.map(|w| w.min(max_windowsize)),
+        }
+    }
+
+    /// Whether an OACK answering a request for `self` is acceptable: it may
+    /// only shrink what was asked for, never add to it.
+    pub fn allows(&self, acked: &TransferOptions) -> bool {
+        fn within<T: PartialOrd>(requested: Option<T>, acked: Option<T>) -> bool {
+            match (requested, acked) {
+                (_, None) => true,
+                (Some(r), Some(a)) => a <= r,
+                (None, Some(_)) => false,
+            }
+        }
+        within(self.blksize, acked.blksize)
+            && within(self.windowsize, acked.windowsize)
+            && (acked.timeout.is_none() || acked.timeout == self.timeout)
+            && (acked.tsize.is_none() || self.tsize.is_some())
+    }
+
+    fn parse(cursor: &mut Cursor<&[u8]>) -> Result<TransferOptions, ParseError> {
+        let mut options = TransferOptions::default();
+        while cursor.has_remaining() {
+            let name = read_cstr(cursor)?.to_ascii_lowercase();
+            let value = read_cstr(cursor)?;
+            match name.as_ref() {
+                "blksize" => {
+                    options.blksize = value
+                        .parse()
+                        .ok()
+                        .filter(|b| (8..=MAX_BLOCK_SIZE).contains(b))
+                }
+                "tsize" => options.tsize = value.parse().ok(),
+                "timeout" => options.timeout = value.parse().ok().filter(|t| *t >= 1),
+                "windowsize" => options.windowsize = value.parse().ok().filter(|w| *w >= 1),
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+
+    fn write(&self, buf: &mut BytesMut) {
+        let mut put = |name: &str, value: String| {
+            put_cstr(buf, name);
+            put_cstr(buf, &value);
+        };
+        if let Some(blksize) = self.blksize {
+            put("blksize", blksize.to_string());
+        }
+        if let Some(tsize) = self.tsize {
+            put("tsize", tsize.to_string());
+        }
+        if let Some(timeout) = self.timeout {
+            put("timeout", timeout.to_string());
+        }
+        if let Some(windowsize) = self.windowsize {
+            put("windowsize", windowsize.to_string());
+        }
+    }
+}
+
+/// Why a datagram could not be decoded into a `Packet`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    /// The datagram ended in the middle of a fixed-size field.
+    Truncated,
+    /// A string field is missing its terminating NUL.
+    Unterminated,
+    UnknownOpCode(u16),
 }
 
-#[derive(PartialEq, Debug)]
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Truncated => write!(f, "truncated packet"),
+            ParseError::Unterminated => write!(f, "unterminated string"),
+            ParseError::UnknownOpCode(op) => write!(f, "unknown opcode {}", op),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Packet {
-    ReadRequest { filename: String, mode: String },
-    WriteRequest { filename: String, mode: String },
-    Data { block_num: u16, data: Vec<u8> },
-    Ack { block_num: u16 },
-    Error { error_code: u16, error_msg: String },
+    ReadRequest {
+        filename: String,
+        mode: String,
+        options: TransferOptions,
+    },
+    WriteRequest {
+        filename: String,
+        mode: String,
+        options: TransferOptions,
+    },
+    Data {
+        block_num: u16,
+        data: Vec<u8>,
+    },
+    Ack {
+        block_num: u16,
+    },
+    Error {
+        error_code: u16,
+        error_msg: String,
+    },
+    OptionAck {
+        options: TransferOptions,
+    },
 }
 
 impl Packet {
-    pub fn from(payload: &[u8]) -> Option<Packet> {
+    pub fn error(code: ErrorCode, msg: &str) -> Packet {
+        Packet::Error {
+            error_code: code as u16,
+            error_msg: msg.to_owned(),
+        }
+    }
+
+    pub fn from(payload: &[u8]) -> Result<Packet, ParseError> {
         let mut cursor = Cursor::new(payload);
-        match OpCode::from(cursor.get_u16()) {
-            OpCode::Rrq => Some(Packet::ReadRequest {
-                filename: read_cstr(&mut cursor),
-                mode: read_cstr(&mut cursor),
+        match OpCode::from(read_u16(&mut cursor)?) {
+            OpCode::Rrq => Ok(Packet::ReadRequest {
+                filename: read_cstr(&mut cursor)?,
+                mode: read_cstr(&mut cursor)?,
+                options: TransferOptions::parse(&mut cursor)?,
             }),
-            OpCode::Wrq => Some(Packet::WriteRequest {
-                filename: read_cstr(&mut cursor),
-                mode: read_cstr(&mut cursor),
+            OpCode::Wrq => Ok(Packet::WriteRequest {
+                filename: read_cstr(&mut cursor)?,
+                mode: read_cstr(&mut cursor)?,
+                options: TransferOptions::parse(&mut cursor)?,
             }),
-            OpCode::Data => Some(Packet::Data {
-                block_num: cursor.get_u16(),
+            OpCode::Data => Ok(Packet::Data {
+                block_num: read_u16(&mut cursor)?,
                 data: {
                     let mut vec = Vec::<u8>::new();
                     let _ = cursor.read_to_end(&mut vec);
                     vec
                 },
             }),
-            OpCode::Ack => Some(Packet::Ack {
-                block_num: cursor.get_u16(),
+            OpCode::Ack => Ok(Packet::Ack {
+                block_num: read_u16(&mut cursor)?,
             }),
-            OpCode::Error => Some(Packet::Error {
-                error_code: cursor.get_u16(),
-                error_msg: read_cstr(&mut cursor),
+            OpCode::Error => Ok(Packet::Error {
+                error_code: read_u16(&mut cursor)?,
+                error_msg: read_cstr(&mut cursor)?,
             }),
-            _ => None,
+            OpCode::Oack => Ok(Packet::OptionAck {
+                options: TransferOptions::parse(&mut cursor)?,
+            }),
+            OpCode::Invalid => Err(ParseError::UnknownOpCode(u16::from_be_bytes([
+                payload[0], payload[1],
+            ]))),
         }
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf;
         match self {
-            Packet::ReadRequest { filename, mode } => {
+            Packet::ReadRequest {
+                filename,
+                mode,
+                options,
+            } => {
                 buf = BytesMut::with_capacity(128);
                 buf.put_u16(OpCode::Rrq as u16);
-                filename.bytes().for_each(|b| buf.put_u8(b));
-                buf.put_u8(0u8);
-                mode.bytes().for_each(|b| buf.put_u8(b));
-                buf.put_u8(0u8);
+                put_cstr(&mut buf, filename);
+                put_cstr(&mut buf, mode);
+                options.write(&mut buf);
             }
-            Packet::WriteRequest { filename, mode } => {
+            Packet::WriteRequest {
+                filename,
+                mode,
+                options,
+            } => {
                 buf = BytesMut::with_capacity(128);
                 buf.put_u16(OpCode::Wrq as u16);
-                filename.bytes().for_each(|b| buf.put_u8(b));
-                buf.put_u8(0u8);
-                mode.bytes().for_each(|b| buf.put_u8(b));
-                buf.put_u8(0u8);
+                put_cstr(&mut buf, filename);
+                put_cstr(&mut buf, mode);
+                options.write(&mut buf);
             }
             Packet::Data { block_num, data } => {
                 buf = BytesMut::with_capacity(4);
@@ -115,43 +342,162 @@ impl Packet {
                 buf = BytesMut::with_capacity(128);
                 buf.put_u16(OpCode::Error as u16);
                 buf.put_u16(*error_code);
-                error_msg.bytes().for_each(|b| buf.put_u8(b));
-                buf.put_u8(0u8);
+                put_cstr(&mut buf, error_msg);
+            }
+            Packet::OptionAck { options } => {
+                buf = BytesMut::with_capacity(128);
+                buf.put_u16(OpCode::Oack as u16);
+                options.write(&mut buf);
             }
         }
         buf.freeze().to_vec()
     }
 }
 
-fn read_cstr(cursor: &mut Cursor<&[u8]>) -> String {
+fn read_u16(cursor: &mut Cursor<&[u8]>) -> Result<u16, ParseError> {
+    if cursor.remaining() < 2 {
+        return Err(ParseError::Truncated);
+    }
+    Ok(cursor.get_u16())
+}
+
+fn read_cstr(cursor: &mut Cursor<&[u8]>) -> Result<String, ParseError> {
     let mut cstr = String::new();
     loop {
+        if !cursor.has_remaining() {
+            return Err(ParseError::Unterminated);
+        }
         let b = cursor.get_u8();
         if b == 0u8 {
             break;
         }
         cstr.push(b as char);
     }
-    cstr
+    Ok(cstr)
+}
+
+fn put_cstr(buf: &mut BytesMut, s: &str) {
+    s.bytes().for_each(|b| buf.put_u8(b));
+    buf.put_u8(0u8);
 }
 
 pub trait LockStep {
     fn process(&mut self, packet: &Packet) -> Option<Packet>;
     fn done(&self) -> bool;
+
+    /// Packets that may follow the reply of `process` without waiting for
+    /// the peer, e.g. the rest of a window of DATA (RFC 7440).
+    fn next_packet(&mut self) -> Option<Packet> {
+        None
+    }
+
+    /// Retransmission timeout negotiated with the peer, if any.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether to linger after `done()` so a lost final ACK can be re-sent
+    /// when the peer retransmits its last packet (RFC 1350, section 6).
+    fn dally(&self) -> bool {
+        false
+    }
+}
+
+/// Options a client asked for, checked against the server's OACK. The
+/// error packet is the reply refusing the OACK.
+fn negotiate(requested: &TransferOptions, acked: &TransferOptions) -> Result<(), Packet> {
+    if requested.allows(acked) {
+        Ok(())
+    } else {
+        Err(Packet::error(
+            ErrorCode::OptionNegotiation,
+            "unacceptable option acknowledgement",
+        ))
+    }
+}
+
+fn timeout_of(options: &TransferOptions) -> Option<Duration> {
+    options.timeout.map(|t| Duration::from_secs(u64::from(t)))
 }
 
 pub struct Receiver {
-    current_block: u16,
+    options: TransferOptions,
+    requested: TransferOptions,
+    /// Number of blocks written so far; the block number on the wire is this
+    /// value truncated to 16 bits, rolling over to 0 after 65535.
+    received: u64,
+    since_ack: usize,
+    nacked: bool,
     done: bool,
     file: std::fs::File,
+    decoder: Option<netascii::Decoder>,
 }
 
 impl Receiver {
-    pub fn new(path: &str) -> std::io::Result<Receiver> {
+    pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Receiver> {
         Ok(Receiver {
-            current_block: 0,
+            options: TransferOptions::default(),
+            requested: TransferOptions::default(),
+            received: 0,
+            since_ack: 0,
+            nacked: false,
             done: false,
             file: File::create(path)?,
+            decoder: None,
+        })
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Receiver {
+        self.decoder = match mode {
+            Mode::NetAscii => Some(netascii::Decoder::default()),
+            Mode::Octet => None,
+        };
+        self
+    }
+
+    /// Use options already agreed on, as a server does after sending OACK.
+    pub fn negotiated(mut self, options: TransferOptions) -> Receiver {
+        self.options = options;
+        self
+    }
+
+    /// Options asked for in the request, adopted once the server OACKs them.
+    pub fn requested(mut self, options: TransferOptions) -> Receiver {
+        self.requested = options;
+        self
+    }
+
+    pub fn options(&self) -> &TransferOptions {
+        &self.options
+    }
+
+    /// Number of bytes written so far.
+    pub fn received_bytes(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn current_block(&self) -> u16 {
+        self.received as u16
+    }
+
+    /// Write a block to the file, decoding it first in netascii mode.
+    fn write_block(&mut self, data: &[u8], last: bool) -> std::io::Result<()> {
+        match &mut self.decoder {
+            Some(decoder) => {
+                self.file.write_all(&decoder.decode(data))?;
+                if last {
+                    self.file.write_all(&decoder.finish())?;
+                }
+                Ok(())
+            }
+            None => self.file.write_all(data),
+        }
+    }
+
+    fn ack_current(&mut self) -> Option<Packet> {
+        self.since_ack = 0;
+        Some(Packet::Ack {
+            block_num: self.current_block(),
         })
     }
 }
@@ -160,19 +506,50 @@ impl LockStep for Receiver {
     fn process(&mut self, packet: &Packet) -> Option<Packet> {
         match packet {
             Packet::WriteRequest { .. } => Some(Packet::Ack { block_num: 0 }),
+            Packet::OptionAck { options } if self.received == 0 => {
+                if let Err(reply) = negotiate(&self.requested, options) {
+                    self.done = true;
+                    return Some(reply);
+                }
+                self.options = *options;
+                Some(Packet::Ack { block_num: 0 })
+            }
             Packet::Data { block_num, data } => {
-                if *block_num != self.current_block + 1 {
+                let windowed = self.options.window_size() > 1;
+                if *block_num != self.current_block().wrapping_add(1)
+                    || data.len() > self.options.block_size()
+                {
+                    // with a window, tell the sender once where to resume;
+                    // in lock step just re-ACK a retransmitted block
+                    if (*block_num == self.current_block() && !windowed)
+                        || (windowed && !self.nacked)
+                    {
+                        self.nacked = true;
+                        return self.ack_current();
+                    }
                     return None;
                 }
-                self.current_block = *block_num;
-                let _ = self.file.write(data);
-                if data.len() < BLOCK_SIZE {
+                self.received += 1;
+                self.since_ack += 1;
+                self.nacked = false;
+                let last = data.len() < self.options.block_size();
+                if let Err(err) = self.write_block(data, last) {
+                    // never ACK a block that didn't make it to disk
                     self.done = true;
+                    let code = match err.kind() {
+                        std::io::ErrorKind::StorageFull => ErrorCode::DiskFull,
+                        _ => ErrorCode::NotDefined,
+                    };
+                    return Some(Packet::error(code, &err.to_string()));
                 }
-
-                Some(Packet::Ack {
-                    block_num: *block_num,
-                })
+                if last {
+                    self.done = true;
+                    return self.ack_current();
+                }
+                if self.since_ack >= self.options.window_size() {
+                    return self.ack_current();
+                }
+                None
             }
             _ => None,
         }
@@ -181,54 +558,145 @@ impl LockStep for Receiver {
     fn done(&self) -> bool {
         self.done
     }
+
+    fn timeout(&self) -> Option<Duration> {
+        timeout_of(&self.options)
+    }
+
+    fn dally(&self) -> bool {
+        true
+    }
 }
 
 pub struct Sender {
-    current_block: u16,
+    options: TransferOptions,
+    requested: TransferOptions,
+    started: bool,
+    /// Number of blocks acknowledged by the receiver.
+    acked: u64,
+    /// Blocks read but not yet acknowledged; `window[0]` is block `acked + 1`.
+    window: VecDeque<Vec<u8>>,
+    /// Index into `window` of the next block to send.
+    pending: usize,
+    eof: bool,
     done: bool,
-    file: std::fs::File,
+    size: u64,
+    mode: Mode,
+    source: Box<dyn Read>,
 }
 
 impl Sender {
-    pub fn new(path: &str) -> std::io::Result<Sender> {
+    pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Sender> {
+        let file = File::open(path)?;
         Ok(Sender {
-            current_block: 0,
+            options: TransferOptions::default(),
+            requested: TransferOptions::default(),
+            started: false,
+            acked: 0,
+            window: VecDeque::new(),
+            pending: 0,
+            eof: false,
             done: false,
-            file: File::open(path)?,
+            size: file.metadata()?.len(),
+            mode: Mode::Octet,
+            source: Box::new(file),
         })
     }
 
-    pub fn next_block(&mut self) -> std::io::Result<Packet> {
-        let mut data = vec![0; BLOCK_SIZE];
-        let size = self.file.read(data.as_mut_slice())?;
-        self.current_block += 1;
-        if size < BLOCK_SIZE {
-            data.truncate(size);
+    pub fn mode(mut self, mode: Mode) -> Sender {
+        if mode == Mode::NetAscii && self.mode == Mode::Octet {
+            let file = std::mem::replace(&mut self.source, Box::new(std::io::empty()));
+            self.source = Box::new(netascii::Encoder::new(BufReader::new(file)));
+        }
+        self.mode = mode;
+        self
+    }
+
+    /// Use options already agreed on, as a server does after sending OACK.
+    pub fn negotiated(mut self, options: TransferOptions) -> Sender {
+        self.options = options;
+        self
+    }
+
+    /// Options asked for in the request, adopted once the server OACKs them.
+    pub fn requested(mut self, options: TransferOptions) -> Sender {
+        self.requested = options;
+        self
+    }
+
+    /// Size of the file being sent, before any netascii translation.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Read blocks until the window is full or the file is exhausted.
+    fn fill_window(&mut self) -> std::io::Result<()> {
+        let block_size = self.options.block_size();
+        while self.window.len() < self.options.window_size() && !self.eof {
+            let mut data = Vec::with_capacity(block_size);
+            (&mut self.source)
+                .take(block_size as u64)
+                .read_to_end(&mut data)?;
+            if data.len() < block_size {
+                self.eof = true;
+            }
+            self.window.push_back(data);
+        }
+        Ok(())
+    }
+
+    /// Start (or restart) sending the window from its first block, or end
+    /// the transfer with an ERROR if the file can't be read.
+    fn send_window(&mut self) -> Option<Packet> {
+        if let Err(err) = self.fill_window() {
             self.done = true;
+            let code = match err.kind() {
+                std::io::ErrorKind::PermissionDenied => ErrorCode::AccessViolation,
+                _ => ErrorCode::NotDefined,
+            };
+            return Some(Packet::error(code, &err.to_string()));
         }
-        Ok(Packet::Data {
-            block_num: self.current_block,
-            data,
-        })
+        self.pending = 0;
+        self.next_packet()
     }
 }
 
 impl LockStep for Sender {
     fn process(&mut self, packet: &Packet) -> Option<Packet> {
         match packet {
-            Packet::ReadRequest { .. } => {
-                if let Ok(packet) = self.next_block() {
-                    return Some(packet);
+            Packet::ReadRequest { .. } if !self.started => {
+                self.started = true;
+                self.send_window()
+            }
+            Packet::OptionAck { options } if !self.started => {
+                if let Err(reply) = negotiate(&self.requested, options) {
+                    self.done = true;
+                    return Some(reply);
                 }
-                None
+                self.options = *options;
+                self.started = true;
+                self.send_window()
+            }
+            Packet::Ack { block_num } if !self.started => {
+                if *block_num != 0 {
+                    return None;
+                }
+                self.started = true;
+                self.send_window()
             }
             Packet::Ack { block_num } => {
-                if *block_num == self.current_block {
-                    if let Ok(packet) = self.next_block() {
-                        return Some(packet);
-                    }
+                // how far into the window the receiver got; an ACK that
+                // doesn't advance is a duplicate and must not trigger a
+                // retransmission (the Sorcerer's Apprentice bug)
+                let advanced = (1..=self.window.len())
+                    .find(|i| (self.acked + *i as u64) as u16 == *block_num)?;
+                self.window.drain(..advanced);
+                self.acked += advanced as u64;
+                if self.window.is_empty() && self.eof {
+                    self.done = true;
+                    return None;
                 }
-                None
+                self.send_window()
             }
             _ => None,
         }
@@ -237,11 +705,25 @@ impl LockStep for Sender {
     fn done(&self) -> bool {
         self.done
     }
+
+    fn next_packet(&mut self) -> Option<Packet> {
+        let data = self.window.get(self.pending)?.clone();
+        self.pending += 1;
+        Some(Packet::Data {
+            block_num: (self.acked + self.pending as u64) as u16,
+            data,
+        })
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        timeout_of(&self.options)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     const INPUT: &str = "rfc1350.txt";
     const OUTPUT: &str = "rfc1350-received.txt";
@@ -256,9 +738,10 @@ mod tests {
         ];
         assert_eq!(
             Packet::from(&bytes),
-            Some(Packet::ReadRequest {
+            Ok(Packet::ReadRequest {
                 filename: INPUT.to_owned(),
                 mode: MODE.to_owned(),
+                options: TransferOptions::default(),
             })
         );
 
@@ -268,36 +751,49 @@ mod tests {
         ];
         assert_eq!(
             Packet::from(&bytes),
-            Some(Packet::WriteRequest {
+            Ok(Packet::WriteRequest {
                 filename: INPUT.to_owned(),
                 mode: MODE.to_owned(),
+                options: TransferOptions::default(),
             })
         );
 
         let bytes = [00u8, 0x03, 0x00, 0x01, 0x0a, 0x0a];
         assert_eq!(
             Packet::from(&bytes),
-            Some(Packet::Data {
+            Ok(Packet::Data {
                 block_num: 1,
                 data: vec![0x0a, 0x0a],
             })
         );
 
         let bytes = [00u8, 0x04, 0x00, 0x01];
-        assert_eq!(Packet::from(&bytes), Some(Packet::Ack { block_num: 1 }));
+        assert_eq!(Packet::from(&bytes), Ok(Packet::Ack { block_num: 1 }));
 
         let bytes = [
             00u8, 0x05, 0x00, 0x02, 0x64, 0x65, 0x6e, 0x69, 0x65, 0x64, 0x00,
         ];
         assert_eq!(
             Packet::from(&bytes),
-            Some(Packet::Error {
+            Ok(Packet::Error {
                 error_code: 2,
                 error_msg: "denied".to_owned(),
             })
         );
     }
 
+    #[test]
+    fn test_error_code_round_trip() {
+        for code in 0..=8u16 {
+            assert_eq!(ErrorCode::from_u16(code).unwrap() as u16, code);
+        }
+        assert_eq!(ErrorCode::from_u16(9), None);
+        assert_eq!(
+            ErrorCode::DiskFull.to_string(),
+            "disk full or allocation exceeded"
+        );
+    }
+
     #[test]
     fn test_packet_serialization() {
         let bytes = [
@@ -308,6 +804,7 @@ mod tests {
             Packet::ReadRequest {
                 filename: INPUT.to_owned(),
                 mode: MODE.to_owned(),
+                options: TransferOptions::default(),
             }
             .to_bytes(),
             bytes
@@ -321,6 +818,7 @@ mod tests {
             Packet::WriteRequest {
                 filename: INPUT.to_owned(),
                 mode: MODE.to_owned(),
+                options: TransferOptions::default(),
             }
             .to_bytes(),
             bytes
@@ -389,6 +887,54 @@ mod tests {
                 data: CONTENT.to_vec()
             })
         );
+        assert!(!sender.done());
+
+        let reply = sender.process(&Packet::Ack { block_num: 1 });
+        assert_eq!(reply, None);
+        assert!(sender.done());
+    }
+
+    #[test]
+    fn test_receiver_reacks_duplicate() {
+        let mut receiver = Receiver::new("rfc1350-duplicate.txt").unwrap();
+        let data = Packet::Data {
+            block_num: 1,
+            data: vec![0u8; BLOCK_SIZE],
+        };
+        assert_eq!(receiver.process(&data), Some(Packet::Ack { block_num: 1 }));
+        assert_eq!(receiver.process(&data), Some(Packet::Ack { block_num: 1 }));
+        assert!(!receiver.done());
+        let _ = std::fs::remove_file("rfc1350-duplicate.txt");
+    }
+
+    #[test]
+    fn test_receiver_reports_failed_write() {
+        for mode in [Mode::Octet, Mode::NetAscii] {
+            let mut receiver = Receiver::new("/dev/full").unwrap().mode(mode);
+            let reply = receiver.process(&Packet::Data {
+                block_num: 1,
+                data: b"line\r\n".to_vec(),
+            });
+            match reply {
+                Some(Packet::Error { error_code, .. }) => {
+                    assert_eq!(error_code, ErrorCode::DiskFull as u16)
+                }
+                other => panic!("expected ERROR, got {:?}", other),
+            }
+            assert!(receiver.done());
+        }
+    }
+
+    #[test]
+    fn test_sender_reports_failed_read() {
+        // a directory opens fine but can't be read
+        let mut sender = Sender::new("/").unwrap();
+        match sender.process(&Packet::Ack { block_num: 0 }) {
+            Some(Packet::Error { error_code, .. }) => {
+                assert_eq!(error_code, ErrorCode::NotDefined as u16)
+            }
+            other => panic!("expected ERROR, got {:?}", other),
+        }
         assert!(sender.done());
     }
 
@@ -400,6 +946,7 @@ mod tests {
         let reply = receiver.process(&Packet::WriteRequest {
             filename: OUTPUT.to_string(),
             mode: MODE.to_string(),
+            options: TransferOptions::default(),
         });
         assert_eq!(reply, Some(Packet::Ack { block_num: 0 }));
         let reply = sender.process(&reply.unwrap());
@@ -410,10 +957,13 @@ mod tests {
                 data: CONTENT.to_vec()
             })
         );
-        assert!(sender.done());
+        assert!(!sender.done());
         let reply = receiver.process(&reply.unwrap());
         assert_eq!(reply, Some(Packet::Ack { block_num: 1 }));
         assert!(receiver.done());
+        let reply = sender.process(&reply.unwrap());
+        assert_eq!(reply, None);
+        assert!(sender.done());
     }
 
     #[test]
@@ -424,6 +974,7 @@ mod tests {
         let reply = sender.process(&Packet::ReadRequest {
             filename: INPUT.to_string(),
             mode: MODE.to_string(),
+            options: TransferOptions::default(),
         });
         assert_eq!(
             reply,
@@ -432,9 +983,343 @@ mod tests {
                 data: CONTENT.to_vec()
             })
         );
-        assert!(sender.done());
+        assert!(!sender.done());
         let reply = receiver.process(&reply.unwrap());
         assert_eq!(reply, Some(Packet::Ack { block_num: 1 }));
         assert!(receiver.done());
+        let reply = sender.process(&reply.unwrap());
+        assert_eq!(reply, None);
+        assert!(sender.done());
+    }
+
+    #[test]
+    fn test_options_parse() {
+        let request = Packet::ReadRequest {
+            filename: INPUT.to_owned(),
+            mode: MODE.to_owned(),
+            options: TransferOptions {
+                blksize: Some(1428),
+                tsize: Some(0),
+                timeout: Some(3),
+                windowsize: Some(8),
+            },
+        };
+        let bytes = request.to_bytes();
+        assert!(bytes
+            .ends_with(b"blksize\x001428\x00tsize\x000\x00timeout\x003\x00windowsize\x008\x00"));
+        assert_eq!(Packet::from(&bytes), Ok(request));
+
+        let oack = Packet::OptionAck {
+            options: TransferOptions {
+                blksize: Some(1024),
+                tsize: Some(123456),
+                ..TransferOptions::default()
+            },
+        };
+        assert_eq!(&oack.to_bytes()[..2], &[0x00, 0x06]);
+        assert_eq!(Packet::from(&oack.to_bytes()), Ok(oack));
+
+        // names are case-insensitive; unknown and out-of-range options are dropped
+        let mut bytes = vec![0x00, 0x02];
+        bytes.extend_from_slice(b"f\x00octet\x00BLKSIZE\x004\x00WindowSize\x004\x00foo\x00bar\x00");
+        assert_eq!(
+            Packet::from(&bytes),
+            Ok(Packet::WriteRequest {
+                filename: "f".to_owned(),
+                mode: MODE.to_owned(),
+                options: TransferOptions {
+                    windowsize: Some(4),
+                    ..TransferOptions::default()
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_options_accept() {
+        let requested = TransferOptions {
+            blksize: Some(65535),
+            tsize: Some(0),
+            timeout: Some(2),
+            windowsize: Some(1000),
+        };
+        let acked = requested.accept(Some(4096), 16);
+        assert_eq!(
+            acked,
+            TransferOptions {
+                blksize: Some(MAX_BLOCK_SIZE),
+                tsize: Some(4096),
+                timeout: Some(2),
+                windowsize: Some(16),
+            }
+        );
+        assert!(requested.allows(&acked));
+        assert!(!acked.allows(&requested));
+        assert!(!TransferOptions::default().allows(&acked));
+        assert!(TransferOptions::default().accept(Some(1), 16).is_empty());
+    }
+
+    #[test]
+    fn test_receiver_rejects_unrequested_option() {
+        let mut receiver = Receiver::new("rfc1350-oack.txt").unwrap();
+        let reply = receiver.process(&Packet::OptionAck {
+            options: TransferOptions {
+                blksize: Some(1024),
+                ..TransferOptions::default()
+            },
+        });
+        assert_eq!(
+            reply,
+            Some(Packet::error(
+                ErrorCode::OptionNegotiation,
+                "unacceptable option acknowledgement"
+            ))
+        );
+        assert!(receiver.done());
+        let _ = std::fs::remove_file("rfc1350-oack.txt");
+    }
+
+    /// Shuttle packets between `sender` and `receiver` until both are done,
+    /// losing every DATA packet whose index in the stream is in `lose`.
+    fn pump(sender: &mut Sender, receiver: &mut Receiver, lose: &[usize]) {
+        let mut last = vec![sender.process(&Packet::Ack { block_num: 0 }).unwrap()];
+        last.extend(std::iter::from_fn(|| sender.next_packet()));
+        let mut to_receiver = last.clone();
+        let mut count = 0;
+        while !sender.done() {
+            let mut to_sender = Vec::new();
+            for packet in to_receiver.drain(..) {
+                count += 1;
+                if !lose.contains(&count) {
+                    to_sender.extend(receiver.process(&packet));
+                }
+            }
+            if to_sender.is_empty() {
+                // timeout: the sender retransmits its window
+                to_receiver = last.clone();
+                continue;
+            }
+            for packet in to_sender {
+                if let Some(reply) = sender.process(&packet) {
+                    last = vec![reply];
+                    last.extend(std::iter::from_fn(|| sender.next_packet()));
+                    to_receiver.extend(last.iter().cloned());
+                }
+            }
+        }
+        assert!(receiver.done());
+    }
+
+    #[test]
+    fn test_windowed_transfer() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+        let content: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&input, &content).unwrap();
+
+        let options = TransferOptions {
+            blksize: Some(100),
+            windowsize: Some(4),
+            ..TransferOptions::default()
+        };
+        let mut sender = Sender::new(&input).unwrap().negotiated(options);
+        let mut receiver = Receiver::new(&output).unwrap().requested(options);
+        let first = Packet::OptionAck { options };
+        let reply = receiver.process(&first).unwrap();
+        assert_eq!(reply, Packet::Ack { block_num: 0 });
+
+        // a window of four blocks goes out per ACK
+        let data = sender.process(&reply).unwrap();
+        assert!(matches!(data, Packet::Data { block_num: 1, .. }));
+        let rest: Vec<_> = std::iter::from_fn(|| sender.next_packet()).collect();
+        assert_eq!(rest.len(), 3);
+        assert_eq!(receiver.process(&data), None);
+        assert_eq!(receiver.process(&rest[0]), None);
+        // block 3 is lost: the receiver ACKs block 2 once to resume there
+        assert_eq!(
+            receiver.process(&rest[2]),
+            Some(Packet::Ack { block_num: 2 })
+        );
+        assert_eq!(receiver.process(&rest[2]), None);
+        let data = sender.process(&Packet::Ack { block_num: 2 }).unwrap();
+        assert!(matches!(data, Packet::Data { block_num: 3, .. }));
+
+        let mut sender = Sender::new(&input).unwrap().negotiated(options);
+        let mut receiver = Receiver::new(&output).unwrap().negotiated(options);
+        pump(&mut sender, &mut receiver, &[2, 7, 8, 30, 99]);
+        assert_eq!(std::fs::read(&output).unwrap(), content);
+    }
+
+    #[test]
+    fn test_block_number_rollover() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+        // more than 65535 blocks of 8 bytes
+        let content: Vec<u8> = (0..70_000 * 8 + 3).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&input, &content).unwrap();
+
+        let options = TransferOptions {
+            blksize: Some(8),
+            windowsize: Some(16),
+            ..TransferOptions::default()
+        };
+        let mut sender = Sender::new(&input).unwrap().negotiated(options);
+        let mut receiver = Receiver::new(&output).unwrap().negotiated(options);
+        pump(&mut sender, &mut receiver, &[65_530, 65_537]);
+        assert_eq!(std::fs::read(&output).unwrap(), content);
+    }
+
+    #[test]
+    fn test_packet_parse_errors() {
+        assert_eq!(Packet::from(&[]), Err(ParseError::Truncated));
+        assert_eq!(Packet::from(&[0x00]), Err(ParseError::Truncated));
+        assert_eq!(
+            Packet::from(&[0x00, 0x03, 0x00]),
+            Err(ParseError::Truncated)
+        );
+        assert_eq!(Packet::from(&[0x00, 0x04]), Err(ParseError::Truncated));
+        assert_eq!(
+            Packet::from(&[0x00, 0x09, 0x00, 0x01]),
+            Err(ParseError::UnknownOpCode(9))
+        );
+        assert_eq!(
+            Packet::from(b"\x00\x01rfc1350.txt"),
+            Err(ParseError::Unterminated)
+        );
+        assert_eq!(
+            Packet::from(b"\x00\x01rfc1350.txt\x00octet"),
+            Err(ParseError::Unterminated)
+        );
+        assert_eq!(
+            Packet::from(b"\x00\x01f\x00octet\x00blksize\x00"),
+            Err(ParseError::Unterminated)
+        );
+        assert_eq!(
+            Packet::from(b"\x00\x05\x00\x01oops"),
+            Err(ParseError::Unterminated)
+        );
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(Mode::parse("octet"), Some(Mode::Octet));
+        assert_eq!(Mode::parse("NetASCII"), Some(Mode::NetAscii));
+        assert_eq!(Mode::parse("mail"), None);
+        assert_eq!(Mode::parse(Mode::NetAscii.as_str()), Some(Mode::NetAscii));
+    }
+
+    #[test]
+    fn test_netascii_transfer() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.txt");
+        let output = dir.path().join("output.txt");
+        // CRs straddle block boundaries with a block size of 8
+        let text = b"line 1\nline\r2\n\n\rend\r";
+        std::fs::write(&input, text).unwrap();
+
+        let options = TransferOptions {
+            blksize: Some(8),
+            ..TransferOptions::default()
+        };
+        let mut sender = Sender::new(&input)
+            .unwrap()
+            .mode(Mode::NetAscii)
+            .negotiated(options);
+        assert_eq!(sender.size(), text.len() as u64);
+        assert_eq!(
+            sender.process(&Packet::Ack { block_num: 0 }),
+            Some(Packet::Data {
+                block_num: 1,
+                data: b"line 1\r\n".to_vec()
+            })
+        );
+
+        let mut sender = Sender::new(&input)
+            .unwrap()
+            .mode(Mode::NetAscii)
+            .negotiated(options);
+        let mut receiver = Receiver::new(&output)
+            .unwrap()
+            .mode(Mode::NetAscii)
+            .negotiated(options);
+        pump(&mut sender, &mut receiver, &[]);
+        assert_eq!(std::fs::read(&output).unwrap(), text);
+    }
+
+    /// Strings are read back byte by byte, so only ASCII survives a round trip.
+    fn cstring() -> impl Strategy<Value = String> {
+        "[\x01-\x7f]{0,32}"
+    }
+
+    fn packet() -> impl Strategy<Value = Packet> {
+        let options = (
+            proptest::option::of(8..=MAX_BLOCK_SIZE),
+            proptest::option::of(any::<u64>()),
+            proptest::option::of(1u8..),
+            proptest::option::of(1u16..),
+        )
+            .prop_map(|(blksize, tsize, timeout, windowsize)| TransferOptions {
+                blksize,
+                tsize,
+                timeout,
+                windowsize,
+            });
+        prop_oneof![
+            (cstring(), cstring(), options.clone()).prop_map(|(filename, mode, options)| {
+                Packet::ReadRequest {
+                    filename,
+                    mode,
+                    options,
+                }
+            }),
+            (cstring(), cstring(), options.clone()).prop_map(|(filename, mode, options)| {
+                Packet::WriteRequest {
+                    filename,
+                    mode,
+                    options,
+                }
+            }),
+            (any::<u16>(), proptest::collection::vec(any::<u8>(), 0..600))
+                .prop_map(|(block_num, data)| Packet::Data { block_num, data }),
+            any::<u16>().prop_map(|block_num| Packet::Ack { block_num }),
+            (any::<u16>(), cstring()).prop_map(|(error_code, error_msg)| Packet::Error {
+                error_code,
+                error_msg
+            }),
+            options.prop_map(|options| Packet::OptionAck { options }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_decoder_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..700)) {
+            let _ = Packet::from(&bytes);
+        }
+
+        #[test]
+        fn proptest_decoder_never_panics_on_truncation(packet in packet(), cut in any::<prop::sample::Index>()) {
+            let bytes = packet.to_bytes();
+            let _ = Packet::from(&bytes[..cut.index(bytes.len() + 1)]);
+        }
+
+        #[test]
+        fn proptest_round_trip(packet in packet()) {
+            prop_assert_eq!(Packet::from(&packet.to_bytes()), Ok(packet));
+        }
+
+        #[test]
+        fn proptest_netascii_round_trip(text in proptest::collection::vec(any::<u8>(), 0..300), blksize in 8u16..64) {
+            let mut encoded = Vec::new();
+            netascii::Encoder::new(&text[..]).read_to_end(&mut encoded).unwrap();
+            let mut decoder = netascii::Decoder::default();
+            let mut decoded = Vec::new();
+            for block in encoded.chunks(blksize as usize) {
+                decoded.extend(decoder.decode(block));
+            }
+            decoded.extend(decoder.finish());
+            prop_assert_eq!(decoded, text);
+        }
     }
 }
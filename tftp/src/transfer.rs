@@ -0,0 +1,173 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::tftp::{ErrorCode, LockStep, Packet, MAX_PACKET_SIZE};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Drives a `LockStep` over a UDP socket, retransmitting what it sent last
+/// whenever the peer stays silent for `timeout`.
+pub struct Transfer<'a> {
+    socket: &'a UdpSocket,
+    peer: SocketAddr,
+    locked: bool,
+    timeout: Duration,
+    max_retries: u32,
+    buf: Vec<u8>,
+}
+
+impl<'a> Transfer<'a> {
+    /// A transfer with a peer whose TID is already known.
+    pub fn new(socket: &'a UdpSocket, peer: SocketAddr) -> Transfer<'a> {
+        Transfer {
+            socket,
+            peer,
+            locked: true,
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            buf: vec![0u8; MAX_PACKET_SIZE],
+        }
+    }
+
+    /// A transfer whose peer TID is learned from the first reply, as a client
+    /// sends its request to the well-known port but talks to an ephemeral one.
+    pub fn unlocked(socket: &'a UdpSocket, server: SocketAddr) -> Transfer<'a> {
+        Transfer {
+            locked: false,
+            ..Transfer::new(socket, server)
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Transfer<'a> {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Transfer<'a> {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Send `first` and then keep the lock step going until it is done.
+    pub fn run<T: LockStep>(&mut self, lock_stepper: &mut T, first: &Packet) -> io::Result<()> {
+        let mut last = self.send_burst(lock_stepper, first)?;
+
+        let mut retries = 0;
+        let mut deadline = Instant::now() + self.timeout_of(lock_stepper);
+        while !lock_stepper.done() {
+            let packet = match self.recv_until(deadline)? {
+                Some(packet) => packet,
+                None => {
+                    if retries >= self.max_retries {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("no response from {} after {} retries", self.peer, retries),
+                        ));
+                    }
+                    retries += 1;
+                    for bytes in &last {
+                        self.socket.send_to(bytes, self.peer)?;
+                    }
+                    deadline = Instant::now() + self.timeout_of(lock_stepper);
+                    continue;
+                }
+            };
+            if let Packet::Error {
+                error_code,
+                error_msg,
+            } = packet
+            {
+                let reason = match ErrorCode::from_u16(error_code) {
+                    Some(code) => format!("peer error {} ({})", error_code, code),
+                    None => format!("peer error {}", error_code),
+                };
+                return Err(io::Error::other(format!("{}: {}", reason, error_msg)));
+            }
+            if let Some(reply) = lock_stepper.process(&packet) {
+                if let Packet::Error { error_msg, .. } = &reply {
+                    self.socket.send_to(&reply.to_bytes(), self.peer)?;
+                    return Err(io::Error::other(error_msg.clone()));
+                }
+                let burst = self.send_burst(lock_stepper, &reply)?;
+                if burst != last {
+                    last = burst;
+                    retries = 0;
+                    deadline = Instant::now() + self.timeout_of(lock_stepper);
+                }
+            }
+        }
+
+        if lock_stepper.dally() {
+            let deadline = Instant::now() + self.timeout_of(lock_stepper);
+            while let Some(packet) = self.recv_until(deadline)? {
+                if let Some(reply) = lock_stepper.process(&packet) {
+                    self.socket.send_to(&reply.to_bytes(), self.peer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Send `first` along with whatever the lock step lets follow it, and
+    /// return the datagrams to retransmit should the peer stay silent.
+    fn send_burst<T: LockStep>(
+        &self,
+        lock_stepper: &mut T,
+        first: &Packet,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let mut burst = vec![first.to_bytes()];
+        while let Some(packet) = lock_stepper.next_packet() {
+            burst.push(packet.to_bytes());
+        }
+        for bytes in &burst {
+            self.socket.send_to(bytes, self.peer)?;
+        }
+        Ok(burst)
+    }
+
+    fn timeout_of<T: LockStep>(&self, lock_stepper: &T) -> Duration {
+        lock_stepper.timeout().unwrap_or(self.timeout)
+    }
+
+    /// Refuse the transfer by sending an ERROR packet to the peer.
+    pub fn reject(&self, code: ErrorCode, msg: &str) -> io::Result<()> {
+        self.socket
+            .send_to(&Packet::error(code, msg).to_bytes(), self.peer)?;
+        Ok(())
+    }
+
+    /// Wait for a well-formed packet from the peer, or `None` once `deadline`
+    /// has passed. Packets from any other TID are answered with an error.
+    fn recv_until(&mut self, deadline: Instant) -> io::Result<Option<Packet>> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(deadline - now))?;
+            let (size, org) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            if !self.locked {
+                self.peer = org;
+                self.locked = true;
+            } else if org != self.peer {
+                let stray = Packet::error(ErrorCode::UnknownTid, "unknown transfer ID");
+                self.socket.send_to(&stray.to_bytes(), org)?;
+                continue;
+            }
+            if let Ok(packet) = Packet::from(&self.buf[..size]) {
+                return Ok(Some(packet));
+            }
+        }
+    }
+}
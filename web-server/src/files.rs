@@ -0,0 +1,343 @@
+//! Serving a directory tree over HTTP.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::http::{Request, Response};
+
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<StaticFiles> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+        Ok(StaticFiles { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn serve(&self, request: &Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::error(405).header("Allow", "GET, HEAD");
+        }
+        let path = match self.resolve(&request.path) {
+            Ok(path) => path,
+            Err(status) => return self.error(status),
+        };
+        if path.is_dir() {
+            if !request.path.ends_with('/') {
+                let mut location = format!("{}/", request.path);
+                if let Some(query) = &request.query {
+                    location = format!("{}?{}", location, query);
+                }
+                return Response::error(301).header("Location", location);
+            }
+            let index = path.join("index.html");
+            if index.is_file() {
+                return self.file(request, &index);
+            }
+            return match listing(&path, &request.path) {
+                Ok(html) => Response::new(200).body(html.into_bytes(), "text/html; charset=utf-8"),
+                Err(e) => self.io_error(e),
+            };
+        }
+        self.file(request, &path)
+    }
+
+    /// Maps a request path onto the file system, refusing anything that
+    /// would end up outside the root.
+    fn resolve(&self, request_path: &str) -> Result<PathBuf, u16> {
+        let decoded = percent_decode(request_path).ok_or(400u16)?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return Err(403),
+                s if s.contains(['\\', '\0']) => return Err(400),
+                s => path.push(s),
+            }
+        }
+        // symbolic links may still point elsewhere
+        let path = fs::canonicalize(path).map_err(|e| io_status(&e))?;
+        if !path.starts_with(&self.root) {
+            return Err(403);
+        }
+        Ok(path)
+    }
+
+    fn file(&self, request: &Request, path: &Path) -> Response {
+        let opened = File::open(path).and_then(|file| {
+            let metadata = file.metadata()?;
+            Ok((file, metadata))
+        });
+        let (file, metadata) = match opened {
+            Ok(opened) => opened,
+            Err(e) => return self.io_error(e),
+        };
+        // HTTP dates only go down to the second
+        let modified = metadata.modified().ok().map(|modified| {
+            let secs = modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            UNIX_EPOCH + Duration::from_secs(secs)
+        });
+        let last_modified = modified.map(httpdate::fmt_http_date);
+
+        let since = request
+            .headers
+            .get("If-Modified-Since")
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+        if let (Some(modified), Some(since)) = (modified, since) {
+            if modified <= since {
+                let response = Response::new(304);
+                return match last_modified {
+                    Some(date) => response.header("Last-Modified", date),
+                    None => response,
+                };
+            }
+        }
+
+        let response = Response::new(200).file(file, metadata.len(), mime_type(path));
+        match last_modified {
+            Some(date) => response.header("Last-Modified", date),
+            None => response,
+        }
+    }
+
+    fn io_error(&self, e: io::Error) -> Response {
+        let status = io_status(&e);
+        if status == 500 {
+            println!("Error serving file: {}", e);
+        }
+        self.error(status)
+    }
+
+    /// An error page, using `<root>/<status>.html` when there is one.
+    fn error(&self, status: u16) -> Response {
+        match fs::read(self.root.join(format!("{}.html", status))) {
+            Ok(page) => Response::new(status).body(page, "text/html; charset=utf-8"),
+            Err(_) => Response::error(status),
+        }
+    }
+}
+
+fn io_status(e: &io::Error) -> u16 {
+    match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => 404,
+        io::ErrorKind::PermissionDenied => 403,
+        _ => 500,
+    }
+}
+
+/// Decodes `%XX` escapes, refusing invalid ones and results that aren't UTF-8.
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = text.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn listing(dir: &Path, request_path: &str) -> io::Result<String> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    let title = escape_html(&percent_decode(request_path).unwrap_or_default());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if request_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in names {
+        let href: String = name
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            href,
+            escape_html(&name)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") | Some("md") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("woff2") => "font/woff2",
+        Some("mp4") => "video/mp4",
+        Some("mp3") => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Headers, Version};
+
+    fn get(path: &str) -> Request {
+        Request {
+            method: "GET".to_owned(),
+            path: path.to_owned(),
+            query: None,
+            version: Version::Http11,
+            headers: Headers::default(),
+            body: Vec::new(),
+        }
+    }
+
+    fn body(response: Response) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out, false, true).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        text.split_once("\r\n\r\n").unwrap().1.to_owned()
+    }
+
+    fn site() -> (tempfile::TempDir, StaticFiles) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("site");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("empty dir")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("docs/a b.txt"), "spaced").unwrap();
+        fs::write(root.join("404.html"), "custom missing").unwrap();
+        fs::write(dir.path().join("secret"), "outside").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), root.join("link")).unwrap();
+        let files = StaticFiles::new(&root).unwrap();
+        (dir, files)
+    }
+
+    #[test]
+    fn test_serve() {
+        let (_dir, files) = site();
+        let response = files.serve(&get("/"));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(response.headers.get("Last-Modified").is_some());
+        assert_eq!(body(response), "home");
+
+        let response = files.serve(&get("/docs/a%20b.txt"));
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(body(response), "spaced");
+
+        let response = files.serve(&get("/docs"));
+        assert_eq!(response.status, 301);
+        assert_eq!(response.headers.get("Location"), Some("/docs/"));
+
+        let response = files.serve(&get("/empty%20dir/"));
+        assert_eq!(response.status, 200);
+        assert!(body(response).contains("<h1>Index of /empty dir/</h1>"));
+        let listing = body(files.serve(&get("/docs/")));
+        assert!(listing.contains("<a href=\"a%20b.txt\">a b.txt</a>"));
+
+        let response = files.serve(&get("/nothing.html"));
+        assert_eq!(response.status, 404);
+        assert_eq!(body(response), "custom missing");
+
+        let mut post = get("/");
+        post.method = "POST".to_owned();
+        assert_eq!(files.serve(&post).status, 405);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let (_dir, files) = site();
+        assert_eq!(files.serve(&get("/../secret")).status, 403);
+        assert_eq!(files.serve(&get("/docs/%2e%2e/%2e%2e/secret")).status, 403);
+        assert_eq!(files.serve(&get("/docs/..%2f..%2fsecret")).status, 403);
+        assert_eq!(files.serve(&get("/link")).status, 403);
+        assert_eq!(files.serve(&get("/%00")).status, 400);
+        assert_eq!(files.serve(&get("/%zz")).status, 400);
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let (_dir, files) = site();
+        let response = files.serve(&get("/"));
+        let modified = response.headers.get("Last-Modified").unwrap().to_owned();
+
+        let mut request = get("/");
+        request.headers.insert("If-Modified-Since", modified);
+        assert_eq!(files.serve(&request).status, 304);
+        request
+            .headers
+            .insert("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(files.serve(&request).status, 200);
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type(Path::new("a/b.PNG")), "image/png");
+        assert_eq!(mime_type(Path::new("noext")), "application/octet-stream");
+    }
+}
@@ -0,0 +1,549 @@
+//! Just enough of HTTP/1.1 to serve files over persistent connections.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::time::SystemTime;
+
+/// Longest request line or header line we accept.
+pub const MAX_LINE: usize = 8 * 1024;
+pub const MAX_HEADERS: usize = 100;
+pub const MAX_BODY: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    BadRequest(&'static str),
+    BodyTooLarge,
+    HeadersTooLarge,
+    UnsupportedVersion,
+}
+
+impl ParseError {
+    /// The status to answer with, or `None` when the connection is unusable.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ParseError::Io(_) => None,
+            ParseError::BadRequest(_) => Some(400),
+            ParseError::BodyTooLarge => Some(413),
+            ParseError::HeadersTooLarge => Some(431),
+            ParseError::UnsupportedVersion => Some(505),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "{}", e),
+            ParseError::BadRequest(why) => write!(f, "bad request: {}", why),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/// Header fields in the order they arrived; names compare case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether a comma-separated header such as `Connection` lists `token`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+        self.0.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.0.push((name.to_owned(), value.into()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// The path part of the target, still percent-encoded, such as `/a%20b.html`.
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads the next request off a connection, or `None` if the client
+    /// closed it cleanly between requests.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Option<Request>, ParseError> {
+        // RFC 9112 section 2.2: ignore empty lines ahead of the request line.
+        let line = loop {
+            match read_line(reader)? {
+                None => return Ok(None),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+        let mut parts = line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(m), Some(t), Some(v)) if parts.next().is_none() => (m, t, v),
+            _ => return Err(ParseError::BadRequest("malformed request line")),
+        };
+        if method.is_empty() || !method.bytes().all(is_token_char) {
+            return Err(ParseError::BadRequest("malformed method"));
+        }
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            v if v.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+            _ => return Err(ParseError::BadRequest("malformed version")),
+        };
+        let (path, query) = split_target(target)?;
+
+        let headers = read_headers(reader)?;
+        if version == Version::Http11 && headers.get("Host").is_none() {
+            return Err(ParseError::BadRequest("missing Host header"));
+        }
+        let body = read_body(reader, &headers)?;
+
+        Ok(Some(Request {
+            method: method.to_owned(),
+            path,
+            query,
+            version,
+            headers,
+            body,
+        }))
+    }
+
+    /// Whether the connection should stay open after answering.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.has_token("Connection", "close"),
+            Version::Http10 => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+}
+
+fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Splits an origin-form or absolute-form target into path and query.
+fn split_target(target: &str) -> Result<(String, Option<String>), ParseError> {
+    let target = if target == "*" || target.starts_with('/') {
+        target
+    } else if let Some(rest) = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("https://"))
+    {
+        rest.find('/').map_or("/", |i| &rest[i..])
+    } else {
+        return Err(ParseError::BadRequest("malformed target"));
+    };
+    Ok(match target.split_once('?') {
+        Some((path, query)) => (path.to_owned(), Some(query.to_owned())),
+        None => (target.to_owned(), None),
+    })
+}
+
+/// Reads a line without its CRLF (or bare LF). `None` means end of stream
+/// before any byte of the line.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut buf = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE as u64 + 2)
+        .read_until(b'\n', &mut buf)?;
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    if buf.pop() != Some(b'\n') {
+        return Err(if buf.len() >= MAX_LINE {
+            ParseError::HeadersTooLarge
+        } else {
+            ParseError::Io(io::ErrorKind::UnexpectedEof.into())
+        });
+    }
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    String::from_utf8(buf)
+        .map(Some)
+        .map_err(|_| ParseError::BadRequest("invalid UTF-8"))
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<Headers, ParseError> {
+    let mut headers = Headers::default();
+    loop {
+        let line = read_line(reader)?.ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if line.starts_with([' ', '\t']) {
+            return Err(ParseError::BadRequest("obsolete line folding"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ParseError::BadRequest("malformed header"))?;
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            return Err(ParseError::BadRequest("malformed header name"));
+        }
+        if headers.0.len() == MAX_HEADERS {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        headers
+            .0
+            .push((name.to_owned(), value.trim_matches([' ', '\t']).to_owned()));
+    }
+}
+
+fn read_body<R: BufRead>(reader: &mut R, headers: &Headers) -> Result<Vec<u8>, ParseError> {
+    let chunked = match headers.get_all("Transfer-Encoding").last() {
+        None => false,
+        // a request whose framing is ambiguous could be smuggling another
+        Some(_) if headers.get("Content-Length").is_some() => {
+            return Err(ParseError::BadRequest(
+                "both Transfer-Encoding and Content-Length",
+            ))
+        }
+        Some(codings) => {
+            let last = codings.rsplit(',').next().unwrap_or("").trim();
+            if !last.eq_ignore_ascii_case("chunked") {
+                return Err(ParseError::BadRequest("unsupported transfer coding"));
+            }
+            true
+        }
+    };
+    if chunked {
+        return read_chunked(reader);
+    }
+
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        let value: usize = match value.trim() {
+            v if !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()) => {
+                v.parse().map_err(|_| ParseError::BodyTooLarge)?
+            }
+            _ => return Err(ParseError::BadRequest("malformed Content-Length")),
+        };
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::BadRequest("conflicting Content-Length"));
+        }
+        length = Some(value);
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_BODY {
+        return Err(ParseError::BodyTooLarge);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let size = line.split(';').next().unwrap().trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| ParseError::BadRequest("malformed chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        if size > MAX_BODY - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        if read_line(reader)?.as_deref() != Some("") {
+            return Err(ParseError::BadRequest("chunk longer than its size"));
+        }
+    }
+    // trailer fields carry nothing we use
+    read_headers(reader)?;
+    Ok(body)
+}
+
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    File(File, u64),
+}
+
+impl Body {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(_, len) => *len,
+        }
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::default(),
+            body: Body::Empty,
+        }
+    }
+
+    /// A short plain page explaining the status.
+    pub fn error(status: u16) -> Response {
+        Response::new(status).body(
+            format!("{} {}\n", status, reason(status)).into_bytes(),
+            "text/plain; charset=utf-8",
+        )
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn body(self, bytes: Vec<u8>, content_type: &str) -> Response {
+        Response {
+            body: Body::Bytes(bytes),
+            ..self.header("Content-Type", content_type)
+        }
+    }
+
+    pub fn file(self, file: File, len: u64, content_type: &str) -> Response {
+        Response {
+            body: Body::File(file, len),
+            ..self.header("Content-Type", content_type)
+        }
+    }
+
+    /// Writes the response, leaving out the body if it answers a `HEAD`.
+    pub fn write_to<W: Write>(
+        self,
+        writer: &mut W,
+        head: bool,
+        keep_alive: bool,
+    ) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason(self.status)
+        )?;
+        write!(
+            writer,
+            "Date: {}\r\n",
+            httpdate::fmt_http_date(SystemTime::now())
+        )?;
+        for (name, value) in self.headers.iter() {
+            write!(writer, "{}: {}\r\n", name, value)?;
+        }
+        let bodiless = self.status == 204 || self.status == 304;
+        if !bodiless {
+            write!(writer, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        if !keep_alive {
+            write!(writer, "Connection: close\r\n")?;
+        }
+        write!(writer, "\r\n")?;
+        if head || bodiless {
+            return Ok(());
+        }
+        match self.body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => writer.write_all(&bytes)?,
+            Body::File(file, len) => {
+                let copied = io::copy(&mut file.take(len), writer)?;
+                if copied < len {
+                    // the file shrank after we announced its length
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Option<Request>, ParseError> {
+        Request::read_from(&mut text.as_bytes())
+    }
+
+    #[test]
+    fn test_request() {
+        let request = parse(
+            "\r\nGET /docs/index.html?lang=en HTTP/1.1\r\n\
+             Host: example.com\r\n\
+             Accept:  text/html \r\n\
+             Connection: keep-alive, Upgrade\r\n\r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/docs/index.html");
+        assert_eq!(request.query.as_deref(), Some("lang=en"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.get("accept"), Some("text/html"));
+        assert!(request.headers.has_token("Connection", "upgrade"));
+        assert!(request.keep_alive());
+        assert!(request.body.is_empty());
+
+        let request = parse("GET http://example.com HTTP/1.0\n\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.path, "/");
+        assert!(!request.keep_alive());
+
+        assert!(parse("").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_bodies() {
+        let mut stream = "POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello\
+                          POST /b HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                          4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\n\
+                          GET /c HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n"
+            .as_bytes();
+        let a = Request::read_from(&mut stream).unwrap().unwrap();
+        assert_eq!(a.body, b"hello");
+        let b = Request::read_from(&mut stream).unwrap().unwrap();
+        assert_eq!(b.path, "/b");
+        assert_eq!(b.body, b"Wikipedia");
+        let c = Request::read_from(&mut stream).unwrap().unwrap();
+        assert_eq!(c.path, "/c");
+        assert!(!c.keep_alive());
+        assert!(Request::read_from(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let status = |text: &str| parse(text).unwrap_err().status();
+        assert_eq!(status("GET /\r\n\r\n"), Some(400));
+        assert_eq!(status("GET / HTTP/1.1\r\n\r\n"), Some(400));
+        assert_eq!(status("GET / HTTP/2.0\r\nHost: x\r\n\r\n"), Some(505));
+        assert_eq!(status("GET / HTTP/1.1\r\nHost : x\r\n\r\n"), Some(400));
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: x\r\n folded\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 99999999999\r\n\r\n"),
+            Some(413)
+        );
+        assert_eq!(
+            status(
+                "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\nffffffffffffffff\r\n"
+            ),
+            Some(413)
+        );
+        let long = format!("GET /{} HTTP/1.1\r\nHost: x\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(status(&long), Some(431));
+        // cut off mid-request
+        assert_eq!(status("GET / HTTP/1.1\r\nHost: x\r\n"), None);
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 10\r\n\r\nshort"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let mut out = Vec::new();
+        Response::new(200)
+            .header("X-Test", "1")
+            .body(b"hi".to_vec(), "text/plain")
+            .write_to(&mut out, false, false)
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\nDate: "));
+        assert!(text.contains("\r\nX-Test: 1\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n"));
+        assert!(text.ends_with("Connection: close\r\n\r\nhi"));
+
+        let mut out = Vec::new();
+        Response::new(200)
+            .body(b"hi".to_vec(), "text/plain")
+            .write_to(&mut out, true, true)
+            .unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.ends_with("Content-Length: 2\r\n\r\n"));
+    }
+}
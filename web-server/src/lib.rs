@@ -1,99 +1,381 @@
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread;
-use std::thread::JoinHandle;
+pub mod files;
+pub mod http;
 
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
-}
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
 
-trait FnBox {
-    fn call_box(self: Box<Self>);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolError {
+    /// The queue is at capacity; only returned by `try_execute`.
+    Full,
+    /// The pool no longer takes jobs.
+    ShutDown,
+    /// Workers were still busy when the shutdown timeout ran out.
+    Timeout,
 }
 
-impl<F: FnOnce()> FnBox for F {
-    fn call_box(self: Box<F>) {
-        (*self)()
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::Full => write!(f, "job queue is full"),
+            PoolError::ShutDown => write!(f, "thread pool is shut down"),
+            PoolError::Timeout => write!(f, "timed out waiting for workers"),
+        }
     }
 }
 
-type Job = Box<dyn FnBox + Send + 'static>;
+impl std::error::Error for PoolError {}
+
+/// A snapshot of what the pool is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Metrics {
+    /// Worker threads alive.
+    pub workers: usize,
+    /// Jobs waiting for a worker.
+    pub queued: usize,
+    /// Jobs running right now.
+    pub active: usize,
+    /// Jobs that ran to the end, panicking or not.
+    pub completed: usize,
+    pub panicked: usize,
+}
+
+/// State shared between the pool and its workers.
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Job>>,
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicUsize,
+    panicked: AtomicUsize,
+    // NOTE: a count under a mutex, so shutdown can wait on the condvar
+    live: Mutex<usize>,
+    exited: Condvar,
+    threads: Mutex<Vec<Option<JoinHandle<()>>>>,
+}
+
+/// Poisoning only means another thread panicked while holding the lock;
+/// everything guarded here stays consistent regardless.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+pub struct ThreadPool {
+    // NOTE: `None` once shut down; dropping the sender is what tells workers
+    // to finish the queue and stop.
+    sender: Mutex<Option<mpsc::SyncSender<Job>>>,
+    shared: Arc<Shared>,
 }
 
-struct Worker {
+/// Lives on a worker's stack. If a job panics, dropping it during the unwind
+/// starts a replacement thread so the pool keeps its size.
+struct Sentinel {
     id: usize,
-    // NOTE: understand why Option is needed
-    thread: Option<thread::JoinHandle<()>>,
+    shared: Arc<Shared>,
+    busy: bool,
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread: JoinHandle<()> = thread::spawn(move || {
-            loop {
-                // NOTE: understand why these operation may fail
-                let message = receiver.lock().unwrap().recv().unwrap();
-                match message {
-                    Message::NewJob(job) => {
-                        println!("Worker {} got a job; executing.", id);
-                        job.call_box();
-                    }
-                    Message::Terminate => {
-                        println!("Worker {} was told to terminate.", id);
-                        break;
-                    }
-                }
-            }
-        });
+impl Sentinel {
+    fn run(&mut self) {
+        loop {
+            // the guard is a temporary, so the lock is released before the job runs
+            let job = match lock(&self.shared.receiver).recv() {
+                Ok(job) => job,
+                // the pool shut down and the queue is drained
+                Err(_) => break,
+            };
+            self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+            self.shared.active.fetch_add(1, Ordering::SeqCst);
+            self.busy = true;
+            job();
+            self.busy = false;
+            self.shared.active.fetch_sub(1, Ordering::SeqCst);
+            self.shared.completed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
 
-        Worker {
-            id,
-            thread: Some(thread),
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if self.busy {
+            self.shared.active.fetch_sub(1, Ordering::SeqCst);
+            self.shared.completed.fetch_add(1, Ordering::SeqCst);
+            self.shared.panicked.fetch_add(1, Ordering::SeqCst);
+        }
+        if thread::panicking() {
+            spawn_worker(self.id, self.shared.clone());
+        }
+        let mut live = lock(&self.shared.live);
+        *live -= 1;
+        if *live == 0 {
+            self.shared.exited.notify_all();
         }
     }
 }
 
+fn spawn_worker(id: usize, shared: Arc<Shared>) {
+    *lock(&shared.live) += 1;
+    let worker = shared.clone();
+    let thread = thread::Builder::new()
+        .name(format!("worker-{}", id))
+        .spawn(move || {
+            Sentinel {
+                id,
+                shared: worker,
+                busy: false,
+            }
+            .run()
+        })
+        .expect("failed to spawn worker thread");
+    // the thread this replaces, if any, is on its way out and needs no join
+    lock(&shared.threads)[id] = Some(thread);
+}
+
 impl ThreadPool {
+    /// A pool of `size` workers whose queue holds 16 jobs per worker.
     pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_capacity(size, size * 16)
+    }
+
+    /// A pool of `size` workers with room for `capacity` waiting jobs.
+    pub fn with_capacity(size: usize, capacity: usize) -> ThreadPool {
         assert!(size > 0);
 
-        let (sender, receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::sync_channel(capacity);
 
         // NOTE: important to understand why Arc<Mutex<...>> is needed.
-        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            queued: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            live: Mutex::new(0),
+            exited: Condvar::new(),
+            threads: Mutex::new((0..size).map(|_| None).collect()),
+        });
 
-        let mut workers = Vec::with_capacity(size);
+        for id in 0..size {
+            spawn_worker(id, shared.clone());
+        }
 
-        for i in 0..size {
-            workers.push(Worker::new(i, receiver.clone()))
+        ThreadPool {
+            sender: Mutex::new(Some(sender)),
+            shared,
         }
+    }
 
-        ThreadPool { workers, sender }
+    fn sender(&self) -> Result<mpsc::SyncSender<Job>, PoolError> {
+        lock(&self.sender).clone().ok_or(PoolError::ShutDown)
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// Queues a job, waiting for room if the queue is full.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolError>
     where
         F: FnOnce() + Send + 'static,
     {
         // NOTE: important to understand why Box is needed
-        let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
+        let sender = self.sender()?;
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        sender.send(Box::new(f)).map_err(|_| {
+            self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+            PoolError::ShutDown
+        })
+    }
+
+    /// Queues a job unless the queue is full, so callers can shed load.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), PoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender()?;
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        sender.try_send(Box::new(f)).map_err(|e| {
+            self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+            match e {
+                mpsc::TrySendError::Full(_) => PoolError::Full,
+                mpsc::TrySendError::Disconnected(_) => PoolError::ShutDown,
+            }
+        })
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            workers: *lock(&self.shared.live),
+            queued: self.shared.queued.load(Ordering::SeqCst),
+            active: self.shared.active.load(Ordering::SeqCst),
+            completed: self.shared.completed.load(Ordering::SeqCst),
+            panicked: self.shared.panicked.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Stops taking jobs and waits up to `timeout` for the queued and running
+    /// ones to finish. Workers still busy after that are left to finish on
+    /// their own.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), PoolError> {
+        self.stop(Some(Instant::now() + timeout))
+    }
+
+    fn stop(&self, deadline: Option<Instant>) -> Result<(), PoolError> {
+        lock(&self.sender).take();
+
+        let mut live = lock(&self.shared.live);
+        while *live > 0 {
+            live = match deadline {
+                None => self
+                    .shared
+                    .exited
+                    .wait(live)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(PoolError::Timeout);
+                    }
+                    self.shared
+                        .exited
+                        .wait_timeout(live, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+        drop(live);
+
+        for thread in lock(&self.shared.threads).iter_mut() {
+            if let Some(thread) = thread.take() {
+                // a panic would have been caught by the sentinel already
+                let _ = thread.join();
+            }
+        }
+        Ok(())
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        for _ in &mut self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        let _ = self.stop(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    fn wait_for(pool: &ThreadPool, done: impl Fn(&Metrics) -> bool) -> Metrics {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let metrics = pool.metrics();
+            if done(&metrics) {
+                return metrics;
+            }
+            assert!(Instant::now() < deadline, "stuck at {:?}", metrics);
+            thread::sleep(Duration::from_millis(5));
         }
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_execute() {
+        let pool = ThreadPool::new(4);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..20 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).unwrap();
+        }
+        let mut results: Vec<i32> = rx.iter().take(20).collect();
+        results.sort();
+        assert_eq!(results, (0..20).collect::<Vec<_>>());
+        let metrics = wait_for(&pool, |m| m.completed == 20);
+        assert_eq!(
+            metrics,
+            Metrics {
+                workers: 4,
+                queued: 0,
+                active: 0,
+                completed: 20,
+                panicked: 0,
             }
+        );
+    }
+
+    #[test]
+    fn test_panicking_job() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed on purpose")).unwrap();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(thread::current().name().map(String::from)).unwrap())
+            .unwrap();
+        // the replacement worker runs what was queued after the panic
+        let name = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(name.as_deref(), Some("worker-0"));
+        let metrics = wait_for(&pool, |m| m.completed == 2);
+        assert_eq!(metrics.panicked, 1);
+        assert_eq!(metrics.workers, 1);
+        assert_eq!(metrics.active, 0);
+        assert_eq!(pool.shutdown(Duration::from_secs(5)), Ok(()));
+    }
+
+    #[test]
+    fn test_try_execute() {
+        let pool = ThreadPool::with_capacity(1, 1);
+        let barrier = Arc::new(Barrier::new(2));
+        let worker_barrier = barrier.clone();
+        pool.execute(move || {
+            worker_barrier.wait();
+        })
+        .unwrap();
+        wait_for(&pool, |m| m.active == 1);
+
+        pool.try_execute(|| {}).unwrap();
+        assert_eq!(pool.try_execute(|| {}), Err(PoolError::Full));
+        assert_eq!(pool.metrics().queued, 1);
+
+        barrier.wait();
+        wait_for(&pool, |m| m.completed == 2);
+        pool.try_execute(|| {}).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_drains_queue() {
+        let pool = ThreadPool::with_capacity(2, 10);
+        let (tx, rx) = mpsc::channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                tx.send(i).unwrap();
+            })
+            .unwrap();
         }
+        drop(tx);
+
+        assert_eq!(pool.shutdown(Duration::from_secs(5)), Ok(()));
+        // every queued job finished before shutdown returned
+        assert_eq!(rx.try_iter().count(), 10);
+        assert_eq!(pool.metrics().workers, 0);
+        assert_eq!(pool.execute(|| {}), Err(PoolError::ShutDown));
+        assert_eq!(pool.try_execute(|| {}), Err(PoolError::ShutDown));
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = rx.recv();
+        })
+        .unwrap();
+        wait_for(&pool, |m| m.active == 1);
+        assert_eq!(
+            pool.shutdown(Duration::from_millis(50)),
+            Err(PoolError::Timeout)
+        );
+        drop(tx);
+        assert_eq!(pool.shutdown(Duration::from_secs(5)), Ok(()));
     }
 }
@@ -1,45 +1,153 @@
-use std::fs::File;
-use std::io::{Read, Write};
+use std::env;
+use std::io::{self, BufReader, BufWriter, Write};
 use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use web_server::ThreadPool;
+use web_server::files::StaticFiles;
+use web_server::http::{ParseError, Request, Response};
+use web_server::{PoolError, ThreadPool};
 
-fn main() {
+/// How long an idle keep-alive connection may hold a worker.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long in-flight requests get to finish once we're asked to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the accept loop checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Set by SIGINT or SIGTERM.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    STOPPING.store(true, Ordering::SeqCst);
+}
+
+fn install_signal_handlers() {
+    let handler: extern "C" fn(libc::c_int) = on_signal;
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        unsafe {
+            libc::signal(signal, handler as libc::sighandler_t);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: web-server [--root dir] [address]");
+    process::exit(2);
+}
+
+fn main() -> io::Result<()> {
+    let mut address = "127.0.0.1:8080".to_owned();
+    let mut root = ".".to_owned();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = args.next().unwrap_or_else(|| usage()),
+            _ if arg.starts_with('-') => usage(),
+            _ => address = arg,
+        }
+    }
+
+    let files = Arc::new(StaticFiles::new(&root)?);
     let pool = ThreadPool::new(4);
 
-    let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
+    let listener = TcpListener::bind(&address)?;
+    // polled, so a signal can stop the loop between connections
+    listener.set_nonblocking(true)?;
+    install_signal_handlers();
+    println!(
+        "Serving {} at http://{}",
+        files.root().display(),
+        listener.local_addr()?
+    );
+    while !STOPPING.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                println!("Failed to accept: {}", e);
+                continue;
+            }
+        };
+        // some platforms hand out sockets that inherit the listener's mode
+        if let Err(e) = stream.set_nonblocking(false) {
+            println!("Failed to set up connection: {}", e);
+            continue;
+        }
 
-        pool.execute(|| {
-            handle_connection(stream);
+        // kept so we can still answer if the pool turns the connection away
+        let mut spare = match stream.try_clone() {
+            Ok(spare) => spare,
+            Err(e) => {
+                println!("Failed to set up connection: {}", e);
+                continue;
+            }
+        };
+        let files = files.clone();
+        let submitted = pool.try_execute(move || {
+            if let Err(e) = handle_connection(stream, &files) {
+                println!("Connection error: {}", e);
+            }
         });
+        if let Err(PoolError::Full) = submitted {
+            // Shedding load beats letting the queue grow without bound.
+            let response = Response::error(503).header("Retry-After", "1");
+            let _ = response.write_to(&mut spare, false, false);
+        }
+    }
+
+    println!("Shutting down: {:?}", pool.metrics());
+    match pool.shutdown(SHUTDOWN_TIMEOUT) {
+        Ok(()) => println!("All requests finished"),
+        Err(e) => println!("Gave up waiting: {}", e),
     }
+    Ok(())
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 512];
-    let n = stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-
-    let request = &buffer[..n];
-    let (status_line, filename) = if request.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "index.html")
-    } else if request.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK\r\n\r\n", "index.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "404.html")
-    };
-
-    let mut file = File::open(format!("web-server/{}", filename)).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-
-    let response = format!("{}{}", status_line, contents);
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+fn handle_connection(stream: TcpStream, files: &StaticFiles) -> io::Result<()> {
+    stream.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let mut request = match Request::read_from(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            // an idle keep-alive connection timing out is routine
+            Err(ParseError::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(ParseError::Io(e)) => return Err(e),
+            Err(e) => {
+                let status = e.status().unwrap_or(400);
+                Response::error(status).write_to(&mut writer, false, false)?;
+                return writer.flush();
+            }
+        };
+
+        // Kept from the original example to show requests running side by side.
+        if request.path == "/sleep" {
+            thread::sleep(Duration::from_secs(5));
+            request.path = "/".to_owned();
+        }
+
+        let keep_alive = request.keep_alive() && !STOPPING.load(Ordering::SeqCst);
+        let response = files.serve(&request);
+        response.write_to(&mut writer, request.method == "HEAD", keep_alive)?;
+        writer.flush()?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
 }